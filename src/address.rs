@@ -0,0 +1,143 @@
+use crate::ast::*;
+
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::{Address, Network, Script};
+
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+// Networks to generate address for.
+pub const NETWORKS: [&str; 5] = ["mainnet", "testnet", "testnet4", "signet", "regtest"];
+
+// Locking script of the output and what is revealed to spend it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[wasm_bindgen]
+pub struct LockingOutput {
+    network: String,
+    address: String,
+    script_pubkey: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    redeem_script: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    witness_script: Option<String>,
+}
+
+#[wasm_bindgen]
+impl LockingOutput {
+    #[wasm_bindgen(getter)]
+    pub fn network(&self) -> String {
+        self.network.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn script_pubkey(&self) -> String {
+        self.script_pubkey.clone()
+    }
+    // P2SH redeem script, pushed by scriptSig.
+    #[wasm_bindgen(getter)]
+    pub fn redeem_script(&self) -> Option<String> {
+        self.redeem_script.clone()
+    }
+    // P2WSH witness script, the last witness item.
+    #[wasm_bindgen(getter)]
+    pub fn witness_script(&self) -> Option<String> {
+        self.witness_script.clone()
+    }
+}
+
+// "mainnet" is the alias of "bitcoin".
+pub fn parse_network(network: &str) -> Result<Network, CompileError> {
+    let name = match network {
+        "mainnet" => "bitcoin",
+        _ => network,
+    };
+    Network::from_str(name).map_err(|_| CompileError {
+        loc: Location {
+            start: 0,
+            end: 0,
+            line: 1,
+            column: 1,
+        },
+        kind: ErrorKind::InvalidNetwork(format!(
+            "Network must be one of {:?} but: {:?}.",
+            NETWORKS, network
+        )),
+    })
+}
+
+fn network_name(network: Network) -> String {
+    match network {
+        Network::Bitcoin => "mainnet".to_string(),
+        _ => network.to_string(),
+    }
+}
+
+/*
+    Wrap the compiled script for the target.
+    - Legacy: P2SH, script is the redeem script.
+    - Segwit: P2WSH, script is the witness script.
+*/
+pub fn build_locking(
+    script: &[u8],
+    target: &Target,
+    network: Network,
+    loc: &Location,
+) -> Result<LockingOutput, CompileError> {
+    let script = Script::from_bytes(script);
+    match target {
+        Target::Legacy => {
+            let address = Address::p2sh(script, network).map_err(|e| CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::ScriptSizeExceeded(format!("Failed to build P2SH: {}.", e)),
+            })?;
+            Ok(LockingOutput {
+                network: network_name(network),
+                address: address.to_string(),
+                script_pubkey: address.script_pubkey().to_hex_string(),
+                redeem_script: Some(script.to_hex_string()),
+                witness_script: None,
+            })
+        }
+        _ => {
+            let address = Address::p2wsh(script, network);
+            Ok(LockingOutput {
+                network: network_name(network),
+                address: address.to_string(),
+                script_pubkey: address.script_pubkey().to_hex_string(),
+                redeem_script: None,
+                witness_script: Some(script.to_hex_string()),
+            })
+        }
+    }
+}
+
+// P2SH-P2WSH, for wallets which cannot send to native segwit.
+pub fn build_nested_locking(script: &[u8], network: Network) -> LockingOutput {
+    let script = Script::from_bytes(script);
+    let address = Address::p2shwsh(script, network);
+    LockingOutput {
+        network: network_name(network),
+        address: address.to_string(),
+        script_pubkey: address.script_pubkey().to_hex_string(),
+        redeem_script: Some(script.to_p2wsh().to_hex_string()),
+        witness_script: Some(script.to_hex_string()),
+    }
+}
+
+// P2TR, committing to taproot output key.
+pub fn build_taproot_locking(output_key: TweakedPublicKey, network: Network) -> LockingOutput {
+    let address = Address::p2tr_tweaked(output_key, network);
+    LockingOutput {
+        network: network_name(network),
+        address: address.to_string(),
+        script_pubkey: address.script_pubkey().to_hex_string(),
+        redeem_script: None,
+        witness_script: None,
+    }
+}
@@ -0,0 +1,108 @@
+#[cfg(test)]
+mod tests {
+    use crate::address::parse_network;
+    use crate::ast::*;
+    use crate::{compile_program, compile_program_for_network};
+
+    use bitcoin::{Address, Network, ScriptBuf};
+    use std::fs;
+    use std::str::FromStr;
+
+    fn singlesig(target: &str) -> String {
+        fs::read_to_string("example/singlesig.bithoven")
+            .unwrap()
+            .replace("target segwit", &format!("target {}", target))
+    }
+
+    // Address must decode back to the scriptPubKey for the network.
+    fn assert_address(address: &str, script_pubkey: &str, network: Network) {
+        let address = Address::from_str(address)
+            .unwrap()
+            .require_network(network)
+            .unwrap();
+        assert_eq!(address.script_pubkey().to_hex_string(), script_pubkey);
+    }
+
+    #[test]
+    fn test_legacy_p2sh() {
+        let output = compile_program(singlesig("legacy")).unwrap();
+        let locking = output.locking().unwrap();
+        let script = ScriptBuf::from_hex(&output.hex()).unwrap();
+
+        assert_eq!(locking.network(), "mainnet");
+        assert!(locking.address().starts_with('3'));
+        assert_eq!(locking.script_pubkey(), script.to_p2sh().to_hex_string());
+        assert_eq!(locking.redeem_script(), Some(output.hex()));
+        assert_eq!(locking.witness_script(), None);
+        assert_address(
+            &locking.address(),
+            &locking.script_pubkey(),
+            Network::Bitcoin,
+        );
+        assert!(output.nested_locking().is_none());
+    }
+
+    #[test]
+    fn test_segwit_p2wsh_and_nested() {
+        let output =
+            compile_program_for_network(singlesig("segwit"), "testnet".to_string()).unwrap();
+        let script = ScriptBuf::from_hex(&output.hex()).unwrap();
+
+        let locking = output.locking().unwrap();
+        assert!(locking.address().starts_with("tb1q"));
+        assert_eq!(locking.script_pubkey(), script.to_p2wsh().to_hex_string());
+        assert_eq!(locking.witness_script(), Some(output.hex()));
+        assert_address(
+            &locking.address(),
+            &locking.script_pubkey(),
+            Network::Testnet,
+        );
+
+        // P2SH-P2WSH redeems the P2WSH scriptPubKey.
+        let nested = output.nested_locking().unwrap();
+        assert!(nested.address().starts_with('2'));
+        assert_eq!(
+            nested.redeem_script(),
+            Some(script.to_p2wsh().to_hex_string())
+        );
+        assert_eq!(nested.witness_script(), Some(output.hex()));
+        assert_address(&nested.address(), &nested.script_pubkey(), Network::Testnet);
+    }
+
+    #[test]
+    fn test_taproot_p2tr() {
+        let source = singlesig("taproot").replace(
+            "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212",
+            "45a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212",
+        );
+        let output = compile_program_for_network(source, "regtest".to_string()).unwrap();
+        let locking = output.locking().unwrap();
+        let taproot = output.taproot().unwrap();
+
+        assert!(locking.address().starts_with("bcrt1p"));
+        assert_eq!(locking.address(), taproot.address());
+        assert_eq!(
+            locking.script_pubkey(),
+            format!("5120{}", taproot.output_key())
+        );
+        assert_eq!(locking.redeem_script(), None);
+        assert_eq!(locking.witness_script(), None);
+        assert_address(
+            &locking.address(),
+            &locking.script_pubkey(),
+            Network::Regtest,
+        );
+    }
+
+    #[test]
+    fn test_network_names() {
+        assert_eq!(parse_network("mainnet").unwrap(), Network::Bitcoin);
+        assert_eq!(parse_network("bitcoin").unwrap(), Network::Bitcoin);
+        assert_eq!(parse_network("signet").unwrap(), Network::Signet);
+        assert_eq!(parse_network("testnet4").unwrap(), Network::Testnet4);
+        assert!(matches!(
+            parse_network("litecoin").unwrap_err().kind,
+            ErrorKind::InvalidNetwork(_)
+        ));
+    }
+}
@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 
 use crate::ast::*;
+use crate::compile::{collect_variables, count_leaves, string_bytes};
+use crate::diagnostic::*;
+use crate::lint::*;
+use crate::locktime::locktime_value;
 use crate::source::*;
 
 /// A Scope holds all the contextual information for a single block of code.
@@ -26,13 +30,22 @@ pub struct Symbol {
     /// The initial depth on the stack (0 = top).
     /// Used to verify consumption order.
     pub stack_position: usize,
+
+    /// Declaration of "let" binding, or None for input stack item.
+    /// Binding is computed by the script, so it is neither consumed once nor ordered.
+    pub local: Option<Location>,
+
+    /// Public key consumed by its hash commitment, which checksig may still take once, as P2PKH.
+    pub hashed: bool,
 }
 
 // Check the duplication here.
 // Check whether it has signature or not, which is crucial security property.
-pub fn build_symbol_table(
+// Symbol table is built without duplicated item, recording what is wrong with the stack.
+pub fn collect_symbol_table(
     stack_vec: &Vec<StackParam>,
-) -> Result<HashMap<String, Symbol>, CompileError> {
+    diagnostics: &mut Diagnostics,
+) -> HashMap<String, Symbol> {
     // Key is identifier
     let mut symbol_table: HashMap<String, Symbol> = HashMap::new();
     let mut has_sig: bool = false;
@@ -45,13 +58,14 @@ pub fn build_symbol_table(
         }
 
         if symbol_table.get(&stack_item.identifier.0).is_some() {
-            return Err(CompileError {
+            diagnostics.error(CompileError {
                 loc: item.loc,
                 kind: ErrorKind::DuplicateVariable(format!(
                     "The name of argument cannot be duplicate: {:?} already exists.",
                     item.identifier.0,
                 )),
             });
+            continue;
         }
         symbol_table.insert(
             item.to_owned().identifier.0,
@@ -59,89 +73,266 @@ pub fn build_symbol_table(
                 ty: item.ty,
                 consume_count: 0,
                 stack_position: stack_vec.len() - 1 - i, // 0 is the top stack position.
+                local: None,
+                hashed: false,
             },
         );
     }
-    if has_sig {
-        Ok(symbol_table)
-    } else {
-        Err(CompileError {
-            loc: stack_vec[0].clone().loc,
+    if !has_sig {
+        diagnostics.error(CompileError {
+            loc: stack_vec
+                .first()
+                .map_or_else(nowhere, |item| item.loc.to_owned()),
             kind: ErrorKind::NoSigRequired(format!(
                 "At least one signature required for stack but: {:?}.",
                 stack_vec,
             )),
-        })
+        });
+    }
+
+    symbol_table
+}
+
+// Location of what is missing from source, e.g. an empty input stack.
+pub(crate) fn nowhere() -> Location {
+    Location {
+        start: 0,
+        end: 0,
+        line: 0,
+        column: 0,
     }
 }
 
+// Check optional pragmas are known and have valid value.
+pub fn check_pragma_option(pragma: &Pragma, option: &PragmaOption) -> Result<(), CompileError> {
+    let invalid_pragma = |msg: String| CompileError {
+        loc: option.loc.to_owned(),
+        kind: ErrorKind::InvalidPragma(msg),
+    };
+    let valid_values: &[&str] = match option.key.0.as_str() {
+        "stack" => &["strict", "relaxed"],
+        "tapleaf" => &["single", "path"],
+        // Free form value, checked below.
+        "internal_key" => &[],
+        // Lint level, e.g. "pragma bithoven allow unused_variable;".
+        "allow" | "warn" | "deny" => {
+            if find_lint(&option.value).is_none() {
+                return Err(CompileError {
+                    loc: option.loc.to_owned(),
+                    kind: ErrorKind::UnknownLint(format!("Unknown lint: {:?}.", option.value)),
+                });
+            }
+            &[]
+        }
+        _ => {
+            return Err(invalid_pragma(format!(
+                "Unknown pragma: {:?}.",
+                option.key.0
+            )));
+        }
+    };
+    if !valid_values.is_empty() && !valid_values.contains(&option.value.as_str()) {
+        return Err(invalid_pragma(format!(
+            "Pragma {:?} must be one of {:?} but: {:?}.",
+            option.key.0, valid_values, option.value
+        )));
+    }
+    // Taproot output pragmas
+    if matches!(option.key.0.as_str(), "tapleaf" | "internal_key")
+        && pragma.target != Target::Taproot
+    {
+        return Err(invalid_pragma(format!(
+            "Pragma {:?} requires taproot target but: {:?}.",
+            option.key.0, pragma.target
+        )));
+    }
+    if option.key.0 == "internal_key"
+        && option
+            .value
+            .parse::<bitcoin::secp256k1::XOnlyPublicKey>()
+            .is_err()
+    {
+        return Err(invalid_pragma(format!(
+            "Internal key must be a 32 bytes x-only public key but: {:?}.",
+            option.value
+        )));
+    }
+
+    Ok(())
+}
+
+// Last given value of optional pragma.
+fn pragma_value<'a>(pragma: &'a Pragma, key: &str) -> Option<&'a str> {
+    pragma
+        .options
+        .iter()
+        .rev()
+        .find(|option| option.key.0 == key)
+        .map(|option| option.value.as_str())
+}
+
+// "pragma bithoven stack relaxed;" lifts consume-once and stack order rules.
+pub fn stack_mode(pragma: &Pragma) -> StackMode {
+    match pragma_value(pragma, "stack") {
+        Some("relaxed") => StackMode::Relaxed,
+        _ => StackMode::Strict,
+    }
+}
+
+// "pragma bithoven tapleaf path;" puts each spending path in its own tapleaf.
+pub fn tapleaf_mode(pragma: &Pragma) -> TapleafMode {
+    match pragma_value(pragma, "tapleaf") {
+        Some("path") => TapleafMode::Path,
+        _ => TapleafMode::Single,
+    }
+}
+
+// "pragma bithoven internal_key "<x-only pubkey>";" sets the key path of taproot output.
+pub fn internal_key(pragma: &Pragma) -> Option<String> {
+    pragma_value(pragma, "internal_key").map(str::to_string)
+}
+
 pub fn analyze(
     ast: &Vec<Statement>,
     input: Vec<Vec<StackParam>>,
-    target: &Target,
+    pragma: &Pragma,
 ) -> Result<(), CompileError> {
+    let mut diagnostics = Diagnostics::default();
+    analyze_diagnostics(ast, input, pragma, &mut diagnostics);
+    diagnostics.into_result()
+}
+
+// Analyze all, recording every finding instead of stopping at the first one.
+pub fn analyze_diagnostics(
+    ast: &Vec<Statement>,
+    input: Vec<Vec<StackParam>>,
+    pragma: &Pragma,
+    diagnostics: &mut Diagnostics,
+) {
+    for option in &pragma.options {
+        diagnostics.check(check_pragma_option(pragma, option));
+    }
+    check_attributes(pragma, diagnostics);
+    let mode = stack_mode(pragma);
+
+    // Each spending path takes its own input stack.
+    let paths = count_leaves(ast);
+    if input.len() != paths {
+        let loc = match input
+            .get(paths)
+            .or(input.last())
+            .and_then(|stack| stack.first())
+        {
+            Some(item) => item.loc.to_owned(),
+            None => ast
+                .first()
+                .map_or_else(nowhere, |stmt| stmt.to_owned().loc()),
+        };
+        diagnostics.error(CompileError {
+            loc,
+            kind: ErrorKind::StackMismatch(format!(
+                "Each spending path needs its own input stack: {} stacks for {} paths.",
+                input.len(),
+                paths
+            )),
+        });
+        return;
+    }
+    check_stack_keys(&input, &pragma.target, diagnostics);
+
     let mut scope_vec: Vec<Scope> = vec![];
     for (branch, stack) in input.iter().enumerate() {
         scope_vec.push(Scope {
-            symbol_table: build_symbol_table(&stack)?,
+            symbol_table: collect_symbol_table(&stack, diagnostics),
             branch: branch,
         });
     }
 
-    analyze_statement(ast, &mut scope_vec, target, 0)?;
-    check_flow(ast)?;
+    analyze_statement(ast, &mut scope_vec, pragma, &mode, 0, diagnostics);
+    collect_flow(ast, diagnostics);
+    check_dead_path(ast, &mut vec![], diagnostics);
 
     // Check unused variable at last.
     for (i, stack) in input.iter().enumerate() {
-        check_unused_variable(&stack, &scope_vec[i].symbol_table)?;
+        collect_unused_variable(&stack, &scope_vec[i].symbol_table, pragma, diagnostics);
     }
 
-    Ok(())
+    check_pubkey_reuse(ast, pragma, diagnostics);
+    check_timelock_mixing(ast, pragma, diagnostics);
+    check_uncommitted_pubkey(ast, pragma, diagnostics);
 }
 
 pub fn analyze_statement(
     ast: &Vec<Statement>,
     scope_vec: &mut Vec<Scope>,
-    target: &Target,
+    pragma: &Pragma,
+    mode: &StackMode,
     mut branch: usize,
-) -> Result<usize, CompileError> {
+    diagnostics: &mut Diagnostics,
+) -> usize {
+    // Bindings declared in this block, which go out of scope at its end.
+    let mut locals: Vec<String> = vec![];
     // Check statements in global scope of current branch.
     for stmt in ast {
         match stmt {
-            Statement::LocktimeStatement { loc, operand, op } => {
-                // BIP 68: Relative locktime (CSV/older) is physically limited to 16 bits
-                // because it relies on the nSequence field's low 16 bits.
-                if matches!(op, LocktimeOp::Csv) {
-                    if *operand < 0 || *operand > u16::MAX as i64 {
-                        return Err(CompileError {
-                            loc: loc.to_owned(),
-                            kind: ErrorKind::IntegerOverflow(format!(
-                                "Relative locktime (older) cannot exceed 65,535 blocks due to BIP 68 limits but got: {}.",
-                            operand
-                            )),
-                        });
-                    }
-                }
-                // BIP112: Absoulte locktim is limited to u32::MAX
-                if *operand < 0 || *operand > u32::MAX as i64 {
-                    return Err(CompileError {
+            Statement::LetStatement {
+                loc,
+                identifier,
+                expr,
+            } => {
+                analyze_expression(expr, &mut scope_vec[branch], pragma, mode, diagnostics);
+                let symbol_table = &mut scope_vec[branch].symbol_table;
+                if symbol_table.contains_key(&identifier.0) {
+                    diagnostics.error(CompileError {
                         loc: loc.to_owned(),
-                        kind: ErrorKind::IntegerOverflow(format!(
-                            "Locktime must be a 32-bit unsigned integer (0-4294967295), but got: {}.",
-                        operand
+                        kind: ErrorKind::DuplicateVariable(format!(
+                            "The name of binding cannot be duplicate: {:?} already exists.",
+                            identifier.0
                         )),
                     });
+                    continue;
                 }
+                let ty = expression_type(expr, symbol_table);
+                symbol_table.insert(
+                    identifier.0.to_owned(),
+                    Symbol {
+                        ty,
+                        consume_count: 0,
+                        stack_position: 0,
+                        local: Some(loc.to_owned()),
+                        hashed: false,
+                    },
+                );
+                locals.push(identifier.0.to_owned());
             }
-            Statement::VerifyStatement(loc, expr) => {
-                check_variable(expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(expr)?
+            Statement::LocktimeStatement {
+                loc,
+                operand,
+                op,
+                unit,
+            } => {
+                // Parameters and constants are already bound and folded,
+                // so anything else than a number is not one.
+                let Expression::NumberLiteral(_, operand) = operand else {
+                    diagnostics.error(CompileError {
+                        loc: operand.to_owned().loc(),
+                        kind: ErrorKind::InvalidOperation(format!(
+                            "Locktime must be a number, or number parameter or constant but: {:?}.",
+                            operand
+                        )),
+                    });
+                    continue;
+                };
+                // Range of nSequence(BIP68) and nLockTime(BIP65) up to unit.
+                if let Err(msg) = locktime_value(op, unit, *operand) {
+                    diagnostics.error(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::IntegerOverflow(msg),
+                    });
+                }
             }
-            Statement::ExpressionStatement(loc, expr) => {
-                check_variable(expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(expr)?
+            Statement::VerifyStatement(_, expr) | Statement::ExpressionStatement(_, _, expr) => {
+                analyze_expression(expr, &mut scope_vec[branch], pragma, mode, diagnostics);
             }
             Statement::IfStatement {
                 loc,
@@ -149,11 +340,15 @@ pub fn analyze_statement(
                 if_block,
                 else_block,
             } => {
-                check_variable(condition_expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(condition_expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(condition_expr)?;
+                analyze_expression(
+                    condition_expr,
+                    &mut scope_vec[branch],
+                    pragma,
+                    mode,
+                    diagnostics,
+                );
 
-                branch = analyze_statement(&if_block, scope_vec, target, branch)?;
+                branch = analyze_statement(&if_block, scope_vec, pragma, mode, branch, diagnostics);
                 if else_block.is_some() {
                     branch += 1;
                     // Mark consumed variable before checkout to new stack branch.
@@ -162,39 +357,170 @@ pub fn analyze_statement(
                         .expect("Non existing stack look up.");
                     let before_stack = &mut before.symbol_table;
                     let current_stack = &mut current.symbol_table;
-                    mark_consumed_stack(before_stack, current_stack)?;
+                    diagnostics.check(mark_consumed_stack(before_stack, current_stack));
 
                     branch = analyze_statement(
                         else_block.to_owned().unwrap().as_ref(),
                         scope_vec,
-                        target,
+                        pragma,
+                        mode,
                         branch,
-                    )?;
+                        diagnostics,
+                    );
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                analyze_expression(selector, &mut scope_vec[branch], pragma, mode, diagnostics);
+                let ty = expression_type(selector, &scope_vec[branch].symbol_table);
+                if !matches!(ty, Type::Number | Type::Boolean) {
+                    diagnostics.error(CompileError {
+                        loc: selector.to_owned().loc(),
+                        kind: ErrorKind::TypeMismatch(format!(
+                            "Selector of match must be a number but: {:?} of {}.",
+                            selector, ty
+                        )),
+                    });
+                }
+
+                let mut values: Vec<i64> = vec![];
+                for (i, arm) in arms.iter().enumerate() {
+                    diagnostics.check(check_overflow(&Expression::NumberLiteral(
+                        arm.loc.to_owned(),
+                        arm.value,
+                    )));
+                    if values.contains(&arm.value) {
+                        diagnostics.error(CompileError {
+                            loc: arm.loc.to_owned(),
+                            kind: ErrorKind::UnreachableCode(format!(
+                                "Arm of match is unreachable, as an earlier arm has the same value: {}.",
+                                arm.value
+                            )),
+                        });
+                    }
+                    values.push(arm.value);
+
+                    // Every arm but the first checks out a new input stack, like else block.
+                    if i > 0 {
+                        branch += 1;
+                        let [before, current]: &mut [Scope; 2] = (&mut scope_vec
+                            [branch - 1..=branch])
+                            .try_into()
+                            .expect("Non existing stack look up.");
+                        diagnostics.check(mark_consumed_stack(
+                            &mut before.symbol_table,
+                            &mut current.symbol_table,
+                        ));
+                    }
+                    branch =
+                        analyze_statement(&arm.block, scope_vec, pragma, mode, branch, diagnostics);
                 }
             }
         }
     }
-    Ok(branch)
+
+    // Binding is checked for use where it goes out of scope, as input stack item is at last.
+    for id in locals {
+        let Some(symbol) = scope_vec[branch].symbol_table.remove(&id) else {
+            continue;
+        };
+        if let (0, Some(loc)) = (symbol.consume_count, symbol.local) {
+            report(
+                diagnostics,
+                pragma,
+                UNUSED_VARIABLE,
+                CompileError {
+                    loc,
+                    kind: ErrorKind::UnusedVariable(format!("Binding unused: {:?}.", id)),
+                },
+            );
+        }
+    }
+    branch
+}
+
+// Type depends on variables, so it is checked only if variables are valid.
+fn analyze_expression(
+    expr: &Expression,
+    scope: &mut Scope,
+    pragma: &Pragma,
+    mode: &StackMode,
+    diagnostics: &mut Diagnostics,
+) {
+    diagnostics.check(check_literal(expr, &pragma.target));
+    if diagnostics.check(check_variable(expr, &mut scope.symbol_table, mode)) {
+        diagnostics.check(check_type(expr, &mut scope.symbol_table));
+    } else {
+        // Referenced variables are not reported as unused again.
+        let mut ids: Vec<Identifier> = vec![];
+        collect_variables(expr, &mut ids);
+        for id in ids {
+            if let Some(symbol) = scope.symbol_table.get_mut(&id.0) {
+                symbol.consume_count = 1;
+            }
+        }
+    }
+    match check_security(expr) {
+        Err(e) if matches!(e.kind, ErrorKind::UselessSig(_)) => {
+            report(diagnostics, pragma, USELESS_SIG, e)
+        }
+        result => {
+            diagnostics.check(result);
+        }
+    }
 }
 
 // Check the existence of unused variable after analysis.
-pub fn check_unused_variable(
+pub fn collect_unused_variable(
     stack_vec: &Vec<StackParam>,
     stack_table: &HashMap<String, Symbol>,
-) -> Result<(), CompileError> {
+    pragma: &Pragma,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut reported: Vec<&String> = vec![];
     for e in stack_vec {
         // If consume count is 0, it's unconsumed.
+        // Duplicated item has a single symbol, so it is reported once.
         if stack_table
             .get(&e.identifier.0)
             .is_some_and(|v| v.consume_count == 0)
+            && !reported.contains(&&e.identifier.0)
         {
-            return Err(CompileError {
-                loc: e.to_owned().loc(),
-                kind: ErrorKind::UnusedVariable(format!("Variable unused: {:?}.", e.identifier)),
-            });
+            reported.push(&e.identifier.0);
+            report(
+                diagnostics,
+                pragma,
+                UNUSED_VARIABLE,
+                CompileError {
+                    loc: e.to_owned().loc(),
+                    kind: ErrorKind::UnusedVariable(format!(
+                        "Variable unused: {:?}.",
+                        e.identifier
+                    )),
+                },
+            );
         }
     }
-    return Ok(());
+}
+
+// Key from witness must be of the kind the target checks signature with.
+// e.g. taproot takes any 33-byte key as unknown key type, which any signature satisfies.
+pub fn check_stack_keys(input: &[Vec<StackParam>], target: &Target, diagnostics: &mut Diagnostics) {
+    for item in input.iter().flatten() {
+        let msg = match (&item.ty, target) {
+            (Type::PubKey, Target::Taproot) => "Taproot takes x-only public key",
+            (Type::XOnly, Target::Legacy | Target::Segwit) => {
+                "X-only public key is only for taproot"
+            }
+            _ => continue,
+        };
+        diagnostics.error(CompileError {
+            loc: item.loc.to_owned(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "{} but: {:?} is {}.",
+                msg, item.identifier.0, item.ty
+            )),
+        });
+    }
 }
 
 // When checkout to next branch(stack), mark shared variables consumed.
@@ -203,6 +529,11 @@ pub fn mark_consumed_stack(
     current_stack: &mut HashMap<String, Symbol>,
 ) -> Result<(), CompileError> {
     for (k, v) in before_stack {
+        // Binding declared before the if/else is in scope of both branches.
+        if v.local.is_some() {
+            current_stack.insert(k.to_string(), v.clone());
+            continue;
+        }
         if current_stack.get(k).is_some() {
             let consumed_item = current_stack.get(k).unwrap().clone();
 
@@ -213,6 +544,8 @@ pub fn mark_consumed_stack(
                     ty: consumed_item.ty,
                     consume_count: 1,
                     stack_position: consumed_item.stack_position,
+                    local: None,
+                    hashed: v.hashed,
                 },
             );
         }
@@ -224,42 +557,54 @@ pub fn mark_consumed_stack(
 // No sequential if/else block && No statement after if/else block.
 // Unreachable Code Detection(No statement after return statement).
 // Final Statement must be expression statement.
-pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
+pub fn collect_flow(ast: &Vec<Statement>, diagnostics: &mut Diagnostics) {
     // No sequential if/else block && No statement after if/else block
     // Check No statement after return statement.
     for (i, statement) in ast.iter().enumerate() {
         match statement {
-            Statement::IfStatement { .. } => {
-                // Check No statement after if/else block,
-                // as it can be placed before if/else block.
+            Statement::IfStatement { .. } | Statement::MatchStatement { .. } => {
+                // Check No statement after if/else or match block,
+                // as it can be placed before the block.
                 if i != ast.len() - 1 {
                     let next = ast[i + 1].to_owned();
-                    return Err(CompileError {
+                    diagnostics.error(CompileError {
                         loc: next.to_owned().loc(),
                         kind: ErrorKind::UnreachableCode(format!(
-                            "No statement after if/else block but: {:?}.",
+                            "No statement after if/else or match block but: {:?}.",
                             next
                         )),
                     });
+                    // Rest of the block is unreachable by the same reason.
+                    break;
                 }
             }
             Statement::ExpressionStatement(..) => {
                 // Check No statement after return statement.
                 if i != ast.len() - 1 {
                     let next = ast[i + 1].to_owned();
-                    return Err(CompileError {
+                    diagnostics.error(CompileError {
                         loc: next.to_owned().loc(),
                         kind: ErrorKind::UnreachableCode(format!(
                             "Unreachable code after return statement: {:?}. Move return statement at the last scope of execution path",
                             next
                         )),
                     });
+                    break;
                 }
             }
             _ => (),
         }
     }
-    let last = ast.last().unwrap().to_owned();
+    let Some(last) = ast.last().cloned() else {
+        diagnostics.error(CompileError {
+            loc: nowhere(),
+            kind: ErrorKind::NoReturn(
+                "Return statement must exist for each possible execution path: empty block."
+                    .to_string(),
+            ),
+        });
+        return;
+    };
     // Final Statement must be expression statement.
     match last {
         Statement::IfStatement {
@@ -267,14 +612,19 @@ pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
             else_block,
             ..
         } => {
-            check_flow(&if_block)?;
+            collect_flow(&if_block, diagnostics);
             if else_block.is_some() {
-                check_flow(&else_block.unwrap())?;
+                collect_flow(&else_block.unwrap(), diagnostics);
+            }
+        }
+        Statement::MatchStatement { arms, .. } => {
+            for arm in arms {
+                collect_flow(&arm.block, diagnostics);
             }
         }
         Statement::ExpressionStatement(..) => (),
         _ => {
-            return Err(CompileError {
+            diagnostics.error(CompileError {
                 loc: last.to_owned().loc(),
                 kind: ErrorKind::NoReturn(format!(
                     "Return statement must exist for each possible execution path: {:?}.",
@@ -283,8 +633,75 @@ pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
             });
         }
     }
+}
 
-    Ok(())
+// Locktimes of a path are satisfied by a single transaction, whose nLockTime is
+// either a height or a time, and so is the nSequence of the input.
+// e.g. "after block 900000;" with "after 2027-01-01T00:00Z;" needs nLockTime below
+// 500,000,000 and above it at once.
+// Locktimes of the same kind are always satisfied together by the largest one, as each only
+// needs the field to be at least its value, e.g. "after block 800000;" is redundant with
+// "after block 900000;", but not dead.
+pub fn check_dead_path<'a>(
+    ast: &'a [Statement],
+    path: &mut Vec<(&'a LocktimeOp, bool, &'a Location)>,
+    diagnostics: &mut Diagnostics,
+) {
+    let depth = path.len();
+    for stmt in ast {
+        match stmt {
+            Statement::LocktimeStatement {
+                loc,
+                op,
+                operand: Expression::NumberLiteral(_, operand),
+                unit,
+            } => {
+                // Out of range is reported by check of the statement.
+                let Ok(value) = locktime_value(op, unit, *operand) else {
+                    continue;
+                };
+                let is_time = is_time_locktime(op, value);
+                if let Some((_, _, other)) = path.iter().find(|(other_op, other_is_time, _)| {
+                    *other_op == op && *other_is_time != is_time
+                }) {
+                    diagnostics.error(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::DeadPath(format!(
+                            "Spending path can never be satisfied: {} locktime is {} based, but the one at line {} on the same path is {} based.",
+                            match op {
+                                LocktimeOp::Cltv => "absolute",
+                                LocktimeOp::Csv => "relative",
+                            },
+                            if is_time { "time" } else { "height" },
+                            other.line,
+                            if is_time { "height" } else { "time" },
+                        )),
+                    });
+                    // Rest of the block is on the same dead path, so it is reported once.
+                    path.truncate(depth);
+                    return;
+                }
+                path.push((op, is_time, loc));
+            }
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                check_dead_path(if_block, path, diagnostics);
+                if let Some(else_block) = else_block {
+                    check_dead_path(else_block, path, diagnostics);
+                }
+            }
+            Statement::MatchStatement { arms, .. } => {
+                for arm in arms {
+                    check_dead_path(&arm.block, path, diagnostics);
+                }
+            }
+            _ => (),
+        }
+    }
+    path.truncate(depth);
 }
 
 // Undefined Variable Check
@@ -292,9 +709,11 @@ pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
 // Scope Enforcement
 // Unconsumed Variable Check
 // Check order of consumption(stack position)
+// In relaxed mode, only existence is checked as code generation moves items by itself.
 pub fn check_variable(
     expression: &Expression,
     symbol_table: &mut HashMap<String, Symbol>,
+    mode: &StackMode,
 ) -> Result<(), CompileError> {
     match expression {
         Expression::Variable(loc, id) => {
@@ -311,7 +730,7 @@ pub fn check_variable(
             }
             let item = symbol_table.get(&id_string).unwrap().to_owned();
             // 2. Check the consumption of variable
-            if item.consume_count != 0 {
+            if *mode == StackMode::Strict && item.local.is_none() && item.consume_count != 0 {
                 return Err(CompileError {
                     loc: expression.to_owned().loc(),
                     kind: ErrorKind::VariableConsumed(format!(
@@ -322,10 +741,13 @@ pub fn check_variable(
             }
 
             // 3. Check whether there is unconsumed variable before this variable.
-            let is_invalid_consumption_order = symbol_table
-                .values()
-                .any(|v| v.stack_position < item.stack_position && v.consume_count == 0);
-            if is_invalid_consumption_order {
+            let is_invalid_consumption_order = item.local.is_none()
+                && symbol_table.values().any(|v| {
+                    v.local.is_none()
+                        && v.stack_position < item.stack_position
+                        && v.consume_count == 0
+                });
+            if *mode == StackMode::Strict && is_invalid_consumption_order {
                 return Err(CompileError {
                     loc: expression.to_owned().loc(),
                     kind: ErrorKind::InvalidConsumptionOrder(format!(
@@ -343,6 +765,8 @@ pub fn check_variable(
                     ty: item.ty,
                     consume_count: 1,
                     stack_position: item.stack_position,
+                    local: item.local,
+                    hashed: item.hashed,
                 },
             );
 
@@ -358,8 +782,8 @@ pub fn check_variable(
                 sig,
                 pubkey,
             } => {
-                check_variable(&sig, symbol_table)?;
-                check_variable(&pubkey, symbol_table)
+                check_variable(&sig, symbol_table, mode)?;
+                check_pubkey_variable(pubkey, symbol_table, mode)
             }
             Factor::MultiSigFactor { loc: _, m: _, n } => {
                 for factor in n {
@@ -369,8 +793,8 @@ pub fn check_variable(
                             sig,
                             pubkey,
                         } => {
-                            check_variable(&sig, symbol_table)?;
-                            check_variable(&pubkey, symbol_table)?
+                            check_variable(&sig, symbol_table, mode)?;
+                            check_pubkey_variable(pubkey, symbol_table, mode)?
                         }
                         _ => continue,
                     }
@@ -383,15 +807,32 @@ pub fn check_variable(
             loc: _,
             operand,
             op,
-        } => check_variable(&operand, symbol_table),
+        } => {
+            // First use of a public key by its hash, e.g. "hash160 pk".
+            let first_hash = match &**operand {
+                Expression::Variable(_, id) => symbol_table
+                    .get(&id.0)
+                    .filter(|item| matches!(item.ty, Type::PubKey | Type::XOnly))
+                    .filter(|item| item.local.is_none())
+                    .is_some_and(|item| item.consume_count == 0),
+                _ => false,
+            };
+            check_variable(&operand, symbol_table, mode)?;
+            if let (true, Expression::Variable(_, id)) = (first_hash, &**operand) {
+                if let Some(item) = symbol_table.get_mut(&id.0) {
+                    item.hashed = true;
+                }
+            }
+            Ok(())
+        }
         Expression::LogicalExpression {
             loc: _,
             lhs,
             op,
             rhs,
         } => {
-            check_variable(&lhs, symbol_table)?;
-            check_variable(&rhs, symbol_table)
+            check_variable(&lhs, symbol_table, mode)?;
+            check_variable(&rhs, symbol_table, mode)
         }
         Expression::CompareExpression {
             loc: _,
@@ -399,32 +840,58 @@ pub fn check_variable(
             op,
             rhs,
         } => {
-            check_variable(&lhs, symbol_table)?;
-            check_variable(&rhs, symbol_table)
+            check_variable(&lhs, symbol_table, mode)?;
+            check_variable(&rhs, symbol_table, mode)
         }
         Expression::UnaryMathExpression {
             loc: _,
             operand,
             op,
-        } => check_variable(&operand, symbol_table),
+        } => check_variable(&operand, symbol_table, mode),
         Expression::BinaryMathExpression {
             loc: _,
             lhs,
             op,
             rhs,
         } => {
-            check_variable(&lhs, symbol_table)?;
-            check_variable(&rhs, symbol_table)
+            check_variable(&lhs, symbol_table, mode)?;
+            check_variable(&rhs, symbol_table, mode)
+        }
+        Expression::TernaryMathExpression {
+            loc: _,
+            operand,
+            lower,
+            upper,
+            op: _,
+        } => {
+            check_variable(operand, symbol_table, mode)?;
+            check_variable(lower, symbol_table, mode)?;
+            check_variable(upper, symbol_table, mode)
         }
         Expression::ByteExpression {
             loc: _,
             operand,
             op: _,
-        } => check_variable(&operand, symbol_table),
+        } => check_variable(&operand, symbol_table, mode),
         _ => Ok(()),
     }
 }
 
+// Public key of checksig, which is taken once more if consumed by its hash commitment before.
+fn check_pubkey_variable(
+    pubkey: &Expression,
+    symbol_table: &mut HashMap<String, Symbol>,
+    mode: &StackMode,
+) -> Result<(), CompileError> {
+    if let Expression::Variable(_, id) = pubkey {
+        if let Some(item) = symbol_table.get_mut(&id.0).filter(|item| item.hashed) {
+            item.hashed = false;
+            return Ok(());
+        }
+    }
+    check_variable(pubkey, symbol_table, mode)
+}
+
 // Check type(e.g. operand of expression).
 // Every arithmetic op takes 32bit integer including boolean.
 // Every bytes op can takes string or signature.
@@ -444,7 +911,7 @@ pub fn check_type(
                 sig,
                 pubkey,
             } => check_type_sig_pubkey(sig, pubkey, symbol_table),
-            Factor::MultiSigFactor { loc: _, m: _, n } => {
+            Factor::MultiSigFactor { loc, m, n } => {
                 for factor in n {
                     match factor {
                         Factor::SingleSigFactor {
@@ -455,6 +922,17 @@ pub fn check_type(
                         _ => continue,
                     }
                 }
+                // 0 of n needs no signature, and more than n can never be satisfied.
+                if *m < 1 || *m as usize > n.len() {
+                    return Err(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::InvalidOperation(format!(
+                            "Required signatures must be from 1 to {} but: {}.",
+                            n.len(),
+                            m
+                        )),
+                    });
+                }
 
                 return Ok(());
             }
@@ -482,7 +960,7 @@ pub fn check_type(
                         )),
                     });
                 }
-                return Ok(());
+                return check_type_bytes_compare(loc, lhs, rhs, symbol_table);
             }
             // compare boolean/number to boolean/number
             if check_type_numeric(&lhs, symbol_table).is_ok()
@@ -504,15 +982,59 @@ pub fn check_type(
             operand,
             op,
         } => check_type_numeric(&operand, symbol_table),
-        Expression::BinaryMathExpression {
-            loc: _,
-            lhs,
-            op,
-            rhs,
-        } => {
+        Expression::BinaryMathExpression { loc, lhs, op, rhs } => {
             check_type_numeric(&lhs, symbol_table)?;
-            check_type_numeric(&rhs, symbol_table)
+            check_type_numeric(&rhs, symbol_table)?;
+            // Constant operands are already folded.
+            if *op == BinaryMathOp::Mul {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::InvalidOperation(format!(
+                        "Multiplication is only for constants, as OP_MUL is disabled: {:?} * {:?}.",
+                        lhs, rhs
+                    )),
+                });
+            }
+            Ok(())
         }
+        Expression::TernaryMathExpression {
+            loc,
+            operand,
+            lower,
+            upper,
+            op: _,
+        } => {
+            check_type_numeric(operand, symbol_table)?;
+            check_type_numeric(lower, symbol_table)?;
+            check_type_numeric(upper, symbol_table)?;
+            // Lower bound is inclusive and upper bound is exclusive.
+            if let (Expression::NumberLiteral(_, lower), Expression::NumberLiteral(_, upper)) =
+                (&**lower, &**upper)
+            {
+                if lower >= upper {
+                    return Err(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::InvalidOperation(format!(
+                            "Range of within must not be empty but: [{:?}, {:?}).",
+                            lower, upper
+                        )),
+                    });
+                }
+            }
+            Ok(())
+        }
+        // Literal operand is already folded.
+        Expression::UnaryCryptoExpression {
+            loc,
+            operand,
+            op: UnaryCryptoOp::TaggedHash(tag),
+        } => Err(CompileError {
+            loc: loc.to_owned(),
+            kind: ErrorKind::InvalidOperation(format!(
+                "Tagged hash is only for constants, as OP_CAT is disabled: {:?} of {:?}.",
+                tag, operand
+            )),
+        }),
         // Allow only ascii encoded string.
         // UTF-8 string's char has various byte size, which makes use of OP_SIZE hard.
         Expression::ByteExpression {
@@ -557,7 +1079,9 @@ pub fn check_type_numeric(
                 )),
             });
         }
-        Expression::StringLiteral(loc, ..) => {
+        Expression::StringLiteral(loc, ..)
+        | Expression::BytesLiteral(loc, ..)
+        | Expression::PubKeyLiteral(loc, ..) => {
             return Err(CompileError {
                 loc: loc,
                 kind: ErrorKind::InvalidOperation(format!(
@@ -570,7 +1094,7 @@ pub fn check_type_numeric(
         Expression::Variable(loc, id) => {
             let id_string = id.0.to_owned();
             let var_type = symbol_table.get(&id_string).unwrap().ty.to_owned();
-            if var_type == Type::Signature || var_type == Type::String {
+            if var_type != Type::Boolean && var_type != Type::Number {
                 return Err(CompileError {
                     loc: loc,
                     kind: ErrorKind::InvalidOperation(format!(
@@ -605,6 +1129,8 @@ pub fn check_type_numeric(
         Expression::UnaryMathExpression { loc, operand, op } => {
             check_type_numeric(&operand, symbol_table)
         }
+        // Within is evaluated to boolean, but its operands need to be checked.
+        Expression::TernaryMathExpression { .. } => check_type(expression, symbol_table),
         _ => Ok(()),
     }
 }
@@ -616,9 +1142,10 @@ pub fn check_type_string(
     match expression.to_owned() {
         // StringLiteral is just string.
         Expression::StringLiteral(loc, val) => Ok(()),
+        Expression::BytesLiteral(..) | Expression::PubKeyLiteral(..) => Ok(()),
         // UnaryCryptoExpression ouputs ascii string.
-        // UnaryCryptoExpression doesn't need to check operand.
-        Expression::UnaryCryptoExpression { .. } => Ok(()),
+        // UnaryCryptoExpression doesn't need to check operand, but tagged hash must be folded.
+        Expression::UnaryCryptoExpression { .. } => check_type(expression, symbol_table),
         // For variable, look up symbol table.
         Expression::Variable(loc, id) => {
             let id_string = id.0.to_owned();
@@ -678,9 +1205,20 @@ pub fn check_type_sig_pubkey(
             });
         }
     };
-    // Only string literal can be pubkey.
+    // Only string or pubkey literal, or key typed item can be pubkey.
     // Public key safety check on ECC.
     match pubkey {
+        // Checked by check_literal() up to target.
+        Expression::PubKeyLiteral(..) => Ok(()),
+        // Checked by check_stack_keys() up to target, and valid key is up to the witness.
+        Expression::Variable(_, id)
+            if matches!(
+                symbol_table.get(&id.0).map(|symbol| &symbol.ty),
+                Some(Type::PubKey | Type::XOnly)
+            ) =>
+        {
+            Ok(())
+        }
         Expression::StringLiteral(loc, data) => {
             // Try decoding hex.
             let hex_pubkey = hex::decode(&data);
@@ -711,7 +1249,7 @@ pub fn check_type_sig_pubkey(
             return Err(CompileError {
                 loc: pubkey.to_owned().loc(),
                 kind: ErrorKind::TypeMismatch(format!(
-                    "Public Key must be from string literal but: {:?}.",
+                    "Public Key must be from string or pubkey literal, or item of pubkey or xonly but: {:?}.",
                     pubkey
                 )),
             });
@@ -719,6 +1257,219 @@ pub fn check_type_sig_pubkey(
     }
 }
 
+// Type of evaluated expression, which a binding takes.
+// Undefined variable is already reported, so it is taken as bytes.
+pub fn expression_type(expression: &Expression, symbol_table: &HashMap<String, Symbol>) -> Type {
+    match expression {
+        Expression::Variable(_, id) => symbol_table
+            .get(&id.0)
+            .map_or(Type::Bytes, |symbol| symbol.ty.to_owned()),
+        Expression::NumberLiteral(..) => Type::Number,
+        Expression::BooleanLiteral(..) => Type::Boolean,
+        Expression::StringLiteral(..) => Type::String,
+        Expression::BytesLiteral(..) => Type::Bytes,
+        Expression::PubKeyLiteral(..) | Expression::UnaryCryptoExpression { .. } => {
+            bytes_kind(expression, symbol_table)
+                .0
+                .unwrap_or(Type::Bytes)
+        }
+        Expression::CheckSigExpression { .. }
+        | Expression::LogicalExpression { .. }
+        | Expression::CompareExpression { .. }
+        | Expression::TernaryMathExpression { .. }
+        | Expression::UnaryMathExpression {
+            op: UnaryMathOp::Not,
+            ..
+        } => Type::Boolean,
+        Expression::UnaryMathExpression { .. }
+        | Expression::BinaryMathExpression { .. }
+        | Expression::ByteExpression { .. } => Type::Number,
+    }
+}
+
+// Kind and length of bytes evaluated expression, if known.
+// String can be either hex or ASCII, and bytes can be anything, so they have no kind.
+fn bytes_kind(
+    expression: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> (Option<Type>, Option<usize>) {
+    let ty = match expression {
+        Expression::Variable(_, id) => match symbol_table.get(&id.0) {
+            Some(symbol) => symbol.ty.to_owned(),
+            None => return (None, None),
+        },
+        Expression::BytesLiteral(_, data) => return (None, Some(data.len() / 2)),
+        Expression::StringLiteral(_, data) => return (None, Some(string_bytes(data).len())),
+        Expression::PubKeyLiteral(_, data) if data.len() == 64 => Type::XOnly,
+        Expression::PubKeyLiteral(..) => Type::PubKey,
+        Expression::UnaryCryptoExpression { operand, op, .. } => {
+            let inner = match &**operand {
+                Expression::UnaryCryptoExpression { op, .. } => Some(op),
+                _ => None,
+            };
+            match (op, inner) {
+                (UnaryCryptoOp::Sha256, Some(UnaryCryptoOp::Sha256)) => Type::Hash256,
+                (UnaryCryptoOp::Sha256, _) => Type::Sha256,
+                (UnaryCryptoOp::Ripemd160, Some(UnaryCryptoOp::Sha256)) => Type::Hash160,
+                (UnaryCryptoOp::Ripemd160, _) => return (None, Some(20)),
+                (UnaryCryptoOp::Hash160, _) => Type::Hash160,
+                (UnaryCryptoOp::Hash256, _) => Type::Hash256,
+                (UnaryCryptoOp::Sha1, _) => return (None, Some(20)),
+                (UnaryCryptoOp::TaggedHash(_), _) => Type::Sha256,
+            }
+        }
+        _ => return (None, None),
+    };
+    let len = match ty {
+        Type::PubKey => 33,
+        Type::XOnly | Type::Sha256 | Type::Hash256 => 32,
+        Type::Hash160 => 20,
+        _ => return (None, None),
+    };
+    (Some(ty), Some(len))
+}
+
+// Equality of bytes never holds for different kinds, e.g. sha256 to hash160, or different lengths.
+pub fn check_type_bytes_compare(
+    loc: &Location,
+    lhs: &Expression,
+    rhs: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    let mismatch = |msg: String| {
+        Err(CompileError {
+            loc: loc.to_owned(),
+            kind: ErrorKind::TypeMismatch(msg),
+        })
+    };
+    match (bytes_kind(lhs, symbol_table), bytes_kind(rhs, symbol_table)) {
+        ((Some(lhs), _), (Some(rhs), _)) if lhs != rhs => mismatch(format!(
+            "Compare type must be same but: {} to {}.",
+            lhs, rhs
+        )),
+        ((_, Some(lhs)), (_, Some(rhs))) if lhs != rhs => mismatch(format!(
+            "Compare length must be same but: {} bytes to {} bytes.",
+            lhs, rhs
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Check typed literal, and public key up to target.
+// Tapscript takes only x-only key, and treats 33-byte key as unknown type which any signature satisfies.
+pub fn check_literal(expression: &Expression, target: &Target) -> Result<(), CompileError> {
+    match expression {
+        Expression::BytesLiteral(loc, data) => {
+            if hex::decode(data).is_err() {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::InvalidLiteral(format!(
+                        "Bytes literal must be hex of whole bytes but: 0x{}.",
+                        data
+                    )),
+                });
+            }
+            Ok(())
+        }
+        Expression::PubKeyLiteral(loc, data) => {
+            let bytes = hex::decode(data).unwrap_or_default();
+            let (ty, valid) = match bytes.len() {
+                33 => (
+                    Type::PubKey,
+                    bitcoin::secp256k1::PublicKey::from_slice(&bytes).is_ok(),
+                ),
+                32 => (
+                    Type::XOnly,
+                    bitcoin::XOnlyPublicKey::from_slice(&bytes).is_ok(),
+                ),
+                _ => (Type::PubKey, false),
+            };
+            let error = |kind: ErrorKind| {
+                Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind,
+                })
+            };
+            match (ty, target) {
+                _ if !valid => error(ErrorKind::MalformedPubkey(format!(
+                    "Public key must be 33-byte compressed or 32-byte x-only but: pk{:?}.",
+                    data
+                ))),
+                (Type::PubKey, Target::Taproot) => error(ErrorKind::InvalidLiteral(format!(
+                    "Taproot takes x-only public key but: pk{:?} is compressed.",
+                    data
+                ))),
+                (Type::XOnly, Target::Legacy | Target::Segwit) => error(ErrorKind::InvalidLiteral(
+                    format!("X-only public key is only for taproot but: pk{:?}.", data),
+                )),
+                _ => Ok(()),
+            }
+        }
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            check_literal(lhs, target)?;
+            check_literal(rhs, target)
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            check_literal(operand, target)?;
+            check_literal(lower, target)?;
+            check_literal(upper, target)
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => check_literal(operand, target),
+        Expression::CheckSigExpression { operand, .. } => check_factor_literal(operand, target),
+        _ => Ok(()),
+    }
+}
+
+// Public key in string literal is decoded as hex, which is checked up to target as pk"..." is.
+// Malformed key is reported by check_type_sig_pubkey().
+fn check_string_pubkey(pubkey: &Expression, target: &Target) -> Result<(), CompileError> {
+    let Expression::StringLiteral(loc, data) = pubkey else {
+        return Ok(());
+    };
+    let error = |msg: String| {
+        Err(CompileError {
+            loc: loc.to_owned(),
+            kind: ErrorKind::InvalidLiteral(msg),
+        })
+    };
+    match (hex::decode(data).map(|bytes| bytes.len()), target) {
+        (Ok(33), Target::Taproot) => error(format!(
+            "Taproot takes x-only public key but: {:?} is compressed.",
+            data
+        )),
+        (Ok(32), Target::Legacy | Target::Segwit) => error(format!(
+            "X-only public key is only for taproot but: {:?}.",
+            data
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_factor_literal(factor: &Factor, target: &Target) -> Result<(), CompileError> {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            check_literal(sig, target)?;
+            check_literal(pubkey, target)?;
+            check_string_pubkey(pubkey, target)
+        }
+        Factor::MultiSigFactor { n, .. } => {
+            for factor in n {
+                check_factor_literal(factor, target)?;
+            }
+            Ok(())
+        }
+    }
+}
+
 // Check any possible vulnerability.
 pub fn check_security(expression: &Expression) -> Result<(), CompileError> {
     check_overflow(expression)?;
@@ -744,6 +1495,16 @@ pub fn check_security(expression: &Expression) -> Result<(), CompileError> {
         Expression::UnaryMathExpression { loc, operand, op } => {
             check_security(&operand)?;
         }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            check_security(operand)?;
+            check_security(lower)?;
+            check_security(upper)?;
+        }
         Expression::UnaryCryptoExpression { loc, operand, op } => {
             check_security(&operand)?;
         }
@@ -794,17 +1555,376 @@ pub fn check_useless_sig(expression: &Expression) -> Result<(), CompileError> {
     }
 }
 
+// Consensus and standardness limits enforced on the compiled script.
+// Reference: <https://github.com/bitcoin/bitcoin/blob/master/src/script/script.h>
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_P2SH_SCRIPT_SIZE: usize = 520;
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+pub const MAX_WITNESS_SCRIPT_SIZE: usize = 10000;
+pub const MAX_P2SH_SIGOPS: usize = 15;
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+// BIP342: every executed signature opcode spends 50 units of the validation weight budget.
+pub const VALIDATION_WEIGHT_PER_SIGOP: usize = 50;
+pub const VALIDATION_WEIGHT_OFFSET: usize = 50;
+
+/// Resource usage of a single spending path(one execution through the if/else tree).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathCost {
+    /// Maximum number of items on stack and altstack, relative to the witness stack.
+    pub stack_depth: i64,
+    /// Non-push opcodes counted the way the interpreter counts them.
+    pub opcode_count: usize,
+    /// Signature operations executed in this path.
+    pub sigops: usize,
+}
+
+// Walk state while enumerating paths over the compiled script.
+#[derive(Debug, Clone, Default)]
+struct PathState {
+    exec: Vec<bool>,
+    depth: i64,
+    cost: PathCost,
+    // Numbers pushed by executed instructions, to size OP_CHECKMULTISIG.
+    pushed: Vec<Option<i64>>,
+}
+
+impl PathState {
+    fn executing(&self) -> bool {
+        self.exec.iter().all(|e| *e)
+    }
+
+    fn shift(&mut self, delta: i64) {
+        self.depth += delta;
+        self.cost.stack_depth = self.cost.stack_depth.max(self.depth);
+    }
+}
+
+// Net effect of an opcode on the combined size of stack and altstack.
+fn opcode_stack_effect(op: bitcoin::Opcode) -> i64 {
+    use bitcoin::opcodes::all::*;
+
+    match op {
+        OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+        | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+        | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+        | OP_PUSHNUM_15 | OP_PUSHNUM_16 => 1,
+        OP_DUP | OP_OVER | OP_TUCK | OP_IFDUP | OP_DEPTH | OP_SIZE => 1,
+        OP_2DUP | OP_2OVER => 2,
+        OP_3DUP => 3,
+        OP_VERIFY | OP_DROP | OP_NIP | OP_ROLL | OP_EQUAL | OP_CHECKSIG => -1,
+        OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX => -1,
+        OP_2DROP | OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_WITHIN | OP_CHECKSIGVERIFY
+        | OP_CHECKSIGADD => -2,
+        // OP_TOALTSTACK, OP_FROMALTSTACK, OP_PICK, OP_SWAP, OP_ROT, unary math, hash, and locktime.
+        _ => 0,
+    }
+}
+
+/// Enumerates every spending path of the compiled script and measures its cost.
+/// Paths are returned in the same order as input stacks are matched to branches,
+/// i.e. the if block before the else block.
+pub fn script_path_costs(script: &[u8]) -> Vec<PathCost> {
+    use bitcoin::opcodes::all::*;
+    use bitcoin::script::Instruction;
+
+    let mut states = vec![PathState::default()];
+    for instruction in bitcoin::Script::from_bytes(script).instructions() {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            // Compiler never emits truncated push, but stop walking rather than guess.
+            Err(_) => break,
+        };
+        let mut next_states = Vec::with_capacity(states.len());
+        for mut state in states {
+            let executing = state.executing();
+            let op = match instruction {
+                Instruction::PushBytes(_) => {
+                    if executing {
+                        state.shift(1);
+                        state.pushed.push(instruction.script_num());
+                    }
+                    next_states.push(state);
+                    continue;
+                }
+                Instruction::Op(op) => op,
+            };
+            // Every non-push opcode counts, even in a non-executed branch.
+            if op.to_u8() > OP_PUSHNUM_16.to_u8() {
+                state.cost.opcode_count += 1;
+            }
+            match op {
+                OP_IF | OP_NOTIF => {
+                    if executing {
+                        state.shift(-1);
+                        state.pushed.push(None);
+                        let mut else_state = state.clone();
+                        state.exec.push(true);
+                        else_state.exec.push(false);
+                        next_states.push(state);
+                        next_states.push(else_state);
+                    } else {
+                        state.exec.push(false);
+                        next_states.push(state);
+                    }
+                    continue;
+                }
+                OP_ELSE => {
+                    // A non-executed parent keeps both sides skipped regardless of toggle.
+                    if let Some(last) = state.exec.last_mut() {
+                        *last = !*last;
+                    }
+                }
+                OP_ENDIF => {
+                    state.exec.pop();
+                }
+                _ if !executing => (),
+                OP_CHECKSIG | OP_CHECKSIGVERIFY | OP_CHECKSIGADD => {
+                    state.cost.sigops += 1;
+                    state.shift(opcode_stack_effect(op));
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    // Stack: <dummy> <sig>*m <m> <pubkey>*n <n>
+                    let len = state.pushed.len();
+                    let n = state.pushed.last().copied().flatten();
+                    let m = n.and_then(|n| {
+                        len.checked_sub(n as usize + 2)
+                            .and_then(|i| state.pushed.get(i).copied().flatten())
+                    });
+                    let keys = n
+                        .filter(|n| (0..=MAX_PUBKEYS_PER_MULTISIG as i64).contains(n))
+                        .unwrap_or(MAX_PUBKEYS_PER_MULTISIG as i64)
+                        as usize;
+                    // Keys are also counted as opcodes once executed.
+                    state.cost.opcode_count += keys;
+                    state.cost.sigops += keys;
+                    let popped = (keys + m.unwrap_or(keys as i64) as usize + 3) as i64;
+                    state.shift(if op == OP_CHECKMULTISIG {
+                        1 - popped
+                    } else {
+                        -popped
+                    });
+                }
+                _ => state.shift(opcode_stack_effect(op)),
+            }
+            if executing {
+                state.pushed.push(instruction.script_num());
+            }
+            next_states.push(state);
+        }
+        states = next_states;
+    }
+
+    states.into_iter().map(|state| state.cost).collect()
+}
+
+// Spending path as written in messages: its name, or index of its input stack if anonymous.
+pub fn path_label(paths: &[Option<PathName>], path: usize) -> String {
+    match paths.get(path) {
+        Some(Some(name)) => format!("{:?}", name.identifier.0),
+        _ => path.to_string(),
+    }
+}
+
+// Signature operations of P2SH redeem script, counted over the whole script regardless of branches
+// as "GetSigOpCount(true)": OP_CHECKMULTISIG counts n keys after OP_n, otherwise 20.
+pub fn p2sh_sigop_count(script: &[u8]) -> usize {
+    use bitcoin::opcodes::all::*;
+    use bitcoin::script::Instruction;
+
+    let mut count = 0;
+    let mut last: Option<bitcoin::Opcode> = None;
+    for instruction in bitcoin::Script::from_bytes(script).instructions() {
+        let Ok(instruction) = instruction else {
+            break;
+        };
+        let op = match instruction {
+            Instruction::Op(op) => Some(op),
+            Instruction::PushBytes(_) => None,
+        };
+        match op {
+            Some(OP_CHECKSIG | OP_CHECKSIGVERIFY) => count += 1,
+            Some(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY) => {
+                count += match last {
+                    Some(n)
+                        if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&n.to_u8()) =>
+                    {
+                        (n.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as usize
+                    }
+                    _ => MAX_PUBKEYS_PER_MULTISIG,
+                }
+            }
+            _ => (),
+        }
+        last = op;
+    }
+    count
+}
+
+// Bytes of compact size prefix for the length.
+fn compact_size_len(len: usize) -> usize {
+    match len {
+        0..=252 => 1,
+        253..=0xffff => 3,
+        _ => 5,
+    }
+}
+
+// Smallest serialized size of a witness item of the type, with its length prefix.
+fn witness_item_size(ty: &Type) -> usize {
+    let len = match ty {
+        // BIP341: 64 bytes with SIGHASH_DEFAULT.
+        Type::Signature => 64,
+        Type::PubKey => 33,
+        Type::XOnly | Type::Sha256 | Type::Hash256 => 32,
+        Type::Hash160 => 20,
+        Type::Bytes => 1,
+        // Zero and false are empty, and so can be a string.
+        Type::Number | Type::Boolean | Type::String => 0,
+    };
+    compact_size_len(len) + len
+}
+
 /*
-This layer checks if the compiled script will be valid according to the strict rules of the Bitcoin network. The goal is to catch errors before deployment.
-Stack Depth Analysis: The Bitcoin stack is limited to 1000 items. Your analyzer must track the maximum possible stack depth for every execution path and throw an error if any path could exceed this limit.
-Opcode Count Limit: A script is limited to 201 opcodes per branch. The analyzer must count the opcodes generated for each path and enforce this limit.
-Script Size Limit: The final compiled script must be under a certain size (e.g., 520 bytes for P2SH, 10,000 bytes for SegWit). Your analyzer should check this.
-Signature Operation (SigOps) Limit: Transactions have a limit on the number of signature-checking operations. The analyzer must count the number of checksig calls in each path and ensure it doesn't exceed the limit.
-Minimal Push Enforcement: The analyzer should ensure that all data pushed to the stack uses the smallest possible opcode (e.g., OP_1 instead of pushing the byte 0x01). This is a standardness rule that prevents malleability and reduces fees.
-Target-Specific Rule Checking: The analyzer must know which "target" it's compiling for (legacy, segwit, taproot) and enforce the rules for that environment.
-Example: If targeting Taproot, it must throw an error if the script tries to use a disabled opcode like OP_CHECKMULTISIG.
+Check the compiled script against the consensus(and standardness) rules of the target,
+so that a contract which can never be spent is rejected before deployment.
+- Stack depth: stack and altstack together are limited to 1000 items in every path.
+- Opcode count: legacy and segwit allow 201 non-push opcodes per script.
+- Script size: 520 bytes for P2SH, 3600(standard) and 10000(consensus) bytes for P2WSH.
+- Sigops: 15 for P2SH over the whole script, and the validation weight budget of BIP342 for
+  each path of tapscript.
 */
-pub fn check_consensus() {}
+// Script of spending paths from the first one, e.g. a tapleaf of a single path.
+// Control block is of the tapleaf, 33 + 32 bytes for each depth in the tree.
+pub fn check_consensus(
+    ast: &[Statement],
+    script: &[u8],
+    input: &[Vec<StackParam>],
+    paths: &[Option<PathName>],
+    first_path: usize,
+    control_block_size: usize,
+    target: &Target,
+) -> Result<(), CompileError> {
+    let script_loc = script_location(ast);
+
+    // 1. Script size
+    let size_limit = match target {
+        Target::Legacy => Some((MAX_P2SH_SCRIPT_SIZE, "P2SH redeem script")),
+        Target::Segwit => {
+            if script.len() > MAX_WITNESS_SCRIPT_SIZE {
+                Some((MAX_WITNESS_SCRIPT_SIZE, "witness script"))
+            } else {
+                Some((
+                    MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+                    "standard P2WSH witness script",
+                ))
+            }
+        }
+        // Tapscript has no script size limit other than the block weight.
+        Target::Taproot => None,
+    };
+    if let Some((limit, kind)) = size_limit {
+        if script.len() > limit {
+            return Err(CompileError {
+                loc: script_loc,
+                kind: ErrorKind::ScriptSizeExceeded(format!(
+                    "Size of {} cannot exceed {} bytes but got: {}.",
+                    kind,
+                    limit,
+                    script.len()
+                )),
+            });
+        }
+    }
+
+    // 2. P2SH sigops are counted statically, not by the path executed.
+    if *target == Target::Legacy {
+        let sigops = p2sh_sigop_count(script);
+        if sigops > MAX_P2SH_SIGOPS {
+            return Err(CompileError {
+                loc: script_loc,
+                kind: ErrorKind::SigOpsExceeded(format!(
+                    "P2SH redeem script counts {} signature operations over all spending paths, beyond the limit of {}.",
+                    sigops, MAX_P2SH_SIGOPS
+                )),
+            });
+        }
+    }
+
+    // 3. Limits for each spending path
+    for (i, cost) in script_path_costs(script).iter().enumerate() {
+        let path = first_path + i;
+        let stack = input.get(path);
+        let loc = stack
+            .and_then(|stack| stack.last())
+            .map_or(script_loc.to_owned(), |item| item.loc.to_owned());
+        let witness_len = stack.map_or(0, |stack| stack.len());
+
+        let stack_depth = witness_len + cost.stack_depth.max(0) as usize;
+        if stack_depth > MAX_STACK_SIZE {
+            return Err(CompileError {
+                loc,
+                kind: ErrorKind::StackDepthExceeded(format!(
+                    "Stack of spending path {} can grow up to {} items, beyond the limit of {}.",
+                    path_label(paths, path),
+                    stack_depth,
+                    MAX_STACK_SIZE
+                )),
+            });
+        }
+
+        if *target != Target::Taproot && cost.opcode_count > MAX_OPS_PER_SCRIPT {
+            return Err(CompileError {
+                loc,
+                kind: ErrorKind::OpcodeCountExceeded(format!(
+                    "Spending path {} counts {} opcodes, beyond the limit of {}.",
+                    path_label(paths, path),
+                    cost.opcode_count,
+                    MAX_OPS_PER_SCRIPT
+                )),
+            });
+        }
+
+        // Budget is funded by the serialized witness: count of items, the smallest item of each
+        // type, the script itself and the control block, each with its length prefix.
+        // Witness sigops are otherwise only limited per transaction(and block).
+        if *target == Target::Taproot {
+            let witness_size = compact_size_len(witness_len + 2)
+                + stack.map_or(0, |stack| {
+                    stack.iter().map(|item| witness_item_size(&item.ty)).sum()
+                })
+                + compact_size_len(script.len())
+                + script.len()
+                + compact_size_len(control_block_size)
+                + control_block_size;
+            let limit = (VALIDATION_WEIGHT_OFFSET + witness_size) / VALIDATION_WEIGHT_PER_SIGOP;
+            if cost.sigops > limit {
+                return Err(CompileError {
+                    loc,
+                    kind: ErrorKind::SigOpsExceeded(format!(
+                        "Spending path {} executes {} signature operations, beyond the limit of {}.",
+                        path_label(paths, path),
+                        cost.sigops,
+                        limit
+                    )),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
 
 pub fn check_fee() {}
 
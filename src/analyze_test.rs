@@ -2,15 +2,33 @@
 mod tests {
     use super::*;
     use crate::analyze::{
-        analyze, build_symbol_table, check_flow, check_overflow, check_type, check_type_sig_pubkey,
-        check_useless_sig, check_variable, Symbol,
+        analyze, check_consensus, check_overflow, check_type, check_type_sig_pubkey,
+        check_useless_sig, check_variable, collect_flow, collect_symbol_table, p2sh_sigop_count,
+        script_path_costs, Symbol,
     };
     // Import analyzer functions
     use crate::ast::*; // Import AST definitions
     use crate::bithoven::BithovenParser; // Import the LALRPOP Parser
+    use crate::constant::{bind_constants, fold_constants};
+    use crate::diagnostic::Diagnostics;
     use std::collections::HashMap;
     use std::fs;
 
+    // First error of the collecting checks, as the analyzer stops at.
+    fn build_symbol_table(
+        stack: &Vec<StackParam>,
+    ) -> Result<HashMap<String, Symbol>, CompileError> {
+        let mut diagnostics = Diagnostics::default();
+        let table = collect_symbol_table(stack, &mut diagnostics);
+        diagnostics.into_result().map(|_| table)
+    }
+
+    fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
+        let mut diagnostics = Diagnostics::default();
+        collect_flow(ast, &mut diagnostics);
+        diagnostics.into_result()
+    }
+
     // --- MOCK HELPERS (for unit tests) ---
     // These functions create mock AST nodes to make tests cleaner.
     // We use a "dummy" location for most mocks.
@@ -53,7 +71,7 @@ mod tests {
     }
 
     fn simple_return(expr: Expression) -> Statement {
-        Statement::ExpressionStatement(loc(0, 0), expr)
+        Statement::ExpressionStatement(loc(0, 0), None, expr)
     }
 
     fn simple_verify(expr: Expression) -> Statement {
@@ -86,6 +104,8 @@ mod tests {
                 ty: Type::Number,
                 consume_count: 0,
                 stack_position: 0,
+                local: None,
+                hashed: false,
             },
         );
         table.insert(
@@ -94,6 +114,8 @@ mod tests {
                 ty: Type::Boolean,
                 consume_count: 0,
                 stack_position: 1,
+                local: None,
+                hashed: false,
             },
         );
         table.insert(
@@ -102,6 +124,8 @@ mod tests {
                 ty: Type::Signature,
                 consume_count: 0,
                 stack_position: 2,
+                local: None,
+                hashed: false,
             },
         );
         table.insert(
@@ -110,6 +134,8 @@ mod tests {
                 ty: Type::String,
                 consume_count: 0,
                 stack_position: 3,
+                local: None,
+                hashed: false,
             },
         );
         table
@@ -165,7 +191,7 @@ mod tests {
         let expr = var("a_num");
 
         assert_eq!(table.get("a_num").unwrap().consume_count, 0);
-        let result = check_variable(&expr, &mut table);
+        let result = check_variable(&expr, &mut table, &StackMode::Strict);
         assert!(result.is_ok());
         assert_eq!(table.get("a_num").unwrap().consume_count, 1);
     }
@@ -174,7 +200,7 @@ mod tests {
     fn test_check_var_err_undefined() {
         let mut table = mock_symbol_table();
         let expr = var("undefined_var");
-        let result = check_variable(&expr, &mut table);
+        let result = check_variable(&expr, &mut table, &StackMode::Strict);
         assert!(result.is_err());
         assert!(matches!(
             result.unwrap_err().kind,
@@ -188,11 +214,11 @@ mod tests {
         let expr = var("a_num");
 
         // First consumption
-        check_variable(&expr, &mut table).unwrap();
+        check_variable(&expr, &mut table, &StackMode::Strict).unwrap();
         assert_eq!(table.get("a_num").unwrap().consume_count, 1);
 
         // Second consumption
-        let result = check_variable(&expr, &mut table);
+        let result = check_variable(&expr, &mut table, &StackMode::Strict);
         assert!(result.is_err());
         assert!(matches!(
             result.unwrap_err().kind,
@@ -210,7 +236,7 @@ mod tests {
             rhs: Box::new(var("a_bool")), // bools are numeric
         };
 
-        check_variable(&expr, &mut table).unwrap();
+        check_variable(&expr, &mut table, &StackMode::Strict).unwrap();
         assert_eq!(table.get("a_num").unwrap().consume_count, 1);
         assert_eq!(table.get("a_bool").unwrap().consume_count, 1);
     }
@@ -226,12 +252,12 @@ mod tests {
             op: BinaryMathOp::Add,
             rhs: Box::new(var("a_bool")), // bools are numeric
         };
-        check_variable(&expr, &mut table).unwrap();
+        check_variable(&expr, &mut table, &StackMode::Strict).unwrap();
 
         // Then test checksig.
         let expr = checksig(var("a_sig"), var("a_str")); // pubkey can be a var here
 
-        check_variable(&expr, &mut table).unwrap();
+        check_variable(&expr, &mut table, &StackMode::Strict).unwrap();
         assert_eq!(table.get("a_sig").unwrap().consume_count, 1);
         assert_eq!(table.get("a_str").unwrap().consume_count, 1);
     }
@@ -526,6 +552,261 @@ mod tests {
         assert!(matches!(res.unwrap_err().kind, ErrorKind::NoReturn(_)));
     }
 
+    // --- check_consensus TESTS ---
+
+    // Helper to parse, analyze and compile, then check consensus limits.
+    fn consensus_result(input: &str) -> Result<(), CompileError> {
+        let parsed = BithovenParser::new().parse(input).expect("Parser failed");
+        analyze(
+            &parsed.output_script,
+            parsed.input_stack.clone(),
+            &parsed.pragma,
+        )
+        .expect("Analyze Error: ");
+        let script = crate::compile::compile(
+            parsed.output_script.clone(),
+            &parsed.input_stack,
+            &parsed.pragma.target,
+        )
+        .unwrap();
+        check_consensus(
+            &parsed.output_script,
+            &script,
+            &parsed.input_stack,
+            &parsed.paths,
+            0,
+            33,
+            &parsed.pragma.target,
+        )
+    }
+
+    #[test]
+    fn test_script_path_costs_branches() {
+        let input = fs::read_to_string("example/htlc.bithoven").unwrap();
+        let parsed = BithovenParser::new().parse(&input).unwrap();
+        let script = crate::compile::compile(
+            parsed.output_script,
+            &parsed.input_stack,
+            &parsed.pragma.target,
+        )
+        .unwrap();
+        let costs = script_path_costs(&script);
+
+        // One path for each input stack, if block first.
+        assert_eq!(costs.len(), 2);
+        assert_eq!(costs[0].sigops, 1);
+        assert_eq!(costs[1].sigops, 1);
+        // Opcodes of the non-executed branch still count.
+        assert_eq!(costs[0].opcode_count, costs[1].opcode_count);
+        // Path 1 never grows beyond the witness stack: the digest is pushed after preimage is hashed.
+        assert_eq!(costs[1].stack_depth, 0);
+    }
+
+    #[test]
+    fn test_check_consensus_examples_ok() {
+        for file in [
+            "htlc.bithoven",
+            "escrow.bithoven",
+            "inheritance.bithoven",
+            "multisig.bithoven",
+        ] {
+            let input = fs::read_to_string(format!("example/{}", file)).unwrap();
+            assert!(consensus_result(&input).is_ok(), "{} exceeds limits", file);
+        }
+    }
+
+    #[test]
+    fn test_check_consensus_opcode_count_exceeded() {
+        let input = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (sig_alice: signature)
+            {{
+                {}
+                return checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+            }}
+        "#,
+            "verify 2 + 3;\n".repeat(101)
+        );
+        let res = consensus_result(&input);
+        assert!(matches!(
+            res.unwrap_err().kind,
+            ErrorKind::OpcodeCountExceeded(_)
+        ));
+
+        // Tapscript has no opcode limit.
+        let taproot = input
+            .replace("target segwit", "target taproot")
+            .replace("\"0245a6", "\"45a6");
+        assert!(consensus_result(&taproot).is_ok());
+    }
+
+    #[test]
+    fn test_check_consensus_script_size_exceeded() {
+        let input = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target legacy;
+            (sig_alice: signature)
+            {{
+                {}
+                return checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+            }}
+        "#,
+            "verify \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\";\n"
+                .repeat(15)
+        );
+        let res = consensus_result(&input);
+        assert!(matches!(
+            res.unwrap_err().kind,
+            ErrorKind::ScriptSizeExceeded(_)
+        ));
+
+        // Same script fits in P2WSH.
+        let segwit = input.replace("target legacy", "target segwit");
+        assert!(consensus_result(&segwit).is_ok());
+    }
+
+    #[test]
+    fn test_check_consensus_sigops_exceeded() {
+        use bitcoin::opcodes::all::{OP_CHECKSIGVERIFY, OP_PUSHNUM_1};
+
+        // P2SH redeem script is too small for 16 pubkeys, so count raw opcodes instead.
+        let script = [OP_PUSHNUM_1, OP_PUSHNUM_1, OP_CHECKSIGVERIFY]
+            .iter()
+            .map(|op| op.to_u8())
+            .collect::<Vec<u8>>()
+            .repeat(16);
+        let input = vec![vec![stack_param("a_sig", Type::Signature)]];
+        let ast = vec![simple_return(bool_lit(true))];
+
+        assert_eq!(script_path_costs(&script)[0].sigops, 16);
+        let res = check_consensus(&ast, &script, &input, &[], 0, 33, &Target::Legacy);
+        assert!(matches!(
+            res.unwrap_err().kind,
+            ErrorKind::SigOpsExceeded(_)
+        ));
+
+        // Witness v0 has no per script sigops limit.
+        assert!(check_consensus(&ast, &script, &input, &[], 0, 33, &Target::Segwit).is_ok());
+    }
+
+    #[test]
+    fn test_check_consensus_p2sh_sigops_over_branches() {
+        use bitcoin::opcodes::all::*;
+
+        let ops = |ops: &[bitcoin::Opcode]| ops.iter().map(|op| op.to_u8()).collect::<Vec<u8>>();
+        let checksigs = |n: usize| ops(&[OP_PUSHNUM_1, OP_PUSHNUM_1, OP_CHECKSIGVERIFY]).repeat(n);
+        let input = vec![
+            vec![stack_param("a_sig", Type::Signature)],
+            vec![stack_param("a_sig", Type::Signature)],
+        ];
+        let ast = vec![simple_return(bool_lit(true))];
+        let check =
+            |script: &[u8]| check_consensus(&ast, script, &input, &[], 0, 0, &Target::Legacy);
+
+        // 8 sigops on each path, but 16 in the redeem script.
+        let branches = [
+            ops(&[OP_IF]),
+            checksigs(8),
+            ops(&[OP_ELSE]),
+            checksigs(8),
+            ops(&[OP_ENDIF]),
+        ]
+        .concat();
+        assert_eq!(script_path_costs(&branches)[0].sigops, 8);
+        assert_eq!(p2sh_sigop_count(&branches), 16);
+        assert!(matches!(
+            check(&branches).unwrap_err().kind,
+            ErrorKind::SigOpsExceeded(_)
+        ));
+        assert!(check(&checksigs(15)).is_ok());
+
+        // OP_CHECKMULTISIG counts n after OP_n, and 20 otherwise.
+        let multisig = ops(&[OP_PUSHNUM_1, OP_PUSHNUM_3, OP_CHECKMULTISIG]);
+        assert_eq!(p2sh_sigop_count(&multisig), 3);
+        assert!(check(&[multisig.clone(), checksigs(12)].concat()).is_ok());
+        assert!(check(&[multisig, checksigs(13)].concat()).is_err());
+        let pushed = [vec![0x01, 0x11], ops(&[OP_CHECKMULTISIG])].concat();
+        assert_eq!(p2sh_sigop_count(&pushed), 20);
+    }
+
+    #[test]
+    fn test_check_consensus_tapscript_budget() {
+        use bitcoin::opcodes::all::*;
+
+        // 4 sigops need the budget of 200: 50 + witness of at least 150 bytes.
+        let script = [
+            [OP_PUSHNUM_1, OP_PUSHNUM_1, OP_CHECKSIGVERIFY].repeat(4),
+            [OP_PUSHNUM_1, OP_DROP].repeat(18),
+        ]
+        .concat()
+        .iter()
+        .map(|op| op.to_u8())
+        .collect::<Vec<u8>>();
+        assert_eq!(script.len(), 48);
+        let ast = vec![simple_return(bool_lit(true))];
+        let check = |stack: Vec<StackParam>, control_block: usize| {
+            check_consensus(
+                &ast,
+                &script,
+                &[stack],
+                &[],
+                0,
+                control_block,
+                &Target::Taproot,
+            )
+        };
+
+        // 1 count + 65 signature + 49 script + 34 control block = 149 bytes.
+        let sig = stack_param("a_sig", Type::Signature);
+        assert!(matches!(
+            check(vec![sig.clone()], 33).unwrap_err().kind,
+            ErrorKind::SigOpsExceeded(_)
+        ));
+        // An empty number is the byte of its length.
+        assert!(check(vec![stack_param("a_num", Type::Number), sig.clone()], 33).is_ok());
+        // So is a control block of a deeper leaf.
+        assert!(check(vec![sig.clone()], 65).is_ok());
+        assert!(check(vec![stack_param("key", Type::PubKey), sig], 33).is_ok());
+    }
+
+    #[test]
+    fn test_check_consensus_multisig_counts_keys() {
+        let input = fs::read_to_string("example/multisig_voting.bithoven").unwrap();
+        let parsed = BithovenParser::new().parse(&input).unwrap();
+        let script = crate::compile::compile(
+            parsed.output_script,
+            &parsed.input_stack,
+            &parsed.pragma.target,
+        )
+        .unwrap();
+        let costs = script_path_costs(&script);
+
+        // OP_CHECKMULTISIG counts every pubkey as sigop(and opcode) once executed.
+        assert_eq!(costs.len(), 2);
+        assert_eq!(costs[0].sigops, 3);
+        assert_eq!(costs[1].sigops, 3);
+        assert_eq!(costs[0].opcode_count, costs[1].opcode_count);
+    }
+
+    #[test]
+    fn test_check_consensus_stack_depth_exceeded() {
+        // 1001 pushes of OP_1, which is within every size and opcode limit of tapscript.
+        let script = vec![bitcoin::opcodes::all::OP_PUSHNUM_1.to_u8(); 1001];
+        let input = vec![vec![stack_param("a_sig", Type::Signature)]];
+        let ast = vec![simple_return(bool_lit(true))];
+        let res = check_consensus(&ast, &script, &input, &[], 0, 33, &Target::Taproot);
+        assert!(matches!(
+            res.unwrap_err().kind,
+            ErrorKind::StackDepthExceeded(_)
+        ));
+
+        let script = vec![bitcoin::opcodes::all::OP_PUSHNUM_1.to_u8(); 999];
+        assert!(check_consensus(&ast, &script, &input, &[], 0, 33, &Target::Taproot).is_ok());
+    }
+
     // --- Analyzer Integration Tests (End-to-End) ---
     // These tests use the LALRPOP parser to test the `analyze` function.
 
@@ -534,11 +815,7 @@ mod tests {
         ($input:expr) => {{
             let parser = BithovenParser::new();
             let parsed = parser.parse($input).expect("Parser failed on valid input");
-            let res = analyze(
-                &parsed.output_script,
-                parsed.input_stack,
-                &parsed.pragma.target,
-            );
+            let res = analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma);
             assert!(res.is_ok(), "Analyzer failed on valid input: {:?}", res);
         }};
     }
@@ -548,11 +825,7 @@ mod tests {
         ($input:expr, $expected_kind:pat) => {{
             let parser = BithovenParser::new();
             let parsed = parser.parse($input).expect("Parser failed on input string");
-            let res = analyze(
-                &parsed.output_script,
-                parsed.input_stack,
-                &parsed.pragma.target,
-            );
+            let res = analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma);
             assert!(res.is_err(), "Analyzer unexpectedly succeeded");
             let err_kind = res.unwrap_err().kind;
             assert!(
@@ -586,10 +859,10 @@ mod tests {
             (preimage: string, sig_b: signature)
             {
                 if true {
-                    return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+                    return checksig(sig_a, "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
                 } else {
                     verify sha256(preimage) == "0000000000000000000000000000000000000000000000000000000000000000";
-                    return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+                    return checksig(sig_b, "a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
                 }
             }
         "#;
@@ -699,9 +972,169 @@ mod tests {
     }
 
     #[test]
-    #[should_panic] // This test should panic because it tries to access scope_vec[1]
-    fn test_analyze_panic_branch_mismatch() {
-        // This tests for a bug in the analyzer itself.
+    fn test_analyze_let_binding() {
+        let source = |body: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (preimage: string, sig_a: signature)
+                (preimage: string, sig_b: signature)
+                {{
+                    {}
+                }}
+                "#,
+                body
+            )
+        };
+        // Binding is computed once, so it is neither consumed nor ordered in strict mode.
+        assert_analyzes!(&source(
+            r#"let digest = sha256 preimage;
+            if digest == sha256 "a" || digest == sha256 "b" {
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            } else {
+                verify digest != sha256 "c";
+                return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+            }"#
+        ));
+
+        // Binding is out of scope after its block.
+        assert_analyze_fails!(
+            &source(
+                r#"if true {
+                let digest = sha256 preimage;
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798") && digest == sha256 "a";
+            } else {
+                verify digest == sha256 "a";
+                return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+            }"#
+            ),
+            ErrorKind::UndefinedVariable(_)
+        );
+
+        let single = |body: &str| source(body).replace("(preimage: string, sig_b: signature)", "");
+        assert_analyze_fails!(
+            &single(
+                r#"let sig_a = sha256 preimage;
+            return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");"#
+            ),
+            ErrorKind::DuplicateVariable(_)
+        );
+
+        // Type is inferred from the value, e.g. sha256 digest is 32 bytes.
+        assert_analyze_fails!(
+            &single(
+                r#"let digest = sha256 preimage;
+            verify digest == 0x1111111111111111111111111111111111111111;
+            return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");"#
+            ),
+            ErrorKind::TypeMismatch(_)
+        );
+        assert_analyze_fails!(
+            &single(
+                r#"let size = len preimage;
+            verify size == 0x00;
+            return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");"#
+            ),
+            ErrorKind::InvalidOperation(_)
+        );
+    }
+
+    #[test]
+    fn test_analyze_else_if_and_match() {
+        let source = |body: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (a: bool, sig_a: signature)
+                (a: bool, b: bool, sig_b: signature)
+                (a: bool, b: bool, sig_c: signature)
+                {{
+                    {}
+                }}
+                "#,
+                body
+            )
+        };
+        // Flattened chain takes input stacks in order, as nested if/else does.
+        assert_analyzes!(&source(
+            r#"if a {
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            } else if b {
+                return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+            } else {
+                return checksig(sig_c, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            }"#
+        ));
+        assert_analyze_fails!(
+            &source(
+                r#"if a {
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            } else if a {
+                return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+            } else {
+                return checksig(sig_c, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            }"#
+            ),
+            ErrorKind::VariableConsumed(_)
+        );
+
+        // Selector is consumed once for every arm.
+        let dispatch = |arms: &str| {
+            source(&format!("match a {{ {} }}", arms))
+                .replace("a: bool, b: bool, ", "a: number, ")
+                .replace("a: bool, ", "a: number, ")
+        };
+        let arm = |value: &str, sig: &str| {
+            format!(
+                r#"{} => {{ return checksig({}, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"); }}"#,
+                value, sig
+            )
+        };
+        assert_analyzes!(&dispatch(&format!(
+            "{} {} {}",
+            arm("0", "sig_a"),
+            arm("1", "sig_b"),
+            arm("2", "sig_c")
+        )));
+        assert_analyze_fails!(
+            &dispatch(&format!("{} {}", arm("0", "sig_a"), arm("1", "sig_b"))),
+            ErrorKind::StackMismatch(_)
+        );
+        assert_analyze_fails!(
+            &dispatch(&format!(
+                "{} {} {}",
+                arm("0", "sig_a"),
+                arm("0", "sig_b"),
+                arm("2", "sig_c")
+            )),
+            ErrorKind::UnreachableCode(_)
+        );
+        assert_analyze_fails!(
+            &dispatch(&format!(
+                "{} {} {}",
+                arm("0", "sig_a"),
+                arm("1", "sig_b"),
+                arm("2147483648", "sig_c")
+            )),
+            ErrorKind::IntegerOverflow(_)
+        );
+        assert_analyze_fails!(
+            &dispatch(&format!(
+                "{} {} {}",
+                arm("0", "sig_a"),
+                arm("1", "sig_b"),
+                arm("2", "sig_c")
+            ))
+            .replace("match a", "match sha256 \"a\""),
+            ErrorKind::TypeMismatch(_)
+        );
+    }
+
+    #[test]
+    fn test_analyze_branch_mismatch() {
+        // Each spending path needs its own stack, reported instead of crashing the analyzer.
         let input = r#"
             pragma bithoven version 1.0.0;
             pragma bithoven target segwit;
@@ -714,16 +1147,28 @@ mod tests {
                 }
             }
         "#;
-        // The parser succeeds
-        let parser = BithovenParser::new();
-        let parsed = parser.parse(input).expect("Parser failed");
+        assert_analyze_fails!(input, ErrorKind::StackMismatch(_));
 
-        // This will call analyze_statement with branch=1, which panics on scope_vec[1]
-        // This is a valid test, as it identifies a crash bug in the analyzer.
-        let _ = analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
+        // No stack, or empty stack and block.
+        assert_analyze_fails!(
+            r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            {
+                return true;
+            }
+        "#,
+            ErrorKind::StackMismatch(_)
+        );
+        assert_analyze_fails!(
+            r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            ()
+            {
+            }
+        "#,
+            ErrorKind::NoSigRequired(_) | ErrorKind::NoReturn(_)
         );
     }
 
@@ -759,20 +1204,18 @@ mod tests {
                 // 5. Parse the content
                 // We don't use assert_parses! here because we want to
                 // provide a custom error message that includes the filename.
-                let parsed = parser.parse(&input).unwrap_or_else(|e| {
+                let mut parsed = parser.parse(&input).unwrap_or_else(|e| {
                     panic!(
                         "\n\nFailed to parse example file: {}\n\nParse Error: {:?}\n\n",
                         file_path_str, e
                     )
                 });
 
-                // 6. Analyze all
-                analyze(
-                    &parsed.output_script,
-                    parsed.input_stack,
-                    &parsed.pragma.target,
-                )
-                .expect("Analyze Error: ");
+                // 6. Analyze all, with constants bound and folded as compiler does.
+                bind_constants(&mut parsed).expect("Constant Error: ");
+                fold_constants(&mut parsed.output_script);
+                analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+                    .expect("Analyze Error: ");
             }
         }
         // 6. A final sanity check
@@ -813,12 +1256,8 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
-        )
-        .expect("Analyze Error: ");
+        analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+            .expect("Analyze Error: ");
     }
 
     #[test]
@@ -851,12 +1290,8 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
-        )
-        .expect("Analyze Error: ");
+        analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+            .expect("Analyze Error: ");
     }
 
     #[test]
@@ -879,12 +1314,8 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
-        )
-        .expect("Analyze Error: ");
+        analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+            .expect("Analyze Error: ");
     }
 
     #[test]
@@ -907,12 +1338,8 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
-        )
-        .expect("Analyze Error: ");
+        analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+            .expect("Analyze Error: ");
     }
 
     #[test]
@@ -935,11 +1362,261 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
-            &parsed.output_script,
-            parsed.input_stack,
-            &parsed.pragma.target,
+        analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+            .expect("Analyze Error: ");
+    }
+
+    #[test]
+    fn test_dead_path() {
+        let analyze_source = |path_a: &str, path_b: &str| {
+            let input = format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+
+                (condition: bool, sig_alice: signature)
+                (condition: bool, sig_bob: signature)
+                {{
+                    older 144;
+                    after 2027-01-01T00:00Z;
+                    if condition {{
+                        {}
+                        return checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+                    }} else {{
+                        {}
+                        return checksig (sig_bob, "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+                    }}
+                }}
+                "#,
+                path_a, path_b
+            );
+            let parsed = crate::parse(input).expect("Parser failed");
+            analyze(&parsed.output_script, parsed.input_stack, &parsed.pragma)
+        };
+
+        // Height and time of different kinds, or on different paths.
+        assert!(analyze_source("after 2028-01-01T00:00Z;", "older 1008 blocks;").is_ok());
+        // Any locktimes of the same kind, by the largest one.
+        assert!(analyze_source(
+            "after 1798761600;\nolder 1;",
+            "after 2026-01-01T00:00Z;\nolder 65535 blocks;"
         )
-        .expect("Analyze Error: ");
+        .is_ok());
+
+        // nLockTime can't be a height below 500,000,000 and a time at once.
+        let err = analyze_source("verify true;", "after block 900000;").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DeadPath(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 14);
+        assert!(err.to_string().contains("line 9"), "{}", err);
+        // Even a bare number, whose kind follows its value.
+        let err = analyze_source("after 499999999;", "verify true;").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DeadPath(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 11);
+
+        // Nor nSequence a count of blocks and of 512 seconds.
+        let err = analyze_source("older 2 days;", "verify true;").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DeadPath(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 11);
+        assert!(err.to_string().contains("line 8"), "{}", err);
+    }
+
+    #[test]
+    fn test_typed_literals() {
+        let source = |target: &str, stack: &str, stmt: &str, pubkey: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target {};
+                ({}sig: signature)
+                {{
+                    {}
+                    return checksig (sig, {});
+                }}
+                "#,
+                target, stack, stmt, pubkey
+            )
+        };
+        let compressed =
+            r#"pk"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798""#;
+        let xonly = r#"pk"79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798""#;
+        let digest = format!("0x{}", "ab".repeat(32));
+
+        assert_analyzes!(&source("segwit", "", "", compressed));
+        assert_analyzes!(&source("taproot", "", "", xonly));
+        assert_analyzes!(&source(
+            "segwit",
+            "preimage: bytes, ",
+            &format!("verify sha256 sha256 preimage == {};", digest),
+            compressed
+        ));
+        assert_analyzes!(&source(
+            "segwit",
+            "preimage: bytes, digest: hash256, ",
+            "verify sha256 sha256 preimage == digest;",
+            compressed
+        ));
+
+        // Key must be of the target.
+        assert_analyze_fails!(
+            &source("segwit", "", "", xonly),
+            ErrorKind::InvalidLiteral(_)
+        );
+        assert_analyze_fails!(
+            &source("taproot", "", "", compressed),
+            ErrorKind::InvalidLiteral(_)
+        );
+        assert_analyze_fails!(
+            &source("segwit", "", "", r#"pk"0279be""#),
+            ErrorKind::MalformedPubkey(_)
+        );
+        assert_analyze_fails!(
+            &source("segwit", "x: bytes, ", "verify x == 0xabc;", compressed),
+            ErrorKind::InvalidLiteral(_)
+        );
+
+        // Key in string literal too, or 33-byte key leaves the tapleaf spendable by any signature.
+        let kind = |source: String| crate::compile_program(source).unwrap_err().kind;
+        assert!(matches!(
+            kind(source("taproot", "", "", &compressed[2..])),
+            ErrorKind::InvalidLiteral(_)
+        ));
+        assert!(matches!(
+            kind(source("segwit", "", "", &xonly[2..])),
+            ErrorKind::InvalidLiteral(_)
+        ));
+        assert!(crate::compile_program(source("taproot", "", "", &xonly[2..])).is_ok());
+        // Folded constant as well.
+        let constant = source("taproot", "", "", "key").replace(
+            "(sig: signature)",
+            &format!(
+                "const key: string = {};\n(sig: signature)",
+                &compressed[2..]
+            ),
+        );
+        assert!(matches!(kind(constant), ErrorKind::InvalidLiteral(_)));
+    }
+
+    #[test]
+    fn test_typed_compare() {
+        let source = |stack: &str, stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                ({}, sig: signature)
+                {{
+                    verify {};
+                    return checksig (sig, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+                }}
+                "#,
+                stack, stmt
+            )
+        };
+        // Digest of other hash never equals.
+        assert_analyze_fails!(
+            &source(
+                "preimage: bytes, digest: hash160",
+                "sha256 preimage == digest"
+            ),
+            ErrorKind::TypeMismatch(_)
+        );
+        assert_analyze_fails!(
+            &source("preimage: bytes", "sha256 preimage == 0xabcd"),
+            ErrorKind::TypeMismatch(_)
+        );
+        assert_analyze_fails!(
+            &source("digest: sha256", "digest + 1 == 2"),
+            ErrorKind::InvalidOperation(_)
+        );
+        assert_analyzes!(&source(
+            "preimage: string, digest: hash160",
+            "ripemd160 sha256 preimage == digest"
+        ));
+        // Builtins have digest types.
+        assert_analyzes!(&source(
+            "preimage: string, digest: hash160",
+            "hash160 preimage == digest"
+        ));
+        assert_analyze_fails!(
+            &source(
+                "preimage: string, digest: hash160",
+                "hash256 preimage == digest"
+            ),
+            ErrorKind::TypeMismatch(_)
+        );
+        // Literal of wrong digest length.
+        assert_analyze_fails!(
+            &source(
+                "preimage: string",
+                "sha1 preimage == \"e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4aa\""
+            ),
+            ErrorKind::TypeMismatch(_)
+        );
+        assert_analyze_fails!(
+            &source("preimage: string", "hash256 preimage == \"secret\""),
+            ErrorKind::TypeMismatch(_)
+        );
+        assert_analyzes!(&source(
+            "preimage: string",
+            "sha1 preimage == \"e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4\""
+        ));
+        // Tagged hash of witness item would need OP_CAT.
+        assert_analyze_fails!(
+            &source(
+                "preimage: string, digest: sha256",
+                "tagged_hash(\"TapLeaf\", preimage) == digest"
+            ),
+            ErrorKind::InvalidOperation(_)
+        );
+    }
+
+    #[test]
+    fn test_witness_pubkey() {
+        let source = |target: &str, ty: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target {};
+                pragma bithoven stack relaxed;
+                (key: {}, sig: signature)
+                {{
+                    verify ripemd160 sha256 key == 0x1111111111111111111111111111111111111111;
+                    return checksig (sig, key);
+                }}
+                "#,
+                target, ty
+            )
+        };
+        assert_analyzes!(&source("segwit", "pubkey"));
+        assert_analyzes!(&source("taproot", "xonly"));
+        // Key must be of the kind the target checks.
+        assert_analyze_fails!(&source("taproot", "pubkey"), ErrorKind::TypeMismatch(_));
+        assert_analyze_fails!(&source("legacy", "xonly"), ErrorKind::TypeMismatch(_));
+        assert_analyze_fails!(&source("segwit", "bytes"), ErrorKind::TypeMismatch(_));
+    }
+
+    #[test]
+    fn test_multisig_threshold() {
+        let source = |m: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (sig_a: signature, sig_b: signature)
+                {{
+                    return checksig [{},
+                        (sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"),
+                        (sig_b, "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5")
+                    ];
+                }}
+                "#,
+                m
+            )
+        };
+        assert_analyzes!(&source("2"));
+        // Anyone can spend with 0, and no one with more than keys.
+        for m in ["0", "3", "99999999999999999999"] {
+            assert_analyze_fails!(&source(m), ErrorKind::InvalidOperation(_));
+        }
     }
 }
@@ -1,17 +1,70 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bithoven {
     pub pragma: Pragma,
+    // Template parameters, e.g. "param alice: pubkey;", bound to literals at compile time.
+    pub params: Vec<StackParam>,
+    // Compile-time constants, e.g. "const week: number = 144 * 7;", placed at each use.
+    pub constants: Vec<Constant>,
     pub input_stack: Vec<Vec<StackParam>>,
+    // Name of each input stack, e.g. "path refund(..)", none if anonymous.
+    pub paths: Vec<Option<PathName>>,
     pub output_script: Vec<Statement>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Pragma {
     pub language: String,
     pub version: String,
     pub target: Target,
+    pub options: Vec<PragmaOption>,
+    // Lint levels of statements, given by attribute.
+    pub attributes: Vec<LintAttribute>,
+}
+
+// Optional pragma given as key and value, e.g. "pragma bithoven stack relaxed;".
+#[derive(Clone, Debug, PartialEq)]
+pub struct PragmaOption {
+    pub loc: Location,
+    pub key: Identifier,
+    pub value: String,
+}
+
+// Attribute on a statement setting lint level, e.g. "#[allow(pubkey_reuse)]".
+#[derive(Clone, Debug, PartialEq)]
+pub struct LintAttribute {
+    pub loc: Location,
+    // Span of the statement the attribute applies to.
+    pub scope: Location,
+    pub level: LintLevel,
+    pub lints: Vec<String>,
+}
+
+// What to do with a lint finding.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// How strictly input stack items must be consumed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StackMode {
+    // Each item is consumed exactly once, in stack order.
+    Strict,
+    // Items can be used in any order and more than once.
+    Relaxed,
+}
+
+// How taproot output commits to the script.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TapleafMode {
+    // Whole script is a single tapleaf.
+    Single,
+    // Each spending path is its own tapleaf.
+    Path,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -28,6 +81,32 @@ pub struct StackParam {
     pub ty: Type,
 }
 
+// Constant with its declared type, whose value is folded to a literal of the type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constant {
+    pub loc: Location,
+    pub identifier: Identifier,
+    pub ty: Type,
+    pub value: Expression,
+}
+
+// Template parameter or constant, declared in any order before input stacks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Declaration {
+    Param(StackParam),
+    Constant(Constant),
+}
+
+// Name of spending path, declared on its input stack and given to its return.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PathName {
+    pub loc: Location,
+    pub identifier: Identifier,
+}
+
+// Input stack with the name of its spending path, as parsed.
+pub type NamedStack = (Option<PathName>, Vec<StackParam>);
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     pub start: usize,
@@ -47,6 +126,16 @@ pub enum Type {
     Number,
     String,
     Boolean,
+    // 33-byte compressed public key.
+    PubKey,
+    // 32-byte x-only public key of taproot.
+    XOnly,
+    // Raw bytes, unlike string which may be ASCII text.
+    Bytes,
+    // Digests: ripemd160(sha256(x)), sha256(x) and sha256(sha256(x)).
+    Hash160,
+    Sha256,
+    Hash256,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -57,13 +146,37 @@ pub enum Statement {
         if_block: Vec<Statement>,
         else_block: Option<Vec<Statement>>,
     },
+    // Dispatch on a number selector, e.g. "match choice { 0 => {..} 1 => {..} }".
+    // Each arm takes its own spending paths, in order of arms.
+    MatchStatement {
+        loc: Location,
+        selector: Expression,
+        arms: Vec<MatchArm>,
+    },
     LocktimeStatement {
         loc: Location,
-        operand: i64,
+        // Number literal, or template parameter until bound.
+        operand: Expression,
         op: LocktimeOp,
+        unit: LocktimeUnit,
+    },
+    // Immutable binding of a computed value, e.g. "let digest = sha256 preimage;".
+    LetStatement {
+        loc: Location,
+        identifier: Identifier,
+        expr: Expression,
     },
     VerifyStatement(Location, Expression),
-    ExpressionStatement(Location, Expression),
+    // Return, with the name of its spending path, e.g. "return refund: ..".
+    ExpressionStatement(Location, Option<PathName>, Expression),
+}
+
+// Arm of match statement, taken when the selector equals its value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchArm {
+    pub loc: Location,
+    pub value: i64,
+    pub block: Vec<Statement>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -72,6 +185,10 @@ pub enum Expression {
     NumberLiteral(Location, i64),
     BooleanLiteral(Location, bool),
     StringLiteral(Location, String),
+    // Hex without "0x", e.g. 0xdeadbeef.
+    BytesLiteral(Location, String),
+    // Hex of compressed or x-only key, e.g. pk"02..".
+    PubKeyLiteral(Location, String),
     LogicalExpression {
         loc: Location,
         lhs: Box<Expression>,
@@ -95,6 +212,13 @@ pub enum Expression {
         op: BinaryMathOp,
         rhs: Box<Expression>,
     },
+    TernaryMathExpression {
+        loc: Location,
+        operand: Box<Expression>,
+        lower: Box<Expression>,
+        upper: Box<Expression>,
+        op: TernaryMathOp,
+    },
     UnaryCryptoExpression {
         loc: Location,
         operand: Box<Expression>,
@@ -136,6 +260,14 @@ pub enum BinaryMathOp {
     Sub,
     Max,
     Min,
+    // Only for constants, as OP_MUL is disabled.
+    Mul,
+}
+
+// Lower bound is inclusive, and upper bound is exclusive.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TernaryMathOp {
+    Within,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -156,6 +288,11 @@ pub enum CheckSigOp {
 pub enum UnaryCryptoOp {
     Sha256,
     Ripemd160,
+    Hash160,
+    Hash256,
+    Sha1,
+    // BIP340 tagged hash. Only for constants, as there is no OP_CAT.
+    TaggedHash(String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -169,6 +306,23 @@ pub enum LocktimeOp {
     Csv,
 }
 
+// Unit of locktime operand, see locktime.rs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocktimeUnit {
+    // Number as written, e.g. "older 144;".
+    Raw,
+    // "older 1008 blocks;" or "after block 900000;"
+    Blocks,
+    // Relative time, e.g. "older 2 days;"
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+    // UNIX timestamp of "after 2027-01-01T00:00Z;"
+    Date,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Factor {
     SingleSigFactor {
@@ -193,6 +347,13 @@ pub struct CompileError {
 pub enum ErrorKind {
     // Parsing Error
     ParseError(String),
+    InvalidPragma(String),
+    InvalidNetwork(String),
+    InvalidTest(String),
+    InvalidScript(String),
+    InvalidPolicy(String),
+    NotRepresentable(String),
+    UnknownLint(String),
 
     // Variable and Scope Errors
     DuplicateVariable(String),
@@ -200,40 +361,123 @@ pub enum ErrorKind {
     VariableConsumed(String),
     UnusedVariable(String),
     InvalidConsumptionOrder(String),
+    StackMismatch(String),
+    MissingParam(String),
+    InvalidParam(String),
 
     // Type Errors
     TypeMismatch(String),
     InvalidOperation(String),
+    InvalidLiteral(String),
 
     // Bitcoin Consensus Errors
     StackDepthExceeded(String),
     OpcodeCountExceeded(String),
+    ScriptSizeExceeded(String),
+    SigOpsExceeded(String),
     DustOutputCreated(String),
+    TaprootError(String),
 
     // Flow Errors
     MultipleReturn(String),
     NoReturn(String),
     UnreachableCode(String),
-    DeadPath,
+    DeadPath(String),
 
     // Security Errors
     IntegerOverflow(String),
     UselessSig(String),
     MalformedPubkey(String),
     NoSigRequired(String),
+    PubkeyReuse(String),
+    TimelockMixing(String),
+    UncommittedPubkey(String),
 }
 
 use std::fmt;
 use wasm_bindgen::JsValue;
 
 // (Optional but recommended) Create a display implementation for a clean error message.
+// Error of an input outside of source, e.g. a parameter value, is at line 0 and has no position.
 impl fmt::Display for CompileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Error at line {}:{}: {:?}",
-            self.loc.line, self.loc.column, self.kind
-        )
+        match self.loc.line {
+            0 => write!(f, "Error: {:?}", self.kind),
+            line => write!(
+                f,
+                "Error at line {}:{}: {:?}",
+                line, self.loc.column, self.kind
+            ),
+        }
+    }
+}
+
+impl ErrorKind {
+    // Name of the variant, e.g. "UnusedVariable".
+    pub fn name(&self) -> String {
+        let debug = format!("{:?}", self);
+        debug.split('(').next().unwrap_or_default().to_string()
+    }
+
+    // Message of the error without its name.
+    pub fn message(&self) -> String {
+        match self {
+            ErrorKind::ParseError(msg)
+            | ErrorKind::InvalidPragma(msg)
+            | ErrorKind::InvalidNetwork(msg)
+            | ErrorKind::InvalidTest(msg)
+            | ErrorKind::InvalidScript(msg)
+            | ErrorKind::InvalidPolicy(msg)
+            | ErrorKind::NotRepresentable(msg)
+            | ErrorKind::UnknownLint(msg)
+            | ErrorKind::DuplicateVariable(msg)
+            | ErrorKind::UndefinedVariable(msg)
+            | ErrorKind::VariableConsumed(msg)
+            | ErrorKind::UnusedVariable(msg)
+            | ErrorKind::InvalidConsumptionOrder(msg)
+            | ErrorKind::StackMismatch(msg)
+            | ErrorKind::MissingParam(msg)
+            | ErrorKind::InvalidParam(msg)
+            | ErrorKind::TypeMismatch(msg)
+            | ErrorKind::InvalidOperation(msg)
+            | ErrorKind::InvalidLiteral(msg)
+            | ErrorKind::StackDepthExceeded(msg)
+            | ErrorKind::OpcodeCountExceeded(msg)
+            | ErrorKind::ScriptSizeExceeded(msg)
+            | ErrorKind::SigOpsExceeded(msg)
+            | ErrorKind::DustOutputCreated(msg)
+            | ErrorKind::TaprootError(msg)
+            | ErrorKind::MultipleReturn(msg)
+            | ErrorKind::NoReturn(msg)
+            | ErrorKind::UnreachableCode(msg)
+            | ErrorKind::DeadPath(msg)
+            | ErrorKind::IntegerOverflow(msg)
+            | ErrorKind::UselessSig(msg)
+            | ErrorKind::MalformedPubkey(msg)
+            | ErrorKind::NoSigRequired(msg)
+            | ErrorKind::PubkeyReuse(msg)
+            | ErrorKind::TimelockMixing(msg)
+            | ErrorKind::UncommittedPubkey(msg) => msg.to_owned(),
+        }
+    }
+}
+
+// Type as written in source code.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Signature => "signature",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Boolean => "bool",
+            Type::PubKey => "pubkey",
+            Type::XOnly => "xonly",
+            Type::Bytes => "bytes",
+            Type::Hash160 => "hash160",
+            Type::Sha256 => "sha256",
+            Type::Hash256 => "hash256",
+        };
+        write!(f, "{}", name)
     }
 }
 
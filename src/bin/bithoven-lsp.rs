@@ -0,0 +1,14 @@
+// Language server of bithoven, speaking LSP over stdio.
+use std::io;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match bithoven::lsp::run(stdin.lock(), stdout.lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("bithoven-lsp: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,10 +1,14 @@
 use bitcoin::opcodes::all::{
-    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_EQUAL,
+    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_DUP, OP_EQUAL,
     OP_EQUALVERIFY, OP_HASH160, OP_HASH256, OP_NUMEQUAL, OP_NUMEQUALVERIFY, OP_PUSHDATA1,
-    OP_PUSHDATA2, OP_PUSHDATA4, OP_RIPEMD160, OP_SHA256, OP_VERIFY,
+    OP_PUSHDATA2, OP_PUSHDATA4, OP_RIPEMD160, OP_SHA256, OP_SWAP, OP_VERIFY,
 };
 
 use crate::ast::*;
+use crate::locktime::locktime_value;
+use crate::source::Locatable;
+use crate::vm::MAX_SCRIPT_ELEMENT_SIZE;
+use std::collections::HashMap;
 
 /*
     1. Pure Push
@@ -20,16 +24,31 @@ pub fn push_int(script: &mut Vec<u8>, data: i64) {
     script.extend_from_slice(builder.as_bytes());
 }
 
+// Bytes of string literal: hex if it decodes, otherwise its text.
+// Bytes literal "0x.." has no such ambiguity.
+pub fn string_bytes(data: &str) -> Vec<u8> {
+    hex::decode(data).unwrap_or(data.as_bytes().to_vec())
+}
+
 // Push any type of byte. Some are overlapped with push_int.
 // Reference: <https://github.com/bitcoin/bips/blob/master/bip-0062.mediawiki#push-operators>
-pub fn push_bytes(script: &mut Vec<u8>, data: String) {
-    // Try decoding hex, and then utf-8
-    let hex_or_utf8 = hex::decode(&data).unwrap_or(data.into_bytes());
-    let bytes = <&bitcoin::script::PushBytes>::try_from(hex_or_utf8.as_slice())
-        .expect("String to bytes error");
+pub fn push_bytes(script: &mut Vec<u8>, data: &[u8], loc: &Location) -> Result<(), CompileError> {
+    let too_large = || CompileError {
+        loc: loc.to_owned(),
+        kind: ErrorKind::ScriptSizeExceeded(format!(
+            "Pushed data cannot exceed {} bytes of a stack element but: {} bytes.",
+            MAX_SCRIPT_ELEMENT_SIZE,
+            data.len()
+        )),
+    };
+    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+        return Err(too_large());
+    }
+    let bytes = <&bitcoin::script::PushBytes>::try_from(data).map_err(|_| too_large())?;
     let builder = bitcoin::script::Builder::new().push_slice(bytes);
 
     script.extend_from_slice(builder.as_bytes());
+    Ok(())
 }
 
 /*
@@ -162,6 +181,18 @@ pub fn push_compare(script: &mut Vec<u8>, operand: BinaryCompareOp) {
     }
 }
 
+// OP_NOT for zero, otherwise value and OP_NUMEQUAL. Compare the selector of match on top.
+pub fn push_selector_check(script: &mut Vec<u8>, value: i64) {
+    let builder = match value {
+        0 => bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NOT),
+        _ => bitcoin::script::Builder::new()
+            .push_int(value)
+            .push_opcode(bitcoin::opcodes::all::OP_NUMEQUAL),
+    };
+
+    script.extend_from_slice(builder.as_bytes());
+}
+
 /*
     4. Math push unary
     - See the top 1 stack item.
@@ -239,6 +270,13 @@ pub fn push_math_binary(script: &mut Vec<u8>, operand: BinaryMathOp) {
 
             script.extend_from_slice(builder.as_bytes());
         }
+        // Disabled opcode, never reached as multiplication is folded or rejected.
+        BinaryMathOp::Mul => {
+            let builder =
+                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_MUL);
+
+            script.extend_from_slice(builder.as_bytes());
+        }
     }
 }
 
@@ -250,7 +288,16 @@ pub fn push_math_binary(script: &mut Vec<u8>, operand: BinaryMathOp) {
 */
 
 // OP_WITHIN
-pub fn push_math_ternary() {}
+pub fn push_math_ternary(script: &mut Vec<u8>, op: TernaryMathOp) {
+    match op {
+        TernaryMathOp::Within => {
+            let builder =
+                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_WITHIN);
+
+            script.extend_from_slice(builder.as_bytes());
+        }
+    }
+}
 
 /*
     7. Crypto push
@@ -259,21 +306,32 @@ pub fn push_math_ternary() {}
     - Push new single stack item.
 */
 // OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256,
-pub fn push_crypto_unary(script: &mut Vec<u8>, op: UnaryCryptoOp) {
-    match op {
-        UnaryCryptoOp::Sha256 => {
-            let builder =
-                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SHA256);
-
-            script.extend_from_slice(builder.as_bytes());
+pub fn push_crypto_unary(
+    script: &mut Vec<u8>,
+    op: UnaryCryptoOp,
+    loc: &Location,
+) -> Result<(), CompileError> {
+    let opcode = match op {
+        UnaryCryptoOp::Sha256 => bitcoin::opcodes::all::OP_SHA256,
+        UnaryCryptoOp::Ripemd160 => bitcoin::opcodes::all::OP_RIPEMD160,
+        UnaryCryptoOp::Hash160 => bitcoin::opcodes::all::OP_HASH160,
+        UnaryCryptoOp::Hash256 => bitcoin::opcodes::all::OP_HASH256,
+        UnaryCryptoOp::Sha1 => bitcoin::opcodes::all::OP_SHA1,
+        // Folded into a bytes literal before compiling, so an unfolded one has a non-constant operand.
+        UnaryCryptoOp::TaggedHash(_) => {
+            return Err(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::InvalidOperation(
+                    "Tagged hash is only for constants, as OP_CAT is disabled.".to_string(),
+                ),
+            })
         }
-        UnaryCryptoOp::Ripemd160 => {
-            let builder =
-                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_RIPEMD160);
+    };
+    let builder = bitcoin::script::Builder::new().push_opcode(opcode);
 
-            script.extend_from_slice(builder.as_bytes());
-        }
-    }
+    script.extend_from_slice(builder.as_bytes());
+
+    Ok(())
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -319,10 +377,10 @@ pub fn push_locktime(script: &mut Vec<u8>, operand: i64, op: LocktimeOp) {
 
             script.extend_from_slice(builder.as_bytes());
         }
+        // Blocks, or 512 seconds with the type flag(BIP68).
         LocktimeOp::Csv => {
-            let height = bitcoin::locktime::relative::Height::from_height(operand as u16);
             let builder = bitcoin::script::Builder::new()
-                .push_sequence(bitcoin::locktime::relative::LockTime::Blocks(height).to_sequence())
+                .push_sequence(bitcoin::Sequence::from_consensus(operand as u32))
                 .push_opcode(bitcoin::opcodes::all::OP_CSV)
                 .push_opcode(bitcoin::opcodes::all::OP_DROP);
 
@@ -331,85 +389,753 @@ pub fn push_locktime(script: &mut Vec<u8>, operand: i64, op: LocktimeOp) {
     }
 }
 
-pub fn push_to_alt_stack(script: &mut Vec<u8>) {
-    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_TOALTSTACK);
+/*
+    10. Stack manipulation push
+    - See the item at given depth.
+    - Push it to the top of stack.
+*/
+
+// OP_DUP, OP_OVER and OP_PICK. Item is copied, so still remains at the depth.
+pub fn push_pick(script: &mut Vec<u8>, depth: usize) {
+    let builder = match depth {
+        0 => bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_DUP),
+        1 => bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_OVER),
+        _ => bitcoin::script::Builder::new()
+            .push_int(depth as i64)
+            .push_opcode(bitcoin::opcodes::all::OP_PICK),
+    };
 
     script.extend_from_slice(builder.as_bytes());
 }
 
-pub fn push_from_alt_stack(script: &mut Vec<u8>) {
-    let builder = bitcoin::script::Builder::new()
-        .push_opcode(bitcoin::opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(bitcoin::opcodes::all::OP_SWAP);
+// OP_SWAP, OP_ROT and OP_ROLL. Item is moved, and nothing to do for the top.
+pub fn push_roll(script: &mut Vec<u8>, depth: usize) {
+    let builder = match depth {
+        0 => bitcoin::script::Builder::new(),
+        1 => bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SWAP),
+        2 => bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_ROT),
+        _ => bitcoin::script::Builder::new()
+            .push_int(depth as i64)
+            .push_opcode(bitcoin::opcodes::all::OP_ROLL),
+    };
 
     script.extend_from_slice(builder.as_bytes());
 }
 
-pub fn compile(ast: Vec<Statement>, target: &Target) -> Vec<u8> {
-    let mut bitcoin_script: Vec<u8> = Vec::new();
+// OP_SWAP. Exchange the top two items.
+pub fn push_swap(script: &mut Vec<u8>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SWAP);
+
+    script.extend_from_slice(builder.as_bytes());
+}
+
+// OP_DROP. Remove the top item.
+pub fn push_drop(script: &mut Vec<u8>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_DROP);
+
+    script.extend_from_slice(builder.as_bytes());
+}
+
+// OP_NIP. Remove the item right below the top.
+pub fn push_nip(script: &mut Vec<u8>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NIP);
+
+    script.extend_from_slice(builder.as_bytes());
+}
+
+/*
+    Stack model
+    - Witness items are only referenced by name in source.
+    - Compiler tracks where each item sits for every spending path sharing the code,
+      and moves it to the top when referenced.
+*/
+
+// An item on the stack as seen by the compiler.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StackSlot {
+    // Witness item given by input stack, or value bound by "let".
+    Variable(String),
+    // Value computed by the script.
+    Temporary,
+}
+
+// Stack of a single spending path.
+#[derive(Clone, Debug)]
+pub struct PathStack {
+    /// Index of the input stack(spending path).
+    pub path: usize,
+    /// Items of the stack, where the top is the last element.
+    pub slots: Vec<StackSlot>,
+    /// How many references of each variable remain in this path.
+    pub remaining_use: HashMap<String, usize>,
+}
 
-    for node in ast {
-        compile_statement(&mut bitcoin_script, node, target);
+// Stacks of every spending path which can reach the code being compiled.
+#[derive(Clone, Debug)]
+pub struct StackModel {
+    pub paths: Vec<PathStack>,
+}
+
+impl StackModel {
+    pub fn new(ast: &[Statement], input: &[Vec<StackParam>]) -> Self {
+        let mut model = StackModel {
+            paths: input
+                .iter()
+                .enumerate()
+                .map(|(i, stack)| PathStack {
+                    path: i,
+                    // Input stack is parsed in reverse, so the first param is the last(top).
+                    slots: stack
+                        .iter()
+                        .map(|item| StackSlot::Variable(item.identifier.0.to_owned()))
+                        .collect(),
+                    remaining_use: HashMap::new(),
+                })
+                .collect(),
+        };
+        count_block_uses(ast, 0, &mut model.paths);
+
+        model
     }
+
+    // Keep only the spending paths which take the branch.
+    pub fn branch(&self, paths: &std::ops::Range<usize>) -> Self {
+        StackModel {
+            paths: self
+                .paths
+                .iter()
+                .filter(|stack| paths.contains(&stack.path))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn push(&mut self, count: usize) {
+        for stack in &mut self.paths {
+            stack
+                .slots
+                .extend(std::iter::repeat_n(StackSlot::Temporary, count));
+        }
+    }
+
+    pub fn pop(&mut self, count: usize) {
+        for stack in &mut self.paths {
+            let len = stack.slots.len().saturating_sub(count);
+            stack.slots.truncate(len);
+        }
+    }
+
+    // Depth(0 = top) of the variable, which must be the same for every spending path.
+    pub fn depth(&self, loc: &Location, id: &Identifier) -> Result<usize, CompileError> {
+        let mut depth: Option<usize> = None;
+        for stack in &self.paths {
+            let position = stack
+                .slots
+                .iter()
+                .rev()
+                .position(|slot| *slot == StackSlot::Variable(id.0.to_owned()));
+            match (position, depth) {
+                (None, _) => {
+                    return Err(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::UndefinedVariable(format!(
+                            "Variable {:?} is not in the input stack of {}th spending path.",
+                            id.0, stack.path
+                        )),
+                    });
+                }
+                (Some(p), Some(d)) if p != d => {
+                    return Err(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::StackMismatch(format!(
+                            "Variable {:?} is at different stack depth across spending paths: {} and {}.",
+                            id.0, d, p
+                        )),
+                    });
+                }
+                (Some(p), _) => depth = Some(p),
+            }
+        }
+
+        depth.ok_or(CompileError {
+            loc: loc.to_owned(),
+            kind: ErrorKind::UndefinedVariable(format!(
+                "Variable {:?} is not in any input stack.",
+                id.0
+            )),
+        })
+    }
+
+    // Count down the reference, and whether it was the last one in every path.
+    pub fn use_variable(&mut self, id: &Identifier) -> bool {
+        let mut is_last = true;
+        for stack in &mut self.paths {
+            let remaining = stack.remaining_use.entry(id.0.to_owned()).or_insert(0);
+            *remaining = remaining.saturating_sub(1);
+            is_last = is_last && *remaining == 0;
+        }
+        is_last
+    }
+
+    // Whether the next reference is the last one in every path.
+    pub fn is_last_use(&self, id: &Identifier) -> bool {
+        self.paths
+            .iter()
+            .all(|stack| stack.remaining_use.get(&id.0).copied().unwrap_or(0) <= 1)
+    }
+
+    // Signatures already on top in the given order(1st on top), each referenced for the last time.
+    pub fn in_place(&self, ids: &[Identifier]) -> bool {
+        !self.paths.is_empty()
+            && self.paths.iter().all(|stack| {
+                stack.slots.len() >= ids.len()
+                    && stack.slots.iter().rev().zip(ids).all(|(slot, id)| {
+                        *slot == StackSlot::Variable(id.0.to_owned())
+                            && stack.remaining_use.get(&id.0) == Some(&1)
+                    })
+            })
+    }
+
+    // Name the computed value on top, e.g. "let h = sha256 preimage;".
+    pub fn bind(&mut self, id: &Identifier) {
+        for stack in &mut self.paths {
+            if let Some(slot) = stack.slots.last_mut() {
+                *slot = StackSlot::Variable(id.0.to_owned());
+            }
+        }
+    }
+
+    pub fn roll(&mut self, depth: usize) {
+        for stack in &mut self.paths {
+            let index = stack.slots.len() - 1 - depth;
+            stack.slots.remove(index);
+            stack.slots.push(StackSlot::Temporary);
+        }
+    }
+}
+
+// Number of spending paths(leaves of if/else tree) of the block.
+// Same as the branch counter of analyzer, which matches input stacks in order:
+// every else block checks out a new input stack.
+pub fn count_leaves(block: &[Statement]) -> usize {
+    1 + block
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => count_leaves(if_block) - 1 + else_block.as_ref().map_or(0, |b| count_leaves(b)),
+            Statement::MatchStatement { arms, .. } => {
+                arms.iter()
+                    .map(|arm| count_leaves(&arm.block))
+                    .sum::<usize>()
+                    - 1
+            }
+            _ => 0,
+        })
+        .sum::<usize>()
+}
+
+// Count references of each variable along every spending path.
+pub fn count_block_uses(block: &[Statement], first_leaf: usize, paths: &mut [PathStack]) {
+    let leaves = first_leaf..first_leaf + count_leaves(block);
+    let mut offset = first_leaf;
+    for stmt in block {
+        let mut ids: Vec<Identifier> = vec![];
+        match stmt {
+            Statement::VerifyStatement(_, expr)
+            | Statement::ExpressionStatement(_, _, expr)
+            | Statement::LetStatement { expr, .. } => {
+                collect_variables(expr, &mut ids);
+            }
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_variables(condition_expr, &mut ids);
+                count_block_uses(if_block, offset, paths);
+                offset += count_leaves(if_block) - 1;
+                if let Some(else_block) = else_block {
+                    count_block_uses(else_block, offset + 1, paths);
+                    offset += count_leaves(else_block);
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                collect_variables(selector, &mut ids);
+                let mut arm_leaf = offset;
+                for arm in arms {
+                    count_block_uses(&arm.block, arm_leaf, paths);
+                    arm_leaf += count_leaves(&arm.block);
+                }
+                offset = arm_leaf - 1;
+            }
+            Statement::LocktimeStatement { .. } => (),
+        }
+        for stack in paths
+            .iter_mut()
+            .filter(|stack| leaves.contains(&stack.path))
+        {
+            for id in &ids {
+                *stack.remaining_use.entry(id.0.to_owned()).or_insert(0) += 1;
+            }
+        }
+    }
+}
+
+// Variables referenced in the expression, in order of evaluation.
+pub fn collect_variables(expr: &Expression, ids: &mut Vec<Identifier>) {
+    match expr {
+        Expression::Variable(_, id) => ids.push(id.to_owned()),
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            collect_variables(lhs, ids);
+            collect_variables(rhs, ids);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            collect_variables(operand, ids);
+            collect_variables(lower, ids);
+            collect_variables(upper, ids);
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => collect_variables(operand, ids),
+        Expression::CheckSigExpression { operand, .. } => collect_factor_variables(operand, ids),
+        Expression::NumberLiteral(..)
+        | Expression::BooleanLiteral(..)
+        | Expression::StringLiteral(..)
+        | Expression::BytesLiteral(..)
+        | Expression::PubKeyLiteral(..) => (),
+    }
+}
+
+pub fn collect_factor_variables(factor: &Factor, ids: &mut Vec<Identifier>) {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            collect_variables(sig, ids);
+            collect_variables(pubkey, ids);
+        }
+        Factor::MultiSigFactor { n, .. } => {
+            for factor in n {
+                collect_factor_variables(factor, ids);
+            }
+        }
+    }
+}
+
+/*
+    Spending path split
+    - Each spending path keeps only the branches it takes.
+    - Condition of taken branch is verified instead, so the input stack stays the same.
+*/
+
+// Statements executed by every spending path, in order of input stack.
+pub fn path_leaves(ast: &[Statement]) -> Vec<Vec<Statement>> {
+    (0..count_leaves(ast))
+        .map(|leaf| specialize_block(ast, 0, leaf))
+        .collect()
+}
+
+pub fn specialize_block(block: &[Statement], first_leaf: usize, leaf: usize) -> Vec<Statement> {
+    let mut specialized: Vec<Statement> = vec![];
+    let mut offset = first_leaf;
+    for stmt in block {
+        match stmt {
+            Statement::IfStatement {
+                loc,
+                condition_expr,
+                if_block,
+                else_block: Some(else_block),
+            } => {
+                let if_paths = offset..offset + count_leaves(if_block);
+                let else_paths = if_paths.end..if_paths.end + count_leaves(else_block);
+                if if_paths.contains(&leaf) {
+                    // verify condition
+                    specialized.push(Statement::VerifyStatement(
+                        loc.to_owned(),
+                        condition_expr.to_owned(),
+                    ));
+                    specialized.extend(specialize_block(if_block, if_paths.start, leaf));
+                } else if else_paths.contains(&leaf) {
+                    // verify !condition
+                    specialized.push(Statement::VerifyStatement(
+                        loc.to_owned(),
+                        Expression::UnaryMathExpression {
+                            loc: loc.to_owned(),
+                            operand: Box::new(condition_expr.to_owned()),
+                            op: UnaryMathOp::Not,
+                        },
+                    ));
+                    specialized.extend(specialize_block(else_block, else_paths.start, leaf));
+                } else {
+                    specialized.push(stmt.to_owned());
+                }
+                offset = else_paths.end - 1;
+            }
+            Statement::MatchStatement {
+                loc,
+                selector,
+                arms,
+            } => {
+                let mut arm_leaf = offset;
+                let mut taken = None;
+                for arm in arms {
+                    let arm_paths = arm_leaf..arm_leaf + count_leaves(&arm.block);
+                    if arm_paths.contains(&leaf) {
+                        taken = Some((arm, arm_leaf));
+                    }
+                    arm_leaf = arm_paths.end;
+                }
+                match taken {
+                    Some((arm, first_leaf)) => {
+                        // verify selector == value
+                        specialized.push(Statement::VerifyStatement(
+                            loc.to_owned(),
+                            Expression::CompareExpression {
+                                loc: loc.to_owned(),
+                                lhs: Box::new(selector.to_owned()),
+                                op: BinaryCompareOp::NumEqual,
+                                rhs: Box::new(Expression::NumberLiteral(
+                                    arm.loc.to_owned(),
+                                    arm.value,
+                                )),
+                            },
+                        ));
+                        specialized.extend(specialize_block(&arm.block, first_leaf, leaf));
+                    }
+                    None => specialized.push(stmt.to_owned()),
+                }
+                offset = arm_leaf - 1;
+            }
+            _ => {
+                offset += count_leaves(std::slice::from_ref(stmt)) - 1;
+                specialized.push(stmt.to_owned());
+            }
+        }
+    }
+
+    specialized
+}
+
+/*
+    Tapleaf of a spending path
+    - Control block already commits to the leaf, so its branch selectors need no check.
+    - Bool item only verified as a selector is dropped from both the script and the input stack.
+*/
+pub fn tapleaf_path(
+    ast: &[Statement],
+    stack: &[StackParam],
+    leaf: usize,
+) -> (Vec<Statement>, Vec<StackParam>) {
+    let statements = specialize_block(ast, 0, leaf);
+    let mut uses = [PathStack {
+        path: 0,
+        slots: vec![],
+        remaining_use: HashMap::new(),
+    }];
+    count_block_uses(&statements, 0, &mut uses);
+
+    let mut selectors: HashMap<String, usize> = HashMap::new();
+    for id in statements.iter().filter_map(selector_item) {
+        *selectors.entry(id.0.to_owned()).or_insert(0) += 1;
+    }
+    let dropped: Vec<String> = selectors
+        .into_iter()
+        .filter(|(id, count)| {
+            stack
+                .iter()
+                .any(|item| item.identifier.0 == *id && item.ty == Type::Boolean)
+                && uses[0].remaining_use.get(id) == Some(count)
+        })
+        .map(|(id, _)| id)
+        .collect();
+
+    (
+        statements
+            .into_iter()
+            .filter(|stmt| selector_item(stmt).is_none_or(|id| !dropped.contains(&id.0)))
+            .collect(),
+        stack
+            .iter()
+            .filter(|item| !dropped.contains(&item.identifier.0))
+            .cloned()
+            .collect(),
+    )
+}
+
+// Item of "verify flag;" or "verify !flag;", as a taken branch is verified.
+fn selector_item(stmt: &Statement) -> Option<&Identifier> {
+    match stmt {
+        Statement::VerifyStatement(_, Expression::Variable(_, id)) => Some(id),
+        Statement::VerifyStatement(
+            _,
+            Expression::UnaryMathExpression {
+                operand,
+                op: UnaryMathOp::Not,
+                ..
+            },
+        ) => match &**operand {
+            Expression::Variable(_, id) => Some(id),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn compile(
+    ast: Vec<Statement>,
+    input: &[Vec<StackParam>],
+    target: &Target,
+) -> Result<Vec<u8>, CompileError> {
+    let mut bitcoin_script: Vec<u8> = Vec::new();
+    let mut model = StackModel::new(&ast, input);
+
+    compile_block(&mut bitcoin_script, ast, 0, target, &mut model)?;
     let optimized_script = opcode_optimizer(bitcoin_script);
 
-    return optimized_script;
+    Ok(optimized_script)
 }
 
-pub fn compile_statement(bitcoin_script: &mut Vec<u8>, stmt: Statement, target: &Target) {
+// Compile statements of a block, where first_leaf is the index of its first spending path.
+pub fn compile_block(
+    bitcoin_script: &mut Vec<u8>,
+    block: Vec<Statement>,
+    first_leaf: usize,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
+    // Block returning a value is the end of spending path.
+    let returned = match block.last() {
+        Some(Statement::ExpressionStatement(loc, ..)) => Some(loc.to_owned()),
+        _ => None,
+    };
+
+    let mut offset = first_leaf;
+    for stmt in block {
+        let leaves = count_leaves(std::slice::from_ref(&stmt));
+        compile_statement(bitcoin_script, stmt, offset, target, model)?;
+        offset += leaves - 1;
+    }
+
+    // Leave only the returned value on stack.
+    if let Some(loc) = returned {
+        let leftover = model
+            .paths
+            .iter()
+            .map(|stack| stack.slots.len().saturating_sub(1))
+            .collect::<Vec<usize>>();
+        if leftover.windows(2).any(|w| w[0] != w[1]) {
+            return Err(CompileError {
+                loc,
+                kind: ErrorKind::StackMismatch(format!(
+                    "Spending paths sharing a return leave different number of stack items: {:?}.",
+                    leftover
+                )),
+            });
+        }
+        for _ in 0..leftover.first().copied().unwrap_or(0) {
+            push_nip(bitcoin_script);
+            model.pop(2);
+            model.push(1);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn compile_statement(
+    bitcoin_script: &mut Vec<u8>,
+    stmt: Statement,
+    first_leaf: usize,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
     match stmt {
         Statement::LocktimeStatement {
-            loc: _,
+            loc,
             operand,
             op,
+            unit,
         } => {
-            push_locktime(bitcoin_script, operand, op);
+            let Expression::NumberLiteral(_, operand) = operand else {
+                return Err(CompileError {
+                    loc,
+                    kind: ErrorKind::InvalidOperation(format!(
+                        "Locktime must be a number but: {:?}.",
+                        operand
+                    )),
+                });
+            };
+            let value = locktime_value(&op, &unit, operand).map_err(|msg| CompileError {
+                loc,
+                kind: ErrorKind::IntegerOverflow(msg),
+            })?;
+            push_locktime(bitcoin_script, value, op);
         }
         Statement::VerifyStatement(_loc, condition_expr) => {
             // compile expression first
-            compile_expression(bitcoin_script, condition_expr, target);
+            compile_expression(bitcoin_script, condition_expr, target, model)?;
             // push verify at last
             push_control_verify(bitcoin_script);
+            model.pop(1);
         }
         Statement::IfStatement {
-            loc: _,
+            loc,
             condition_expr,
             if_block,
             else_block,
         } => {
             // compile expression first
-            compile_expression(bitcoin_script, condition_expr, target);
+            compile_expression(bitcoin_script, condition_expr, target, model)?;
             push_control_if(bitcoin_script);
+            model.pop(1);
+
+            // Each block is reachable only by its own spending paths.
+            let if_paths = first_leaf..first_leaf + count_leaves(&if_block);
+            let mut if_model = model.branch(&if_paths);
             // recursive to compile expression inside if block
-            for if_stmt in if_block {
-                compile_statement(bitcoin_script, if_stmt, target);
-            }
-            if else_block.is_some() {
-                push_control_else(bitcoin_script);
-                // recursive to compile expression inside else block
-                for else_stmt in else_block.unwrap() {
-                    compile_statement(bitcoin_script, else_stmt, target);
+            compile_block(bitcoin_script, if_block, first_leaf, target, &mut if_model)?;
+            match else_block {
+                Some(else_block) => {
+                    let else_paths = if_paths.end..if_paths.end + count_leaves(&else_block);
+                    let mut else_model = model.branch(&else_paths);
+                    push_control_else(bitcoin_script);
+                    // recursive to compile expression inside else block
+                    compile_block(
+                        bitcoin_script,
+                        else_block,
+                        else_paths.start,
+                        target,
+                        &mut else_model,
+                    )?;
+                    model.paths.retain(|stack| {
+                        !if_paths.contains(&stack.path) && !else_paths.contains(&stack.path)
+                    });
+                    model.paths.extend(if_model.paths);
+                    model.paths.extend(else_model.paths);
+                    model.paths.sort_by_key(|stack| stack.path);
+                }
+                None => {
+                    // Not taking the branch must leave the same stack.
+                    let unchanged = model.branch(&if_paths);
+                    if if_model
+                        .paths
+                        .iter()
+                        .zip(unchanged.paths.iter())
+                        .any(|(taken, skipped)| taken.slots != skipped.slots)
+                    {
+                        return Err(CompileError {
+                            loc,
+                            kind: ErrorKind::StackMismatch(
+                                "If block without else block must not consume stack items."
+                                    .to_string(),
+                            ),
+                        });
+                    }
+                    model.paths.retain(|stack| !if_paths.contains(&stack.path));
+                    model.paths.extend(if_model.paths);
+                    model.paths.sort_by_key(|stack| stack.path);
                 }
             }
             push_control_end(bitcoin_script);
         }
-        Statement::ExpressionStatement(_loc, expr) => {
-            compile_expression(bitcoin_script, expr, target);
+        Statement::MatchStatement { selector, arms, .. } => {
+            compile_expression(bitcoin_script, selector, target, model)?;
+
+            // Each arm but the last checks a copy of selector, and drops it when taken.
+            // Last arm verifies selector, so any other value fails.
+            let arm_count = arms.len();
+            let mut arm_leaf = first_leaf;
+            let mut arm_models: Vec<StackModel> = vec![];
+            for (i, arm) in arms.into_iter().enumerate() {
+                let arm_paths = arm_leaf..arm_leaf + count_leaves(&arm.block);
+                let mut arm_model = model.branch(&arm_paths);
+                if i + 1 < arm_count {
+                    push_pick(bitcoin_script, 0);
+                    push_selector_check(bitcoin_script, arm.value);
+                    push_control_if(bitcoin_script);
+                    push_drop(bitcoin_script);
+                } else {
+                    push_selector_check(bitcoin_script, arm.value);
+                    push_control_verify(bitcoin_script);
+                }
+                arm_model.pop(1);
+                compile_block(
+                    bitcoin_script,
+                    arm.block,
+                    arm_paths.start,
+                    target,
+                    &mut arm_model,
+                )?;
+                arm_models.push(arm_model);
+                if i + 1 < arm_count {
+                    push_control_else(bitcoin_script);
+                }
+                arm_leaf = arm_paths.end;
+            }
+            for _ in 1..arm_count {
+                push_control_end(bitcoin_script);
+            }
+
+            let match_paths = first_leaf..arm_leaf;
+            model
+                .paths
+                .retain(|stack| !match_paths.contains(&stack.path));
+            for arm_model in arm_models {
+                model.paths.extend(arm_model.paths);
+            }
+            model.paths.sort_by_key(|stack| stack.path);
+        }
+        Statement::ExpressionStatement(_loc, _, expr) => {
+            compile_expression(bitcoin_script, expr, target, model)?;
+        }
+        Statement::LetStatement {
+            identifier, expr, ..
+        } => {
+            compile_expression(bitcoin_script, expr, target, model)?;
+            // Value is kept on stack until its last use, and never referenced is dropped at once.
+            if model.paths.iter().any(|stack| {
+                stack
+                    .remaining_use
+                    .get(&identifier.0)
+                    .is_some_and(|n| *n > 0)
+            }) {
+                model.bind(&identifier);
+            } else {
+                push_drop(bitcoin_script);
+                model.pop(1);
+            }
         }
     }
+
+    Ok(())
 }
 
 // Bitcoin script follows Reverse Polish Notation.
 // Therefore, we should push operands first, then op.
-// The challenge is when we face the identifier, the given inputs.
-pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target: &Target) {
+// The challenge is when we face the identifier, the given inputs:
+// the stack model tells where the item is, so it is picked(or rolled) to the top.
+pub fn compile_expression(
+    bitcoin_script: &mut Vec<u8>,
+    expr: Expression,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
     match expr {
         Expression::CheckSigExpression {
             loc: _,
             operand,
             op: _,
         } => {
-            compile_factor(bitcoin_script, *operand.to_owned(), target);
+            compile_factor(bitcoin_script, *operand.to_owned(), target, model)?;
             match *operand {
                 Factor::SingleSigFactor {
                     loc: _,
@@ -417,6 +1143,8 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
                     pubkey: _,
                 } => {
                     push_checksig(bitcoin_script, CheckSigType::Single);
+                    model.pop(2);
+                    model.push(1);
                 }
                 Factor::MultiSigFactor { loc: _, m: _, n: _ } => {
                     match *target {
@@ -424,6 +1152,8 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
                             // Final Key OP_NUMEQUAL
                             // OP_NUMEQUAL
                             push_compare(bitcoin_script, BinaryCompareOp::NumEqual);
+                            model.pop(2);
+                            model.push(1);
                         }
                         // Legacy & Segwit
                         _ => {
@@ -433,14 +1163,10 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
                 }
             }
         }
-        Expression::UnaryCryptoExpression {
-            loc: _,
-            operand,
-            op,
-        } => {
+        Expression::UnaryCryptoExpression { loc, operand, op } => {
             // To do. need to panic for wrong operand for crypto op
-            compile_expression(bitcoin_script, *operand, target);
-            push_crypto_unary(bitcoin_script, op);
+            compile_expression(bitcoin_script, *operand, target, model)?;
+            push_crypto_unary(bitcoin_script, op, &loc)?;
         }
         Expression::LogicalExpression {
             loc: _,
@@ -449,12 +1175,12 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             rhs,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            compile_expression(bitcoin_script, *lhs, target, model)?;
+            compile_expression(bitcoin_script, *rhs, target, model)?;
             // push logical opcode
             push_logical(bitcoin_script, op);
+            model.pop(2);
+            model.push(1);
         }
         Expression::CompareExpression {
             loc: _,
@@ -463,12 +1189,12 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             rhs,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            compile_expression(bitcoin_script, *lhs, target, model)?;
+            compile_expression(bitcoin_script, *rhs, target, model)?;
             // push compare opcode
             push_compare(bitcoin_script, op);
+            model.pop(2);
+            model.push(1);
         }
         Expression::UnaryMathExpression {
             loc: _,
@@ -476,23 +1202,42 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *operand, target);
+            compile_expression(bitcoin_script, *operand, target, model)?;
             // push math unary opcode
             push_math_unary(bitcoin_script, op);
         }
-        Expression::BinaryMathExpression {
-            loc: _,
-            lhs,
-            op,
-            rhs,
-        } => {
+        Expression::BinaryMathExpression { loc, lhs, op, rhs } => {
+            if op == BinaryMathOp::Mul {
+                return Err(CompileError {
+                    loc,
+                    kind: ErrorKind::InvalidOperation(
+                        "Multiplication is only for constants, as OP_MUL is disabled.".to_string(),
+                    ),
+                });
+            }
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            compile_expression(bitcoin_script, *lhs, target, model)?;
+            compile_expression(bitcoin_script, *rhs, target, model)?;
             // push math binary opcode
             push_math_binary(bitcoin_script, op);
+            model.pop(2);
+            model.push(1);
+        }
+        Expression::TernaryMathExpression {
+            loc: _,
+            operand,
+            lower,
+            upper,
+            op,
+        } => {
+            // recursive to compile x, min and max in order of OP_WITHIN
+            compile_expression(bitcoin_script, *operand, target, model)?;
+            compile_expression(bitcoin_script, *lower, target, model)?;
+            compile_expression(bitcoin_script, *upper, target, model)?;
+            // push math ternary opcode
+            push_math_ternary(bitcoin_script, op);
+            model.pop(3);
+            model.push(1);
         }
         Expression::ByteExpression {
             loc: _,
@@ -500,54 +1245,110 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op: _,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *operand, target);
+            compile_expression(bitcoin_script, *operand, target, model)?;
             // push byte opcode
             push_bytes_len(bitcoin_script);
         }
-        Expression::StringLiteral(_loc, data) => {
-            push_bytes(bitcoin_script, data);
+        Expression::StringLiteral(loc, data) => {
+            push_bytes(bitcoin_script, &string_bytes(&data), &loc)?;
+            model.push(1);
+        }
+        // Hex is checked by analyzer.
+        Expression::BytesLiteral(loc, data) | Expression::PubKeyLiteral(loc, data) => {
+            let bytes = hex::decode(&data).map_err(|_| CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::InvalidLiteral(format!("Literal must be hex but: {:?}.", data)),
+            })?;
+            push_bytes(bitcoin_script, &bytes, &loc)?;
+            model.push(1);
         }
         Expression::BooleanLiteral(_loc, data) => {
             push_int(bitcoin_script, data.into());
+            model.push(1);
         }
         Expression::NumberLiteral(_loc, data) => {
             push_int(bitcoin_script, data);
+            model.push(1);
+        }
+        Expression::Variable(loc, id) => {
+            compile_variable(bitcoin_script, &loc, &id, model)?;
         }
-        _ => (),
     }
+
+    Ok(())
 }
 
-pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Target) {
+// Bring the witness item to the top.
+// Last reference moves the item, otherwise it is copied for later use.
+pub fn compile_variable(
+    bitcoin_script: &mut Vec<u8>,
+    loc: &Location,
+    id: &Identifier,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
+    let depth = model.depth(loc, id)?;
+    if model.use_variable(id) {
+        push_roll(bitcoin_script, depth);
+        model.roll(depth);
+    } else {
+        push_pick(bitcoin_script, depth);
+        model.push(1);
+    }
+
+    Ok(())
+}
+
+pub fn compile_factor(
+    bitcoin_script: &mut Vec<u8>,
+    factor: Factor,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
     match factor {
         Factor::SingleSigFactor {
             loc: _,
             sig,
             pubkey,
-        } => {
-            // For sig, no need to push any but check the type(must be from stack)
-            match *sig {
-                Expression::Variable(..) => (),
-                _ => {
-                    panic!("Signature should be from argument(stack).")
-                }
+        } => match (&*sig, &*pubkey) {
+            // Key from witness right above its signature, e.g. <sig> <pubkey>, nothing to move.
+            (Expression::Variable(_, sig_id), Expression::Variable(_, key_id))
+                if model.in_place(&[key_id.to_owned(), sig_id.to_owned()]) =>
+            {
+                model.use_variable(key_id);
+                model.use_variable(sig_id);
+                model.pop(2);
+                model.push(2);
             }
-            match *pubkey {
-                Expression::StringLiteral(..) => {
-                    compile_expression(bitcoin_script, *pubkey, target);
-                }
-                _ => {
-                    // Could be changed to embrace variable later
-                    panic!("Public key should be from string literal.")
-                }
+            _ => {
+                compile_signature(bitcoin_script, *sig, target, model)?;
+                compile_pubkey(bitcoin_script, *pubkey, target, model)?;
             }
-        }
-        Factor::MultiSigFactor { loc, m, n } => {
+        },
+        Factor::MultiSigFactor { loc: _, m, n } => {
             match target {
                 Target::Taproot => {
-                    // push pubkey
-                    for (i, e) in n.iter().enumerate() {
-                        let data = e.to_owned();
-                        compile_factor(bitcoin_script, data, target);
+                    for (i, e) in n.into_iter().enumerate() {
+                        if let Factor::SingleSigFactor { sig, pubkey, .. } = e {
+                            match (i, &*sig) {
+                                // OP_CHECKSIGADD takes <sig> <n> <pubkey>,
+                                // nothing to move if the signature is right below the sum.
+                                (1.., Expression::Variable(loc, id))
+                                    if model.depth(loc, id)? == 1 && model.is_last_use(id) =>
+                                {
+                                    model.use_variable(id);
+                                    model.roll(1);
+                                }
+                                _ => {
+                                    // Signature is brought to the top
+                                    compile_signature(bitcoin_script, *sig, target, model)?;
+                                    // Keep the sum on top.
+                                    if i != 0 {
+                                        push_swap(bitcoin_script);
+                                    }
+                                }
+                            }
+                            compile_pubkey(bitcoin_script, *pubkey, target, model)?;
+                        }
                         push_checksig(
                             bitcoin_script,
                             // 1st key pushes OP_CHECKSIG
@@ -557,14 +1358,51 @@ pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Tar
                                 // Other push OP_CHECKSIGADD
                                 CheckSigType::Add
                             },
-                        )
+                        );
+                        model.pop(if i == 0 { 2 } else { 3 });
+                        model.push(1);
                     }
                     // push m
                     push_int(bitcoin_script, m as i64);
+                    model.push(1);
                 }
                 // Legacy & Segwit
                 _ => {
-                    let num = n.len() as i64;
+                    let num = n.len();
+                    // OP_CHECKMULTISIG pops one more item than signatures(NULLDUMMY).
+                    // If all signatures are required, push the dummy here.
+                    if num == m as usize {
+                        push_int(bitcoin_script, 0);
+                        model.push(1);
+                    }
+                    // Signatures must be in the same order of pubkeys,
+                    // the 1st signature on the top: <dummy> <sig_m> ... <sig_1>
+                    let sigs = n
+                        .iter()
+                        .filter_map(|e| match e {
+                            Factor::SingleSigFactor { sig, .. } => Some(*sig.to_owned()),
+                            _ => None,
+                        })
+                        .collect::<Vec<Expression>>();
+                    let ids = sigs
+                        .iter()
+                        .filter_map(|sig| match sig {
+                            Expression::Variable(_, id) => Some(id.to_owned()),
+                            _ => None,
+                        })
+                        .collect::<Vec<Identifier>>();
+                    if num != m as usize && ids.len() == num && model.in_place(&ids) {
+                        // Already in order, nothing to move.
+                        for id in &ids {
+                            model.use_variable(id);
+                        }
+                        model.pop(num);
+                        model.push(num);
+                    } else {
+                        for sig in sigs.into_iter().rev() {
+                            compile_signature(bitcoin_script, sig, target, model)?;
+                        }
+                    }
                     // push m
                     push_int(bitcoin_script, m as i64);
                     // push pubkey in reverse order.
@@ -572,15 +1410,62 @@ pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Tar
                     // and expression is checksig [m, (sig1, pub1), ..., (sig_n, pub_n)]
                     // OP_CHECKMULTISIG requires the sig list in the same order of pubkeys.
                     for e in n.iter().rev() {
-                        let data = e.to_owned();
-                        compile_factor(bitcoin_script, data, target);
+                        if let Factor::SingleSigFactor { pubkey, .. } = e {
+                            compile_pubkey(bitcoin_script, *pubkey.to_owned(), target, model)?;
+                        }
                     }
                     // push n
-                    push_int(bitcoin_script, num);
+                    push_int(bitcoin_script, num as i64);
+                    // <dummy> <sig>*m <m> <pubkey>*n <n> => <result>
+                    // pubkeys are already in the model, m and n are not.
+                    model.pop(num + m as usize + 1);
+                    model.push(1);
                 }
             }
         }
     }
+
+    Ok(())
+}
+
+// Signature must be from stack
+pub fn compile_signature(
+    bitcoin_script: &mut Vec<u8>,
+    sig: Expression,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
+    match sig {
+        Expression::Variable(..) => compile_expression(bitcoin_script, sig, target, model),
+        _ => Err(CompileError {
+            loc: sig.to_owned().loc(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "Signature must be from arguments but: {:?}.",
+                sig
+            )),
+        }),
+    }
+}
+
+pub fn compile_pubkey(
+    bitcoin_script: &mut Vec<u8>,
+    pubkey: Expression,
+    target: &Target,
+    model: &mut StackModel,
+) -> Result<(), CompileError> {
+    match pubkey {
+        // Key typed item is brought from witness like any other item.
+        Expression::StringLiteral(..)
+        | Expression::PubKeyLiteral(..)
+        | Expression::Variable(..) => compile_expression(bitcoin_script, pubkey, target, model),
+        _ => Err(CompileError {
+            loc: pubkey.to_owned().loc(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "Public Key must be from string or pubkey literal, or item of pubkey or xonly but: {:?}.",
+                pubkey
+            )),
+        }),
+    }
 }
 
 // From compiled opcodes, optimize opcodes.
@@ -598,39 +1483,35 @@ pub fn opcode_optimizer(bitcoin_script: Vec<u8>) -> Vec<u8> {
             break;
         }
 
-        // Skip OP_PUSHBYTES_N
-        if op <= 0x4b {
-            optimized_script.extend_from_slice(&bitcoin_script[i..=i + op as usize]);
-            i += (op + 1) as usize;
+        // Skip OP_PUSHBYTES_N and OP_PUSHDATAN, whose length is unsigned little-endian.
+        let size_len = match bitcoin::Opcode::from(op) {
+            _ if op <= 0x4b => Some(0),
+            OP_PUSHDATA1 => Some(1),
+            OP_PUSHDATA2 => Some(2),
+            OP_PUSHDATA4 => Some(4),
+            _ => None,
+        };
+        if let Some(size_len) = size_len {
+            let data_len = match size_len {
+                0 => Some(op as usize),
+                _ => bitcoin_script.get(i + 1..i + 1 + size_len).map(|size| {
+                    size.iter()
+                        .rev()
+                        .fold(0, |len, byte| (len << 8) | *byte as usize)
+                }),
+            };
+            // Truncated push is kept as it is.
+            let end = data_len
+                .map_or(bitcoin_script.len(), |data_len| {
+                    (i + 1 + size_len).saturating_add(data_len)
+                })
+                .min(bitcoin_script.len());
+            optimized_script.extend_from_slice(&bitcoin_script[i..end]);
+            i = end;
             continue;
         }
 
         match bitcoin::Opcode::from(op) {
-            // Skip OP_PUSHDATAN
-            OP_PUSHDATA1 => {
-                let num_to_read = bitcoin::script::read_scriptint(&[bitcoin_script[i + 1]])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 1) as usize]);
-                i += (num_to_read + 1 + 1) as usize;
-                continue;
-            }
-            OP_PUSHDATA2 => {
-                let num_to_read = bitcoin::script::read_scriptint(&bitcoin_script[i + 1..=i + 2])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 2) as usize]);
-                i += (num_to_read + 1 + 2) as usize;
-                continue;
-            }
-            OP_PUSHDATA4 => {
-                let num_to_read = bitcoin::script::read_scriptint(&bitcoin_script[i + 1..=i + 4])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 4) as usize]);
-                i += (num_to_read + 1 + 4) as usize;
-                continue;
-            }
             // else try optimizing
             OP_EQUAL => {
                 let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
@@ -677,6 +1558,15 @@ pub fn opcode_optimizer(bitcoin_script: Vec<u8>) -> Vec<u8> {
                     continue;
                 }
             }
+            // Swapping the copy with its original changes nothing.
+            OP_DUP => {
+                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
+                if next == OP_SWAP {
+                    optimized_script.push(OP_DUP.to_u8());
+                    i += 2;
+                    continue;
+                }
+            }
             //OP_NOT => {},
             _ => {}
         }
@@ -0,0 +1,563 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::compile::push_crypto_unary;
+    use crate::compile_program;
+    use crate::test_util::{PUBKEY_A, PUBKEY_B};
+    use crate::testing::run_tests;
+
+    fn compile_asm(source: &str) -> String {
+        compile_program(source.to_string())
+            .expect("Compile failed on valid input")
+            .asm()
+    }
+
+    fn compile_err(source: &str) -> ErrorKind {
+        compile_program(source.to_string())
+            .expect_err("Compile unexpectedly succeeded")
+            .kind
+    }
+
+    #[test]
+    fn test_strict_order_emits_no_stack_manipulation() {
+        // Every item is already on top when referenced.
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (preimage: string, sig: signature)
+            {{
+                verify sha256 preimage == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert!(!asm.contains("OP_TOALTSTACK"));
+        assert!(!asm.contains("OP_PICK") && !asm.contains("OP_ROLL"));
+        assert!(!asm.contains("OP_SWAP") && !asm.contains("OP_ROT"));
+        assert!(asm.starts_with("OP_SHA256"));
+    }
+
+    #[test]
+    fn test_relaxed_any_order_and_reuse() {
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven stack relaxed;
+            (a: number, b: number, c: number, sig: signature)
+            {{
+                verify c + a == 5;
+                verify a < b;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        // c is moved over a and b, a is copied for the later reference.
+        assert!(asm.starts_with(
+            "OP_ROT OP_OVER OP_ADD OP_PUSHNUM_5 OP_EQUALVERIFY OP_SWAP OP_LESSTHAN OP_VERIFY"
+        ));
+    }
+
+    #[test]
+    fn test_deep_reference_uses_pick_and_roll() {
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven stack relaxed;
+            (a: number, b: number, c: number, d: number, sig: signature)
+            {{
+                verify d == 4;
+                verify d + a == 5;
+                verify b == c;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert!(asm.starts_with("OP_PUSHNUM_3 OP_PICK"));
+        assert!(asm.contains("OP_PUSHNUM_3 OP_ROLL"));
+    }
+
+    #[test]
+    fn test_leftover_items_are_removed() {
+        // 1-of-3 consumes one signature and one dummy, the other item below the result is dropped.
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (sig_a: signature, sig_b: signature, sig_c: signature)
+            {{
+                return checksig [1, (sig_a, "{}"), (sig_b, "{}"), (sig_c, "{}")];
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B, PUBKEY_A
+        ));
+        assert!(asm.starts_with("OP_PUSHNUM_1 OP_PUSHBYTES_33"));
+        assert!(asm.ends_with("OP_CHECKMULTISIG OP_NIP"));
+    }
+
+    #[test]
+    fn test_taproot_multisig_keeps_sum_on_top() {
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target taproot;
+            (sig_a: signature, sig_b: signature)
+            {{
+                return checksig [2, (sig_a, "{}"), (sig_b, "{}")];
+            }}
+            "#,
+            &PUBKEY_A[2..],
+            &PUBKEY_B[2..]
+        ));
+        // sig_b is already below the sum of the 1st checksig.
+        assert!(!asm.contains("OP_SWAP"));
+        assert!(asm.ends_with("OP_CHECKSIGADD OP_PUSHNUM_2 OP_NUMEQUAL"));
+    }
+
+    #[test]
+    fn test_invalid_pragma_option() {
+        let kind = compile_err(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven stack loose;
+            (sig: signature)
+            {{
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert!(matches!(kind, ErrorKind::InvalidPragma(_)));
+    }
+
+    #[test]
+    fn test_stack_mismatch_across_paths() {
+        // x is at different depth for the two paths sharing the verify.
+        let kind = compile_err(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven stack relaxed;
+            (flag: bool, x: number, sig_a: signature)
+            (flag: bool, sig_b: signature, x: number)
+            {{
+                verify x == 3;
+                if flag {{
+                    return checksig (sig_a, "{}");
+                }} else {{
+                    return checksig (sig_b, "{}");
+                }}
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B
+        ));
+        assert!(matches!(kind, ErrorKind::StackMismatch(_)));
+    }
+
+    #[test]
+    fn test_typed_literals_push_bytes() {
+        let source = |preimage: &str, digest: &str, pubkey: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (preimage: {}, sig: signature)
+                {{
+                    verify sha256 preimage == {};
+                    return checksig (sig, {});
+                }}
+                "#,
+                preimage, digest, pubkey
+            )
+        };
+        let digest = "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+        let typed = compile_asm(&source(
+            "bytes",
+            &format!("0x{}", digest),
+            &format!("pk\"{}\"", PUBKEY_A),
+        ));
+        assert_eq!(
+            typed,
+            compile_asm(&source(
+                "string",
+                &format!("\"{}\"", digest),
+                &format!("\"{}\"", PUBKEY_A)
+            ))
+        );
+
+        // Bytes literal is never read as text, unlike string.
+        let asm = compile_asm(&source(
+            "bytes",
+            &format!("0x{}", "ab".repeat(32)),
+            &format!("pk\"{}\"", PUBKEY_A),
+        ));
+        assert!(asm.contains(&format!("OP_PUSHBYTES_32 {}", "ab".repeat(32))));
+        let asm = compile_asm(&source(
+            "string",
+            &format!("\"{}\"", "t".repeat(32)),
+            &format!("pk\"{}\"", PUBKEY_A),
+        ));
+        assert!(asm.contains(&format!("OP_PUSHBYTES_32 {}", "74".repeat(32))));
+    }
+
+    #[test]
+    fn test_large_push() {
+        let source = |data: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (data: bytes, sig: signature)
+                {{
+                    verify data == {};
+                    return checksig (sig, "{}");
+                }}
+                "#,
+                data, PUBKEY_A
+            )
+        };
+        // Length of OP_PUSHDATA is unsigned, e.g. 200 is 0xc8.
+        let asm = compile_asm(&source(&format!("0x{}", "ab".repeat(200))));
+        assert!(asm.contains(&format!("OP_PUSHDATA1 {} OP_EQUALVERIFY", "ab".repeat(200))));
+        let asm = compile_asm(&source(&format!("0x{}", "ab".repeat(520))));
+        assert!(asm.contains(&format!("OP_PUSHDATA2 {} OP_EQUALVERIFY", "ab".repeat(520))));
+
+        // Over the limit of a stack element, reported with its location.
+        let result = compile_program(source(&format!("\"{}\"", "t".repeat(521))));
+        assert_eq!(result.clone().unwrap_err().loc.line, 6);
+        assert!(matches!(
+            result.unwrap_err().kind,
+            ErrorKind::ScriptSizeExceeded(_)
+        ));
+    }
+
+    #[test]
+    fn test_let_binding() {
+        // Digest is computed once, and compared on each spending path.
+        let source = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (preimage: string, sig_alice: signature)
+            (preimage: string, sig_bob: signature)
+            {{
+                let digest = sha256 preimage;
+                if digest == "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b" {{
+                    return checksig (sig_alice, "{}");
+                }} else {{
+                    verify digest == "d9298a10d1b0735837dc4bd85dac641b0f3cef27a47e5d53a54f2f3f5b2fcffa";
+                    return checksig (sig_bob, "{}");
+                }}
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B
+        );
+        let asm = compile_asm(&source);
+        assert_eq!(asm.matches("OP_SHA256").count(), 1);
+        // Digest is copied for the condition, and left below the result of first path.
+        assert!(asm.starts_with("OP_SHA256 OP_DUP"));
+        assert!(asm.contains("OP_CHECKSIG OP_NIP OP_ELSE"));
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+            key bob = "{}";
+
+            test "alice" {{
+                path 0;
+                witness preimage = "secret", sig_alice = sig(alice);
+                expect success;
+            }}
+
+            test "bob" {{
+                path 1;
+                witness preimage = "other", sig_bob = sig(bob);
+                expect success;
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B
+        );
+        assert!(run_tests(source.to_string(), &tests).unwrap().passed());
+
+        // Binding never referenced is dropped at once.
+        let asm = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven allow unused_variable;
+            (n: number, sig: signature)
+            {{
+                let unused = n + 1;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert!(asm.starts_with("OP_PUSHNUM_1 OP_ADD OP_DROP OP_PUSHBYTES_33"));
+    }
+
+    #[test]
+    fn test_reused_binding_needs_no_swap() {
+        let source = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (x: string, sig: signature)
+            {{
+                let h = sha256 x;
+                verify h == h;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        );
+        let asm = compile_asm(&source);
+        assert!(
+            asm.starts_with("OP_SHA256 OP_DUP OP_EQUALVERIFY OP_PUSHBYTES_33"),
+            "{}",
+            asm
+        );
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+
+            test "alice" {{
+                witness x = "secret", sig = sig(alice);
+                expect success;
+            }}
+            "#,
+            PUBKEY_A
+        );
+        assert!(run_tests(source, &tests).unwrap().passed());
+    }
+
+    #[test]
+    fn test_match_dispatch() {
+        let source = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (choice: number, sig_alice: signature)
+            (choice: number, sig_bob: signature)
+            (choice: number, preimage: string, sig_carol: signature)
+            {{
+                match choice {{
+                    0 => {{
+                        return checksig (sig_alice, "{}");
+                    }}
+                    1 => {{
+                        older 144;
+                        return checksig (sig_bob, "{}");
+                    }}
+                    2 => {{
+                        verify sha256 preimage == "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b";
+                        return checksig (sig_carol, "{}");
+                    }}
+                }}
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B, PUBKEY_B
+        );
+        // Copy of selector is checked and dropped, and the last arm verifies it.
+        let asm = compile_asm(&source);
+        assert!(asm.starts_with("OP_DUP OP_NOT OP_IF OP_DROP"));
+        assert!(asm.contains("OP_ELSE OP_DUP OP_PUSHNUM_1 OP_NUMEQUAL OP_IF OP_DROP"));
+        assert!(asm.contains("OP_ELSE OP_PUSHNUM_2 OP_NUMEQUALVERIFY OP_SHA256"));
+        assert!(asm.ends_with("OP_CHECKSIG OP_ENDIF OP_ENDIF"));
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+            key bob = "{}";
+
+            test "alice" {{
+                path 0;
+                witness choice = 0, sig_alice = sig(alice);
+                expect success;
+            }}
+
+            test "bob after timeout" {{
+                path 1;
+                witness choice = 1, sig_bob = sig(bob);
+                sequence 144;
+                expect success;
+            }}
+
+            test "bob with preimage" {{
+                path 2;
+                witness choice = 2, preimage = "secret", sig_carol = sig(bob);
+                expect success;
+            }}
+
+            test "no arm" {{
+                path 2;
+                witness choice = 3, preimage = "secret", sig_carol = sig(bob);
+                expect failure;
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B
+        );
+        let report = run_tests(source.to_string(), &tests).unwrap();
+        assert!(report.passed(), "{:?}", report);
+
+        // "else if" compiles as nested if/else.
+        let chain = compile_asm(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (a: bool, sig_alice: signature)
+            (a: bool, b: bool, sig_bob: signature)
+            (a: bool, b: bool, sig_carol: signature)
+            {{
+                if a {{
+                    return checksig (sig_alice, "{}");
+                }} else if b {{
+                    older 144;
+                    return checksig (sig_bob, "{}");
+                }} else {{
+                    return checksig (sig_carol, "{}");
+                }}
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B, PUBKEY_B
+        ));
+        assert!(chain.starts_with("OP_IF OP_PUSHBYTES_33"));
+        assert!(chain.contains("OP_CHECKSIG OP_ELSE OP_IF OP_PUSHBYTES_2 9000 OP_CSV"));
+        assert!(chain.ends_with("OP_CHECKSIG OP_ENDIF OP_ENDIF"));
+    }
+
+    #[test]
+    fn test_hash_builtins() {
+        let source = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (a: string, b: string, c: string, sig: signature)
+            {{
+                verify hash160 a == "d1b64100879ad93ceaa3c15929b6fe8550f54967";
+                verify hash256 b == "3881219d087dd9c634373fd33dfa33a2cb6bfc6c520b64b8bb60ef2ceb534ae7";
+                verify sha1 c == "e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4";
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        );
+        let asm = compile_asm(&source);
+        assert!(asm.starts_with(
+            "OP_HASH160 OP_PUSHBYTES_20 d1b64100879ad93ceaa3c15929b6fe8550f54967 OP_EQUALVERIFY"
+        ));
+        assert!(asm.contains("OP_HASH256 OP_PUSHBYTES_32"));
+        assert!(asm.contains(
+            "OP_SHA1 OP_PUSHBYTES_20 e5e9fa1ba31ecd1ae84f75caaa474f3a663f05f4 OP_EQUALVERIFY"
+        ));
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+
+            test "preimage" {{
+                witness a = "secret", b = "secret", c = "secret", sig = sig(alice);
+                expect success;
+            }}
+
+            test "wrong preimage" {{
+                witness a = "secret", b = "secret", c = "public", sig = sig(alice);
+                expect failure;
+            }}
+            "#,
+            PUBKEY_A
+        );
+        let report = run_tests(source.to_string(), &tests).unwrap();
+        assert!(report.passed(), "{:?}", report);
+
+        // Tagged hash has no opcode, so it is an error rather than a panic if left unfolded.
+        let loc = Location {
+            start: 7,
+            end: 42,
+            line: 3,
+            column: 5,
+        };
+        let mut script = vec![];
+        let err = push_crypto_unary(
+            &mut script,
+            UnaryCryptoOp::TaggedHash("TapLeaf".to_string()),
+            &loc,
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidOperation(_)));
+        assert_eq!(err.loc, loc);
+        assert!(script.is_empty());
+    }
+
+    #[test]
+    fn test_within() {
+        let source = |stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (amount: number, sig: signature)
+                {{
+                    {}
+                    return checksig (sig, "{}");
+                }}
+                "#,
+                stmt, PUBKEY_A
+            )
+        };
+        let within = source("verify within(amount, 1000, 2000);");
+        assert!(compile_asm(&within).starts_with(
+            "OP_PUSHBYTES_2 e803 OP_PUSHBYTES_2 d007 OP_WITHIN OP_VERIFY OP_PUSHBYTES_33"
+        ));
+
+        // Lower bound is inclusive, and upper bound is exclusive.
+        let tests = format!(
+            r#"
+            key alice = "{}";
+
+            test "lower bound" {{
+                witness amount = 1000, sig = sig(alice);
+                expect success;
+            }}
+
+            test "below upper bound" {{
+                witness amount = 1999, sig = sig(alice);
+                expect success;
+            }}
+
+            test "upper bound" {{
+                witness amount = 2000, sig = sig(alice);
+                expect failure;
+            }}
+
+            test "below lower bound" {{
+                witness amount = 999, sig = sig(alice);
+                expect failure;
+            }}
+            "#,
+            PUBKEY_A
+        );
+        let report = run_tests(within, &tests).unwrap();
+        assert!(report.passed(), "{:?}", report);
+
+        assert!(matches!(
+            compile_err(&source("verify within(amount, 1000, 2147483648);")),
+            ErrorKind::IntegerOverflow(_)
+        ));
+        assert!(matches!(
+            compile_err(&source("verify within(amount, 2000, 1000);")),
+            ErrorKind::InvalidOperation(_)
+        ));
+        assert!(matches!(
+            compile_err(&source("verify within(amount, \"00\", 1000);")),
+            ErrorKind::InvalidOperation(_)
+        ));
+    }
+}
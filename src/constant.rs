@@ -0,0 +1,227 @@
+/*
+    Compile-time constants, e.g. "const week: number = 144 * 7;", and constant folding.
+    - Value of constant is folded to a literal of its type, and placed at each use like a
+      template parameter. It can refer to earlier constants, but not to parameters.
+    - Arithmetic on number literals is folded after parameters are bound, e.g. "older 144 * 7;",
+      so the analyzer checks the folded value, e.g. for overflow.
+    - Multiplication has no opcode(OP_MUL is disabled), so it is only for constant operands.
+      So is tagged hash, e.g. tagged_hash("TapLeaf", "..."), which would need OP_CAT.
+*/
+use crate::ast::*;
+use crate::compile::{collect_variables, string_bytes};
+use crate::diagnostic::Diagnostics;
+use crate::format::expression;
+use crate::param::{bind_expression, bind_statement, is_bound};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use std::collections::HashMap;
+
+pub fn bind_constants(utxo: &mut Bithoven) -> Result<(), CompileError> {
+    let mut diagnostics = Diagnostics::default();
+    bind_constants_diagnostics(utxo, &mut diagnostics);
+    diagnostics.into_result()
+}
+
+pub fn bind_constants_diagnostics(utxo: &mut Bithoven, diagnostics: &mut Diagnostics) {
+    let mut declared: Vec<&Identifier> = vec![];
+    let mut literals: HashMap<String, Expression> = HashMap::new();
+    for constant in &utxo.constants {
+        let name = &constant.identifier;
+        let is_declared = declared.contains(&name)
+            || utxo.params.iter().any(|param| param.identifier == *name)
+            || utxo
+                .input_stack
+                .iter()
+                .flatten()
+                .any(|item| item.identifier == *name);
+        if is_declared || is_bound(&utxo.output_script, &name.0) {
+            diagnostics.error(CompileError {
+                loc: constant.loc.to_owned(),
+                kind: ErrorKind::DuplicateVariable(format!(
+                    "The name of constant cannot be duplicate: {:?} already exists.",
+                    name.0
+                )),
+            });
+            continue;
+        }
+        declared.push(name);
+
+        let mut value = constant.value.to_owned();
+        bind_expression(&mut value, &literals);
+        fold_expression(&mut value);
+        match literal(constant, value) {
+            Ok(literal) => {
+                literals.insert(name.0.to_owned(), literal);
+            }
+            Err(e) => diagnostics.error(e),
+        }
+    }
+
+    for stmt in utxo.output_script.iter_mut() {
+        bind_statement(stmt, &literals);
+    }
+}
+
+// Folded value as the literal of declared type.
+// Key and digest can be given as string, as they are in checksig.
+fn literal(constant: &Constant, value: Expression) -> Result<Expression, CompileError> {
+    let loc = constant.loc.to_owned();
+    let hex_len = |data: &str| hex::decode(data).map_or(None, |bytes| Some(bytes.len()));
+    let mut ids: Vec<Identifier> = vec![];
+    collect_variables(&value, &mut ids);
+    if let Some(id) = ids.first() {
+        return Err(CompileError {
+            loc,
+            kind: ErrorKind::UndefinedVariable(format!(
+                "Value of constant {:?} can only refer to earlier constants but: {:?}.",
+                constant.identifier.0, id.0
+            )),
+        });
+    }
+    match (&constant.ty, value) {
+        (Type::Number, value @ Expression::NumberLiteral(..))
+        | (Type::Boolean, value @ Expression::BooleanLiteral(..))
+        | (Type::String, value @ Expression::StringLiteral(..))
+        | (Type::Bytes, value @ Expression::BytesLiteral(..)) => Ok(value),
+        (
+            Type::PubKey | Type::XOnly,
+            Expression::PubKeyLiteral(_, data) | Expression::StringLiteral(_, data),
+        ) if hex_len(&data) == Some(if constant.ty == Type::PubKey { 33 } else { 32 }) => {
+            Ok(Expression::PubKeyLiteral(loc, data.to_lowercase()))
+        }
+        (
+            Type::Hash160 | Type::Sha256 | Type::Hash256,
+            Expression::BytesLiteral(_, data) | Expression::StringLiteral(_, data),
+        ) if hex_len(&data) == Some(if constant.ty == Type::Hash160 { 20 } else { 32 }) => {
+            Ok(Expression::BytesLiteral(loc, data.to_lowercase()))
+        }
+        (ty, value) => Err(CompileError {
+            loc,
+            kind: ErrorKind::TypeMismatch(format!(
+                "Value of constant {:?} must be a literal of {} but: {}.",
+                constant.identifier.0,
+                ty,
+                expression(&value)
+            )),
+        }),
+    }
+}
+
+pub fn fold_constants(block: &mut [Statement]) {
+    for stmt in block.iter_mut() {
+        match stmt {
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                fold_expression(condition_expr);
+                fold_constants(if_block);
+                if let Some(else_block) = else_block {
+                    fold_constants(else_block);
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                fold_expression(selector);
+                for arm in arms.iter_mut() {
+                    fold_constants(&mut arm.block);
+                }
+            }
+            Statement::LocktimeStatement { operand: expr, .. }
+            | Statement::LetStatement { expr, .. }
+            | Statement::VerifyStatement(_, expr)
+            | Statement::ExpressionStatement(_, _, expr) => fold_expression(expr),
+        }
+    }
+}
+
+// Arithmetic on number literals, from the innermost.
+// Out of i64 range is saturated, to be reported as overflow by analyzer.
+pub fn fold_expression(expr: &mut Expression) {
+    match expr {
+        Expression::BinaryMathExpression { loc, lhs, op, rhs } => {
+            fold_expression(lhs);
+            fold_expression(rhs);
+            if let (Expression::NumberLiteral(_, a), Expression::NumberLiteral(_, b)) =
+                (&**lhs, &**rhs)
+            {
+                let value = match op {
+                    BinaryMathOp::Add => a.saturating_add(*b),
+                    BinaryMathOp::Sub => a.saturating_sub(*b),
+                    BinaryMathOp::Mul => a.saturating_mul(*b),
+                    BinaryMathOp::Max => *a.max(b),
+                    BinaryMathOp::Min => *a.min(b),
+                };
+                *expr = Expression::NumberLiteral(loc.to_owned(), value);
+            }
+        }
+        Expression::UnaryMathExpression { loc, operand, op } => {
+            fold_expression(operand);
+            if let Expression::NumberLiteral(_, a) = **operand {
+                *expr = match op {
+                    UnaryMathOp::Add => {
+                        Expression::NumberLiteral(loc.to_owned(), a.saturating_add(1))
+                    }
+                    UnaryMathOp::Sub => {
+                        Expression::NumberLiteral(loc.to_owned(), a.saturating_sub(1))
+                    }
+                    UnaryMathOp::Negate => {
+                        Expression::NumberLiteral(loc.to_owned(), a.saturating_neg())
+                    }
+                    UnaryMathOp::Abs => {
+                        Expression::NumberLiteral(loc.to_owned(), a.saturating_abs())
+                    }
+                    UnaryMathOp::Not => Expression::BooleanLiteral(loc.to_owned(), a == 0),
+                };
+            }
+        }
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. } => {
+            fold_expression(lhs);
+            fold_expression(rhs);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            fold_expression(operand);
+            fold_expression(lower);
+            fold_expression(upper);
+        }
+        Expression::UnaryCryptoExpression { loc, operand, op } => {
+            fold_expression(operand);
+            if let UnaryCryptoOp::TaggedHash(tag) = op {
+                let data = match &**operand {
+                    Expression::StringLiteral(_, data) => string_bytes(data),
+                    Expression::BytesLiteral(_, data) => match hex::decode(data) {
+                        Ok(data) => data,
+                        Err(_) => return,
+                    },
+                    _ => return,
+                };
+                *expr = Expression::BytesLiteral(loc.to_owned(), tagged_hash(tag, &data));
+            }
+        }
+        Expression::ByteExpression { operand, .. } => fold_expression(operand),
+        Expression::CheckSigExpression { .. }
+        | Expression::Variable(..)
+        | Expression::NumberLiteral(..)
+        | Expression::BooleanLiteral(..)
+        | Expression::StringLiteral(..)
+        | Expression::BytesLiteral(..)
+        | Expression::PubKeyLiteral(..) => (),
+    }
+}
+
+// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || data), in hex.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> String {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_ref());
+    engine.input(tag_hash.as_ref());
+    engine.input(data);
+    hex::encode(sha256::Hash::from_engine(engine).to_byte_array())
+}
@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::format::format_source;
+    use crate::test_util::{error_kind, PUBKEY_A};
+    use crate::{compile_program, program_to_policy};
+
+    const DIGEST: &str = "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+
+    fn source(declarations: &str, body: &str) -> String {
+        format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+{}
+(n: number, preimage: string, sig: signature)
+{{
+{}
+}}
+"#,
+            declarations, body
+        )
+    }
+
+    #[test]
+    fn test_constants() {
+        let declarations = format!(
+            "const alice: pubkey = pk\"{}\";\n// Refers to the earlier constant.\nconst week: number = 144 * 7;\nconst timeout: number = week + 1;\nconst digest: hash256 = 0x{};\n",
+            PUBKEY_A, DIGEST
+        );
+        let body = "    older timeout;\n    verify n == timeout;\n    verify sha256 sha256 preimage == digest;\n    return checksig (sig, alice);";
+        let constants = compile_program(source(&declarations, body)).unwrap();
+
+        let literals = compile_program(source(
+            "",
+            &format!(
+                "    older 1009;\n    verify n == 1009;\n    verify sha256 sha256 preimage == 0x{};\n    return checksig (sig, pk\"{}\");",
+                DIGEST, PUBKEY_A
+            ),
+        ))
+        .unwrap();
+        assert_eq!(constants.bytes(), literals.bytes());
+
+        // Formatter keeps constants and parameters in order of declaration.
+        let template = source(
+            &format!("param bob: pubkey;\n{}param limit: number;\n", declarations),
+            body,
+        );
+        assert_eq!(format_source(&template).unwrap(), template);
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let asm = |body: &str| {
+            compile_program(source(
+                "",
+                &format!("    {}\n    return checksig (sig, \"{}\");", body, PUBKEY_A),
+            ))
+            .unwrap()
+            .asm()
+        };
+        // Multiplication binds tighter than addition.
+        assert!(asm("older 1000 + 144 * 7;\n    verify n == len preimage;")
+            .starts_with("OP_PUSHBYTES_2 d807 OP_CSV OP_DROP"));
+        assert!(
+            asm("verify n == (max(2, 3)) * (4 - 1) - negate 1;\n    verify len preimage > 0;")
+                .starts_with("OP_PUSHNUM_10 OP_EQUALVERIFY")
+        );
+        let policy = format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n\n(sig: signature)\n{{\n    older 144 * 7;\n    return checksig (sig, \"{}\");\n}}\n",
+            PUBKEY_A
+        );
+        assert_eq!(
+            program_to_policy(policy, vec![]).unwrap(),
+            format!("and(older(1008),pk({}))", PUBKEY_A)
+        );
+
+        // Folded value is checked for overflow.
+        let body = |stmt: &str| {
+            source(
+                "",
+                &format!(
+                    "    {}\n    verify len preimage > 0;\n    return checksig (sig, \"{}\");",
+                    stmt, PUBKEY_A
+                ),
+            )
+        };
+        assert!(compile_program(body("verify n == 2147483648 - 1;")).is_ok());
+        assert!(matches!(
+            error_kind(compile_program(body("verify n == 65536 * 32768;"))),
+            ErrorKind::IntegerOverflow(_)
+        ));
+        assert!(matches!(
+            error_kind(compile_program(body("older 65535 + 1;\n    verify n > 0;"))),
+            ErrorKind::IntegerOverflow(_)
+        ));
+
+        // No opcode multiplies witness items.
+        assert!(matches!(
+            error_kind(compile_program(body("verify n * 2 == 4;"))),
+            ErrorKind::InvalidOperation(_)
+        ));
+    }
+
+    #[test]
+    fn test_fold_tagged_hash() {
+        // BIP340 tagged hash of literal is folded into its digest.
+        let body = |stmt: &str| {
+            source(
+                "",
+                &format!(
+                    "    verify n > 0;\n    {}\n    return checksig (sig, \"{}\");",
+                    stmt, PUBKEY_A
+                ),
+            )
+        };
+        let asm = compile_program(body(
+            "verify sha256 preimage == tagged_hash(\"TapLeaf\", 0xc0);",
+        ))
+        .unwrap()
+        .asm();
+        assert!(asm.contains(
+            "OP_SHA256 OP_PUSHBYTES_32 e6690d341d969d71ae9f4129b3475382a30aaca362e62a7b9519ee76e84b40db OP_EQUALVERIFY"
+        ));
+        let empty = compile_program(body(
+            "verify sha256 preimage == tagged_hash(\"BIP0340/challenge\", \"\");",
+        ))
+        .unwrap()
+        .asm();
+        assert!(empty.contains("c216d352f5818b7b4beacd4ae0a26fe888080823d2a598856661bcd54f1b3713"));
+
+        // Only a literal can be hashed.
+        assert!(matches!(
+            error_kind(compile_program(body(
+                "verify tagged_hash(\"TapLeaf\", preimage) == 0x00;"
+            ))),
+            ErrorKind::InvalidOperation(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_constants() {
+        let body = "    verify n == k;\n    verify len preimage > 0;\n    return checksig (sig, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");";
+        assert!(compile_program(source("const k: number = 1;\n", body)).is_ok());
+
+        for (declarations, expected) in [
+            (
+                "const n: number = 1;\nconst k: number = 1;\n",
+                "DuplicateVariable",
+            ),
+            (
+                "const k: number = 1;\nconst k: number = 2;\n",
+                "DuplicateVariable",
+            ),
+            (
+                "param k: number;\nconst k: number = 1;\n",
+                "DuplicateVariable",
+            ),
+            ("const k: number = \"1\";\n", "TypeMismatch"),
+            ("const k: hash160 = 0xabcd;\n", "TypeMismatch"),
+            ("const k: signature = 0x00;\n", "TypeMismatch"),
+            (
+                "const k: number = later;\nconst later: number = 1;\n",
+                "UndefinedVariable",
+            ),
+            (
+                "param p: number;\nconst k: number = p + 1;\n",
+                "UndefinedVariable",
+            ),
+        ] {
+            assert_eq!(
+                error_kind(compile_program(source(declarations, body))).name(),
+                expected,
+                "{}",
+                declarations
+            );
+        }
+    }
+}
@@ -0,0 +1,987 @@
+/*
+    Decompiler from Bitcoin Script to bithoven source.
+    - Script is executed symbolically: each stack item is an expression of literals and witness items.
+    - Witness items are pulled from below when the script reaches under its own pushes,
+      so each branch of OP_IF(spending path) gets its own input stack, top first.
+    - Code after OP_ENDIF is lifted into both branches, as bithoven has no statement after if/else.
+    - Opcodes which can't be lifted are kept as comments, with the stack before them.
+*/
+use crate::analyze::nowhere;
+use crate::ast::*;
+use crate::compile::{collect_variables, path_leaves};
+use crate::format::{expression, stack_line, statement, target_name, INDENT};
+use crate::locktime::{locktime_operand, locktime_value};
+use crate::vm::{decode_num, encode_num};
+
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext, Opcode};
+use bitcoin::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::Script;
+
+use std::collections::HashMap;
+
+/// Source decompiled from a script.
+pub struct Decompiled {
+    /// Bithoven source, where opcodes not lifted are left as comments.
+    pub source: String,
+    /// Whether the whole script is lifted, so the source compiles to an equivalent script.
+    pub complete: bool,
+}
+
+/// Decompiles a script. Without target, it is taproot if the script has OP_CHECKSIGADD or
+/// checks a signature against a 32-byte x-only key, otherwise segwit.
+pub fn decompile(script: &[u8], target: Option<Target>) -> Result<Decompiled, CompileError> {
+    let nodes = parse_script(script)?;
+    let target = target.unwrap_or_else(|| {
+        let instructions = Script::from_bytes(script)
+            .instructions()
+            .collect::<Vec<_>>();
+        let tapscript = instructions.windows(2).any(|pair| match pair {
+            [Ok(Instruction::PushBytes(key)), Ok(Instruction::Op(op))] => {
+                key.len() == 32 && (*op == OP_CHECKSIG || *op == OP_CHECKSIGVERIFY)
+            }
+            _ => false,
+        }) || instructions.contains(&Ok(Instruction::Op(OP_CHECKSIGADD)));
+        if tapscript {
+            Target::Taproot
+        } else {
+            Target::Segwit
+        }
+    });
+
+    let mut lifter = Lifter {
+        target,
+        types: HashMap::new(),
+        items: 0,
+        stacks: vec![],
+        complete: true,
+    };
+    let block = lifter.block(&nodes, State::default());
+    Ok(lifter.finish(block))
+}
+
+// Instruction, or if/else block, with its asm to show if not lifted.
+#[derive(Clone, Debug)]
+struct Node {
+    asm: String,
+    kind: NodeKind,
+}
+
+#[derive(Clone, Debug)]
+enum NodeKind {
+    Push(Vec<u8>),
+    Op(Opcode),
+    If {
+        notif: bool,
+        if_block: Vec<Node>,
+        else_block: Option<Vec<Node>>,
+    },
+    // If block with more than one OP_ELSE.
+    Unsupported,
+}
+
+// If block being parsed.
+struct Frame {
+    start: usize,
+    notif: bool,
+    // Set when OP_ELSE is met.
+    if_block: Option<Vec<Node>>,
+    supported: bool,
+}
+
+fn invalid_script(msg: String) -> CompileError {
+    CompileError {
+        loc: nowhere(),
+        kind: ErrorKind::InvalidScript(msg),
+    }
+}
+
+// Instructions nested by OP_IF, OP_ELSE and OP_ENDIF.
+fn parse_script(script: &[u8]) -> Result<Vec<Node>, CompileError> {
+    let mut instructions = vec![];
+    for instruction in Script::from_bytes(script).instruction_indices() {
+        instructions.push(instruction.map_err(|e| invalid_script(format!("{}.", e)))?);
+    }
+
+    let mut blocks: Vec<Vec<Node>> = vec![vec![]];
+    let mut frames: Vec<Frame> = vec![];
+    for (i, (pc, instruction)) in instructions.iter().enumerate() {
+        let end = instructions
+            .get(i + 1)
+            .map_or(script.len(), |(next, _)| *next);
+        let kind = match instruction {
+            Instruction::PushBytes(data) => NodeKind::Push(data.as_bytes().to_vec()),
+            Instruction::Op(op @ (OP_IF | OP_NOTIF)) => {
+                frames.push(Frame {
+                    start: *pc,
+                    notif: *op == OP_NOTIF,
+                    if_block: None,
+                    supported: true,
+                });
+                blocks.push(vec![]);
+                continue;
+            }
+            Instruction::Op(OP_ELSE) => {
+                let Some(frame) = frames.last_mut() else {
+                    return Err(invalid_script(format!("OP_ELSE without OP_IF at {}.", pc)));
+                };
+                let block = blocks.pop().unwrap_or_default();
+                match frame.if_block {
+                    None => frame.if_block = Some(block),
+                    // OP_ELSE toggles the branch again.
+                    Some(_) => frame.supported = false,
+                }
+                blocks.push(vec![]);
+                continue;
+            }
+            Instruction::Op(OP_ENDIF) => {
+                let Some(frame) = frames.pop() else {
+                    return Err(invalid_script(format!("OP_ENDIF without OP_IF at {}.", pc)));
+                };
+                let block = blocks.pop().unwrap_or_default();
+                let kind = match (frame.supported, frame.if_block) {
+                    (false, _) => NodeKind::Unsupported,
+                    (true, Some(if_block)) => NodeKind::If {
+                        notif: frame.notif,
+                        if_block,
+                        else_block: Some(block),
+                    },
+                    (true, None) => NodeKind::If {
+                        notif: frame.notif,
+                        if_block: block,
+                        else_block: None,
+                    },
+                };
+                if let Some(parent) = blocks.last_mut() {
+                    parent.push(Node {
+                        asm: Script::from_bytes(&script[frame.start..end]).to_asm_string(),
+                        kind,
+                    });
+                }
+                continue;
+            }
+            Instruction::Op(op) => NodeKind::Op(*op),
+        };
+        if let Some(block) = blocks.last_mut() {
+            block.push(Node {
+                asm: Script::from_bytes(&script[*pc..end]).to_asm_string(),
+                kind,
+            });
+        }
+    }
+
+    if let Some(frame) = frames.last() {
+        return Err(invalid_script(format!(
+            "OP_IF at {} without OP_ENDIF.",
+            frame.start
+        )));
+    }
+    Ok(blocks.pop().unwrap_or_default())
+}
+
+// Data push is a number if the compiler pushes the number the same way.
+fn literal(data: &[u8]) -> Expression {
+    if let Ok(n) = decode_num(data, 4) {
+        let pushed = Builder::new().push_int(n);
+        if let Ok(bytes) = PushBytesBuf::try_from(data.to_vec()) {
+            if pushed.as_bytes() == Builder::new().push_slice(bytes).as_bytes() {
+                return Expression::NumberLiteral(nowhere(), n);
+            }
+        }
+    }
+    Expression::StringLiteral(nowhere(), hex::encode(data))
+}
+
+// Stack of a spending path being lifted.
+#[derive(Clone, Default)]
+struct State {
+    // Top is the last element.
+    stack: Vec<Expression>,
+    // Witness items pulled so far, top first.
+    params: Vec<String>,
+}
+
+// Lifted code. Unlike AST, it keeps what could not be lifted.
+enum Lifted {
+    Statement(Statement),
+    If {
+        condition: Expression,
+        if_block: Vec<Lifted>,
+        else_block: Vec<Lifted>,
+    },
+    // Opcodes not lifted, and the stack before them.
+    Raw {
+        asm: String,
+        stack: Vec<Expression>,
+    },
+    // Items left under the returned value, which fails the clean stack rule.
+    Leftover(Vec<Expression>),
+}
+
+struct Lifter {
+    target: Target,
+    // Type of witness item, inferred from its first typed use.
+    types: HashMap<String, Type>,
+    // Number of witness items pulled, to name the next one.
+    items: usize,
+    // Input stack of each spending path, in order of paths.
+    stacks: Vec<Vec<String>>,
+    complete: bool,
+}
+
+impl Lifter {
+    fn block(&mut self, nodes: &[Node], mut state: State) -> Vec<Lifted> {
+        let mut lifted: Vec<Lifted> = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            if let NodeKind::If {
+                notif,
+                if_block,
+                else_block,
+            } = &node.kind
+            {
+                let condition = self.pop(&mut state);
+                let mut condition = self.boolean(condition);
+                if *notif {
+                    condition = Expression::UnaryMathExpression {
+                        loc: nowhere(),
+                        operand: Box::new(condition),
+                        op: UnaryMathOp::Not,
+                    };
+                }
+                // Code after OP_ENDIF runs on both branches.
+                let tail = &nodes[i + 1..];
+                let if_nodes = [if_block.as_slice(), tail].concat();
+                let else_nodes = [else_block.as_deref().unwrap_or_default(), tail].concat();
+                let if_block = self.block(&if_nodes, state.clone());
+                let else_block = self.block(&else_nodes, state);
+                lifted.push(Lifted::If {
+                    condition,
+                    if_block,
+                    else_block,
+                });
+                return lifted;
+            }
+
+            let before = state.clone();
+            match self.instruction(&mut state, &node.kind) {
+                Ok(Some(stmt)) => lifted.push(Lifted::Statement(stmt)),
+                Ok(None) => (),
+                Err(()) => {
+                    self.complete = false;
+                    lifted.push(Lifted::Raw {
+                        asm: nodes[i..]
+                            .iter()
+                            .map(|node| node.asm.to_owned())
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                        stack: before.stack,
+                    });
+                    self.stacks.push(before.params);
+                    return lifted;
+                }
+            }
+        }
+
+        // End of spending path returns the top.
+        let value = self.pop(&mut state);
+        let value = self.boolean(value);
+        lifted.push(Lifted::Statement(Statement::ExpressionStatement(
+            nowhere(),
+            None,
+            value,
+        )));
+        if !state.stack.is_empty() && self.target != Target::Legacy {
+            self.complete = false;
+            lifted.push(Lifted::Leftover(state.stack));
+        }
+        self.stacks.push(state.params);
+        lifted
+    }
+
+    // Lift an instruction, which may give a statement. Err if it can't be lifted.
+    fn instruction(&mut self, state: &mut State, kind: &NodeKind) -> Result<Option<Statement>, ()> {
+        let op = match kind {
+            NodeKind::Push(data) => {
+                state.stack.push(literal(data));
+                return Ok(None);
+            }
+            NodeKind::Op(op) => *op,
+            _ => return Err(()),
+        };
+        // OP_1NEGATE, OP_1..OP_16
+        if let Class::PushNum(n) = op.classify(ClassifyContext::Legacy) {
+            state
+                .stack
+                .push(Expression::NumberLiteral(nowhere(), n as i64));
+            return Ok(None);
+        }
+
+        let item = match op {
+            OP_VERIFY => {
+                let value = self.pop(state);
+                let value = self.boolean(value);
+                return Ok(Some(Statement::VerifyStatement(nowhere(), value)));
+            }
+            OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY | OP_CHECKMULTISIGVERIFY => {
+                let op = match op {
+                    OP_EQUALVERIFY => OP_EQUAL,
+                    OP_NUMEQUALVERIFY => OP_NUMEQUAL,
+                    OP_CHECKSIGVERIFY => OP_CHECKSIG,
+                    _ => OP_CHECKMULTISIG,
+                };
+                self.instruction(state, &NodeKind::Op(op))?;
+                return self.instruction(state, &NodeKind::Op(OP_VERIFY));
+            }
+            // Operand is left on stack, to be dropped.
+            OP_CSV | OP_CLTV => {
+                let op = match op {
+                    OP_CSV => LocktimeOp::Csv,
+                    _ => LocktimeOp::Cltv,
+                };
+                let Expression::NumberLiteral(_, value) = self.peek(state, 0) else {
+                    return Err(());
+                };
+                // Lifted only if it compiles back to the same value, e.g. no other flag of nSequence.
+                let (operand, unit) = locktime_operand(&op, value);
+                return match locktime_value(&op, &unit, operand) {
+                    Ok(lifted) if lifted == value => Ok(Some(Statement::LocktimeStatement {
+                        loc: nowhere(),
+                        operand: Expression::NumberLiteral(nowhere(), operand),
+                        op,
+                        unit,
+                    })),
+                    _ => Err(()),
+                };
+            }
+
+            // Stack manipulation
+            OP_DUP | OP_OVER | OP_PICK => {
+                let depth = match op {
+                    OP_DUP => 0,
+                    OP_OVER => 1,
+                    _ => self.depth(state)?,
+                };
+                self.peek(state, depth)
+            }
+            OP_SWAP | OP_ROT | OP_ROLL => {
+                let depth = match op {
+                    OP_SWAP => 1,
+                    OP_ROT => 2,
+                    _ => self.depth(state)?,
+                };
+                self.ensure(state, depth + 1);
+                state.stack.remove(state.stack.len() - 1 - depth)
+            }
+            OP_DROP => {
+                self.pop(state);
+                return Ok(None);
+            }
+            OP_NIP => {
+                self.ensure(state, 2);
+                state.stack.remove(state.stack.len() - 2);
+                return Ok(None);
+            }
+
+            // OP_SIZE keeps the operand, which the compiler drops by OP_SWAP OP_DROP.
+            OP_SIZE => {
+                let operand = self.peek(state, 0);
+                Expression::ByteExpression {
+                    loc: nowhere(),
+                    operand: Box::new(self.string(operand)),
+                    op: ByteOp::Size,
+                }
+            }
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS => {
+                let operand = self.pop(state);
+                Expression::UnaryMathExpression {
+                    loc: nowhere(),
+                    operand: Box::new(self.numeric(operand)),
+                    op: match op {
+                        OP_1ADD => UnaryMathOp::Add,
+                        OP_1SUB => UnaryMathOp::Sub,
+                        OP_NEGATE => UnaryMathOp::Negate,
+                        _ => UnaryMathOp::Abs,
+                    },
+                }
+            }
+            OP_NOT => match self.pop(state) {
+                // OP_EQUAL OP_NOT
+                Expression::CompareExpression {
+                    loc,
+                    lhs,
+                    op: BinaryCompareOp::Equal,
+                    rhs,
+                } => Expression::CompareExpression {
+                    loc,
+                    lhs,
+                    op: BinaryCompareOp::NotEqual,
+                    rhs,
+                },
+                operand => Expression::UnaryMathExpression {
+                    loc: nowhere(),
+                    operand: Box::new(self.boolean(operand)),
+                    op: UnaryMathOp::Not,
+                },
+            },
+            OP_BOOLAND | OP_BOOLOR => {
+                let rhs = self.pop(state);
+                let lhs = self.pop(state);
+                Expression::LogicalExpression {
+                    loc: nowhere(),
+                    lhs: Box::new(self.boolean(lhs)),
+                    op: match op {
+                        OP_BOOLAND => BinaryLogicalOp::BoolAnd,
+                        _ => BinaryLogicalOp::BoolOr,
+                    },
+                    rhs: Box::new(self.boolean(rhs)),
+                }
+            }
+            OP_EQUAL => {
+                let rhs = self.pop(state);
+                let lhs = self.pop(state);
+                // Both sides take the type of either side.
+                let (lhs, rhs) = match (self.type_of(&lhs), self.type_of(&rhs)) {
+                    (Some(Type::String | Type::Signature | Type::PubKey | Type::XOnly), _)
+                    | (_, Some(Type::String | Type::Signature | Type::PubKey | Type::XOnly)) => {
+                        (self.string(lhs), self.string(rhs))
+                    }
+                    (None, None) => (lhs, rhs),
+                    _ => (self.numeric(lhs), self.numeric(rhs)),
+                };
+                Expression::CompareExpression {
+                    loc: nowhere(),
+                    lhs: Box::new(lhs),
+                    op: BinaryCompareOp::Equal,
+                    rhs: Box::new(rhs),
+                }
+            }
+            OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHANOREQUAL => {
+                let rhs = self.pop(state);
+                let lhs = self.pop(state);
+                Expression::CompareExpression {
+                    loc: nowhere(),
+                    lhs: Box::new(self.numeric(lhs)),
+                    op: match op {
+                        OP_LESSTHAN => BinaryCompareOp::Less,
+                        OP_GREATERTHAN => BinaryCompareOp::Greater,
+                        OP_LESSTHANOREQUAL => BinaryCompareOp::LessOrEqual,
+                        _ => BinaryCompareOp::GreaterOrEqual,
+                    },
+                    rhs: Box::new(self.numeric(rhs)),
+                }
+            }
+            OP_ADD | OP_SUB | OP_MAX | OP_MIN => {
+                let rhs = self.pop(state);
+                let lhs = self.pop(state);
+                Expression::BinaryMathExpression {
+                    loc: nowhere(),
+                    lhs: Box::new(self.numeric(lhs)),
+                    op: match op {
+                        OP_ADD => BinaryMathOp::Add,
+                        OP_SUB => BinaryMathOp::Sub,
+                        OP_MAX => BinaryMathOp::Max,
+                        _ => BinaryMathOp::Min,
+                    },
+                    rhs: Box::new(self.numeric(rhs)),
+                }
+            }
+            OP_WITHIN => {
+                let upper = self.pop(state);
+                let lower = self.pop(state);
+                let operand = self.pop(state);
+                Expression::TernaryMathExpression {
+                    loc: nowhere(),
+                    operand: Box::new(self.numeric(operand)),
+                    lower: Box::new(self.numeric(lower)),
+                    upper: Box::new(self.numeric(upper)),
+                    op: TernaryMathOp::Within,
+                }
+            }
+            OP_SHA256 | OP_RIPEMD160 | OP_SHA1 | OP_HASH160 | OP_HASH256 => {
+                let operand = self.pop(state);
+                let operand = self.string(operand);
+                Expression::UnaryCryptoExpression {
+                    loc: nowhere(),
+                    operand: Box::new(operand),
+                    op: match op {
+                        OP_SHA256 => UnaryCryptoOp::Sha256,
+                        OP_RIPEMD160 => UnaryCryptoOp::Ripemd160,
+                        OP_SHA1 => UnaryCryptoOp::Sha1,
+                        OP_HASH160 => UnaryCryptoOp::Hash160,
+                        _ => UnaryCryptoOp::Hash256,
+                    },
+                }
+            }
+
+            // Signature
+            OP_CHECKSIG => {
+                let pubkey = self.pop(state);
+                let sig = self.pop(state);
+                checksig(self.signature(sig, pubkey)?)
+            }
+            // Sum of signatures on taproot: <sig> <n> <pubkey> OP_CHECKSIGADD
+            OP_CHECKSIGADD if self.target == Target::Taproot => {
+                let pubkey = self.pop(state);
+                let sum = self.pop(state);
+                let sig = self.pop(state);
+                let factor = self.signature(sig, pubkey)?;
+                Expression::BinaryMathExpression {
+                    loc: nowhere(),
+                    lhs: Box::new(self.numeric(sum)),
+                    op: BinaryMathOp::Add,
+                    rhs: Box::new(checksig(factor)),
+                }
+            }
+            // Multisig on taproot: <sum> <m> OP_NUMEQUAL
+            OP_NUMEQUAL if self.target == Target::Taproot => {
+                let m = self.pop(state);
+                let sum = self.pop(state);
+                match (m, sum_of_signatures(&sum)) {
+                    (Expression::NumberLiteral(_, m), Some(n))
+                        if m >= 1 && m as usize <= n.len() =>
+                    {
+                        checksig(Factor::MultiSigFactor {
+                            loc: nowhere(),
+                            m: m as u32,
+                            n,
+                        })
+                    }
+                    _ => return Err(()),
+                }
+            }
+            // <dummy> <sig>*m <m> <pubkey>*n <n> OP_CHECKMULTISIG
+            OP_CHECKMULTISIG if self.target != Target::Taproot => {
+                let n = match self.pop(state) {
+                    Expression::NumberLiteral(_, n) if (1..=20).contains(&n) => n as usize,
+                    _ => return Err(()),
+                };
+                // 1st pubkey on the top.
+                let pubkeys = (0..n).map(|_| self.pop(state)).collect::<Vec<Expression>>();
+                let m = match self.pop(state) {
+                    Expression::NumberLiteral(_, m) if (1..=n as i64).contains(&m) => m as usize,
+                    _ => return Err(()),
+                };
+                let mut sigs = (0..m).map(|_| self.pop(state)).collect::<Vec<Expression>>();
+                let dummy = self.pop(state);
+                if m < n {
+                    // Compiler puts a signature for every pubkey: the one after m-th is the dummy,
+                    // and the rest are left on stack.
+                    sigs.push(dummy);
+                    self.ensure(state, n - m - 1);
+                    sigs.extend(state.stack.iter().rev().take(n - m - 1).cloned());
+                } else if !matches!(
+                    dummy,
+                    Expression::NumberLiteral(_, 0) | Expression::Variable(..)
+                ) {
+                    return Err(());
+                }
+                let n = sigs
+                    .into_iter()
+                    .zip(pubkeys)
+                    .map(|(sig, pubkey)| self.signature(sig, pubkey))
+                    .collect::<Result<Vec<Factor>, ()>>()?;
+                checksig(Factor::MultiSigFactor {
+                    loc: nowhere(),
+                    m: m as u32,
+                    n,
+                })
+            }
+            _ => return Err(()),
+        };
+        state.stack.push(item);
+
+        Ok(None)
+    }
+
+    // Pull witness items until the stack has the count.
+    fn ensure(&mut self, state: &mut State, count: usize) {
+        while state.stack.len() < count {
+            let name = format!("item{}", self.items);
+            self.items += 1;
+            state.stack.insert(
+                0,
+                Expression::Variable(nowhere(), Identifier(name.to_owned())),
+            );
+            state.params.push(name);
+        }
+    }
+
+    fn pop(&mut self, state: &mut State) -> Expression {
+        self.ensure(state, 1);
+        state
+            .stack
+            .pop()
+            .unwrap_or(Expression::BooleanLiteral(nowhere(), false))
+    }
+
+    fn peek(&mut self, state: &mut State, depth: usize) -> Expression {
+        self.ensure(state, depth + 1);
+        state.stack[state.stack.len() - 1 - depth].to_owned()
+    }
+
+    // Depth of OP_PICK and OP_ROLL, which must be a literal.
+    fn depth(&mut self, state: &mut State) -> Result<usize, ()> {
+        match self.pop(state) {
+            Expression::NumberLiteral(_, n) if (0..=1000).contains(&n) => Ok(n as usize),
+            _ => Err(()),
+        }
+    }
+
+    // Signature must be a witness item, and pubkey a literal or witness item.
+    fn signature(&mut self, sig: Expression, pubkey: Expression) -> Result<Factor, ()> {
+        match (&sig, &pubkey) {
+            (
+                Expression::Variable(..),
+                Expression::StringLiteral(..) | Expression::Variable(..),
+            ) => {
+                self.constrain(&sig, Type::Signature);
+                self.key(&pubkey);
+                Ok(Factor::SingleSigFactor {
+                    loc: nowhere(),
+                    sig: Box::new(sig),
+                    pubkey: Box::new(pubkey),
+                })
+            }
+            _ => Err(()),
+        }
+    }
+
+    /*
+        Type inference
+        - Witness item takes the type of its first typed use.
+        - Literal takes the form of its use, e.g. 0 and 1 are false and true for condition.
+    */
+    fn constrain(&mut self, expr: &Expression, ty: Type) {
+        if let Expression::Variable(_, id) = expr {
+            self.types.entry(id.0.to_owned()).or_insert(ty);
+        }
+    }
+
+    // Key is more specific than string, e.g. hashed before checked as in P2PKH.
+    fn key(&mut self, expr: &Expression) {
+        if let Expression::Variable(_, id) = expr {
+            let ty = match self.target {
+                Target::Taproot => Type::XOnly,
+                _ => Type::PubKey,
+            };
+            match self.types.get(&id.0) {
+                None | Some(Type::String) => {
+                    self.types.insert(id.0.to_owned(), ty);
+                }
+                Some(_) => (),
+            }
+        }
+    }
+
+    fn type_of(&self, expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Variable(_, id) => self.types.get(&id.0).cloned(),
+            Expression::StringLiteral(..) | Expression::UnaryCryptoExpression { .. } => {
+                Some(Type::String)
+            }
+            _ => Some(Type::Number),
+        }
+    }
+
+    fn boolean(&mut self, expr: Expression) -> Expression {
+        self.constrain(&expr, Type::Boolean);
+        match expr {
+            Expression::NumberLiteral(loc, 0) => Expression::BooleanLiteral(loc, false),
+            Expression::NumberLiteral(loc, 1) => Expression::BooleanLiteral(loc, true),
+            expr => expr,
+        }
+    }
+
+    fn numeric(&mut self, expr: Expression) -> Expression {
+        self.constrain(&expr, Type::Number);
+        expr
+    }
+
+    fn string(&mut self, expr: Expression) -> Expression {
+        self.constrain(&expr, Type::String);
+        match expr {
+            // Number pushed as data, not by OP_1NEGATE or OP_1..OP_16.
+            Expression::NumberLiteral(loc, n) if n == 0 || !(-1..=16).contains(&n) => {
+                Expression::StringLiteral(loc, hex::encode(encode_num(n)))
+            }
+            expr => expr,
+        }
+    }
+
+    fn finish(self, mut block: Vec<Lifted>) -> Decompiled {
+        // Strict stack mode needs each item used once, in stack order.
+        let mut relaxed = false;
+        let mut unused = false;
+        for (leaf, params) in path_leaves(&to_ast(&block)).iter().zip(&self.stacks) {
+            let mut ids: Vec<Identifier> = vec![];
+            for stmt in leaf {
+                if let Statement::VerifyStatement(_, expr)
+                | Statement::ExpressionStatement(_, _, expr) = stmt
+                {
+                    collect_variables(expr, &mut ids);
+                }
+            }
+            let ids = ids.into_iter().map(|id| id.0).collect::<Vec<String>>();
+            relaxed |= ids.len() > params.len() || ids[..] != params[..ids.len()];
+            unused |= params.iter().any(|param| !ids.contains(param));
+        }
+
+        // Name witness items by type, numbered if there are more than one of the type.
+        let mut items: Vec<&String> = vec![];
+        for param in self.stacks.iter().flatten() {
+            if !items.contains(&param) {
+                items.push(param);
+            }
+        }
+        let type_of = |item: &String| self.types.get(item).cloned().unwrap_or(Type::String);
+        let mut names: HashMap<String, String> = HashMap::new();
+        for (ty, prefix) in [
+            (Type::Signature, "sig"),
+            (Type::Boolean, "condition"),
+            (Type::Number, "num"),
+            (Type::String, "data"),
+            (Type::PubKey, "key"),
+            (Type::XOnly, "key"),
+        ] {
+            let typed = items
+                .iter()
+                .filter(|item| type_of(item) == ty)
+                .collect::<Vec<_>>();
+            for (i, item) in typed.iter().enumerate() {
+                let name = match typed.len() {
+                    1 => prefix.to_string(),
+                    _ => format!("{}_{}", prefix, i),
+                };
+                names.insert(item.to_string(), name);
+            }
+        }
+        visit_block(&mut block, &mut |expr| match expr {
+            Expression::Variable(_, id) => {
+                if let Some(name) = names.get(&id.0) {
+                    id.0 = name.to_owned();
+                }
+            }
+            Expression::StringLiteral(_, data) => *data = text(data),
+            _ => (),
+        });
+
+        let mut source = format!(
+            "pragma bithoven version {};\npragma bithoven target {};\n",
+            env!("CARGO_PKG_VERSION"),
+            target_name(&self.target)
+        );
+        if relaxed {
+            source.push_str("pragma bithoven stack relaxed;\n");
+        }
+        if unused {
+            source.push_str("pragma bithoven allow unused_variable;\n");
+        }
+        source.push('\n');
+        for stack in &self.stacks {
+            let params = stack
+                .iter()
+                .map(|item| StackParam {
+                    loc: nowhere(),
+                    identifier: Identifier(names[item].to_owned()),
+                    ty: type_of(item),
+                })
+                .collect::<Vec<StackParam>>();
+            source.push_str(&stack_line(&params.iter().collect::<Vec<&StackParam>>()));
+            source.push('\n');
+        }
+        source.push_str("{\n");
+        print_block(&block, 1, &mut source);
+        source.push_str("}\n");
+
+        Decompiled {
+            source,
+            complete: self.complete,
+        }
+    }
+}
+
+fn checksig(factor: Factor) -> Expression {
+    Expression::CheckSigExpression {
+        loc: nowhere(),
+        operand: Box::new(factor),
+        op: CheckSigOp::CheckSig,
+    }
+}
+
+// Signatures of OP_CHECKSIG followed by OP_CHECKSIGADD, in order.
+fn sum_of_signatures(expr: &Expression) -> Option<Vec<Factor>> {
+    match expr {
+        Expression::CheckSigExpression { operand, .. } => match &**operand {
+            Factor::SingleSigFactor { .. } => Some(vec![*operand.to_owned()]),
+            _ => None,
+        },
+        Expression::BinaryMathExpression {
+            lhs,
+            op: BinaryMathOp::Add,
+            rhs,
+            ..
+        } => {
+            let mut factors = sum_of_signatures(lhs)?;
+            factors.extend(sum_of_signatures(rhs).filter(|factor| factor.len() == 1)?);
+            Some(factors)
+        }
+        _ => None,
+    }
+}
+
+// Hex string is shown as text if it is printable, and the text is not hex itself.
+fn text(data: &str) -> String {
+    match hex::decode(data).map(String::from_utf8) {
+        Ok(Ok(text))
+            if !text.is_empty()
+                && text.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+                && !text.contains(['"', '\\'])
+                && hex::decode(&text).is_err() =>
+        {
+            text
+        }
+        _ => data.to_string(),
+    }
+}
+
+fn to_ast(block: &[Lifted]) -> Vec<Statement> {
+    block
+        .iter()
+        .filter_map(|item| match item {
+            Lifted::Statement(stmt) => Some(stmt.to_owned()),
+            Lifted::If {
+                condition,
+                if_block,
+                else_block,
+            } => Some(Statement::IfStatement {
+                loc: nowhere(),
+                condition_expr: condition.to_owned(),
+                if_block: to_ast(if_block),
+                else_block: Some(to_ast(else_block)),
+            }),
+            Lifted::Raw { .. } | Lifted::Leftover(..) => None,
+        })
+        .collect()
+}
+
+// Visit expressions of the block, operands first.
+// Pubkeys are always hex literals, so only signatures of factor are visited.
+fn visit_block(block: &mut [Lifted], f: &mut dyn FnMut(&mut Expression)) {
+    for item in block {
+        match item {
+            Lifted::Statement(
+                Statement::VerifyStatement(_, expr) | Statement::ExpressionStatement(_, _, expr),
+            ) => visit(expr, f),
+            Lifted::Statement(_) => (),
+            Lifted::If {
+                condition,
+                if_block,
+                else_block,
+            } => {
+                visit(condition, f);
+                visit_block(if_block, f);
+                visit_block(else_block, f);
+            }
+            Lifted::Raw { stack, .. } | Lifted::Leftover(stack) => {
+                for expr in stack {
+                    visit(expr, f);
+                }
+            }
+        }
+    }
+}
+
+fn visit(expr: &mut Expression, f: &mut dyn FnMut(&mut Expression)) {
+    match expr {
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            visit(lhs, f);
+            visit(rhs, f);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            visit(operand, f);
+            visit(lower, f);
+            visit(upper, f);
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => visit(operand, f),
+        Expression::CheckSigExpression { operand, .. } => visit_factor(operand, f),
+        _ => (),
+    }
+    f(expr);
+}
+
+fn visit_factor(factor: &mut Factor, f: &mut dyn FnMut(&mut Expression)) {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            visit(sig, f);
+            // Key literal stays hex, never shown as text.
+            if let Expression::Variable(..) = **pubkey {
+                visit(pubkey, f);
+            }
+        }
+        Factor::MultiSigFactor { n, .. } => {
+            for factor in n {
+                visit_factor(factor, f);
+            }
+        }
+    }
+}
+
+fn print_block(block: &[Lifted], depth: usize, out: &mut String) {
+    let indent = INDENT.repeat(depth);
+    for item in block {
+        match item {
+            Lifted::Statement(stmt) => {
+                out.push_str(&format!("{}{};\n", indent, statement(stmt, &indent)));
+            }
+            Lifted::If {
+                condition,
+                if_block,
+                else_block,
+            } => {
+                let stmt = Statement::IfStatement {
+                    loc: nowhere(),
+                    condition_expr: condition.to_owned(),
+                    if_block: vec![],
+                    else_block: None,
+                };
+                out.push_str(&format!("{}{} {{\n", indent, statement(&stmt, &indent)));
+                print_block(if_block, depth + 1, out);
+                out.push_str(&format!("{}}} else {{\n", indent));
+                print_block(else_block, depth + 1, out);
+                out.push_str(&format!("{}}}\n", indent));
+            }
+            Lifted::Raw { asm, stack } => {
+                out.push_str(&format!("{}// Not lifted: {}\n", indent, asm));
+                out.push_str(&format!("{}// Stack: {}\n", indent, stack_text(stack)));
+            }
+            Lifted::Leftover(stack) => {
+                out.push_str(&format!(
+                    "{}// Left under the returned value, failing clean stack rule: {}\n",
+                    indent,
+                    stack_text(stack)
+                ));
+            }
+        }
+    }
+}
+
+// Stack items from the bottom.
+fn stack_text(stack: &[Expression]) -> String {
+    match stack.is_empty() {
+        true => "(empty)".to_string(),
+        false => stack
+            .iter()
+            .map(expression)
+            .collect::<Vec<String>>()
+            .join(", "),
+    }
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::decompile::*;
+    use crate::format::format_source;
+    use crate::test_util::{PUBKEY_A, PUBKEY_B};
+    use crate::{compile_program, parse};
+
+    use std::fs;
+
+    fn decompile_hex(script: &str) -> Decompiled {
+        decompile(&hex::decode(script).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn test_examples_round_trip() {
+        for entry in fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "bithoven") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let target = parse(source.clone()).unwrap().pragma.target;
+            let script = compile_program(source).unwrap().bytes();
+
+            let decompiled = decompile(&script, Some(target.clone())).unwrap();
+            assert!(decompiled.complete, "{:?}", path);
+            assert_eq!(
+                format_source(&decompiled.source).unwrap(),
+                decompiled.source
+            );
+            assert_eq!(
+                compile_program(decompiled.source).unwrap().bytes(),
+                script,
+                "{:?}",
+                path
+            );
+
+            // Target is inferred from the script, except legacy which looks the same as segwit.
+            if target != Target::Legacy {
+                let inferred = decompile(&script, None).unwrap();
+                assert_eq!(
+                    compile_program(inferred.source).unwrap().bytes(),
+                    script,
+                    "{:?}",
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_standard_multisig() {
+        // 2 <A> <B> <A> 3 OP_CHECKMULTISIG, with the dummy from witness.
+        let decompiled =
+            decompile_hex(&format!("5221{}21{}21{}53ae", PUBKEY_A, PUBKEY_B, PUBKEY_A));
+        assert!(decompiled.complete);
+        assert_eq!(
+            decompiled.source,
+            format!(
+                r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+(sig_0: signature, sig_1: signature, sig_2: signature)
+{{
+    return checksig [2,
+        (sig_0, "{}"),
+        (sig_1, "{}"),
+        (sig_2, "{}")
+    ];
+}}
+"#,
+                PUBKEY_A, PUBKEY_B, PUBKEY_A
+            )
+        );
+    }
+
+    #[test]
+    fn test_code_after_endif() {
+        // OP_IF <A> OP_ELSE 144 OP_CSV OP_DROP <B> OP_ENDIF OP_CHECKSIG
+        let script = format!("6321{}67029000b27521{}68ac", PUBKEY_A, PUBKEY_B);
+        let decompiled = decompile_hex(&script);
+        assert!(decompiled.complete);
+        assert!(decompiled.source.contains(
+            "(condition: bool, sig_0: signature)\n(condition: bool, sig_1: signature)\n"
+        ));
+        assert!(decompiled.source.contains(&format!(
+            "    }} else {{\n        older 144;\n        return checksig (sig_1, \"{}\");\n",
+            PUBKEY_B
+        )));
+        assert!(compile_program(decompiled.source).is_ok());
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        // Pubkey is from witness, committed by its hash.
+        let script = "76a914111111111111111111111111111111111111111188ac";
+        let decompiled = decompile_hex(script);
+        assert!(decompiled.complete);
+        assert!(decompiled.source.contains(
+            "(key: pubkey, sig: signature)\n{\n    verify hash160 key == \"1111111111111111111111111111111111111111\";\n    return checksig (sig, key);\n"
+        ));
+        assert_eq!(
+            compile_program(decompiled.source).unwrap().bytes(),
+            hex::decode(script).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_within() {
+        // <1000> <2000> OP_WITHIN OP_VERIFY <A> OP_CHECKSIG
+        let script = format!("02e80302d007a56921{}ac", PUBKEY_A);
+        let decompiled = decompile_hex(&script);
+        assert!(decompiled.complete);
+        assert!(decompiled
+            .source
+            .contains("(num: number, sig: signature)\n{\n    verify within(num, 1000, 2000);\n"));
+        assert_eq!(
+            compile_program(decompiled.source).unwrap().bytes(),
+            hex::decode(script).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_not_lifted() {
+        let decompiled = decompile_hex("76a914111111111111111111111111111111111111111188abac");
+        assert!(!decompiled.complete);
+        assert!(decompiled.source.contains(
+            "    verify hash160 data == \"1111111111111111111111111111111111111111\";\n    // Not lifted: OP_CODESEPARATOR OP_CHECKSIG\n    // Stack: data\n"
+        ));
+        // Still parses.
+        assert!(format_source(&decompiled.source).is_ok());
+
+        // Item under the result fails clean stack rule.
+        let decompiled = decompile_hex("76a87693");
+        assert!(!decompiled.complete);
+        assert!(decompiled
+            .source
+            .contains("pragma bithoven stack relaxed;\n"));
+        assert!(decompiled
+            .source
+            .contains("return sha256 data + sha256 data;\n    // Left under the returned value"));
+    }
+
+    #[test]
+    fn test_invalid_script() {
+        for script in ["4c05aa", "63", "6768", "51676768"] {
+            let result = decompile(&hex::decode(script).unwrap(), None);
+            assert!(
+                matches!(
+                    result,
+                    Err(CompileError {
+                        kind: ErrorKind::InvalidScript(_),
+                        ..
+                    })
+                ),
+                "{}",
+                script
+            );
+        }
+    }
+}
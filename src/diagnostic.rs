@@ -0,0 +1,226 @@
+use crate::ast::*;
+use crate::BithovenOutput;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// A finding of the compiler with its severity and span.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[wasm_bindgen]
+pub struct Diagnostic {
+    severity: Severity,
+    loc: Location,
+    kind: ErrorKind,
+    // Lint name, if the finding is a lint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lint: Option<String>,
+}
+
+#[wasm_bindgen]
+impl Diagnostic {
+    // "error" or "warning"
+    #[wasm_bindgen(getter)]
+    pub fn severity(&self) -> String {
+        self.severity.to_string()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn line(&self) -> usize {
+        self.loc.line
+    }
+    #[wasm_bindgen(getter)]
+    pub fn column(&self) -> usize {
+        self.loc.column
+    }
+    #[wasm_bindgen(getter)]
+    pub fn start(&self) -> usize {
+        self.loc.start
+    }
+    #[wasm_bindgen(getter)]
+    pub fn end(&self) -> usize {
+        self.loc.end
+    }
+    // Name of the error kind, e.g. "UnusedVariable".
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.kind.name()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.kind.message()
+    }
+    // Lint name, e.g. "unused_variable".
+    #[wasm_bindgen(getter)]
+    pub fn lint(&self) -> Option<String> {
+        self.lint.clone()
+    }
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, error: CompileError) -> Self {
+        Diagnostic {
+            severity,
+            loc: error.loc,
+            kind: error.kind,
+            lint: None,
+        }
+    }
+    pub fn with_lint(mut self, lint: &str) -> Self {
+        self.lint = Some(lint.to_string());
+        self
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    pub fn loc(&self) -> &Location {
+        &self.loc
+    }
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+    pub fn lint_name(&self) -> Option<&str> {
+        self.lint.as_deref()
+    }
+    pub fn to_error(&self) -> CompileError {
+        CompileError {
+            loc: self.loc.to_owned(),
+            kind: self.kind.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        match self.loc.line {
+            0 => write!(f, "{}: {:?}", severity, self.kind)?,
+            line => write!(
+                f,
+                "{} at line {}:{}: {:?}",
+                severity, line, self.loc.column, self.kind
+            )?,
+        }
+        match &self.lint {
+            Some(lint) => write!(f, " [{}]", lint),
+            None => Ok(()),
+        }
+    }
+}
+
+/*
+    Collector of diagnostics.
+    - Checks record what they find and keep going, so every mistake is reported in a single pass.
+    - Check which depends on a failed one is skipped, not to report the same mistake twice.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn error(&mut self, error: CompileError) {
+        self.items.push(Diagnostic::new(Severity::Error, error));
+    }
+
+    pub fn warning(&mut self, error: CompileError) {
+        self.items.push(Diagnostic::new(Severity::Warning, error));
+    }
+
+    pub fn push(&mut self, severity: Severity, error: CompileError, lint: Option<&str>) {
+        let diagnostic = Diagnostic::new(severity, error);
+        self.items.push(match lint {
+            Some(lint) => diagnostic.with_lint(lint),
+            None => diagnostic,
+        });
+    }
+
+    // Record error of the check, and whether it passed.
+    pub fn check(&mut self, result: Result<(), CompileError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                self.error(e);
+                false
+            }
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.is_error())
+    }
+
+    // First error found, which is what a failing check returns.
+    pub fn first_error(&self) -> Option<CompileError> {
+        self.items
+            .iter()
+            .find(|d| d.is_error())
+            .map(|d| d.to_error())
+    }
+
+    pub fn into_result(self) -> Result<(), CompileError> {
+        match self.first_error() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    // Diagnostics in order of source location.
+    pub fn into_vec(mut self) -> Vec<Diagnostic> {
+        self.items.sort_by_key(|d| (d.loc.line, d.loc.column));
+        self.items
+    }
+}
+
+// Compiled output if there is no error, with every diagnostic found.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[wasm_bindgen]
+pub struct DiagnosticOutput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<BithovenOutput>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[wasm_bindgen]
+impl DiagnosticOutput {
+    #[wasm_bindgen]
+    pub fn to_object(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self).unwrap()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> Option<BithovenOutput> {
+        self.output.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.clone()
+    }
+}
+
+impl DiagnosticOutput {
+    pub fn new(output: Option<BithovenOutput>, diagnostics: Diagnostics) -> Self {
+        DiagnosticOutput {
+            output,
+            diagnostics: diagnostics.into_vec(),
+        }
+    }
+}
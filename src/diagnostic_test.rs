@@ -0,0 +1,139 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::diagnostic::*;
+    use crate::test_util::{diagnose, example, PUBKEY_A};
+
+    fn codes(output: &DiagnosticOutput) -> Vec<String> {
+        output.diagnostics().iter().map(|d| d.code()).collect()
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let output = diagnose(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            pragma bithoven colour red;
+            (a: number, a: number, sig: signature)
+            {{
+                verify b == 1;
+                older 70000;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+
+        assert!(output.output().is_none());
+        let codes = codes(&output);
+        for code in [
+            "InvalidPragma",
+            "DuplicateVariable",
+            "UndefinedVariable",
+            "IntegerOverflow",
+        ] {
+            assert!(
+                codes.contains(&code.to_string()),
+                "No {} in {:?}",
+                code,
+                codes
+            );
+        }
+        // In order of location.
+        let lines = output
+            .diagnostics()
+            .iter()
+            .map(|d| d.line())
+            .collect::<Vec<usize>>();
+        assert!(lines.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(output.diagnostics()[0].line(), 4);
+        assert!(output.diagnostics().iter().all(|d| d.severity() == "error"));
+    }
+
+    #[test]
+    fn test_no_cascading_unused_variable() {
+        // sig is referenced in the failed check, so it is not unused.
+        let output = diagnose(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (sig: signature, x: number)
+            {{
+                verify x == 1;
+                return checksig (sig, "{}");
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert_eq!(codes(&output), vec!["InvalidConsumptionOrder"]);
+        assert_eq!(output.diagnostics()[0].line(), 6);
+    }
+
+    #[test]
+    fn test_flow_errors_of_each_branch() {
+        let output = diagnose(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (c: bool, sig_a: signature)
+            (c: bool, sig_b: signature)
+            {{
+                if c {{
+                    verify checksig (sig_a, "{0}");
+                }} else {{
+                    verify checksig (sig_b, "{0}");
+                }}
+            }}
+            "#,
+            PUBKEY_A
+        ));
+        assert_eq!(codes(&output), vec!["NoReturn", "NoReturn"]);
+    }
+
+    #[test]
+    fn test_parse_error_stops() {
+        let output = diagnose("pragma bithoven version 0.0.1;\n(sig: signature {");
+        assert_eq!(codes(&output), vec!["ParseError"]);
+        assert_eq!(output.diagnostics()[0].line(), 2);
+    }
+
+    #[test]
+    fn test_valid_program_has_output() {
+        let source = example("htlc.bithoven");
+        let output = diagnose(&source);
+        assert!(output.diagnostics().is_empty());
+        assert_eq!(
+            output.output().unwrap().hex(),
+            crate::compile_program(source).unwrap().hex()
+        );
+    }
+
+    #[test]
+    fn test_first_error_is_compile_error() {
+        let mut diagnostics = Diagnostics::default();
+        let error = |line| CompileError {
+            loc: Location {
+                start: 0,
+                end: 0,
+                line,
+                column: 1,
+            },
+            kind: ErrorKind::UnusedVariable("x".to_string()),
+        };
+        diagnostics.warning(error(1));
+        assert!(!diagnostics.has_errors());
+        diagnostics.error(error(3));
+        diagnostics.error(error(2));
+        assert_eq!(diagnostics.first_error(), Some(error(3)));
+
+        let items = diagnostics.into_vec();
+        assert_eq!(items[0].severity(), "warning");
+        assert_eq!(items[1].line(), 2);
+        assert_eq!(items[0].message(), "x");
+        assert_eq!(
+            items[0].to_string(),
+            "Warning at line 1:1: UnusedVariable(\"x\")"
+        );
+    }
+}
@@ -0,0 +1,757 @@
+/*
+    Formatter of bithoven source.
+    - Source is printed from AST, so indentation, spacing and parentheses are normalized.
+    - Comments and attributes are not in AST, so they are put back by location:
+      on the line before the item following them, or at the end of line if they were on the same line.
+    - Pragmas are printed in canonical order: version, target, then options in order of PRAGMA_ORDER.
+*/
+use crate::ast::*;
+use crate::locktime::{format_date, unit_name};
+use crate::lsp::{KEYWORDS, TYPES};
+use crate::parse;
+use crate::source::*;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+pub const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 100;
+const PRAGMA_ORDER: &[&str] = &["stack", "tapleaf", "internal_key", "allow", "warn", "deny"];
+
+/// Formats bithoven source, keeping comments. Source must parse.
+pub fn format_source(source: &str) -> Result<String, CompileError> {
+    let utxo = parse(source.to_string())?;
+    Ok(Formatter::new(source, &utxo.pragma.attributes).contract(&utxo))
+}
+
+// Comment or attribute, which is not in AST.
+struct Trivia {
+    start: usize,
+    end: usize,
+    text: String,
+    is_comment: bool,
+}
+
+// Pragma with its comments, to be sorted.
+struct PragmaLine {
+    order: usize,
+    leading: Vec<String>,
+    line: String,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    // Strings and comments are blanked out to find punctuation.
+    blank: String,
+    trivia: Vec<Trivia>,
+    // Index of trivia to print next.
+    next: usize,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, attributes: &[LintAttribute]) -> Self {
+        let mut trivia: Vec<Trivia> = collect_comments(source)
+            .into_iter()
+            .map(|(start, end)| Trivia {
+                start,
+                end,
+                text: source[start..end].trim_end().to_string(),
+                is_comment: true,
+            })
+            .collect();
+        trivia.extend(attributes.iter().map(|attribute| Trivia {
+            start: attribute.loc.start,
+            end: attribute.loc.end,
+            text: attribute_text(attribute),
+            is_comment: false,
+        }));
+        trivia.sort_by_key(|t| t.start);
+
+        Formatter {
+            source,
+            blank: blank_trivia(source),
+            trivia,
+            next: 0,
+            out: String::new(),
+        }
+    }
+
+    fn contract(mut self, utxo: &Bithoven) -> String {
+        let header_end = self.pragmas(&utxo.pragma);
+        self.out.push('\n');
+
+        // Template parameters and constants, in order of declaration.
+        let mut declarations: Vec<(&Location, String)> = utxo
+            .params
+            .iter()
+            .map(|param| (&param.loc, param_line(param)))
+            .chain(
+                utxo.constants
+                    .iter()
+                    .map(|constant| (&constant.loc, constant_line(constant))),
+            )
+            .collect();
+        declarations.sort_by_key(|(loc, _)| loc.start);
+        let mut prev: Option<usize> = Some(header_end);
+        for (i, (loc, line)) in declarations.iter().enumerate() {
+            let mut leading = self.leading(loc.start, &mut prev);
+            if prev.is_some_and(|prev| self.has_blank_line(prev, loc.start)) {
+                leading.push(String::new());
+            }
+            // Blank line after pragmas is already printed.
+            if i == 0 && leading.first().is_some_and(String::is_empty) {
+                leading.remove(0);
+            }
+            for line in leading {
+                self.indented_line("", &line);
+            }
+            let trailing = self.trailing(loc.end);
+            self.out.push_str(line);
+            self.out.push_str(&trailing);
+            self.out.push('\n');
+            prev = Some(loc.end);
+        }
+        if !declarations.is_empty() {
+            self.out.push('\n');
+        }
+
+        // Input stacks, in order of declaration.
+        let mut stack_end = 0;
+        for (i, (stack, path)) in utxo.input_stack.iter().zip(&utxo.paths).enumerate() {
+            let params: Vec<&StackParam> = stack.iter().rev().collect();
+            let start = path
+                .as_ref()
+                .map(|path| path.loc.start)
+                .or(params.first().map(|first| first.loc.start));
+            let end = params
+                .last()
+                .map(|last| last.loc.end)
+                .or(path.as_ref().map(|path| path.loc.end));
+            if let (Some(start), Some(end)) = (start, end) {
+                let mut leading = self.leading(start, &mut prev);
+                if prev.is_some_and(|prev| self.has_blank_line(prev, start)) {
+                    leading.push(String::new());
+                }
+                // Blank line after pragmas is already printed.
+                if i == 0 && leading.first().is_some_and(String::is_empty) {
+                    leading.remove(0);
+                }
+                for line in leading {
+                    self.indented_line("", &line);
+                }
+                stack_end = end;
+            }
+            let trailing = self.trailing(stack_end);
+            if let Some(path) = path {
+                self.out.push_str(&format!("path {}", path.identifier.0));
+            }
+            self.out.push_str(&stack_line(&params));
+            self.out.push_str(&trailing);
+            self.out.push('\n');
+            prev = Some(stack_end);
+        }
+
+        // Script
+        let open = self.find_from(stack_end, '{');
+        if prev.is_some_and(|prev| self.has_blank_line(prev, open)) {
+            self.out.push('\n');
+        }
+        let trailing = self.trailing(open + 1);
+        self.out.push('{');
+        self.out.push_str(&trailing);
+        self.out.push('\n');
+        let script_end = utxo
+            .output_script
+            .last()
+            .map_or(open + 1, |stmt| stmt.to_owned().loc().end);
+        let close = self.find_from(script_end, '}');
+        self.block(&utxo.output_script, 1, close);
+        let trailing = self.trailing(close + 1);
+        self.out.push('}');
+        self.out.push_str(&trailing);
+        self.out.push('\n');
+
+        // Comments after the contract.
+        let mut prev = Some(close + 1);
+        for line in self.leading(self.source.len(), &mut prev) {
+            self.indented_line("", &line);
+        }
+        self.out
+    }
+
+    // Pragmas, returning the end of the last one in source.
+    fn pragmas(&mut self, pragma: &Pragma) -> usize {
+        lazy_static! {
+            static ref PRAGMA_REGEX: Regex =
+                Regex::new(r"pragma\s+bithoven\s+(version|target)\b[^;]*").unwrap();
+        }
+        // Version and target are not located by parser.
+        let mut spans: Vec<(usize, usize, PragmaLine)> = vec![];
+        let blank = self.blank.clone();
+        for found in PRAGMA_REGEX.captures_iter(&blank) {
+            let span = found.get(0).unwrap();
+            let (order, line) = match &found[1] {
+                "version" => (
+                    0,
+                    format!("pragma {} version {};", pragma.language, pragma.version),
+                ),
+                _ => (
+                    1,
+                    format!(
+                        "pragma {} target {};",
+                        pragma.language,
+                        target_name(&pragma.target)
+                    ),
+                ),
+            };
+            if spans.iter().all(|(_, _, p)| p.order != order) {
+                spans.push((
+                    span.start(),
+                    span.end(),
+                    PragmaLine {
+                        order,
+                        leading: vec![],
+                        line,
+                    },
+                ));
+            }
+        }
+        for option in &pragma.options {
+            let order = PRAGMA_ORDER
+                .iter()
+                .position(|key| *key == option.key.0)
+                .map_or(2 + PRAGMA_ORDER.len(), |i| 2 + i);
+            let line = format!(
+                "pragma {} {} {};",
+                pragma.language,
+                option.key.0,
+                pragma_value(&option.value)
+            );
+            spans.push((
+                option.loc.start,
+                option.loc.end,
+                PragmaLine {
+                    order,
+                    leading: vec![],
+                    line,
+                },
+            ));
+        }
+
+        // Comments are taken in order of source, then pragmas are sorted.
+        spans.sort_by_key(|(start, ..)| *start);
+        let header_end = spans.last().map_or(0, |(_, end, _)| *end);
+        let mut lines: Vec<PragmaLine> = vec![];
+        for (start, end, mut pragma_line) in spans {
+            pragma_line.leading = self.leading(start, &mut None);
+            pragma_line.line.push_str(&self.trailing(end));
+            lines.push(pragma_line);
+        }
+        lines.sort_by_key(|p| p.order);
+        for pragma_line in lines {
+            for line in pragma_line.leading {
+                self.indented_line("", &line);
+            }
+            self.out.push_str(&pragma_line.line);
+            self.out.push('\n');
+        }
+        header_end
+    }
+
+    // Statements of block, and comments until the close brace of block.
+    fn block(&mut self, stmts: &[Statement], depth: usize, close: usize) {
+        let indent = INDENT.repeat(depth);
+        let mut prev: Option<usize> = None;
+        for stmt in stmts {
+            let loc = stmt.to_owned().loc();
+            // Comments in the middle of statement are put before it.
+            let inner_end = match stmt {
+                Statement::IfStatement { condition_expr, .. } => {
+                    condition_expr.to_owned().loc().end
+                }
+                Statement::MatchStatement { selector, .. } => selector.to_owned().loc().end,
+                _ => loc.end,
+            };
+            let mut leading = self.leading(inner_end, &mut prev);
+            if prev.is_some_and(|prev| self.has_blank_line(prev, loc.start)) {
+                leading.push(String::new());
+            }
+            for line in leading {
+                self.indented_line(&indent, &line);
+            }
+
+            match stmt {
+                Statement::IfStatement {
+                    condition_expr,
+                    if_block,
+                    else_block,
+                    ..
+                } => {
+                    let header = statement(stmt, &indent);
+                    let end =
+                        self.if_statement(header, condition_expr, if_block, else_block, depth);
+                    prev = Some(end);
+                }
+                Statement::MatchStatement { arms, .. } => {
+                    let trailing = self.trailing(inner_end);
+                    self.out.push_str(&format!(
+                        "{}{} {{{}\n",
+                        indent,
+                        statement(stmt, &indent),
+                        trailing
+                    ));
+                    let arm_indent = format!("{}{}", indent, INDENT);
+                    let mut end = inner_end;
+                    let mut arm_prev: Option<usize> = None;
+                    for arm in arms {
+                        for line in self.leading(arm.loc.start, &mut arm_prev) {
+                            self.indented_line(&arm_indent, &line);
+                        }
+                        let open = self.find_from(arm.loc.start, '{');
+                        let trailing = self.trailing(open + 1);
+                        self.out
+                            .push_str(&format!("{}{} => {{{}\n", arm_indent, arm.value, trailing));
+                        let arm_end = arm
+                            .block
+                            .last()
+                            .map_or(open + 1, |s| s.to_owned().loc().end);
+                        let arm_close = self.find_from(arm_end, '}');
+                        self.block(&arm.block, depth + 2, arm_close);
+                        end = arm_close + 1;
+                        let trailing = self.trailing(end);
+                        self.out
+                            .push_str(&format!("{}}}{}\n", arm_indent, trailing));
+                        arm_prev = Some(end);
+                    }
+                    let close = self.find_from(end, '}');
+                    for line in self.leading(close, &mut arm_prev) {
+                        self.indented_line(&arm_indent, &line);
+                    }
+                    let trailing = self.trailing(close + 1);
+                    self.out.push_str(&format!("{}}}{}\n", indent, trailing));
+                    prev = Some(close + 1);
+                }
+                _ => {
+                    let trailing = self.trailing(loc.end);
+                    self.out.push_str(&format!(
+                        "{}{};{}\n",
+                        indent,
+                        statement(stmt, &indent),
+                        trailing
+                    ));
+                    prev = Some(loc.end);
+                }
+            }
+        }
+
+        // Comments at the end of block.
+        for line in self.leading(close, &mut prev) {
+            self.indented_line(&indent, &line);
+        }
+    }
+
+    // If statement until its close brace, returning the end. "else if" is kept as written.
+    fn if_statement(
+        &mut self,
+        header: String,
+        condition_expr: &Expression,
+        if_block: &[Statement],
+        else_block: &Option<Vec<Statement>>,
+        depth: usize,
+    ) -> usize {
+        let indent = INDENT.repeat(depth);
+        let inner_end = condition_expr.to_owned().loc().end;
+        let trailing = self.trailing(inner_end);
+        self.out
+            .push_str(&format!("{}{} {{{}\n", indent, header, trailing));
+        let if_end = if_block
+            .last()
+            .map_or(inner_end, |s| s.to_owned().loc().end);
+        let if_close = self.find_from(if_end, '}');
+        self.block(if_block, depth + 1, if_close);
+        let mut end = if_close + 1;
+        if let Some(else_block) = else_block {
+            let open = self.find_from(end, '{');
+            // Nested if before any brace is "else if".
+            if let [Statement::IfStatement {
+                loc,
+                condition_expr,
+                if_block,
+                else_block,
+            }] = else_block.as_slice()
+            {
+                if loc.start < open {
+                    let header = format!(
+                        "}} else if {}",
+                        wrap(condition_expr, &indent, "} else if  {".len())
+                    );
+                    return self.if_statement(header, condition_expr, if_block, else_block, depth);
+                }
+            }
+            let trailing = self.trailing(open + 1);
+            self.out
+                .push_str(&format!("{}}} else {{{}\n", indent, trailing));
+            let else_end = else_block
+                .last()
+                .map_or(open + 1, |s| s.to_owned().loc().end);
+            let else_close = self.find_from(else_end, '}');
+            self.block(else_block, depth + 1, else_close);
+            end = else_close + 1;
+        }
+        let trailing = self.trailing(end);
+        self.out.push_str(&format!("{}}}{}\n", indent, trailing));
+        end
+    }
+
+    // Blank line is not indented.
+    fn indented_line(&mut self, indent: &str, line: &str) {
+        if !line.is_empty() {
+            self.out.push_str(indent);
+            self.out.push_str(line);
+        }
+        self.out.push('\n');
+    }
+
+    // Trivia before the offset, each as a line. Blank line between them is kept.
+    fn leading(&mut self, before: usize, prev: &mut Option<usize>) -> Vec<String> {
+        let mut lines: Vec<String> = vec![];
+        while let Some(trivia) = self.trivia.get(self.next).filter(|t| t.start < before) {
+            if let Some(prev) = *prev {
+                if self.has_blank_line(prev, trivia.start) {
+                    lines.push(String::new());
+                }
+            }
+            lines.push(trivia.text.to_owned());
+            *prev = Some(trivia.end);
+            self.next += 1;
+        }
+        lines
+    }
+
+    // Comment on the same line after the offset.
+    fn trailing(&mut self, after: usize) -> String {
+        match self.trivia.get(self.next) {
+            Some(trivia)
+                if trivia.is_comment
+                    && trivia.start >= after
+                    && !self.source[after..trivia.start].contains('\n') =>
+            {
+                self.next += 1;
+                format!(" {}", trivia.text)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn has_blank_line(&self, from: usize, to: usize) -> bool {
+        from < to && self.source[from..to].matches('\n').count() >= 2
+    }
+
+    // Offset of the punctuation at or after the offset.
+    fn find_from(&self, from: usize, c: char) -> usize {
+        let from = from.min(self.blank.len());
+        self.blank[from..]
+            .find(c)
+            .map_or(self.blank.len(), |i| from + i)
+    }
+}
+
+pub fn param_line(param: &StackParam) -> String {
+    format!("param {}: {};", param.identifier.0, param.ty)
+}
+
+pub fn constant_line(constant: &Constant) -> String {
+    format!(
+        "const {}: {} = {};",
+        constant.identifier.0,
+        constant.ty,
+        expression(&constant.value)
+    )
+}
+
+// Input stack, wrapped after comma if too long.
+pub fn stack_line(params: &[&StackParam]) -> String {
+    let mut lines: Vec<String> = vec![String::from("(")];
+    for (i, param) in params.iter().enumerate() {
+        let item = format!("{}: {}", param.identifier.0, param.ty);
+        let last = lines.last_mut().unwrap();
+        let separator = if i == 0 { "" } else { " " };
+        if i > 0 && last.len() + separator.len() + item.len() + 2 > MAX_WIDTH {
+            lines.push(format!("{}{}", INDENT, item));
+        } else {
+            last.push_str(separator);
+            last.push_str(&item);
+        }
+        if i + 1 < params.len() {
+            lines.last_mut().unwrap().push(',');
+        }
+    }
+    lines.last_mut().unwrap().push(')');
+    lines.join("\n")
+}
+
+fn attribute_text(attribute: &LintAttribute) -> String {
+    let level = match attribute.level {
+        LintLevel::Allow => "allow",
+        LintLevel::Warn => "warn",
+        LintLevel::Deny => "deny",
+    };
+    format!("#[{}({})]", level, attribute.lints.join(", "))
+}
+
+pub fn target_name(target: &Target) -> &'static str {
+    match target {
+        Target::Legacy => "legacy",
+        Target::Segwit => "segwit",
+        Target::Taproot => "taproot",
+    }
+}
+
+// Pragma value is written bare if it can be, otherwise as string.
+fn pragma_value(value: &str) -> String {
+    lazy_static! {
+        static ref BARE_REGEX: Regex = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*|[0-9]+)$").unwrap();
+    }
+    // "path" is a keyword, which is also a pragma value.
+    let keyword = KEYWORDS.contains(&value) && value != "path";
+    if BARE_REGEX.is_match(value) && !keyword && !TYPES.contains(&value) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+// Statement without semicolon, wrapped if too long. Blocks of if statement are printed by formatter.
+pub fn statement(stmt: &Statement, indent: &str) -> String {
+    match stmt {
+        Statement::LocktimeStatement {
+            op, operand, unit, ..
+        } => match (op, unit, operand) {
+            (LocktimeOp::Cltv, LocktimeUnit::Date, Expression::NumberLiteral(_, timestamp)) => {
+                format!("after {}", format_date(*timestamp))
+            }
+            (LocktimeOp::Cltv, LocktimeUnit::Blocks, _) => {
+                format!("after block {}", expression(operand))
+            }
+            (LocktimeOp::Cltv, _, _) => format!("after {}", expression(operand)),
+            (LocktimeOp::Csv, LocktimeUnit::Raw, _) => format!("older {}", expression(operand)),
+            (LocktimeOp::Csv, _, _) => format!(
+                "older {} {}",
+                expression(operand),
+                unit_name(unit, matches!(operand, Expression::NumberLiteral(_, 1)))
+            ),
+        },
+        Statement::VerifyStatement(_, expr) => {
+            format!("verify {}", wrap(expr, indent, "verify ;".len()))
+        }
+        Statement::LetStatement {
+            identifier, expr, ..
+        } => {
+            let binding = format!("let {} = ", identifier.0);
+            format!(
+                "{}{}",
+                binding,
+                wrap(expr, indent, binding.len() + ";".len())
+            )
+        }
+        Statement::ExpressionStatement(_, None, expr) => {
+            format!("return {}", wrap(expr, indent, "return ;".len()))
+        }
+        Statement::ExpressionStatement(_, Some(path), expr) => {
+            let label = format!("return {}: ", path.identifier.0);
+            format!("{}{}", label, wrap(expr, indent, label.len() + ";".len()))
+        }
+        Statement::IfStatement { condition_expr, .. } => {
+            format!("if {}", wrap(condition_expr, indent, "if  {".len()))
+        }
+        Statement::MatchStatement { selector, .. } => {
+            format!("match {}", wrap(selector, indent, "match  {".len()))
+        }
+    }
+}
+
+/*
+    Line longer than MAX_WIDTH is wrapped, if it can be.
+    - Logical expression is broken before each operator of the chain.
+    - Multisig is broken into a line per signature.
+*/
+fn wrap(expr: &Expression, indent: &str, extra: usize) -> String {
+    let flat = expression(expr);
+    if indent.len() + extra + flat.len() <= MAX_WIDTH {
+        return flat;
+    }
+    let inner = format!("{}{}", indent, INDENT);
+    match expr {
+        Expression::LogicalExpression { .. } => {
+            // Left associative chain: ((a && b) || c)
+            let mut chain: Vec<String> = vec![];
+            let mut current = expr;
+            while let Expression::LogicalExpression { lhs, op, rhs, .. } = current {
+                chain.push(format!("{} {}", logical_op(op), operand(rhs, 1)));
+                current = lhs;
+            }
+            chain.push(operand(current, 0));
+            chain.reverse();
+            chain.join(&format!("\n{}", inner))
+        }
+        Expression::CheckSigExpression {
+            operand: factor, ..
+        } => match &**factor {
+            Factor::MultiSigFactor { m, n, .. } => format!(
+                "checksig [{},\n{}{}\n{}]",
+                m,
+                inner,
+                n.iter()
+                    .map(self::factor)
+                    .collect::<Vec<String>>()
+                    .join(&format!(",\n{}", inner)),
+                indent
+            ),
+            _ => flat,
+        },
+        _ => flat,
+    }
+}
+
+/*
+    Precedence of expression, following the grammar(Expression0..4).
+    - 0: logical, 1: compare, 2: binary math, 3: product, 4: unary, 5: atom.
+    - Operand of lower precedence than its position takes is parenthesized.
+*/
+fn precedence(expr: &Expression) -> u8 {
+    match expr {
+        Expression::LogicalExpression { .. } => 0,
+        Expression::CompareExpression { .. } => 1,
+        Expression::BinaryMathExpression {
+            op: BinaryMathOp::Mul,
+            ..
+        } => 3,
+        Expression::BinaryMathExpression { .. } => 2,
+        Expression::UnaryMathExpression { .. }
+        | Expression::TernaryMathExpression { .. }
+        | Expression::UnaryCryptoExpression { .. }
+        | Expression::CheckSigExpression { .. }
+        | Expression::ByteExpression { .. } => 4,
+        Expression::Variable(..)
+        | Expression::NumberLiteral(..)
+        | Expression::BooleanLiteral(..)
+        | Expression::StringLiteral(..)
+        | Expression::BytesLiteral(..)
+        | Expression::PubKeyLiteral(..) => 5,
+    }
+}
+
+fn operand(expr: &Expression, min: u8) -> String {
+    if precedence(expr) < min {
+        format!("({})", expression(expr))
+    } else {
+        expression(expr)
+    }
+}
+
+pub fn expression(expr: &Expression) -> String {
+    match expr {
+        Expression::Variable(_, id) => id.0.to_owned(),
+        Expression::NumberLiteral(_, n) => n.to_string(),
+        Expression::BooleanLiteral(_, b) => b.to_string(),
+        Expression::StringLiteral(_, s) => format!("\"{}\"", s),
+        Expression::BytesLiteral(_, s) => format!("0x{}", s),
+        Expression::PubKeyLiteral(_, s) => format!("pk\"{}\"", s),
+        Expression::LogicalExpression { lhs, op, rhs, .. } => {
+            format!("{} {} {}", operand(lhs, 0), logical_op(op), operand(rhs, 1))
+        }
+        Expression::CompareExpression { lhs, op, rhs, .. } => {
+            let op = match op {
+                BinaryCompareOp::Equal | BinaryCompareOp::NumEqual => "==",
+                BinaryCompareOp::NotEqual | BinaryCompareOp::NumNotEqual => "!=",
+                BinaryCompareOp::Greater => ">",
+                BinaryCompareOp::GreaterOrEqual => ">=",
+                BinaryCompareOp::Less => "<",
+                BinaryCompareOp::LessOrEqual => "<=",
+            };
+            format!("{} {} {}", operand(lhs, 2), op, operand(rhs, 2))
+        }
+        Expression::BinaryMathExpression { lhs, op, rhs, .. } => match op {
+            BinaryMathOp::Add => format!("{} + {}", operand(lhs, 2), operand(rhs, 3)),
+            BinaryMathOp::Sub => format!("{} - {}", operand(lhs, 2), operand(rhs, 3)),
+            BinaryMathOp::Max => format!("max({}, {})", operand(lhs, 2), operand(rhs, 2)),
+            BinaryMathOp::Min => format!("min({}, {})", operand(lhs, 2), operand(rhs, 2)),
+            BinaryMathOp::Mul => format!("{} * {}", operand(lhs, 3), operand(rhs, 4)),
+        },
+        Expression::UnaryMathExpression { operand: e, op, .. } => {
+            let op = match op {
+                UnaryMathOp::Add => "++",
+                UnaryMathOp::Sub => "--",
+                UnaryMathOp::Negate => "negate ",
+                UnaryMathOp::Abs => "abs ",
+                UnaryMathOp::Not => "!",
+            };
+            let e = operand(e, 4);
+            // "-- -4" rather than "---4"
+            let space = if !op.ends_with(' ') && e.starts_with(['-', '+', '!']) {
+                " "
+            } else {
+                ""
+            };
+            format!("{}{}{}", op, space, e)
+        }
+        Expression::TernaryMathExpression {
+            operand: e,
+            lower,
+            upper,
+            op,
+            ..
+        } => match op {
+            TernaryMathOp::Within => format!(
+                "within({}, {}, {})",
+                operand(e, 2),
+                operand(lower, 2),
+                operand(upper, 2)
+            ),
+        },
+        Expression::UnaryCryptoExpression { operand: e, op, .. } => {
+            let op = match op {
+                UnaryCryptoOp::Sha256 => "sha256",
+                UnaryCryptoOp::Ripemd160 => "ripemd160",
+                UnaryCryptoOp::Hash160 => "hash160",
+                UnaryCryptoOp::Hash256 => "hash256",
+                UnaryCryptoOp::Sha1 => "sha1",
+                UnaryCryptoOp::TaggedHash(tag) => {
+                    return format!("tagged_hash(\"{}\", {})", tag, expression(e))
+                }
+            };
+            format!("{} {}", op, operand(e, 4))
+        }
+        Expression::ByteExpression { operand: e, op, .. } => match op {
+            ByteOp::Size => format!("len {}", operand(e, 4)),
+        },
+        Expression::CheckSigExpression {
+            operand: factor,
+            op,
+            ..
+        } => match op {
+            CheckSigOp::CheckSig => format!("checksig {}", self::factor(factor)),
+        },
+    }
+}
+
+fn logical_op(op: &BinaryLogicalOp) -> &'static str {
+    match op {
+        BinaryLogicalOp::BoolOr => "||",
+        BinaryLogicalOp::BoolAnd => "&&",
+    }
+}
+
+fn factor(factor: &Factor) -> String {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            format!("({}, {})", operand(sig, 5), operand(pubkey, 5))
+        }
+        Factor::MultiSigFactor { m, n, .. } => {
+            let mut items = vec![m.to_string()];
+            items.extend(n.iter().map(self::factor));
+            format!("[{}]", items.join(", "))
+        }
+    }
+}
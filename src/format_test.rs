@@ -0,0 +1,210 @@
+#[cfg(test)]
+mod tests {
+    use crate::compile_program;
+    use crate::format::*;
+    use crate::test_util::PUBKEY_A;
+
+    use std::fs;
+
+    #[test]
+    fn test_examples_round_trip() {
+        for entry in fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "bithoven") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let formatted = format_source(&source).unwrap();
+
+            assert_eq!(format_source(&formatted).unwrap(), formatted, "{:?}", path);
+            assert_eq!(
+                compile_program(formatted.clone()).map(|output| output.hex()),
+                compile_program(source.clone()).map(|output| output.hex()),
+                "{:?}",
+                path
+            );
+            assert_eq!(
+                formatted.matches("//").count(),
+                source.matches("//").count(),
+                "{:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_and_keep_comments() {
+        let source = format!(
+            r#"// Header
+pragma   bithoven version 0.0.1;
+pragma bithoven target segwit; // Target
+pragma bithoven warn unused_variable;
+pragma bithoven stack relaxed;
+
+(sig:signature,x:number)
+{{
+  verify x>=(1+2)-3;   // Trailing
+
+    /* Block */
+   #[allow( pubkey_reuse )]
+  return checksig(sig,"{}");
+  // End of block
+}}
+// End of file"#,
+            PUBKEY_A
+        );
+        let expected = format!(
+            r#"// Header
+pragma bithoven version 0.0.1;
+pragma bithoven target segwit; // Target
+pragma bithoven stack relaxed;
+pragma bithoven warn unused_variable;
+
+(sig: signature, x: number)
+{{
+    verify x >= 1 + 2 - 3; // Trailing
+
+    /* Block */
+    #[allow(pubkey_reuse)]
+    return checksig (sig, "{}");
+    // End of block
+}}
+// End of file
+"#,
+            PUBKEY_A
+        );
+        assert_eq!(format_source(&source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_keyword_pragma_value() {
+        // "path" is also the keyword of named spending path.
+        let source = r#"pragma bithoven version 0.0.1;
+pragma bithoven target taproot;
+pragma bithoven tapleaf path;
+
+(sig: signature)
+{
+    return checksig (sig, pk"45a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+}
+"#;
+        assert_eq!(format_source(source).unwrap(), source);
+    }
+
+    #[test]
+    fn test_else_if_and_match() {
+        let source = format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+(a: bool, sig: signature)
+(a: bool, b: bool, sig: signature)
+(a: bool, b: bool, n: number, sig: signature)
+(a: bool, b: bool, n: number, sig: signature)
+{{
+  if a {{ return checksig(sig,"{0}"); }}
+  else   if b {{ // Second
+    return checksig(sig,"{0}");
+  }} else {{
+    match n {{
+      // First arm
+      0=>{{ return checksig(sig,"{0}"); }}
+      1 => {{ // Second arm
+        older 10;
+        return checksig(sig,"{0}");
+      }}
+      // After arms
+    }}
+  }}
+}}
+"#,
+            PUBKEY_A
+        );
+        let expected = format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+(a: bool, sig: signature)
+(a: bool, b: bool, sig: signature)
+(a: bool, b: bool, n: number, sig: signature)
+(a: bool, b: bool, n: number, sig: signature)
+{{
+    if a {{
+        return checksig (sig, "{0}");
+    }} else if b {{ // Second
+        return checksig (sig, "{0}");
+    }} else {{
+        match n {{
+            // First arm
+            0 => {{
+                return checksig (sig, "{0}");
+            }}
+            1 => {{ // Second arm
+                older 10;
+                return checksig (sig, "{0}");
+            }}
+            // After arms
+        }}
+    }}
+}}
+"#,
+            PUBKEY_A
+        );
+        let formatted = format_source(&source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        // Nested if in else block is kept as it is written.
+        let nested = format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n\n(a: bool, sig: signature)\n(a: bool, b: bool, sig: signature)\n(a: bool, b: bool, sig: signature)\n{{\n    if a {{\n        return checksig (sig, \"{0}\");\n    }} else {{\n        if b {{\n            return checksig (sig, \"{0}\");\n        }} else {{\n            return checksig (sig, \"{0}\");\n        }}\n    }}\n}}\n",
+            PUBKEY_A
+        );
+        assert_eq!(format_source(&nested).unwrap(), nested);
+    }
+
+    #[test]
+    fn test_parentheses() {
+        let format_expr = |expr: &str| {
+            let source = format!(
+                "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n(sig: signature, a: number, b: number)\n{{\n    verify {};\n    return checksig (sig, \"{}\");\n}}\n",
+                expr, PUBKEY_A
+            );
+            let formatted = format_source(&source).unwrap();
+            formatted.lines().nth(5).unwrap().trim().to_string()
+        };
+
+        assert_eq!(format_expr("a - (b + 1)"), "verify a - (b + 1);");
+        assert_eq!(format_expr("(a - b) + 1"), "verify a - b + 1;");
+        assert_eq!(format_expr("!(a == b)"), "verify !(a == b);");
+        assert_eq!(
+            format_expr("(a == 1) && ((b == 2) || true)"),
+            "verify a == 1 && (b == 2 || true);"
+        );
+        assert_eq!(
+            format_expr("max((a + 1), b) == 0"),
+            "verify max(a + 1, b) == 0;"
+        );
+        assert_eq!(format_expr("a - -- -4 > 0"), "verify a - -- -4 > 0;");
+        assert_eq!(
+            format_expr("len(sha256(a)) == 32"),
+            "verify len sha256 a == 32;"
+        );
+    }
+
+    #[test]
+    fn test_wrap_long_line() {
+        let source = format!(
+            "pragma bithoven version 0.0.1; pragma bithoven target segwit; (a: signature, b: signature) {{ return checksig [2, (a, \"{}\"), (b, \"{}\")]; }}",
+            PUBKEY_A, PUBKEY_A
+        );
+        let formatted = format_source(&source).unwrap();
+        assert!(formatted.contains(&format!(
+            "    return checksig [2,\n        (a, \"{}\"),\n        (b, \"{}\")\n    ];\n",
+            PUBKEY_A, PUBKEY_A
+        )));
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        // Parse error
+        assert!(format_source("pragma bithoven version 0.0.1;").is_err());
+    }
+}
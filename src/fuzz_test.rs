@@ -0,0 +1,352 @@
+#[cfg(test)]
+mod tests {
+    use crate::lsp;
+    use crate::test_util::{PUBKEY_A, XONLY_A};
+    use crate::{
+        compile_program, compile_program_with_diagnostics, format_program, program_to_policy,
+    };
+
+    use std::fs;
+    use std::panic;
+
+    // Deterministic xorshift, so a failing source is reproduced by the same seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    const TOKENS: &[&str] = &[
+        "pragma",
+        "bithoven",
+        "version",
+        "0.0.1",
+        "target",
+        "legacy",
+        "segwit",
+        "taproot",
+        "stack",
+        "relaxed",
+        "tapleaf",
+        "path",
+        "allow",
+        "warn",
+        "deny",
+        "unused_variable",
+        "param",
+        "const",
+        "let",
+        "if",
+        "else",
+        "match",
+        "return",
+        "verify",
+        "older",
+        "after",
+        "block",
+        "days",
+        "2027-01-01T00:00Z",
+        "checksig",
+        "sha256",
+        "ripemd160",
+        "sha1",
+        "tagged_hash",
+        "len",
+        "min",
+        "max",
+        "within",
+        "abs",
+        "signature",
+        "bool",
+        "number",
+        "string",
+        "pubkey",
+        "xonly",
+        "bytes",
+        "hash160",
+        "hash256",
+        "true",
+        "false",
+        "sig",
+        "x",
+        "y",
+        "0",
+        "1",
+        "-1",
+        "16",
+        "65535",
+        "65536",
+        "4294967296",
+        "99999999999999999999",
+        "\"\"",
+        "\"aa\"",
+        "0x",
+        "0xaa",
+        "0xzz",
+        "pk\"\"",
+        "pk\"02\"",
+        "(",
+        ")",
+        "{",
+        "}",
+        "[",
+        "]",
+        ",",
+        ";",
+        ":",
+        "=",
+        "=>",
+        "==",
+        "!=",
+        "<",
+        ">",
+        "<=",
+        ">=",
+        "+",
+        "-",
+        "*",
+        "!",
+        "&&",
+        "||",
+        "#[allow(pubkey_reuse)]",
+        "//",
+        "/*",
+        "*/",
+        "\n",
+    ];
+
+    // Nothing given as source may panic, whatever is reported.
+    fn assert_no_panic(source: &str) {
+        let result = panic::catch_unwind(|| {
+            let _ = compile_program(source.to_string());
+            let _ = compile_program_with_diagnostics(source.to_string(), "mainnet".to_string());
+            let _ = format_program(source.to_string());
+            let _ = program_to_policy(source.to_string(), vec![]);
+            let _ = lsp::diagnostics(source);
+        });
+        assert!(result.is_ok(), "Panicked on source:\n{}", source);
+    }
+
+    fn token_soup(rng: &mut Rng) -> String {
+        (0..rng.below(40))
+            .map(|_| rng.pick(TOKENS))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    // Example with tokens deleted, repeated or replaced.
+    fn mutate(rng: &mut Rng, source: &str) -> String {
+        let mut tokens = source.split(' ').collect::<Vec<&str>>();
+        for _ in 0..1 + rng.below(4) {
+            let i = rng.below(tokens.len());
+            match rng.below(3) {
+                0 => {
+                    tokens.remove(i);
+                }
+                1 => tokens.insert(i, tokens[i]),
+                _ => tokens[i] = rng.pick(TOKENS),
+            }
+            if tokens.is_empty() {
+                break;
+            }
+        }
+        tokens.join(" ")
+    }
+
+    // Well typed contract, to reach code generation.
+    struct Contract<'a> {
+        rng: &'a mut Rng,
+        target: &'a str,
+        // Input stack items of each type.
+        items: Vec<(&'static str, &'static str)>,
+    }
+
+    impl Contract<'_> {
+        fn item(&mut self, ty: &str) -> Option<&'static str> {
+            let typed = self
+                .items
+                .iter()
+                .filter(|(_, item_ty)| *item_ty == ty)
+                .map(|(name, _)| *name)
+                .collect::<Vec<&str>>();
+            (!typed.is_empty()).then(|| self.rng.pick(&typed))
+        }
+
+        fn key(&mut self) -> String {
+            let key = if self.target == "taproot" {
+                self.item("xonly")
+            } else {
+                self.item("pubkey")
+            };
+            match (key, self.rng.below(3)) {
+                (Some(key), 0) => key.to_string(),
+                _ if self.target == "taproot" => format!("pk\"{}\"", XONLY_A),
+                _ => format!("\"{}\"", PUBKEY_A),
+            }
+        }
+
+        fn checksig(&mut self) -> String {
+            let sig = self.item("signature").unwrap_or("sig");
+            match self.rng.below(3) {
+                0 => {
+                    let other = self.item("signature").unwrap_or("sig");
+                    let (key, other_key) = (self.key(), self.key());
+                    format!(
+                        "checksig [{}, ({}, {}), ({}, {})]",
+                        self.rng.below(4),
+                        sig,
+                        key,
+                        other,
+                        other_key
+                    )
+                }
+                _ => format!("checksig ({}, {})", sig, self.key()),
+            }
+        }
+
+        fn boolean(&mut self, depth: usize) -> String {
+            match self.rng.below(if depth == 0 { 3 } else { 8 }) {
+                0 => self.item("bool").unwrap_or("true").to_string(),
+                1 => self.checksig(),
+                2 => self.rng.pick(&["true", "false"]).to_string(),
+                3 => format!("{} && {}", self.boolean(depth - 1), self.boolean(0)),
+                4 => format!("{} || {}", self.boolean(depth - 1), self.boolean(0)),
+                5 => {
+                    let op = self.rng.pick(&["==", "!=", "<", ">", "<=", ">="]);
+                    format!("{} {} {}", self.number(depth - 1), op, self.number(0))
+                }
+                6 => format!("{} == {}", self.bytes(depth - 1), self.bytes(0)),
+                _ => format!("!{}", self.item("bool").unwrap_or("false")),
+            }
+        }
+
+        fn number(&mut self, depth: usize) -> String {
+            let literal = self
+                .rng
+                .pick(&["0", "1", "-1", "16", "17", "1000", "2147483647"]);
+            match self.rng.below(if depth == 0 { 2 } else { 6 }) {
+                0 => self.item("number").unwrap_or(literal).to_string(),
+                1 => literal.to_string(),
+                2 => format!("{} + {}", self.number(depth - 1), self.number(0)),
+                3 => format!("{} - {}", self.number(depth - 1), self.number(0)),
+                4 => {
+                    let op = self.rng.pick(&["min", "max"]);
+                    format!("{}({}, {})", op, self.number(depth - 1), self.number(0))
+                }
+                _ => format!("len {}", self.bytes(0)),
+            }
+        }
+
+        fn bytes(&mut self, depth: usize) -> String {
+            let literal = self
+                .rng
+                .pick(&["\"aa\"", "\"text\"", "0xabcd", "0x", "0x00"]);
+            match self.rng.below(if depth == 0 { 3 } else { 5 }) {
+                0 => self.item("string").unwrap_or(literal).to_string(),
+                1 => self.item("bytes").unwrap_or(literal).to_string(),
+                2 => literal.to_string(),
+                3 => format!("sha256 {}", self.bytes(depth - 1)),
+                _ => match self.rng.below(20) {
+                    // Over the limit of a stack element.
+                    0 => format!("0x{}", "ab".repeat(521)),
+                    _ => format!("0x{}", "ab".repeat(self.rng.below(300))),
+                },
+            }
+        }
+
+        fn block(&mut self, depth: usize) -> String {
+            let mut lines = vec![];
+            for _ in 0..self.rng.below(3) {
+                lines.push(match self.rng.below(4) {
+                    0 => format!("older {};", self.rng.pick(&["1", "144", "65535"])),
+                    1 => format!("after {};", self.rng.pick(&["1", "840000", "1700000000"])),
+                    _ => format!("verify {};", self.boolean(2)),
+                });
+            }
+            if depth > 0 && self.rng.below(2) == 0 {
+                let condition = self.boolean(1);
+                let (if_block, else_block) = (self.block(depth - 1), self.block(depth - 1));
+                lines.push(format!(
+                    "if {} {{\n{}\n}} else {{\n{}\n}}",
+                    condition, if_block, else_block
+                ));
+            } else {
+                let value = match self.rng.below(3) {
+                    0 => self.boolean(2),
+                    _ => self.checksig(),
+                };
+                lines.push(format!("return {};", value));
+            }
+            lines.join("\n")
+        }
+    }
+
+    fn contract(rng: &mut Rng) -> String {
+        let target = rng.pick(&["legacy", "segwit", "taproot"]);
+        let mut items = vec![("sig", "signature")];
+        for name in ["a", "b", "c", "d"].into_iter().take(rng.below(5)) {
+            let ty = rng.pick(&[
+                "bool",
+                "number",
+                "string",
+                "bytes",
+                "signature",
+                "pubkey",
+                "xonly",
+            ]);
+            items.push((name, ty));
+        }
+        let stack = items
+            .iter()
+            .map(|(name, ty)| format!("{}: {}", name, ty))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let relaxed = rng.below(4) != 0;
+        let body = Contract { rng, target, items }.block(2);
+        let paths = 1 + body.matches("} else {").count();
+        format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target {};\n{}pragma bithoven allow unused_variable;\n{}\n{{\n{}\n}}\n",
+            target,
+            if relaxed { "pragma bithoven stack relaxed;\n" } else { "" },
+            vec![format!("({})", stack); paths].join("\n"),
+            body
+        )
+    }
+
+    #[test]
+    fn test_fuzz_no_panic() {
+        let mut rng = Rng(0x5eed_b17b_0c0d_e5e5);
+        let mut examples: Vec<String> = vec![];
+        for entry in fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "bithoven") {
+                examples.push(fs::read_to_string(path).unwrap());
+            }
+        }
+
+        for _ in 0..100 {
+            assert_no_panic(&token_soup(&mut rng));
+        }
+        for example in &examples {
+            for _ in 0..5 {
+                assert_no_panic(&mutate(&mut rng, example));
+            }
+        }
+        for _ in 0..200 {
+            assert_no_panic(&contract(&mut rng));
+        }
+    }
+}
@@ -1,13 +1,50 @@
+mod address;
+mod address_test;
 mod analyze;
 mod analyze_test;
 mod ast;
 mod compile;
+mod compile_test;
+mod constant;
+mod constant_test;
+pub mod decompile;
+mod decompile_test;
+pub mod diagnostic;
+mod diagnostic_test;
 mod examples_test;
+pub mod format;
+mod format_test;
+mod fuzz_test;
+pub mod lint;
+mod lint_test;
+mod locktime;
+mod locktime_test;
+pub mod lsp;
+mod lsp_test;
+pub mod param;
+mod param_test;
 mod parser_test;
+mod path;
+mod path_test;
+pub mod policy;
+mod policy_test;
 mod source;
+mod taproot;
+mod taproot_test;
+mod test_util;
+pub mod testing;
+mod testing_test;
+pub mod vm;
+mod vm_test;
+mod witness;
+mod witness_test;
 
 // Re-export only type for error.
-pub use ast::{CompileError, ErrorKind, Location};
+pub use address::LockingOutput;
+pub use ast::{CompileError, ErrorKind, LintLevel, Location, Target};
+pub use diagnostic::{Diagnostic, DiagnosticOutput, Severity};
+pub use taproot::{TapLeafOutput, TaprootOutput};
+pub use witness::{WitnessElement, WitnessTemplate};
 
 use ast::*;
 use compile::*;
@@ -17,11 +54,20 @@ use serde::{Deserialize, Serialize};
 
 use wasm_bindgen::prelude::*;
 
+use crate::address::*;
 use crate::analyze::*;
+use crate::constant::*;
+use crate::diagnostic::*;
+use crate::param::*;
+use crate::path::*;
 use crate::source::*;
+use crate::taproot::*;
+use crate::witness::*;
 
 use lalrpop_util::ParseError;
 
+use bitcoin::Network;
+
 use std::fmt::Debug;
 use std::result::Result;
 
@@ -34,13 +80,29 @@ pub struct BithovenOutput {
     asm: String,
     hex: String,
     bytes: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locking: Option<LockingOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nested_locking: Option<LockingOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    taproot: Option<TaprootOutput>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    witness: Vec<WitnessTemplate>,
 }
 
 #[wasm_bindgen]
 impl BithovenOutput {
     #[wasm_bindgen(constructor)]
     pub fn new(asm: String, hex: String, bytes: Vec<u8>) -> Self {
-        BithovenOutput { asm, hex, bytes }
+        BithovenOutput {
+            asm,
+            hex,
+            bytes,
+            locking: None,
+            nested_locking: None,
+            taproot: None,
+            witness: vec![],
+        }
     }
     #[wasm_bindgen]
     pub fn to_object(&self) -> JsValue {
@@ -64,14 +126,39 @@ impl BithovenOutput {
     pub fn bytes(&self) -> Vec<u8> {
         self.bytes.clone()
     }
+    // P2SH for legacy, P2WSH for segwit and P2TR for taproot target.
+    #[wasm_bindgen(getter)]
+    pub fn locking(&self) -> Option<LockingOutput> {
+        self.locking.clone()
+    }
+    // P2SH-P2WSH, only for segwit target.
+    #[wasm_bindgen(getter)]
+    pub fn nested_locking(&self) -> Option<LockingOutput> {
+        self.nested_locking.clone()
+    }
+    // Taproot output, only for taproot target.
+    #[wasm_bindgen(getter)]
+    pub fn taproot(&self) -> Option<TaprootOutput> {
+        self.taproot.clone()
+    }
+    // Witness template of each spending path, in order of paths.
+    #[wasm_bindgen(getter)]
+    pub fn witness(&self) -> Vec<WitnessTemplate> {
+        self.witness.clone()
+    }
 }
 
 fn parse(source: String) -> Result<Bithoven, CompileError> {
     let line_index = build_line_index(&source);
     match bithoven::BithovenParser::new().parse(&source) {
         Ok(mut utxo) => {
+            set_pragma_location(&mut utxo.pragma, &line_index);
+            set_param_location(&mut utxo.params, &line_index);
+            set_constant_location(&mut utxo.constants, &line_index);
             set_stack_location(&mut utxo.input_stack, &line_index);
+            set_path_location(utxo.paths.iter_mut().flatten(), &line_index);
             set_ast_location(&mut utxo.output_script, &line_index);
+            utxo.pragma.attributes = parse_attributes(&source, &utxo.output_script, &line_index)?;
             Ok(utxo)
         }
         Err(e) => {
@@ -81,8 +168,7 @@ fn parse(source: String) -> Result<Bithoven, CompileError> {
                 ParseError::UnrecognizedEof { location, .. } => *location,
                 ParseError::UnrecognizedToken { token, .. } => token.0,
                 ParseError::ExtraToken { token, .. } => token.0,
-                // Handle other error variants...
-                _ => 0,
+                ParseError::User { error } => error.0,
             };
 
             let (line, column) = get_line_and_column(&line_index, location);
@@ -100,10 +186,10 @@ fn parse(source: String) -> Result<Bithoven, CompileError> {
                     line,
                     column,
                 },
-                kind: ErrorKind::ParseError(format!(
-                    "Invalid Statement: \"{}\"",
-                    invalid_statement
-                )),
+                kind: ErrorKind::ParseError(match e {
+                    ParseError::User { error } => error.1,
+                    _ => format!("Invalid Statement: \"{}\"", invalid_statement),
+                }),
             })
         }
     }
@@ -116,25 +202,355 @@ fn parse(source: String) -> Result<Bithoven, CompileError> {
 ///
 /// # Returns
 ///
-/// A `BithovenOutput` containing ASM, Hex, and Bytes.
+/// A `BithovenOutput` containing ASM, Hex, and Bytes, with the locking script and address of mainnet.
+/// For taproot target, it also contains the taproot output. With `pragma bithoven tapleaf path;`,
+/// each spending path is a tapleaf and ASM, Hex, and Bytes are left empty.
 #[wasm_bindgen]
 pub fn compile_program(source: String) -> Result<BithovenOutput, CompileError> {
+    compile_program_for_network(source, "mainnet".to_string())
+}
+
+/// Compiles Bithoven source code into Bitcoin Script, with address of the given network.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+/// * `network` - One of "mainnet", "testnet", "testnet4", "signet" and "regtest"
+///
+/// # Returns
+///
+/// A `BithovenOutput` containing ASM, Hex, Bytes, and the locking script and address.
+#[wasm_bindgen]
+pub fn compile_program_for_network(
+    source: String,
+    network: String,
+) -> Result<BithovenOutput, CompileError> {
+    compile_program_with_params(source, network, vec![])
+}
+
+/// Compiles Bithoven template into Bitcoin Script, with the values of its parameters.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+/// * `network` - One of "mainnet", "testnet", "testnet4", "signet" and "regtest"
+/// * `params` - Value of each parameter as "name=value", e.g. "timeout=144"
+///
+/// # Returns
+///
+/// A `BithovenOutput` as `compile_program_for_network`. Every declared parameter must have a value.
+#[wasm_bindgen]
+pub fn compile_program_with_params(
+    source: String,
+    network: String,
+    params: Vec<String>,
+) -> Result<BithovenOutput, CompileError> {
+    let network = parse_network(&network)?;
+
     // Parse
-    let utxo: Bithoven = parse(source)?;
+    let mut utxo: Bithoven = parse(source)?;
+
+    // Bind constants and template parameters, then fold arithmetic of literals
+    bind_constants(&mut utxo)?;
+    bind_params(&mut utxo, &params, Unbound::Error)?;
+    fold_constants(&mut utxo.output_script);
+
+    // Match input stacks to named spending paths
+    resolve_paths(&mut utxo)?;
 
     // Analyze
-    analyze(
+    analyze(&utxo.output_script, utxo.input_stack.clone(), &utxo.pragma)?;
+
+    build_output(&utxo, network)
+}
+
+/// Compiles Bithoven source code, reporting every diagnostic instead of stopping at the first error.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+/// * `network` - One of "mainnet", "testnet", "testnet4", "signet" and "regtest"
+///
+/// # Returns
+///
+/// A `DiagnosticOutput` with every error and warning in order of location, and the compiled
+/// output if there is no error. Parse error stops the compilation, as there is nothing to analyze.
+#[wasm_bindgen]
+pub fn compile_program_with_diagnostics(source: String, network: String) -> DiagnosticOutput {
+    compile_program_with_lints(source, network, vec![], vec![], vec![], vec![])
+}
+
+/// Compiles Bithoven source code like `compile_program_with_diagnostics`, with lint levels.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+/// * `network` - One of "mainnet", "testnet", "testnet4", "signet" and "regtest"
+/// * `allow`, `warn`, `deny` - Lint names to set the level, e.g. "unused_variable"
+/// * `params` - Value of each template parameter as "name=value"
+///
+/// # Returns
+///
+/// A `DiagnosticOutput` as `compile_program_with_diagnostics`. Pragmas and attributes in the
+/// source take precedence over the given levels.
+#[wasm_bindgen]
+pub fn compile_program_with_lints(
+    source: String,
+    network: String,
+    allow: Vec<String>,
+    warn: Vec<String>,
+    deny: Vec<String>,
+    params: Vec<String>,
+) -> DiagnosticOutput {
+    diagnose(
+        source,
+        network,
+        &[("allow", allow), ("warn", warn), ("deny", deny)],
+        &params,
+        Unbound::Error,
+    )
+}
+
+// Every diagnostic of source. Output is left out if a parameter is bound to placeholder.
+fn diagnose(
+    source: String,
+    network: String,
+    levels: &[(&str, Vec<String>)],
+    params: &[String],
+    unbound: Unbound,
+) -> DiagnosticOutput {
+    let mut diagnostics = Diagnostics::default();
+
+    let network = match parse_network(&network) {
+        Ok(network) => network,
+        Err(e) => {
+            diagnostics.error(e);
+            return DiagnosticOutput::new(None, diagnostics);
+        }
+    };
+    let mut utxo: Bithoven = match parse(source) {
+        Ok(utxo) => utxo,
+        Err(e) => {
+            diagnostics.error(e);
+            return DiagnosticOutput::new(None, diagnostics);
+        }
+    };
+    set_lint_levels(&mut utxo.pragma, levels);
+
+    let placeholder = unbound == Unbound::Placeholder && !utxo.params.is_empty();
+    bind_constants_diagnostics(&mut utxo, &mut diagnostics);
+    bind_params_diagnostics(&mut utxo, params, unbound, &mut diagnostics);
+    fold_constants(&mut utxo.output_script);
+    resolve_paths_diagnostics(&mut utxo, &mut diagnostics);
+    if diagnostics.has_errors() {
+        return DiagnosticOutput::new(None, diagnostics);
+    }
+
+    analyze_diagnostics(
         &utxo.output_script,
         utxo.input_stack.clone(),
+        &utxo.pragma,
+        &mut diagnostics,
+    );
+    if diagnostics.has_errors() {
+        return DiagnosticOutput::new(None, diagnostics);
+    }
+
+    match build_output(&utxo, network) {
+        Ok(_) if placeholder => DiagnosticOutput::new(None, diagnostics),
+        Ok(output) => DiagnosticOutput::new(Some(output), diagnostics),
+        Err(e) => {
+            diagnostics.error(e);
+            DiagnosticOutput::new(None, diagnostics)
+        }
+    }
+}
+
+/// Formats Bithoven source code, keeping comments.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+///
+/// # Returns
+///
+/// The formatted source, or the parse error.
+#[wasm_bindgen]
+pub fn format_program(source: String) -> Result<String, CompileError> {
+    format::format_source(&source)
+}
+
+/// Converts Bithoven source code into a Miniscript policy.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code
+/// * `params` - Value of each template parameter as "name=value"
+///
+/// # Returns
+///
+/// The policy, e.g. "or(pk(A),and(older(144),pk(B)))", or the construct which is not representable.
+/// Pubkey and hash parameters without value are left as their names.
+#[wasm_bindgen]
+pub fn program_to_policy(source: String, params: Vec<String>) -> Result<String, CompileError> {
+    policy::to_policy(&source, &params).map(|policy| policy.to_string())
+}
+
+/// Converts a Miniscript policy or descriptor into Bithoven source code.
+///
+/// # Arguments
+///
+/// * `policy` - A policy, or a descriptor "sh(..)", "wsh(..)" or "tr(..)"
+/// * `target` - One of "legacy", "segwit" and "taproot", defaults to the one of descriptor or segwit
+///
+/// # Returns
+///
+/// The source, with a spending path for each way to satisfy the policy.
+#[wasm_bindgen]
+pub fn policy_to_program(policy: String, target: Option<String>) -> Result<String, CompileError> {
+    let target = match target.as_deref() {
+        None => None,
+        Some("legacy") => Some(Target::Legacy),
+        Some("segwit") => Some(Target::Segwit),
+        Some("taproot") => Some(Target::Taproot),
+        Some(target) => {
+            return Err(CompileError {
+                loc: nowhere(),
+                kind: ErrorKind::InvalidPragma(format!("Invalid target {:?}.", target)),
+            })
+        }
+    };
+    policy::from_policy(&policy, target)
+}
+
+// Lint levels given outside of source, placed before pragmas of source to be overridden.
+fn set_lint_levels(pragma: &mut Pragma, levels: &[(&str, Vec<String>)]) {
+    let options = levels.iter().flat_map(|(level, lints)| {
+        lints.iter().map(|lint| PragmaOption {
+            loc: nowhere(),
+            key: Identifier(level.to_string()),
+            value: lint.to_owned(),
+        })
+    });
+    pragma
+        .options
+        .splice(0..0, options.collect::<Vec<PragmaOption>>());
+}
+
+// Compile analyzed contract, and build locking output and witness templates.
+fn build_output(utxo: &Bithoven, network: Network) -> Result<BithovenOutput, CompileError> {
+    let script_loc = script_location(&utxo.output_script);
+
+    // Compile each spending path as its own tapleaf
+    if utxo.pragma.target == Target::Taproot && tapleaf_mode(&utxo.pragma) == TapleafMode::Path {
+        let (path_leaves, input_stack): (Vec<Vec<Statement>>, Vec<Vec<StackParam>>) = utxo
+            .input_stack
+            .iter()
+            .enumerate()
+            .map(|(path, stack)| tapleaf_path(&utxo.output_script, stack, path))
+            .unzip();
+        let mut leaves: Vec<(usize, Option<String>, Vec<u8>)> = vec![];
+        for (path, leaf) in path_leaves.iter().enumerate() {
+            let input = &input_stack[path..=path];
+            let script = compile(leaf.clone(), input, &utxo.pragma.target)?;
+            let name = utxo.paths[path]
+                .as_ref()
+                .map(|name| name.identifier.0.to_owned());
+            leaves.push((path, name, script));
+        }
+        let taproot = build_taproot(
+            leaves.clone(),
+            internal_key(&utxo.pragma),
+            network,
+            &script_loc,
+        )?;
+        // Control block of each leaf depends on the tree, so checked after it is built.
+        for ((leaf, (_, _, script)), tapleaf) in
+            path_leaves.iter().zip(&leaves).zip(taproot.leaves())
+        {
+            check_consensus(
+                leaf,
+                script,
+                &input_stack,
+                &utxo.paths,
+                tapleaf.path(),
+                tapleaf.control_block().len() / 2,
+                &utxo.pragma.target,
+            )?;
+        }
+        let mut output = BithovenOutput::new(String::new(), String::new(), vec![]);
+        output.locking = Some(build_taproot_locking(taproot.tweaked_output_key(), network));
+        output.taproot = Some(taproot);
+        output.witness = witness_templates(
+            &input_stack,
+            &utxo.paths,
+            &utxo.output_script,
+            &utxo.pragma.target,
+            &output,
+        );
+        return Ok(output);
+    }
+
+    // Compile
+    let script = compile(
+        utxo.output_script.clone(),
+        &utxo.input_stack,
         &utxo.pragma.target,
     )?;
 
-    // Compile
-    let script = compile(utxo.output_script.clone(), &utxo.pragma.target);
+    // Check consensus limits of compiled script
+    check_consensus(
+        &utxo.output_script,
+        &script,
+        &utxo.input_stack,
+        &utxo.paths,
+        0,
+        bitcoin::taproot::TAPROOT_CONTROL_BASE_SIZE,
+        &utxo.pragma.target,
+    )?;
 
-    Ok(BithovenOutput::new(
+    let mut output = BithovenOutput::new(
         bitcoin::Script::from_bytes(&script).to_asm_string(),
         bitcoin::Script::from_bytes(&script).to_hex_string(),
         bitcoin::Script::from_bytes(&script).to_bytes(),
-    ))
+    );
+    match utxo.pragma.target {
+        // Whole script is a single tapleaf
+        Target::Taproot => {
+            let taproot = build_taproot(
+                vec![(0, None, script)],
+                internal_key(&utxo.pragma),
+                network,
+                &script_loc,
+            )?;
+            output.locking = Some(build_taproot_locking(taproot.tweaked_output_key(), network));
+            output.taproot = Some(taproot);
+        }
+        Target::Segwit => {
+            output.locking = Some(build_locking(
+                &script,
+                &utxo.pragma.target,
+                network,
+                &script_loc,
+            )?);
+            output.nested_locking = Some(build_nested_locking(&script, network));
+        }
+        Target::Legacy => {
+            output.locking = Some(build_locking(
+                &script,
+                &utxo.pragma.target,
+                network,
+                &script_loc,
+            )?);
+        }
+    }
+    output.witness = witness_templates(
+        &utxo.input_stack,
+        &utxo.paths,
+        &utxo.output_script,
+        &utxo.pragma.target,
+        &output,
+    );
+
+    Ok(output)
 }
@@ -0,0 +1,466 @@
+/*
+    Lints: findings which are not always a mistake, with configurable level.
+    - allow: ignored, warn: reported as warning, deny: reported as error.
+    - Level is set, from the lowest to the highest priority, by default, CLI flag("--warn <lint>"),
+      pragma("pragma bithoven warn <lint>;") and attribute("#[warn(<lint>)]") on a statement.
+*/
+use crate::ast::*;
+use crate::compile::{collect_variables, path_leaves};
+use crate::diagnostic::*;
+use crate::locktime::locktime_value;
+use crate::param::bind_expression;
+
+use std::collections::HashMap;
+
+pub struct Lint {
+    pub name: &'static str,
+    pub default: LintLevel,
+    pub description: &'static str,
+}
+
+pub const UNUSED_VARIABLE: &str = "unused_variable";
+pub const USELESS_SIG: &str = "useless_sig";
+pub const PUBKEY_REUSE: &str = "pubkey_reuse";
+pub const TIMELOCK_MIXING: &str = "timelock_mixing";
+pub const UNCOMMITTED_PUBKEY: &str = "uncommitted_pubkey";
+
+pub const LINTS: &[Lint] = &[
+    Lint {
+        name: UNUSED_VARIABLE,
+        default: LintLevel::Deny,
+        description: "Input stack item is never used.",
+    },
+    Lint {
+        name: USELESS_SIG,
+        default: LintLevel::Deny,
+        description: "Result of checksig is discarded, e.g. negated by \"!\".",
+    },
+    Lint {
+        name: PUBKEY_REUSE,
+        default: LintLevel::Warn,
+        description: "Same public key is checked more than once on a spending path.",
+    },
+    Lint {
+        name: TIMELOCK_MIXING,
+        default: LintLevel::Warn,
+        description: "Spending paths mix block height and time based locktimes.",
+    },
+    Lint {
+        name: UNCOMMITTED_PUBKEY,
+        default: LintLevel::Warn,
+        description:
+            "Public key from witness is not bound by a hash commitment on its spending path.",
+    },
+];
+
+pub fn find_lint(name: &str) -> Option<&'static Lint> {
+    LINTS.iter().find(|lint| lint.name == name)
+}
+
+pub fn parse_lint_level(level: &str) -> Option<LintLevel> {
+    match level {
+        "allow" => Some(LintLevel::Allow),
+        "warn" => Some(LintLevel::Warn),
+        "deny" => Some(LintLevel::Deny),
+        _ => None,
+    }
+}
+
+// Level of the lint found at the location.
+pub fn lint_level(pragma: &Pragma, name: &str, loc: &Location) -> LintLevel {
+    let mut level = find_lint(name).map_or(LintLevel::Deny, |lint| lint.default);
+
+    // CLI flags come first in pragma options, so the last one wins.
+    for option in &pragma.options {
+        if option.value == name {
+            if let Some(option_level) = parse_lint_level(&option.key.0) {
+                level = option_level;
+            }
+        }
+    }
+
+    // Innermost statement wins.
+    let mut scope_len = usize::MAX;
+    for attribute in &pragma.attributes {
+        let scope = &attribute.scope;
+        if attribute.lints.iter().any(|lint| lint == name)
+            && scope.start <= loc.start
+            && loc.start < scope.end
+            && scope.end - scope.start <= scope_len
+        {
+            scope_len = scope.end - scope.start;
+            level = attribute.level;
+        }
+    }
+
+    level
+}
+
+// Report lint finding with its level.
+pub fn report(diagnostics: &mut Diagnostics, pragma: &Pragma, name: &str, error: CompileError) {
+    match lint_level(pragma, name, &error.loc) {
+        LintLevel::Allow => (),
+        LintLevel::Warn => diagnostics.push(Severity::Warning, error, Some(name)),
+        LintLevel::Deny => diagnostics.push(Severity::Error, error, Some(name)),
+    }
+}
+
+// Lint names in attributes must be known. Pragma options are checked with other pragmas.
+pub fn check_attributes(pragma: &Pragma, diagnostics: &mut Diagnostics) {
+    for attribute in &pragma.attributes {
+        for lint in &attribute.lints {
+            if find_lint(lint).is_none() {
+                diagnostics.error(CompileError {
+                    loc: attribute.loc.to_owned(),
+                    kind: ErrorKind::UnknownLint(format!("Unknown lint: {:?}.", lint)),
+                });
+            }
+        }
+    }
+}
+
+// Public key literals checked by checksig in the statements.
+fn collect_pubkeys(stmts: &[Statement], pubkeys: &mut Vec<(Location, String)>) {
+    fn from_factor(factor: &Factor, pubkeys: &mut Vec<(Location, String)>) {
+        match factor {
+            Factor::SingleSigFactor { pubkey, .. } => {
+                if let Expression::StringLiteral(loc, key) | Expression::PubKeyLiteral(loc, key) =
+                    &**pubkey
+                {
+                    pubkeys.push((loc.to_owned(), key.to_lowercase()));
+                }
+            }
+            Factor::MultiSigFactor { n, .. } => {
+                for factor in n {
+                    from_factor(factor, pubkeys);
+                }
+            }
+        }
+    }
+    fn from_expression(expr: &Expression, pubkeys: &mut Vec<(Location, String)>) {
+        match expr {
+            Expression::CheckSigExpression { operand, .. } => from_factor(operand, pubkeys),
+            Expression::LogicalExpression { lhs, rhs, .. }
+            | Expression::CompareExpression { lhs, rhs, .. }
+            | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+                from_expression(lhs, pubkeys);
+                from_expression(rhs, pubkeys);
+            }
+            Expression::TernaryMathExpression {
+                operand,
+                lower,
+                upper,
+                ..
+            } => {
+                from_expression(operand, pubkeys);
+                from_expression(lower, pubkeys);
+                from_expression(upper, pubkeys);
+            }
+            Expression::UnaryMathExpression { operand, .. }
+            | Expression::UnaryCryptoExpression { operand, .. }
+            | Expression::ByteExpression { operand, .. } => from_expression(operand, pubkeys),
+            _ => (),
+        }
+    }
+
+    for stmt in stmts {
+        match stmt {
+            Statement::VerifyStatement(_, expr)
+            | Statement::LetStatement { expr, .. }
+            | Statement::ExpressionStatement(_, _, expr) => from_expression(expr, pubkeys),
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                from_expression(condition_expr, pubkeys);
+                collect_pubkeys(if_block, pubkeys);
+                if let Some(else_block) = else_block {
+                    collect_pubkeys(else_block, pubkeys);
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                from_expression(selector, pubkeys);
+                for arm in arms {
+                    collect_pubkeys(&arm.block, pubkeys);
+                }
+            }
+            Statement::LocktimeStatement { .. } => (),
+        }
+    }
+}
+
+// A key checked twice on a path needs two signatures of the same signer, which is rarely intended.
+pub fn check_pubkey_reuse(ast: &[Statement], pragma: &Pragma, diagnostics: &mut Diagnostics) {
+    let mut reported: Vec<Location> = vec![];
+    for leaf in path_leaves(ast) {
+        let mut pubkeys: Vec<(Location, String)> = vec![];
+        collect_pubkeys(&leaf, &mut pubkeys);
+
+        let mut seen: HashMap<String, Location> = HashMap::new();
+        for (loc, key) in pubkeys {
+            match seen.get(&key) {
+                Some(first) if !reported.contains(&loc) => {
+                    reported.push(loc.to_owned());
+                    report(
+                        diagnostics,
+                        pragma,
+                        PUBKEY_REUSE,
+                        CompileError {
+                            loc,
+                            kind: ErrorKind::PubkeyReuse(format!(
+                                "Public key {:?} is already checked at line {} on the same spending path.",
+                                key, first.line
+                            )),
+                        },
+                    );
+                }
+                Some(_) => (),
+                None => {
+                    seen.insert(key, loc);
+                }
+            }
+        }
+    }
+}
+
+// Locktime statements in the statements, recursively.
+fn collect_locktimes<'a>(stmts: &'a [Statement], locktimes: &mut Vec<&'a Statement>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::LocktimeStatement { .. } => locktimes.push(stmt),
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_locktimes(if_block, locktimes);
+                if let Some(else_block) = else_block {
+                    collect_locktimes(else_block, locktimes);
+                }
+            }
+            Statement::MatchStatement { arms, .. } => {
+                for arm in arms {
+                    collect_locktimes(&arm.block, locktimes);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Whether the locktime is time based, instead of block height.
+pub fn is_time_locktime(op: &LocktimeOp, operand: i64) -> bool {
+    match op {
+        // BIP65: below 500,000,000 is block height.
+        LocktimeOp::Cltv => operand >= 500_000_000,
+        // BIP68: type flag(bit 22) of nSequence.
+        LocktimeOp::Csv => operand & (1 << 22) != 0,
+    }
+}
+
+// Contract which mixes height and time locktimes of the same kind is hard to reason about.
+// Mixing them on a single path can never be satisfied, which is not a lint but DeadPath.
+pub fn check_timelock_mixing(ast: &[Statement], pragma: &Pragma, diagnostics: &mut Diagnostics) {
+    let mut locktimes: Vec<&Statement> = vec![];
+    collect_locktimes(ast, &mut locktimes);
+    // Locations of locktimes executed by each spending path.
+    let path_locktimes = path_leaves(ast)
+        .iter()
+        .map(|leaf| {
+            let mut locktimes: Vec<&Statement> = vec![];
+            collect_locktimes(leaf, &mut locktimes);
+            locktimes
+                .iter()
+                .filter_map(|stmt| match stmt {
+                    Statement::LocktimeStatement { loc, .. } => Some(loc.to_owned()),
+                    _ => None,
+                })
+                .collect::<Vec<Location>>()
+        })
+        .collect::<Vec<Vec<Location>>>();
+
+    let mut first: HashMap<String, (bool, Location)> = HashMap::new();
+    for stmt in locktimes {
+        if let Statement::LocktimeStatement {
+            loc,
+            op,
+            operand: Expression::NumberLiteral(_, operand),
+            unit,
+        } = stmt
+        {
+            // Out of range is reported by analyzer.
+            let Ok(value) = locktime_value(op, unit, *operand) else {
+                continue;
+            };
+            let is_time = is_time_locktime(op, value);
+            let kind = format!("{:?}", op);
+            match first.get(&kind) {
+                // Both on a single path is already reported by analyzer as DeadPath.
+                Some((first_is_time, first_loc))
+                    if *first_is_time != is_time
+                        && path_locktimes
+                            .iter()
+                            .any(|locs| locs.contains(first_loc) && locs.contains(loc)) => {}
+                Some((first_is_time, first_loc)) if *first_is_time != is_time => {
+                    report(
+                        diagnostics,
+                        pragma,
+                        TIMELOCK_MIXING,
+                        CompileError {
+                            loc: loc.to_owned(),
+                            kind: ErrorKind::TimelockMixing(format!(
+                                "{} locktime is {} based, but the one at line {} is {} based.",
+                                match op {
+                                    LocktimeOp::Cltv => "Absolute",
+                                    LocktimeOp::Csv => "Relative",
+                                },
+                                if is_time { "time" } else { "height" },
+                                first_loc.line,
+                                if *first_is_time { "time" } else { "height" },
+                            )),
+                        },
+                    );
+                }
+                Some(_) => (),
+                None => {
+                    first.insert(kind, (is_time, loc.to_owned()));
+                }
+            }
+        }
+    }
+}
+
+// Witness items checked by checksig as public key.
+fn collect_witness_keys(expr: &Expression, keys: &mut Vec<(Location, String)>) {
+    fn from_factor(factor: &Factor, keys: &mut Vec<(Location, String)>) {
+        match factor {
+            Factor::SingleSigFactor { pubkey, .. } => {
+                if let Expression::Variable(loc, id) = &**pubkey {
+                    keys.push((loc.to_owned(), id.0.to_owned()));
+                }
+            }
+            Factor::MultiSigFactor { n, .. } => {
+                for factor in n {
+                    from_factor(factor, keys);
+                }
+            }
+        }
+    }
+    match expr {
+        Expression::CheckSigExpression { operand, .. } => from_factor(operand, keys),
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            collect_witness_keys(lhs, keys);
+            collect_witness_keys(rhs, keys);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            collect_witness_keys(operand, keys);
+            collect_witness_keys(lower, keys);
+            collect_witness_keys(upper, keys);
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => collect_witness_keys(operand, keys),
+        _ => (),
+    }
+}
+
+// Items committed by "<hash> item == <literal>", which must hold on the path.
+// Only conjunction is followed, as either side of "||" may not hold.
+fn collect_commitments(expr: &Expression, committed: &mut Vec<String>) {
+    fn hashed_item(expr: &Expression) -> Option<&String> {
+        match expr {
+            Expression::UnaryCryptoExpression { operand, .. } => match &**operand {
+                Expression::Variable(_, id) => Some(&id.0),
+                operand => hashed_item(operand),
+            },
+            _ => None,
+        }
+    }
+    fn is_constant(expr: &Expression) -> bool {
+        let mut ids: Vec<Identifier> = vec![];
+        collect_variables(expr, &mut ids);
+        ids.is_empty()
+    }
+    match expr {
+        Expression::LogicalExpression {
+            lhs,
+            op: BinaryLogicalOp::BoolAnd,
+            rhs,
+            ..
+        } => {
+            collect_commitments(lhs, committed);
+            collect_commitments(rhs, committed);
+        }
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::Equal,
+            rhs,
+            ..
+        } => {
+            for (hash, digest) in [(lhs, rhs), (rhs, lhs)] {
+                if let Some(item) = hashed_item(hash).filter(|_| is_constant(digest)) {
+                    committed.push(item.to_owned());
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+// Anyone can satisfy checksig with a key of their own, unless the script commits to the key.
+pub fn check_uncommitted_pubkey(ast: &[Statement], pragma: &Pragma, diagnostics: &mut Diagnostics) {
+    let mut reported: Vec<Location> = vec![];
+    for leaf in path_leaves(ast) {
+        let mut keys: Vec<(Location, String)> = vec![];
+        let mut committed: Vec<String> = vec![];
+        // Binding is followed to its value, e.g. "let h = ripemd160 sha256 key;".
+        let mut bindings: HashMap<String, Expression> = HashMap::new();
+        for stmt in &leaf {
+            match stmt {
+                Statement::LetStatement {
+                    identifier, expr, ..
+                } => {
+                    let mut expr = expr.to_owned();
+                    bind_expression(&mut expr, &bindings);
+                    bindings.insert(identifier.0.to_owned(), expr);
+                }
+                Statement::VerifyStatement(_, expr)
+                | Statement::ExpressionStatement(_, _, expr) => {
+                    let mut expr = expr.to_owned();
+                    bind_expression(&mut expr, &bindings);
+                    collect_witness_keys(&expr, &mut keys);
+                    collect_commitments(&expr, &mut committed);
+                }
+                _ => (),
+            }
+        }
+
+        for (loc, key) in keys {
+            if committed.contains(&key) || reported.contains(&loc) {
+                continue;
+            }
+            reported.push(loc.to_owned());
+            report(
+                diagnostics,
+                pragma,
+                UNCOMMITTED_PUBKEY,
+                CompileError {
+                    loc,
+                    kind: ErrorKind::UncommittedPubkey(format!(
+                        "Public key {:?} is from witness without hash commitment, so any key can sign.",
+                        key
+                    )),
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,342 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::ErrorKind;
+    use crate::diagnostic::*;
+    use crate::test_util::{diagnose, PUBKEY_A, PUBKEY_B};
+    use crate::testing::run_tests;
+    use crate::{compile_program, compile_program_with_lints};
+
+    fn lints(output: &DiagnosticOutput) -> Vec<(String, String)> {
+        output
+            .diagnostics()
+            .iter()
+            .filter_map(|d| d.lint().map(|lint| (lint, d.severity())))
+            .collect()
+    }
+
+    // Unused x at the bottom of the stack, and the same key checked twice.
+    fn contract(pragma: &str, attribute: &str) -> String {
+        format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            {}
+            (sig_alice: signature, sig_bob: signature, x: number)
+            {{
+                verify checksig (sig_alice, "{}");
+                {}
+                return checksig (sig_bob, "{}");
+            }}
+            "#,
+            pragma, PUBKEY_A, attribute, PUBKEY_A
+        )
+    }
+
+    #[test]
+    fn test_default_levels() {
+        let output = diagnose(&contract("", ""));
+        assert!(output.output().is_none());
+        assert_eq!(
+            lints(&output),
+            vec![
+                ("unused_variable".to_string(), "error".to_string()),
+                ("pubkey_reuse".to_string(), "warning".to_string()),
+            ]
+        );
+        // Lint name is shown with the diagnostic.
+        assert!(output.diagnostics()[0]
+            .to_string()
+            .ends_with("[unused_variable]"));
+        // Fatal lint is still an error for the plain API.
+        assert!(compile_program(contract("", "")).is_err());
+    }
+
+    #[test]
+    fn test_pragma_and_attribute() {
+        let output = diagnose(&contract(
+            "pragma bithoven warn unused_variable;",
+            "#[allow(pubkey_reuse)] // same signer twice",
+        ));
+        assert!(output.output().is_some());
+        assert_eq!(
+            lints(&output),
+            vec![("unused_variable".to_string(), "warning".to_string())]
+        );
+        // Unused item is dropped at the end of script.
+        assert!(output
+            .output()
+            .unwrap()
+            .asm()
+            .ends_with("OP_CHECKSIG OP_NIP"));
+
+        let output = diagnose(&contract(
+            "pragma bithoven allow unused_variable;",
+            "#[deny(pubkey_reuse)]",
+        ));
+        assert!(output.output().is_none());
+        assert_eq!(
+            lints(&output),
+            vec![("pubkey_reuse".to_string(), "error".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_flag_is_overridden_by_source() {
+        let compile = |source: String, allow: &[&str], deny: &[&str]| {
+            compile_program_with_lints(
+                source,
+                "mainnet".to_string(),
+                allow.iter().map(|s| s.to_string()).collect(),
+                vec![],
+                deny.iter().map(|s| s.to_string()).collect(),
+                vec![],
+            )
+        };
+        let output = compile(contract("", ""), &["unused_variable", "pubkey_reuse"], &[]);
+        assert!(output.output().is_some());
+        assert!(output.diagnostics().is_empty());
+
+        let output = compile(
+            contract("pragma bithoven warn pubkey_reuse;", ""),
+            &["unused_variable"],
+            &["pubkey_reuse"],
+        );
+        assert!(output.output().is_some());
+        assert_eq!(
+            lints(&output),
+            vec![("pubkey_reuse".to_string(), "warning".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_unknown_lint() {
+        let output = diagnose(&contract("pragma bithoven allow unused_variables;", ""));
+        assert!(output
+            .diagnostics()
+            .iter()
+            .any(|d| d.code() == "UnknownLint" && d.line() == 4));
+
+        let output = diagnose(&contract("", "#[allow(pubkey_reused)]"));
+        assert!(output
+            .diagnostics()
+            .iter()
+            .any(|d| d.code() == "UnknownLint" && d.line() == 8));
+
+        // Malformed attribute, or attribute without statement.
+        let output = diagnose(&contract("", "#[allow]"));
+        assert_eq!(output.diagnostics()[0].code(), "ParseError");
+        let mut source = contract("", "");
+        source.insert_str(source.rfind('}').unwrap(), "#[allow(pubkey_reuse)]");
+        let output = diagnose(&source);
+        assert_eq!(output.diagnostics()[0].code(), "ParseError");
+    }
+
+    #[test]
+    fn test_timelock_mixing() {
+        let output = diagnose(&format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (condition: bool, sig_alice: signature)
+            (condition: bool, sig_bob: signature)
+            {{
+                if condition {{
+                    after 900000;
+                    return checksig (sig_alice, "{}");
+                }} else {{
+                    after 1800000000;
+                    return checksig (sig_bob, "{}");
+                }}
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B
+        ));
+        assert!(output.output().is_some());
+        assert_eq!(
+            lints(&output),
+            vec![("timelock_mixing".to_string(), "warning".to_string())]
+        );
+        assert_eq!(output.diagnostics()[0].line(), 11);
+    }
+
+    #[test]
+    fn test_timelock_mixing_dead_path() {
+        let source = |stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (condition: bool, sig_alice: signature)
+                (condition: bool, sig_bob: signature)
+                {{
+                    after 900000;
+                    if condition {{
+                        {}
+                        return checksig (sig_alice, "{}");
+                    }} else {{
+                        return checksig (sig_bob, "{}");
+                    }}
+                }}
+                "#,
+                stmt, PUBKEY_A, PUBKEY_B
+            )
+        };
+
+        // Mixed on a single path is only reported as DeadPath.
+        let output = diagnose(&source("after 1800000000;"));
+        let diagnostics = output.diagnostics();
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].code(), "DeadPath");
+        assert_eq!(diagnostics[0].line(), 9);
+        assert!(lints(&output).is_empty());
+        // Later locktimes on the dead path are not reported again.
+        let output = diagnose(&source(
+            "after 2027-01-01T00:00Z;\n                        after 200;",
+        ));
+        let diagnostics = output.diagnostics();
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].code(), "DeadPath");
+        assert_eq!(diagnostics[0].line(), 9);
+
+        // Relative locktime doesn't mix with absolute one.
+        assert!(diagnose(&source("older 2 days;")).diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_uncommitted_pubkey() {
+        let source = |stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                pragma bithoven stack relaxed;
+                (key: pubkey, sig: signature)
+                {{
+                    {}
+                    return checksig (sig, key);
+                }}
+                "#,
+                stmt
+            )
+        };
+        // P2PKH
+        let output = diagnose(&source(
+            "verify ripemd160 sha256 key == 0x1111111111111111111111111111111111111111;",
+        ));
+        assert!(output.diagnostics().is_empty());
+        assert_eq!(
+            output.output().unwrap().asm(),
+            "OP_DUP OP_HASH160 OP_PUSHBYTES_20 1111111111111111111111111111111111111111 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+
+        // Any key, or a commitment which may not hold.
+        for stmt in [
+            "",
+            "verify sha256 key == sha256 sig;",
+            "verify ripemd160 sha256 key == 0x1111111111111111111111111111111111111111 || len sig == 0;",
+        ] {
+            let output = diagnose(&source(stmt));
+            assert!(output.output().is_some(), "{:?}", output.diagnostics());
+            assert_eq!(
+                lints(&output),
+                vec![("uncommitted_pubkey".to_string(), "warning".to_string())],
+                "{}",
+                stmt
+            );
+            assert_eq!(output.diagnostics()[0].line(), 8);
+        }
+    }
+
+    #[test]
+    fn test_let_binding() {
+        let source = |stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                pragma bithoven stack relaxed;
+                (key: pubkey, sig: signature)
+                {{
+                    {}
+                    return checksig (sig, key);
+                }}
+                "#,
+                stmt
+            )
+        };
+        // Commitment is followed through the binding.
+        let output = diagnose(&source(
+            "let hash = ripemd160 sha256 key;\n verify hash == 0x1111111111111111111111111111111111111111;",
+        ));
+        assert!(output.diagnostics().is_empty());
+
+        let output = diagnose(&source("let hash = ripemd160 sha256 key;"));
+        assert_eq!(
+            lints(&output),
+            vec![
+                ("unused_variable".to_string(), "error".to_string()),
+                ("uncommitted_pubkey".to_string(), "warning".to_string())
+            ]
+        );
+        assert_eq!(output.diagnostics()[0].line(), 7);
+    }
+
+    #[test]
+    fn test_p2pkh_strict() {
+        let source = |stmt: &str| {
+            format!(
+                r#"
+                pragma bithoven version 0.0.1;
+                pragma bithoven target segwit;
+                (pk: pubkey, sig: signature)
+                {{
+                    {}
+                    return checksig (sig, pk);
+                }}
+                "#,
+                stmt
+            )
+        };
+        // hash160 of PUBKEY_A
+        let p2pkh = source("verify hash160 pk == 0xd4eac89d456a48d2405645c4602264961215968b;");
+        let output = diagnose(&p2pkh);
+        assert!(
+            output.diagnostics().is_empty(),
+            "{:?}",
+            output.diagnostics()
+        );
+        assert_eq!(
+            output.output().unwrap().asm(),
+            "OP_DUP OP_HASH160 OP_PUSHBYTES_20 d4eac89d456a48d2405645c4602264961215968b OP_EQUALVERIFY OP_CHECKSIG"
+        );
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+            key bob = "{}";
+            test "key of the hash" {{
+                witness pk = "{}", sig = sig(alice);
+                expect success;
+            }}
+            test "other key" {{
+                witness pk = "{}", sig = sig(bob);
+                expect failure OP_EQUALVERIFY;
+            }}
+            "#,
+            PUBKEY_A, PUBKEY_B, PUBKEY_A, PUBKEY_B
+        );
+        let report = run_tests(p2pkh, &tests).unwrap();
+        assert!(report.passed(), "{:?}", report);
+
+        // Only a use by its hash leaves the key for checksig, and only once.
+        for stmt in [
+            "verify len pk == 33;",
+            "verify hash160 pk == 0xd4eac89d456a48d2405645c4602264961215968b;\n verify sha256 pk == sha256 \"00\";",
+        ] {
+            assert!(matches!(
+                compile_program(source(stmt)).unwrap_err().kind,
+                ErrorKind::VariableConsumed(msg) if msg.contains("pk")
+            ));
+        }
+    }
+}
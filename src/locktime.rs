@@ -0,0 +1,209 @@
+/*
+    Units of locktime, e.g. "older 2 days;", "after block 900000;" and "after 2027-01-01T00:00Z;".
+    - "after" takes nLockTime(BIP65): block height below 500,000,000, otherwise UNIX timestamp.
+    - "older" takes nSequence(BIP68): 16-bit count of blocks, or of 512 seconds with the type flag.
+      Time is rounded up to 512 seconds, so that the lock is never shorter than written.
+    - Bare number is taken as written, e.g. "older 144;" and "after 1798761600;".
+*/
+use crate::ast::*;
+use crate::vm::{LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG};
+
+// BIP68: relative time is counted in 512 seconds.
+pub const SEQUENCE_GRANULARITY: i64 = 512;
+
+// Seconds of a unit of relative time.
+pub fn unit_seconds(unit: &LocktimeUnit) -> Option<i64> {
+    match unit {
+        LocktimeUnit::Seconds => Some(1),
+        LocktimeUnit::Minutes => Some(60),
+        LocktimeUnit::Hours => Some(60 * 60),
+        LocktimeUnit::Days => Some(24 * 60 * 60),
+        LocktimeUnit::Weeks => Some(7 * 24 * 60 * 60),
+        LocktimeUnit::Raw | LocktimeUnit::Blocks | LocktimeUnit::Date => None,
+    }
+}
+
+// Keyword of unit after "older", singular for 1.
+pub fn unit_name(unit: &LocktimeUnit, singular: bool) -> &'static str {
+    let (one, many) = match unit {
+        LocktimeUnit::Blocks => ("block", "blocks"),
+        LocktimeUnit::Seconds => ("second", "seconds"),
+        LocktimeUnit::Minutes => ("minute", "minutes"),
+        LocktimeUnit::Hours => ("hour", "hours"),
+        LocktimeUnit::Days => ("day", "days"),
+        LocktimeUnit::Weeks => ("week", "weeks"),
+        LocktimeUnit::Raw | LocktimeUnit::Date => ("", ""),
+    };
+    if singular {
+        one
+    } else {
+        many
+    }
+}
+
+// Consensus value of locktime: nLockTime for "after", and nSequence for "older".
+pub fn locktime_value(op: &LocktimeOp, unit: &LocktimeUnit, operand: i64) -> Result<i64, String> {
+    let max_blocks = SEQUENCE_LOCKTIME_MASK as i64;
+    match (op, unit) {
+        (LocktimeOp::Csv, LocktimeUnit::Raw | LocktimeUnit::Blocks) => {
+            // BIP 68: Relative locktime (CSV/older) is physically limited to 16 bits
+            // because it relies on the nSequence field's low 16 bits.
+            if !(0..=max_blocks).contains(&operand) {
+                return Err(format!(
+                    "Relative locktime (older) cannot exceed 65,535 blocks due to BIP 68 limits but got: {}.",
+                    operand
+                ));
+            }
+            Ok(operand)
+        }
+        (LocktimeOp::Csv, LocktimeUnit::Date) => Err(format!(
+            "Relative locktime (older) must be blocks or time, not a date but got: {}.",
+            format_date(operand)
+        )),
+        (LocktimeOp::Csv, unit) => {
+            let seconds = unit_seconds(unit).unwrap_or(1);
+            let intervals = operand
+                .saturating_mul(seconds)
+                .saturating_add(SEQUENCE_GRANULARITY - 1)
+                / SEQUENCE_GRANULARITY;
+            if operand < 0 || intervals > max_blocks {
+                return Err(format!(
+                    "Relative locktime (older) cannot exceed {} seconds(65,535 * 512) due to BIP 68 limits but got: {} {}.",
+                    max_blocks * SEQUENCE_GRANULARITY,
+                    operand,
+                    unit_name(unit, operand == 1)
+                ));
+            }
+            Ok(SEQUENCE_LOCKTIME_TYPE_FLAG as i64 | intervals)
+        }
+        // BIP65: Absolute locktime is limited to u32::MAX
+        (LocktimeOp::Cltv, LocktimeUnit::Raw) => {
+            if !(0..=u32::MAX as i64).contains(&operand) {
+                return Err(format!(
+                    "Locktime must be a 32-bit unsigned integer (0-4294967295), but got: {}.",
+                    operand
+                ));
+            }
+            Ok(operand)
+        }
+        // BIP65: below 500,000,000 is block height, and timestamp from it.
+        (LocktimeOp::Cltv, LocktimeUnit::Blocks) => {
+            if !(0..LOCKTIME_THRESHOLD).contains(&operand) {
+                return Err(format!(
+                    "Block height of absolute locktime (after) must be below 500,000,000 but got: {}.",
+                    operand
+                ));
+            }
+            Ok(operand)
+        }
+        (LocktimeOp::Cltv, LocktimeUnit::Date) => {
+            if !(LOCKTIME_THRESHOLD..=u32::MAX as i64).contains(&operand) {
+                return Err(format!(
+                    "Date of absolute locktime (after) must be from {} to {} but got: {}.",
+                    format_date(LOCKTIME_THRESHOLD),
+                    format_date(u32::MAX as i64),
+                    format_date(operand)
+                ));
+            }
+            Ok(operand)
+        }
+        (LocktimeOp::Cltv, unit) => Err(format!(
+            "Absolute locktime (after) must be a block height or date, not {}.",
+            unit_name(unit, false)
+        )),
+    }
+}
+
+// Operand and unit which read best for the consensus value, e.g. of decompiled script.
+pub fn locktime_operand(op: &LocktimeOp, value: i64) -> (i64, LocktimeUnit) {
+    match op {
+        LocktimeOp::Csv if value & SEQUENCE_LOCKTIME_TYPE_FLAG as i64 != 0 => {
+            let seconds = (value & SEQUENCE_LOCKTIME_MASK as i64) * SEQUENCE_GRANULARITY;
+            for unit in [
+                LocktimeUnit::Weeks,
+                LocktimeUnit::Days,
+                LocktimeUnit::Hours,
+                LocktimeUnit::Minutes,
+            ] {
+                let unit_seconds = unit_seconds(&unit).unwrap_or(1);
+                if seconds > 0 && seconds % unit_seconds == 0 {
+                    return (seconds / unit_seconds, unit);
+                }
+            }
+            (seconds, LocktimeUnit::Seconds)
+        }
+        LocktimeOp::Cltv if value >= LOCKTIME_THRESHOLD => (value, LocktimeUnit::Date),
+        _ => (value, LocktimeUnit::Raw),
+    }
+}
+
+/*
+    UTC date, e.g. "2027-01-01T00:00Z" or "2027-01-01T00:00:30Z", to UNIX timestamp.
+    - Range of each field is checked by lexer, but not the days of month.
+    - Invalid date, e.g. "2027-02-30T00:00Z", is an error reported by parser.
+*/
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let field = |range: std::ops::Range<usize>| {
+        date.get(range)
+            .and_then(|s| s.parse::<i64>().ok())
+            .unwrap_or(0)
+    };
+    let (year, month, day) = (field(0..4), field(5..7), field(8..10));
+    let (hour, minute) = (field(11..13), field(14..16));
+    let second = if date.len() > 17 { field(17..19) } else { 0 };
+
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return Err(format!(
+            "Invalid date {:?}: {:04}-{:02} has no day {}.",
+            date, year, month, day
+        ));
+    }
+    Ok(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// UNIX timestamp to UTC date, with seconds only if any.
+pub fn format_date(timestamp: i64) -> String {
+    let (days, time) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (time / 3600, time % 3600 / 60, time % 60);
+    match second {
+        0 => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}Z",
+            year, month, day, hour, minute
+        ),
+        _ => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, hour, minute, second
+        ),
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar.
+// Reference: <https://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days.saturating_add(719468);
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
@@ -0,0 +1,161 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::compile_program;
+    use crate::decompile::decompile;
+    use crate::format::format_source;
+    use crate::locktime::*;
+    use crate::test_util::{error_kind, PUBKEY_A};
+    use crate::testing::run_tests;
+
+    fn source(stmts: &str) -> String {
+        format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n\n(sig: signature)\n{{\n{}\n    return checksig (sig, \"{}\");\n}}\n",
+            stmts, PUBKEY_A
+        )
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse_date("1970-01-01T00:00Z"), Ok(0));
+        assert_eq!(parse_date("2027-01-01T00:00Z"), Ok(1798761600));
+        assert_eq!(parse_date("2024-02-29T12:30:15Z"), Ok(1709209815));
+        // No such day.
+        assert!(parse_date("2027-02-29T00:00Z").is_err());
+
+        assert_eq!(format_date(1798761600), "2027-01-01T00:00Z");
+        assert_eq!(format_date(1709209815), "2024-02-29T12:30:15Z");
+        assert_eq!(format_date(500_000_000), "1985-11-05T00:53:20Z");
+    }
+
+    #[test]
+    fn test_locktime_value() {
+        use LocktimeOp::*;
+        use LocktimeUnit::*;
+
+        assert_eq!(locktime_value(&Csv, &Raw, 144), Ok(144));
+        assert_eq!(locktime_value(&Csv, &Blocks, 1008), Ok(1008));
+        // 172800 seconds are rounded up to 338 * 512 seconds.
+        assert_eq!(locktime_value(&Csv, &Days, 2), Ok((1 << 22) | 338));
+        assert_eq!(locktime_value(&Csv, &Seconds, 512), Ok((1 << 22) | 1));
+        assert_eq!(
+            locktime_value(&Csv, &Seconds, 65535 * 512),
+            Ok((1 << 22) | 65535)
+        );
+        assert!(locktime_value(&Csv, &Seconds, 65535 * 512 + 1).is_err());
+        assert!(locktime_value(&Csv, &Blocks, 65536).is_err());
+        assert!(locktime_value(&Csv, &Days, -1).is_err());
+
+        assert_eq!(locktime_value(&Cltv, &Blocks, 900000), Ok(900000));
+        assert!(locktime_value(&Cltv, &Blocks, 500_000_000).is_err());
+        assert_eq!(locktime_value(&Cltv, &Date, 1798761600), Ok(1798761600));
+        assert!(locktime_value(&Cltv, &Date, 0).is_err());
+        assert!(locktime_value(&Cltv, &Days, 1).is_err());
+
+        // Back to the unit which reads best.
+        assert_eq!(locktime_operand(&Csv, 144), (144, Raw));
+        assert_eq!(locktime_operand(&Csv, (1 << 22) | 675), (4, Days));
+        assert_eq!(locktime_operand(&Csv, (1 << 22) | 338), (173056, Seconds));
+        assert_eq!(locktime_operand(&Cltv, 900000), (900000, Raw));
+        assert_eq!(locktime_operand(&Cltv, 1798761600), (1798761600, Date));
+    }
+
+    #[test]
+    fn test_compile_units() {
+        let time = source("    older 2 days;\n    after 2027-01-01T00:00Z;");
+        let asm = compile_program(time.clone()).unwrap().asm();
+        assert!(asm.starts_with(
+            "OP_PUSHBYTES_3 520140 OP_CSV OP_DROP OP_PUSHBYTES_4 80ec366b OP_CLTV OP_DROP"
+        ));
+        assert_eq!(format_source(&time).unwrap(), time);
+
+        // Blocks are the same as bare number.
+        let blocks = compile_program(source("    older 1008 blocks;\n    after block 900000;"));
+        let bare = compile_program(source("    older 1008;\n    after 900000;"));
+        assert_eq!(blocks.unwrap().bytes(), bare.unwrap().bytes());
+
+        // Unit is a keyword only after the operand.
+        let named = source("    older 1 day;")
+            .replace("(sig:", "(days:")
+            .replace("(sig,", "(days,");
+        assert_eq!(format_source(&named).unwrap(), named);
+
+        let tests = format!(
+            r#"
+            key alice = "{}";
+
+            test "after two days" {{
+                witness sig = sig(alice);
+                sequence 4194642;
+                locktime 1798761600;
+                expect success;
+            }}
+
+            test "in blocks" {{
+                witness sig = sig(alice);
+                sequence 4000;
+                locktime 1798761600;
+                expect failure;
+            }}
+
+            test "before the date" {{
+                witness sig = sig(alice);
+                sequence 4194642;
+                locktime 1798761599;
+                expect failure;
+            }}
+            "#,
+            PUBKEY_A
+        );
+        let report = run_tests(time, &tests).unwrap();
+        assert!(report.passed(), "{:?}", report);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for stmt in [
+            "older 389 days;",
+            "older 65536 blocks;",
+            "after block 500000000;",
+            "after 1970-01-01T00:00Z;",
+            "after 2106-02-08T00:00Z;",
+        ] {
+            assert!(
+                matches!(
+                    error_kind(compile_program(source(&format!("    {}", stmt)))),
+                    ErrorKind::IntegerOverflow(_)
+                ),
+                "{}",
+                stmt
+            );
+        }
+        assert!(compile_program(source("    older 388 days;")).is_ok());
+
+        // Date with no such day is not a valid literal, reported at the date.
+        let result = compile_program(source("    after 2027-02-30T00:00Z;"));
+        let err = result.clone().unwrap_err();
+        assert_eq!((err.loc.line, err.loc.column), (6, 11));
+        assert!(matches!(
+            error_kind(result),
+            ErrorKind::ParseError(msg) if msg.contains("2027-02-30T00:00Z")
+        ));
+    }
+
+    #[test]
+    fn test_decompile_units() {
+        let script = compile_program(source("    older 4 days;\n    after 2027-06-01T12:30:15Z;"))
+            .unwrap()
+            .bytes();
+        let decompiled = decompile(&script, None).unwrap();
+        assert!(decompiled.complete);
+        // 4 days are exactly 675 * 512 seconds.
+        assert!(decompiled
+            .source
+            .contains("    older 4 days;\n    after 2027-06-01T12:30:15Z;\n"));
+        assert_eq!(compile_program(decompiled.source).unwrap().bytes(), script);
+
+        // Other flag of nSequence is not lifted.
+        let disabled = hex::decode("050000008000b275").unwrap();
+        assert!(!decompile(&disabled, None).unwrap().complete);
+    }
+}
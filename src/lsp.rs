@@ -0,0 +1,579 @@
+/*
+    Language server of bithoven, speaking LSP(JSON-RPC) over stdio.
+    - Documents are synced in full, and analyzed again on every change.
+    - Positions of LSP are 0-based line and UTF-16 character, converted from byte offset of source.
+    - Variables are resolved per spending path, as each path has its own input stack.
+*/
+use crate::ast::*;
+use crate::compile::path_leaves;
+use crate::diagnostic::Diagnostics;
+use crate::param::Unbound;
+use crate::path::resolve_paths_diagnostics;
+use crate::source::*;
+use crate::{diagnose, parse};
+
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// Keywords and types for completion.
+pub const KEYWORDS: &[&str] = &[
+    "pragma",
+    "bithoven",
+    "version",
+    "target",
+    "param",
+    "const",
+    "legacy",
+    "segwit",
+    "taproot",
+    "if",
+    "else",
+    "match",
+    "return",
+    "path",
+    "verify",
+    "older",
+    "after",
+    "block",
+    "blocks",
+    "seconds",
+    "minutes",
+    "hours",
+    "days",
+    "weeks",
+    "negate",
+    "abs",
+    "max",
+    "min",
+    "within",
+    "checksig",
+    "sha256",
+    "ripemd160",
+    "sha1",
+    "tagged_hash",
+    "len",
+    "true",
+    "false",
+];
+pub const TYPES: &[&str] = &[
+    "bool",
+    "number",
+    "string",
+    "signature",
+    "pubkey",
+    "xonly",
+    "bytes",
+    "hash160",
+    "hash256",
+];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// LSP enums
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+const COMPLETION_KEYWORD: u8 = 14;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_VARIABLE: u8 = 13;
+
+/// Reads a message framed by "Content-Length" header. Returns None at the end of input.
+/// Malformed JSON body is returned as null, to be answered with parse error.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+/// Writes a message framed by "Content-Length" header.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves messages until "exit", returning the exit code.
+/// Exit code is 0 only if "shutdown" was requested before, as the protocol requires.
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut reader)? {
+        for reply in server.handle(&message) {
+            write_message(&mut writer, &reply)?;
+        }
+        if server.exited {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+    }
+    Ok(1)
+}
+
+#[derive(Debug, Default)]
+pub struct Server {
+    // Source of open documents by uri.
+    documents: HashMap<String, String>,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    /// Handles a request or notification, returning responses and notifications to send.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        if message.is_null() {
+            return vec![error_response(&Value::Null, PARSE_ERROR, "Invalid JSON")];
+        }
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            // Response of the client, nothing is requested by this server.
+            None => return vec![],
+        };
+        let params = &message["params"];
+
+        // Notifications
+        let id = match message.get("id") {
+            Some(id) => id,
+            None => {
+                return match method {
+                    "textDocument/didOpen" => {
+                        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                        let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                        self.documents.insert(uri.to_string(), text.to_string());
+                        vec![self.publish_diagnostics(uri)]
+                    }
+                    "textDocument/didChange" => {
+                        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                        // Full sync: the last change has the whole text.
+                        match params["contentChanges"]
+                            .as_array()
+                            .and_then(|changes| changes.last())
+                            .and_then(|change| change["text"].as_str())
+                        {
+                            Some(text) => {
+                                self.documents.insert(uri.to_string(), text.to_string());
+                                vec![self.publish_diagnostics(uri)]
+                            }
+                            None => vec![],
+                        }
+                    }
+                    "textDocument/didClose" => {
+                        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                        self.documents.remove(uri);
+                        vec![json!({
+                            "jsonrpc": "2.0",
+                            "method": "textDocument/publishDiagnostics",
+                            "params": { "uri": uri, "diagnostics": [] },
+                        })]
+                    }
+                    "exit" => {
+                        self.exited = true;
+                        vec![]
+                    }
+                    _ => vec![],
+                };
+            }
+        };
+
+        // Requests
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": {
+                    "name": "bithoven-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Some(Value::Null)
+            }
+            "textDocument/completion" => Some(completion()),
+            "textDocument/hover" | "textDocument/definition" | "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.documents.get(uri).map(|source| match method {
+                    "textDocument/hover" => hover(source, &params["position"]),
+                    "textDocument/definition" => definition(uri, source, &params["position"]),
+                    _ => document_symbols(source),
+                })
+            }
+            _ => {
+                return vec![error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("Unknown method: {}", method),
+                )];
+            }
+        };
+
+        match result {
+            Some(result) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            None => vec![error_response(id, INVALID_PARAMS, "Unknown document")],
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics(source) },
+        })
+    }
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Every error and warning of the source, as LSP diagnostics.
+pub fn diagnostics(source: &str) -> Vec<Value> {
+    let line_index = build_line_index(source);
+    // Template is checked with placeholder of each parameter, as values are given at compile time.
+    let output = diagnose(
+        source.to_string(),
+        "mainnet".to_string(),
+        &[],
+        &[],
+        Unbound::Placeholder,
+    );
+    output
+        .diagnostics()
+        .iter()
+        .map(|d| {
+            // Parse error has only line and column, so it spans to the end of line.
+            let start = line_index
+                .get(d.line().saturating_sub(1))
+                .map_or(0, |line_start| line_start + d.column().saturating_sub(1))
+                .min(source.len());
+            let end = if d.end() > start {
+                d.end()
+            } else {
+                source[start..]
+                    .find('\n')
+                    .map_or(source.len(), |i| start + i)
+            };
+            json!({
+                "range": range(source, &line_index, start, end),
+                "severity": if d.is_error() { SEVERITY_ERROR } else { SEVERITY_WARNING },
+                "code": d.lint().unwrap_or_else(|| d.code()),
+                "source": "bithoven",
+                "message": d.message(),
+            })
+        })
+        .collect()
+}
+
+fn completion() -> Value {
+    let items: Vec<Value> = KEYWORDS
+        .iter()
+        .chain(TYPES)
+        .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }))
+        .collect();
+    json!(items)
+}
+
+/// Variable under the position with its type and stack position on each spending path.
+pub fn hover(source: &str, position: &Value) -> Value {
+    let line_index = build_line_index(source);
+    let (utxo, (loc, name, paths)) = match lookup(source, &line_index, position) {
+        Some(found) => found,
+        None => return Value::Null,
+    };
+
+    let mut lines: Vec<String> = vec![];
+    for path in paths {
+        if let Some((param, position)) = find_param(&utxo.input_stack[path], &name) {
+            lines.push(format!(
+                "- path {}: `{}`, stack position {}",
+                path, param.ty, position
+            ));
+        }
+    }
+    json!({
+        "contents": {
+            "kind": "markdown",
+            "value": format!(
+                "```bithoven\n{}\n```\nStack position 0 is the top of the input stack.\n\n{}",
+                name,
+                lines.join("\n")
+            ),
+        },
+        "range": range(source, &line_index, loc.start, loc.end),
+    })
+}
+
+/// Declarations in the input stack of the variable under the position.
+pub fn definition(uri: &str, source: &str, position: &Value) -> Value {
+    let line_index = build_line_index(source);
+    let (utxo, (_, name, paths)) = match lookup(source, &line_index, position) {
+        Some(found) => found,
+        None => return Value::Null,
+    };
+
+    let locations: Vec<Value> = paths
+        .into_iter()
+        .filter_map(|path| find_param(&utxo.input_stack[path], &name))
+        .map(|(param, _)| {
+            json!({
+                "uri": uri,
+                "range": range(source, &line_index, param.loc.start, param.loc.end),
+            })
+        })
+        .collect();
+    json!(locations)
+}
+
+/// Each spending path with its input stack.
+pub fn document_symbols(source: &str) -> Value {
+    let line_index = build_line_index(source);
+    let mut utxo = match parse(source.to_string()) {
+        Ok(utxo) => utxo,
+        Err(_) => return json!([]),
+    };
+    // Named paths are kept in order of declaration if they don't match.
+    resolve_paths_diagnostics(&mut utxo, &mut Diagnostics::default());
+    let script_loc = script_location(&utxo.output_script);
+
+    let symbols: Vec<Value> = utxo
+        .input_stack
+        .iter()
+        .zip(&utxo.paths)
+        .enumerate()
+        .map(|(path, (stack, name))| {
+            // Stack is kept from the top, which is the reverse of declaration.
+            let params: Vec<&StackParam> = stack.iter().rev().collect();
+            let start = match name {
+                Some(name) => name.loc.start,
+                None => params.first().map_or(script_loc.start, |p| p.loc.start),
+            };
+            let end = params.last().map_or(start, |p| p.loc.end);
+            let children: Vec<Value> = params
+                .iter()
+                .map(|param| {
+                    let param_range = range(source, &line_index, param.loc.start, param.loc.end);
+                    json!({
+                        "name": param.identifier.0,
+                        "detail": param.ty.to_string(),
+                        "kind": SYMBOL_VARIABLE,
+                        "range": param_range,
+                        "selectionRange": param_range,
+                    })
+                })
+                .collect();
+            let path_range = range(source, &line_index, start, end);
+            json!({
+                "name": match name {
+                    Some(name) => format!("path {}", name.identifier.0),
+                    None => format!("path {}", path),
+                },
+                "detail": format!(
+                    "({})",
+                    params
+                        .iter()
+                        .map(|p| format!("{}: {}", p.identifier.0, p.ty))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+                "kind": SYMBOL_FUNCTION,
+                "range": path_range,
+                "selectionRange": path_range,
+                "children": children,
+            })
+        })
+        .collect();
+    json!(symbols)
+}
+
+// Location and name of variable, with spending paths it is resolved on.
+type Resolved = (Location, String, Vec<usize>);
+
+// Parsed source and the variable under the position.
+fn lookup(source: &str, line_index: &[usize], position: &Value) -> Option<(Bithoven, Resolved)> {
+    let offset = offset(source, line_index, position)?;
+    let mut utxo = parse(source.to_string()).ok()?;
+    resolve_paths_diagnostics(&mut utxo, &mut Diagnostics::default());
+    let resolved = resolve(&utxo, offset)?;
+    Some((utxo, resolved))
+}
+
+// Variable at the offset.
+// It is either a declaration in the input stack, or a use in the script.
+fn resolve(utxo: &Bithoven, offset: usize) -> Option<Resolved> {
+    let contains = |loc: &Location| loc.start <= offset && offset <= loc.end;
+
+    for (path, stack) in utxo.input_stack.iter().enumerate() {
+        if let Some(param) = stack.iter().find(|param| contains(&param.loc)) {
+            return Some((
+                param.loc.to_owned(),
+                param.identifier.0.to_owned(),
+                vec![path],
+            ));
+        }
+    }
+
+    let mut uses: Vec<(Location, String)> = vec![];
+    collect_block_uses(&utxo.output_script, &mut uses);
+    let (loc, name) = uses.into_iter().find(|(loc, _)| contains(loc))?;
+
+    // Paths which execute the use.
+    let paths = path_leaves(&utxo.output_script)
+        .iter()
+        .enumerate()
+        .filter(|(path, leaf)| {
+            let mut leaf_uses: Vec<(Location, String)> = vec![];
+            collect_block_uses(leaf, &mut leaf_uses);
+            *path < utxo.input_stack.len() && leaf_uses.iter().any(|(l, _)| *l == loc)
+        })
+        .map(|(path, _)| path)
+        .collect();
+    Some((loc, name, paths))
+}
+
+// Param of the name and its position from the top.
+fn find_param<'a>(stack: &'a [StackParam], name: &str) -> Option<(&'a StackParam, usize)> {
+    stack
+        .iter()
+        .position(|param| param.identifier.0 == name)
+        .map(|i| (&stack[i], stack.len() - 1 - i))
+}
+
+fn collect_block_uses(block: &[Statement], uses: &mut Vec<(Location, String)>) {
+    for stmt in block {
+        match stmt {
+            Statement::VerifyStatement(_, expr)
+            | Statement::LetStatement { expr, .. }
+            | Statement::ExpressionStatement(_, _, expr) => collect_uses(expr, uses),
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_uses(condition_expr, uses);
+                collect_block_uses(if_block, uses);
+                if let Some(else_block) = else_block {
+                    collect_block_uses(else_block, uses);
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                collect_uses(selector, uses);
+                for arm in arms {
+                    collect_block_uses(&arm.block, uses);
+                }
+            }
+            Statement::LocktimeStatement { .. } => (),
+        }
+    }
+}
+
+fn collect_uses(expr: &Expression, uses: &mut Vec<(Location, String)>) {
+    match expr {
+        Expression::Variable(loc, id) => uses.push((loc.to_owned(), id.0.to_owned())),
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            collect_uses(lhs, uses);
+            collect_uses(rhs, uses);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            collect_uses(operand, uses);
+            collect_uses(lower, uses);
+            collect_uses(upper, uses);
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => collect_uses(operand, uses),
+        Expression::CheckSigExpression { operand, .. } => collect_factor_uses(operand, uses),
+        Expression::NumberLiteral(..)
+        | Expression::BooleanLiteral(..)
+        | Expression::StringLiteral(..)
+        | Expression::BytesLiteral(..)
+        | Expression::PubKeyLiteral(..) => (),
+    }
+}
+
+fn collect_factor_uses(factor: &Factor, uses: &mut Vec<(Location, String)>) {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            collect_uses(sig, uses);
+            collect_uses(pubkey, uses);
+        }
+        Factor::MultiSigFactor { n, .. } => {
+            for factor in n {
+                collect_factor_uses(factor, uses);
+            }
+        }
+    }
+}
+
+// LSP position of the byte offset.
+fn position(source: &str, line_index: &[usize], offset: usize) -> Value {
+    let offset = offset.min(source.len());
+    let (line, _) = get_line_and_column(line_index, offset);
+    let line_start = line_index[line - 1];
+    let character = source
+        .get(line_start..offset)
+        .map_or(0, |prefix| prefix.encode_utf16().count());
+    json!({ "line": line - 1, "character": character })
+}
+
+fn range(source: &str, line_index: &[usize], start: usize, end: usize) -> Value {
+    json!({
+        "start": position(source, line_index, start),
+        "end": position(source, line_index, end),
+    })
+}
+
+// Byte offset of the LSP position.
+fn offset(source: &str, line_index: &[usize], position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = *line_index.get(line)?;
+    let line_end = line_index.get(line + 1).map_or(source.len(), |next| *next);
+
+    let mut units = 0;
+    for (i, c) in source[line_start..line_end].char_indices() {
+        if units >= character {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(line_end)
+}
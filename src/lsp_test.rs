@@ -0,0 +1,161 @@
+#[cfg(test)]
+mod tests {
+    use crate::lsp::*;
+
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::io::BufReader;
+
+    const URI: &str = "file:///htlc.bithoven";
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "text": text } },
+        }))
+    }
+
+    fn request(server: &mut Server, method: &str, line: u64, character: u64) -> Value {
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            },
+        }));
+        replies[0]["result"].to_owned()
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let mut buffer: Vec<u8> = vec![];
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize" });
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: "));
+
+        let mut reader = BufReader::new(&buffer[..]);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert!(read_message(&mut reader).unwrap().is_some());
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+
+        // Exit without shutdown is an error.
+        let mut output: Vec<u8> = vec![];
+        assert_eq!(run(BufReader::new(&buffer[..]), &mut output).unwrap(), 1);
+        assert!(String::from_utf8(output).unwrap().contains("capabilities"));
+    }
+
+    #[test]
+    fn test_live_diagnostics() {
+        let mut server = Server::default();
+        let source = fs::read_to_string("example/htlc.bithoven").unwrap();
+        let published = open(&mut server, &source);
+        assert_eq!(published[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(published[0]["params"]["diagnostics"], json!([]));
+
+        // Undefined variable on line 10 (0-based).
+        let broken = source.replace("older 1000;", "verify age > 1000;");
+        let published = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": broken }],
+            },
+        }));
+        let diagnostics = published[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["code"], "UndefinedVariable");
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({
+                "start": { "line": 9, "character": 15 },
+                "end": { "line": 9, "character": 18 },
+            })
+        );
+
+        // Parse error spans to the end of line.
+        let published = open(&mut server, &source.replace("older 1000;", "older;"));
+        let diagnostics = published[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics[0]["code"], "ParseError");
+        assert_eq!(diagnostics[0]["range"]["end"]["line"], 9);
+    }
+
+    #[test]
+    fn test_hover_and_definition() {
+        let mut server = Server::default();
+        open(
+            &mut server,
+            &fs::read_to_string("example/htlc.bithoven").unwrap(),
+        );
+
+        // "preimage" in "verify sha256 sha256 preimage == ...".
+        let hover = request(&mut server, "textDocument/hover", 14, 30);
+        let value = hover["contents"]["value"].as_str().unwrap();
+        assert!(value.contains("preimage"));
+        assert!(value.contains("path 1: `string`, stack position 1"));
+        assert!(!value.contains("path 0"));
+
+        // "condition" of if statement is declared on both paths.
+        let definition = request(&mut server, "textDocument/definition", 7, 8);
+        assert_eq!(
+            definition,
+            json!([
+                {
+                    "uri": URI,
+                    "range": {
+                        "start": { "line": 3, "character": 1 },
+                        "end": { "line": 3, "character": 16 },
+                    },
+                },
+                {
+                    "uri": URI,
+                    "range": {
+                        "start": { "line": 4, "character": 1 },
+                        "end": { "line": 4, "character": 16 },
+                    },
+                },
+            ])
+        );
+
+        // Nothing on keyword.
+        assert_eq!(
+            request(&mut server, "textDocument/hover", 9, 9),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_completion_and_symbols() {
+        let mut server = Server::default();
+        open(
+            &mut server,
+            &fs::read_to_string("example/htlc.bithoven").unwrap(),
+        );
+
+        let completion = request(&mut server, "textDocument/completion", 0, 0);
+        let labels: Vec<&str> = completion
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect();
+        assert!(labels.contains(&"checksig"));
+        assert!(labels.contains(&"signature"));
+
+        let symbols = request(&mut server, "textDocument/documentSymbol", 0, 0);
+        assert_eq!(symbols[0]["name"], "path 0");
+        assert_eq!(
+            symbols[1]["detail"],
+            "(condition: bool, preimage: string, sig_bob: signature)"
+        );
+        assert_eq!(symbols[1]["children"].as_array().unwrap().len(), 3);
+
+        // Unknown method
+        let replies = server.handle(&json!({ "jsonrpc": "2.0", "id": 2, "method": "foo/bar" }));
+        assert_eq!(replies[0]["error"]["code"], -32601);
+    }
+}
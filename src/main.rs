@@ -1,5 +1,10 @@
-use bithoven::compile_program;
-use clap::{Parser, Subcommand};
+use bithoven::decompile::decompile;
+use bithoven::format::format_source;
+use bithoven::policy::{from_policy, to_policy};
+use bithoven::testing::{run_tests, TestResult};
+use bithoven::vm::{execute, MockSigChecker, SigVersion, TxContext, SEQUENCE_FINAL};
+use bithoven::{compile_program_with_lints, compile_program_with_params, Target};
+use clap::{Args, Parser, Subcommand};
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
@@ -24,36 +29,243 @@ enum Commands {
         /// - "json": Prints JSON to stdout
         /// - "asm": Prints ASM to stdout
         /// - "hex": Prints Hex to stdout
+        /// - "address": Prints address to stdout
         #[arg(short, long, default_value = "file")]
         format: String,
+
+        /// Network of the address: "mainnet", "testnet", "testnet4", "signet" or "regtest"
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+
+        /// Lint to ignore, e.g. "unused_variable". Repeat for each lint.
+        #[arg(short = 'A', long)]
+        allow: Vec<String>,
+
+        /// Lint to report as warning. Repeat for each lint.
+        #[arg(short = 'W', long)]
+        warn: Vec<String>,
+
+        /// Lint to report as error. Repeat for each lint.
+        #[arg(short = 'D', long)]
+        deny: Vec<String>,
+
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+    /// Executes a compiled .bithoven file against a witness stack
+    Run {
+        /// The source file to run
+        file: PathBuf,
+
+        /// Witness item in hex, stack bottom first. Repeat for each item, "" for empty.
+        #[arg(short, long)]
+        witness: Vec<String>,
+
+        /// Valid signature as "<sig hex>:<pubkey hex>". Repeat for each signature.
+        /// Without any, every non-empty signature is valid.
+        #[arg(short, long)]
+        sig: Vec<String>,
+
+        /// nSequence of the spending input
+        #[arg(long, default_value_t = SEQUENCE_FINAL)]
+        sequence: u32,
+
+        /// nLockTime of the spending transaction
+        #[arg(long, default_value_t = 0)]
+        locktime: u32,
+
+        /// Version of the spending transaction
+        #[arg(long, default_value_t = 2)]
+        tx_version: i32,
+
+        /// Spending path of the tapleaf to run by index or name, if each path is a tapleaf
+        #[arg(short, long, default_value = "0")]
+        path: String,
+
+        /// Output format: "trace" (default) or "json"
+        #[arg(short, long, default_value = "trace")]
+        format: String,
+
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+    /// Runs the tests of a .bithoven file
+    Test {
+        /// The source file to test
+        file: PathBuf,
+
+        /// The test file. Defaults to <filename>.test, e.g. htlc.bithoven.test
+        #[arg(short, long)]
+        tests: Option<PathBuf>,
+
+        /// Output format: "text" (default) or "json"
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+    /// Formats .bithoven files in place
+    Fmt {
+        /// The source files to format
+        files: Vec<PathBuf>,
+
+        /// Only check formatting, exiting with 1 if any file is not formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Decompiles a hex script to .bithoven source
+    Decompile {
+        /// The script in hex
+        script: String,
+
+        /// Target of the script: "legacy", "segwit" or "taproot".
+        /// Defaults to taproot if the script has OP_CHECKSIGADD or x-only keys, otherwise segwit.
+        #[arg(short, long)]
+        target: Option<String>,
+    },
+    /// Prints the Miniscript policy of a .bithoven file
+    Policy {
+        /// The source file to convert
+        file: PathBuf,
+
+        #[command(flatten)]
+        params: ParamArgs,
+    },
+    /// Converts a Miniscript policy or descriptor to .bithoven source
+    ImportPolicy {
+        /// The policy, e.g. "or(pk(A),and(older(144),pk(B)))", or a descriptor "wsh(..)"
+        policy: String,
+
+        /// Target of the source: "legacy", "segwit" or "taproot".
+        /// Defaults to the one of descriptor, otherwise segwit.
+        #[arg(short, long)]
+        target: Option<String>,
     },
 }
 
+// Values of template parameters.
+#[derive(Args)]
+struct ParamArgs {
+    /// Value of template parameter as "name=value", e.g. "timeout=144". Repeat for each parameter.
+    #[arg(long)]
+    param: Vec<String>,
+
+    /// JSON file of parameter values, e.g. {"alice": "02..", "timeout": 144}.
+    /// Values given by --param take precedence.
+    #[arg(long)]
+    params: Option<PathBuf>,
+}
+
+// Parameter values as "name=value", from the file first to be overridden by flags.
+fn param_values(args: ParamArgs) -> Vec<String> {
+    let mut values = vec![];
+    if let Some(file) = &args.params {
+        let json: serde_json::Value = match serde_json::from_str(&read_source(file)) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Invalid JSON {:?}: {}", file, e);
+                std::process::exit(1);
+            }
+        };
+        let Some(object) = json.as_object() else {
+            eprintln!("Parameters {:?} must be a JSON object", file);
+            std::process::exit(1);
+        };
+        for (name, value) in object {
+            match value {
+                serde_json::Value::String(value) => values.push(format!("{}={}", name, value)),
+                serde_json::Value::Number(value) => values.push(format!("{}={}", name, value)),
+                _ => {
+                    eprintln!("Parameter {:?} must be a string or number", name);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    values.extend(args.param);
+    values
+}
+
+fn read_source(file: &PathBuf) -> String {
+    match fs::read_to_string(file) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading file {:?}: {}", file, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn decode_hex(value: &str) -> Vec<u8> {
+    match hex::decode(value) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Invalid hex {:?}: {}", value, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_target(target: &str) -> Target {
+    match target {
+        "legacy" => Target::Legacy,
+        "segwit" => Target::Segwit,
+        "taproot" => Target::Taproot,
+        _ => {
+            eprintln!("Invalid target {:?}", target);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Compile { file, format } => {
+        Commands::Compile {
+            file,
+            format,
+            network,
+            allow,
+            warn,
+            deny,
+            params,
+        } => {
             // 1. Read the source file
-            let source = match fs::read_to_string(&file) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error reading file {:?}: {}", file, e);
-                    std::process::exit(1);
-                }
-            };
+            let source = read_source(&file);
 
-            // 2. Compile using the library
-            match compile_program(source) {
-                Ok(output) => {
+            // 2. Compile using the library, printing every error and warning to stderr
+            let params = param_values(params);
+            let result = compile_program_with_lints(source, network, allow, warn, deny, params);
+            for diagnostic in result.diagnostics() {
+                eprintln!("{}", diagnostic);
+            }
+            match result.output() {
+                Some(output) => {
                     // 3. Serialize output to JSON (used for both 'json' and 'file' modes)
                     let json_output = serde_json::to_string_pretty(&output)
                         .expect("Failed to serialize output to JSON");
 
                     // 4. Handle output based on format flag
                     match format.as_str() {
-                        "hex" => println!("{}", output.hex()),
-                        "asm" => println!("{}", output.asm()),
+                        "hex" | "asm" => match output.taproot() {
+                            // Each spending path is a tapleaf, print one per line.
+                            Some(taproot) if output.bytes().is_empty() => {
+                                for leaf in taproot.leaves() {
+                                    match format.as_str() {
+                                        "hex" => println!("{}", leaf.hex()),
+                                        _ => println!("{}", leaf.asm()),
+                                    }
+                                }
+                            }
+                            _ => match format.as_str() {
+                                "hex" => println!("{}", output.hex()),
+                                _ => println!("{}", output.asm()),
+                            },
+                        },
+                        "address" => {
+                            if let Some(locking) = output.locking() {
+                                println!("{}", locking.address());
+                            }
+                        }
                         "json" => println!("{}", json_output),
                         _ => {
                             // Default: Write to file "filename.bithoven.json"
@@ -86,8 +298,243 @@ fn main() {
                         }
                     }
                 }
+                None => std::process::exit(1),
+            }
+        }
+        Commands::Run {
+            file,
+            witness,
+            sig,
+            sequence,
+            locktime,
+            tx_version,
+            path,
+            format,
+            params,
+        } => {
+            let source = read_source(&file);
+            let output =
+                match compile_program_with_params(source, "mainnet".into(), param_values(params)) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                };
+
+            // Rules follow the target: tapscript, witness script or redeem script.
+            let (script, version, control_block) = match output.taproot() {
+                Some(taproot) => {
+                    let leaves = taproot.leaves();
+                    let index = path.parse::<usize>().ok().or_else(|| {
+                        output
+                            .witness()
+                            .iter()
+                            .find(|template| template.name().as_ref() == Some(&path))
+                            .map(|template| template.path())
+                    });
+                    // Single tapleaf is shared by every path.
+                    let leaf = match leaves.iter().find(|leaf| Some(leaf.path()) == index) {
+                        Some(leaf) if output.bytes().is_empty() => leaf,
+                        None if output.bytes().is_empty() => {
+                            eprintln!("No tapleaf for spending path {}", path);
+                            std::process::exit(1);
+                        }
+                        _ => &leaves[0],
+                    };
+                    (
+                        decode_hex(&leaf.hex()),
+                        SigVersion::Tapscript,
+                        decode_hex(&leaf.control_block()),
+                    )
+                }
+                None => match output.locking().and_then(|l| l.witness_script()) {
+                    Some(_) => (output.bytes(), SigVersion::WitnessV0, vec![]),
+                    None => (output.bytes(), SigVersion::Legacy, vec![]),
+                },
+            };
+
+            let checker = if sig.is_empty() {
+                MockSigChecker::accept_any()
+            } else {
+                let mut checker = MockSigChecker::default();
+                for pair in sig {
+                    let Some((sig, pubkey)) = pair.split_once(':') else {
+                        eprintln!("Invalid signature {:?}, expected <sig>:<pubkey>", pair);
+                        std::process::exit(1);
+                    };
+                    checker.valid.insert((decode_hex(sig), decode_hex(pubkey)));
+                }
+                checker
+            };
+            let ctx = TxContext {
+                version: tx_version,
+                lock_time: locktime,
+                sequence,
+                control_block,
+            };
+            let witness = witness.iter().map(|item| decode_hex(item)).collect();
+
+            let execution = execute(&script, witness, version, &ctx, &checker);
+            match format.as_str() {
+                "json" => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&execution)
+                        .expect("Failed to serialize execution to JSON")
+                ),
+                _ => {
+                    for step in &execution.steps {
+                        println!(
+                            "{:>5} {} {:<24} [{}] alt[{}]",
+                            step.pc,
+                            if step.executed { ' ' } else { '-' },
+                            step.opcode,
+                            step.stack.join(" "),
+                            step.altstack.join(" ")
+                        );
+                    }
+                    match &execution.error {
+                        None => println!("Success"),
+                        Some(e) => println!("Failed: {}", e),
+                    }
+                }
+            }
+            if !execution.success() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Test {
+            file,
+            tests,
+            format,
+        } => {
+            let tests = tests.unwrap_or_else(|| {
+                let mut tests = file.clone();
+                if let Some(file_name) = tests.file_name() {
+                    let new_name = format!("{}.test", file_name.to_string_lossy());
+                    tests.set_file_name(new_name);
+                }
+                tests
+            });
+
+            let report = match run_tests(read_source(&file), &read_source(&tests)) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            match format.as_str() {
+                "json" => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report)
+                        .expect("Failed to serialize test report to JSON")
+                ),
+                _ => {
+                    // Named path is printed by its name.
+                    let label = |result: &TestResult| {
+                        result.path_name.clone().unwrap_or(result.path.to_string())
+                    };
+                    for result in &report.results {
+                        match result.passed {
+                            true => {
+                                println!("test {:?} (path {}) ... ok", result.name, label(result))
+                            }
+                            false => println!(
+                                "test {:?} (path {}) ... FAILED: {}",
+                                result.name,
+                                label(result),
+                                result.message
+                            ),
+                        }
+                    }
+                    println!();
+                    for path in 0..report.paths {
+                        let results = report
+                            .results
+                            .iter()
+                            .filter(|result| result.path == path)
+                            .collect::<Vec<_>>();
+                        let passed = results.iter().filter(|result| result.passed).count();
+                        match results.len() {
+                            0 => println!("path {}: no test", path),
+                            total => println!(
+                                "path {}: {} passed; {} failed",
+                                label(results[0]),
+                                passed,
+                                total - passed
+                            ),
+                        }
+                    }
+                    let passed = report.results.iter().filter(|result| result.passed).count();
+                    println!(
+                        "test result: {}. {} passed; {} failed",
+                        if report.passed() { "ok" } else { "FAILED" },
+                        passed,
+                        report.results.len() - passed
+                    );
+                }
+            }
+            if !report.passed() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Fmt { files, check } => {
+            let mut unformatted = false;
+            for file in files {
+                let source = read_source(&file);
+                let formatted = match format_source(&source) {
+                    Ok(formatted) => formatted,
+                    Err(e) => {
+                        eprintln!("{:?}: {}", file, e);
+                        std::process::exit(1);
+                    }
+                };
+                if formatted == source {
+                    continue;
+                }
+                if check {
+                    println!("Would reformat: {}", file.display());
+                    unformatted = true;
+                } else if let Err(e) = fs::write(&file, formatted) {
+                    eprintln!("Error writing file {:?}: {}", file, e);
+                    std::process::exit(1);
+                }
+            }
+            if unformatted {
+                std::process::exit(1);
+            }
+        }
+        Commands::Decompile { script, target } => {
+            let target = target.map(|target| parse_target(&target));
+            match decompile(&decode_hex(&script), target) {
+                Ok(decompiled) => {
+                    print!("{}", decompiled.source);
+                    if !decompiled.complete {
+                        eprintln!("Warning: part of the script could not be lifted, see comments.");
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Policy { file, params } => {
+            match to_policy(&read_source(&file), &param_values(params)) {
+                Ok(policy) => println!("{}", policy),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::ImportPolicy { policy, target } => {
+            let target = target.map(|target| parse_target(&target));
+            match from_policy(&policy, target) {
+                Ok(source) => print!("{}", source),
                 Err(e) => {
-                    // Print compile error to stderr
                     eprintln!("{}", e);
                     std::process::exit(1);
                 }
@@ -0,0 +1,294 @@
+/*
+    Template parameters, e.g. "param alice: pubkey;".
+    - Each use of a parameter is replaced by the literal of its value before analysis,
+      so the rest of the compiler sees a contract written with literals.
+    - Value is given as "name=value": hex for keys, bytes and digests, decimal for number,
+      and hex or text for string, as its literal.
+    - Template without values can still be analyzed, e.g. by editor, with placeholder of
+      the same type bound to each parameter.
+*/
+use crate::analyze::nowhere;
+use crate::ast::*;
+use crate::diagnostic::Diagnostics;
+use crate::source::Locatable;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+use std::collections::{HashMap, HashSet};
+
+// What parameter without value is bound to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unbound {
+    // MissingParam error.
+    Error,
+    // Placeholder of the same type, only for analysis.
+    Placeholder,
+    // Left as variable of its name.
+    Keep,
+}
+
+pub fn bind_params(
+    utxo: &mut Bithoven,
+    values: &[String],
+    unbound: Unbound,
+) -> Result<(), CompileError> {
+    let mut diagnostics = Diagnostics::default();
+    bind_params_diagnostics(utxo, values, unbound, &mut diagnostics);
+    diagnostics.into_result()
+}
+
+pub fn bind_params_diagnostics(
+    utxo: &mut Bithoven,
+    values: &[String],
+    unbound: Unbound,
+    diagnostics: &mut Diagnostics,
+) {
+    // Declarations
+    let mut declared: HashMap<String, StackParam> = HashMap::new();
+    for param in &utxo.params {
+        let name = &param.identifier.0;
+        let is_stack_item = utxo
+            .input_stack
+            .iter()
+            .flatten()
+            .any(|item| item.identifier == param.identifier);
+        if declared.contains_key(name) || is_stack_item || is_bound(&utxo.output_script, name) {
+            diagnostics.error(CompileError {
+                loc: param.loc.to_owned(),
+                kind: ErrorKind::DuplicateVariable(format!(
+                    "The name of parameter cannot be duplicate: {:?} already exists.",
+                    name
+                )),
+            });
+            continue;
+        }
+        declared.insert(name.to_owned(), param.to_owned());
+    }
+
+    // Values
+    let mut literals: HashMap<String, Expression> = HashMap::new();
+    // Names whose value is already reported as invalid, so not reported again as missing.
+    let mut invalid: HashSet<String> = HashSet::new();
+    for value in values {
+        let Some((name, value)) = value.split_once('=') else {
+            diagnostics.error(invalid_param(format!(
+                "Parameter value must be given as name=value but: {:?}.",
+                value
+            )));
+            continue;
+        };
+        let Some(param) = declared.get(name.trim()) else {
+            diagnostics.error(invalid_param(format!(
+                "Unknown parameter: {:?}.",
+                name.trim()
+            )));
+            continue;
+        };
+        match literal(param, value.trim()) {
+            Ok(literal) => {
+                literals.insert(param.identifier.0.to_owned(), literal);
+            }
+            Err(e) => {
+                invalid.insert(param.identifier.0.to_owned());
+                diagnostics.error(e);
+            }
+        }
+    }
+
+    // Parameters without value
+    for param in &utxo.params {
+        let name = &param.identifier.0;
+        if literals.contains_key(name) || !declared.contains_key(name) {
+            continue;
+        }
+        match unbound {
+            Unbound::Error if invalid.contains(name) => (),
+            Unbound::Error => diagnostics.error(CompileError {
+                loc: param.loc.to_owned(),
+                kind: ErrorKind::MissingParam(format!(
+                    "No value is given for parameter: {:?}.",
+                    name
+                )),
+            }),
+            Unbound::Placeholder => {
+                literals.insert(name.to_owned(), placeholder(param));
+            }
+            Unbound::Keep => (),
+        }
+    }
+
+    for stmt in utxo.output_script.iter_mut() {
+        bind_statement(stmt, &literals);
+    }
+}
+
+// Whether the name is bound by "let" in the block.
+pub fn is_bound(block: &[Statement], name: &str) -> bool {
+    block.iter().any(|stmt| match stmt {
+        Statement::LetStatement { identifier, .. } => identifier.0 == name,
+        Statement::IfStatement {
+            if_block,
+            else_block,
+            ..
+        } => is_bound(if_block, name) || else_block.as_ref().is_some_and(|b| is_bound(b, name)),
+        Statement::MatchStatement { arms, .. } => arms.iter().any(|arm| is_bound(&arm.block, name)),
+        _ => false,
+    })
+}
+
+// Value given outside of source has no location in it.
+fn invalid_param(msg: String) -> CompileError {
+    CompileError {
+        loc: nowhere(),
+        kind: ErrorKind::InvalidParam(msg),
+    }
+}
+
+// Literal of the given value, which is placed at each use of parameter.
+fn literal(param: &StackParam, value: &str) -> Result<Expression, CompileError> {
+    let loc = param.loc.to_owned();
+    let error = |msg: String| CompileError {
+        loc: param.loc.to_owned(),
+        kind: ErrorKind::InvalidParam(msg),
+    };
+    if param.ty == Type::Number {
+        return value.parse::<i64>().map_or_else(
+            |_| {
+                Err(error(format!(
+                    "Value of number parameter {:?} must be an integer but: {:?}.",
+                    param.identifier.0, value
+                )))
+            },
+            |n| Ok(Expression::NumberLiteral(loc.to_owned(), n)),
+        );
+    }
+    if param.ty == Type::String {
+        return Ok(Expression::StringLiteral(loc, value.to_string()));
+    }
+
+    let value = value.strip_prefix("0x").unwrap_or(value).to_lowercase();
+    let len = hex::decode(&value).map_or(None, |bytes| Some(bytes.len()));
+    match (&param.ty, len) {
+        (Type::PubKey, Some(33)) | (Type::XOnly, Some(32)) => {
+            Ok(Expression::PubKeyLiteral(loc, value))
+        }
+        (Type::Bytes, Some(_))
+        | (Type::Hash160, Some(20))
+        | (Type::Sha256 | Type::Hash256, Some(32)) => Ok(Expression::BytesLiteral(loc, value)),
+        (ty, _) => Err(error(format!(
+            "Value of {} parameter {:?} must be hex of {} but: {:?}.",
+            ty,
+            param.identifier.0,
+            match ty {
+                Type::PubKey => "33 bytes",
+                Type::XOnly | Type::Sha256 | Type::Hash256 => "32 bytes",
+                Type::Hash160 => "20 bytes",
+                _ => "whole bytes",
+            },
+            value
+        ))),
+    }
+}
+
+// Placeholder derived from the name, so the same parameter is bound to the same value.
+fn placeholder(param: &StackParam) -> Expression {
+    let loc = param.loc.to_owned();
+    let seed = sha256::Hash::hash(format!("bithoven param {}", param.identifier.0).as_bytes())
+        .to_byte_array();
+    let pubkey = SecretKey::from_slice(&seed)
+        .map(|secret| PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret));
+    match (&param.ty, pubkey) {
+        (Type::Number, _) => Expression::NumberLiteral(loc, 1),
+        (Type::String, _) => Expression::StringLiteral(loc, hex::encode(seed)),
+        (Type::PubKey, Ok(pubkey)) => Expression::PubKeyLiteral(loc, pubkey.to_string()),
+        (Type::XOnly, Ok(pubkey)) => {
+            Expression::PubKeyLiteral(loc, pubkey.x_only_public_key().0.to_string())
+        }
+        (Type::Hash160, _) => Expression::BytesLiteral(loc, hex::encode(&seed[..20])),
+        // Seed out of curve order, with negligible probability, is left malformed.
+        _ => Expression::BytesLiteral(loc, hex::encode(seed)),
+    }
+}
+
+pub fn bind_statement(stmt: &mut Statement, literals: &HashMap<String, Expression>) {
+    match stmt {
+        Statement::IfStatement {
+            condition_expr,
+            if_block,
+            else_block,
+            ..
+        } => {
+            bind_expression(condition_expr, literals);
+            for stmt in if_block.iter_mut() {
+                bind_statement(stmt, literals);
+            }
+            for stmt in else_block.iter_mut().flatten() {
+                bind_statement(stmt, literals);
+            }
+        }
+        Statement::MatchStatement { selector, arms, .. } => {
+            bind_expression(selector, literals);
+            for stmt in arms.iter_mut().flat_map(|arm| arm.block.iter_mut()) {
+                bind_statement(stmt, literals);
+            }
+        }
+        Statement::LocktimeStatement { operand, .. }
+        | Statement::LetStatement { expr: operand, .. } => bind_expression(operand, literals),
+        Statement::VerifyStatement(_, expr) | Statement::ExpressionStatement(_, _, expr) => {
+            bind_expression(expr, literals)
+        }
+    }
+}
+
+// Replace each variable of the given names, keeping the location of its use.
+pub fn bind_expression(expr: &mut Expression, literals: &HashMap<String, Expression>) {
+    match expr {
+        Expression::Variable(loc, Identifier(name)) => {
+            if let Some(literal) = literals.get(name) {
+                let loc = loc.to_owned();
+                *expr = literal.to_owned();
+                *expr.loc_mut() = loc;
+            }
+        }
+        Expression::NumberLiteral(..)
+        | Expression::BooleanLiteral(..)
+        | Expression::StringLiteral(..)
+        | Expression::BytesLiteral(..)
+        | Expression::PubKeyLiteral(..) => (),
+        Expression::LogicalExpression { lhs, rhs, .. }
+        | Expression::CompareExpression { lhs, rhs, .. }
+        | Expression::BinaryMathExpression { lhs, rhs, .. } => {
+            bind_expression(lhs, literals);
+            bind_expression(rhs, literals);
+        }
+        Expression::TernaryMathExpression {
+            operand,
+            lower,
+            upper,
+            ..
+        } => {
+            bind_expression(operand, literals);
+            bind_expression(lower, literals);
+            bind_expression(upper, literals);
+        }
+        Expression::UnaryMathExpression { operand, .. }
+        | Expression::UnaryCryptoExpression { operand, .. }
+        | Expression::ByteExpression { operand, .. } => bind_expression(operand, literals),
+        Expression::CheckSigExpression { operand, .. } => bind_factor(operand, literals),
+    }
+}
+
+fn bind_factor(factor: &mut Factor, literals: &HashMap<String, Expression>) {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => {
+            bind_expression(sig, literals);
+            bind_expression(pubkey, literals);
+        }
+        Factor::MultiSigFactor { n, .. } => {
+            for factor in n.iter_mut() {
+                bind_factor(factor, literals);
+            }
+        }
+    }
+}
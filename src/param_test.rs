@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::format::format_source;
+    use crate::lsp;
+    use crate::test_util::{error_kind, PUBKEY_A, PUBKEY_B};
+    use crate::{
+        compile_program, compile_program_with_lints, compile_program_with_params, BithovenOutput,
+    };
+
+    use std::fs;
+
+    const DIGEST: &str = "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+
+    // example/htlc.bithoven as a template.
+    const HTLC: &str = r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+param alice: pubkey;
+param bob: pubkey;
+// Relative locktime of refund.
+param timeout: number;
+param digest: hash256;
+
+(condition: bool, sig_alice: signature)
+(condition: bool, preimage: string, sig_bob: signature)
+{
+    if condition {
+        older timeout;
+        return checksig (sig_alice, alice);
+    } else {
+        verify sha256 sha256 preimage == digest;
+        return checksig (sig_bob, bob);
+    }
+}
+"#;
+
+    fn values() -> Vec<String> {
+        vec![
+            format!("alice={}", PUBKEY_A),
+            format!("bob = {}", PUBKEY_B),
+            "timeout=1000".to_string(),
+            format!("digest={}", DIGEST),
+        ]
+    }
+
+    fn compile_htlc(values: Vec<String>) -> Result<BithovenOutput, CompileError> {
+        compile_program_with_params(HTLC.to_string(), "mainnet".to_string(), values)
+    }
+
+    #[test]
+    fn test_bind_params() {
+        let output = compile_htlc(values()).unwrap();
+        let htlc = compile_program(fs::read_to_string("example/htlc.bithoven").unwrap()).unwrap();
+        assert_eq!(output.bytes(), htlc.bytes());
+
+        // Later value takes precedence.
+        let mut values = values();
+        values.push("timeout=144".to_string());
+        let output = compile_htlc(values).unwrap();
+        assert!(output.asm().contains("OP_PUSHBYTES_2 9000 OP_CSV"));
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        // Every parameter needs a value, reported at its declaration.
+        let result = compile_program(HTLC.to_string());
+        assert_eq!(result.clone().unwrap_err().loc.line, 4);
+        assert!(matches!(
+            result.unwrap_err().kind,
+            ErrorKind::MissingParam(msg) if msg.contains("\"alice\"")
+        ));
+
+        for value in [
+            "timeout".to_string(),
+            "carol=1".to_string(),
+            "timeout=soon".to_string(),
+            "alice=02aa".to_string(),
+            "digest=abcd".to_string(),
+        ] {
+            let mut values = values();
+            values.push(value.clone());
+            assert!(
+                matches!(error_kind(compile_htlc(values)), ErrorKind::InvalidParam(_)),
+                "{}",
+                value
+            );
+        }
+
+        let duplicate = HTLC.replace("param digest: hash256;", "param preimage: hash256;");
+        assert!(matches!(
+            error_kind(compile_program_with_params(
+                duplicate,
+                "mainnet".to_string(),
+                values()
+            )),
+            ErrorKind::DuplicateVariable(_)
+        ));
+
+        // Unknown parameter is not in source, so it has no position.
+        let mut unknown = values();
+        unknown.push("carol=1".to_string());
+        let message = "InvalidParam(\"Unknown parameter: \\\"carol\\\".\")";
+        assert_eq!(
+            compile_htlc(unknown.clone()).unwrap_err().to_string(),
+            format!("Error: {}", message)
+        );
+        let diagnostics = compile_program_with_lints(
+            HTLC.to_string(),
+            "mainnet".to_string(),
+            vec![],
+            vec![],
+            vec![],
+            unknown,
+        )
+        .diagnostics();
+        assert_eq!(diagnostics[0].to_string(), format!("Error: {}", message));
+
+        // Invalid value is reported once, not also as missing.
+        let mut invalid = values();
+        invalid[0] = "alice=zz".to_string();
+        let codes = compile_program_with_lints(
+            HTLC.to_string(),
+            "mainnet".to_string(),
+            vec![],
+            vec![],
+            vec![],
+            invalid,
+        )
+        .diagnostics()
+        .iter()
+        .map(|d| d.code())
+        .collect::<Vec<_>>();
+        assert_eq!(codes, vec!["InvalidParam"]);
+
+        // Bound value is checked as a literal at its use.
+        let mut values = values();
+        values.push(format!("alice=04{}", &PUBKEY_A[2..]));
+        assert!(matches!(
+            error_kind(compile_htlc(values)),
+            ErrorKind::MalformedPubkey(_)
+        ));
+    }
+
+    #[test]
+    fn test_template_without_values() {
+        // Checked with placeholders by editor.
+        assert!(lsp::diagnostics(HTLC).is_empty());
+        let mismatch = HTLC.replace("older timeout;", "older alice;");
+        assert_eq!(lsp::diagnostics(&mismatch).len(), 1);
+
+        assert_eq!(format_source(HTLC).unwrap(), HTLC);
+    }
+}
@@ -110,7 +110,8 @@ mod tests {
         // This test validates that behavior.
 
         let input = r#"(a: bool, b: string, c: number)"#;
-        let ast = assert_parses!(StackParser::new(), input);
+        let (path, ast) = assert_parses!(StackParser::new(), input);
+        assert_eq!(path, None);
 
         assert_eq!(ast.len(), 3);
         assert_eq!(ast[0].identifier, Identifier("c".to_string()));
@@ -126,10 +127,25 @@ mod tests {
     #[test]
     fn test_empty_stack() {
         let input = r#"()"#;
-        let ast = assert_parses!(StackParser::new(), input);
+        let (_, ast) = assert_parses!(StackParser::new(), input);
         assert!(ast.is_empty());
     }
 
+    #[test]
+    fn test_named_stack() {
+        let input = r#"path refund(sig: signature)"#;
+        let (path, ast) = assert_parses!(StackParser::new(), input);
+        assert_eq!(path.unwrap().identifier, Identifier("refund".to_string()));
+        assert_eq!(ast.len(), 1);
+
+        // Return refers to the path by its name.
+        assert_parses!(
+            StatementParser::new(),
+            "return refund: checksig (sig, \"aa\");"
+        );
+        assert_fails!(StackParser::new(), "path (sig: signature)");
+    }
+
     #[test]
     fn test_expression_associativity() {
         // `1 + 2 - 3` should be parsed as `(1 + 2) - 3`
@@ -283,6 +299,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_else_if_chain() {
+        // "else if" is the else block of a nested if.
+        let input = "if a { return 1; } else if b { return 2; } else { return 3; }";
+        let ast = assert_parses!(StatementParser::new(), input);
+        let Statement::IfStatement {
+            else_block: Some(else_block),
+            ..
+        } = ast
+        else {
+            panic!("Expected IfStatement with else block");
+        };
+        assert!(matches!(
+            else_block.as_slice(),
+            [Statement::IfStatement {
+                else_block: Some(_),
+                ..
+            }]
+        ));
+        assert_parses!(
+            StatementParser::new(),
+            "if a { return 1; } else if b { return 2; }"
+        );
+        assert_fails!(
+            StatementParser::new(),
+            "if a { return 1; } else if { return 2; }"
+        );
+    }
+
+    #[test]
+    fn test_match_statement() {
+        let input = "match choice { 0 => { return 1; } 2 => { verify x; return 2; } }";
+        let ast = assert_parses!(StatementParser::new(), input);
+        let Statement::MatchStatement { selector, arms, .. } = ast else {
+            panic!("Expected MatchStatement");
+        };
+        assert!(
+            matches!(selector, Expression::Variable(_, Identifier(ref name)) if name == "choice")
+        );
+        assert_eq!(
+            arms.iter().map(|arm| arm.value).collect::<Vec<i64>>(),
+            vec![0, 2]
+        );
+        assert_eq!(arms[1].block.len(), 2);
+
+        // Arm takes a number literal, and match needs an arm.
+        assert_fails!(StatementParser::new(), "match choice { }");
+        assert_fails!(
+            StatementParser::new(),
+            "match choice { x => { return 1; } }"
+        );
+        assert_fails!(StatementParser::new(), "match choice { 0 => return 1; }");
+    }
+
     #[test]
     fn test_verify_statement() {
         let input = "verify true;";
@@ -303,7 +373,7 @@ mod tests {
         let ast_older = assert_parses!(StatementParser::new(), input_older);
         if let Statement::LocktimeStatement { op, operand, .. } = ast_older {
             assert_eq!(op, LocktimeOp::Csv);
-            assert_eq!(operand, 144);
+            assert!(matches!(operand, Expression::NumberLiteral(_, 144)));
         } else {
             panic!("Expected LocktimeStatement for older");
         }
@@ -312,7 +382,7 @@ mod tests {
         let ast_after = assert_parses!(StatementParser::new(), input_after);
         if let Statement::LocktimeStatement { op, operand, .. } = ast_after {
             assert_eq!(op, LocktimeOp::Cltv);
-            assert_eq!(operand, 500000);
+            assert!(matches!(operand, Expression::NumberLiteral(_, 500000)));
         } else {
             panic!("Expected LocktimeStatement for after");
         }
@@ -391,6 +461,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_within_expression() {
+        let ast = assert_parses!(Expression0Parser::new(), "within(a + 1, 0, max(b, 10))");
+        if let Expression::TernaryMathExpression {
+            operand, upper, op, ..
+        } = ast
+        {
+            assert_eq!(op, TernaryMathOp::Within);
+            assert!(matches!(*operand, Expression::BinaryMathExpression { .. }));
+            assert!(matches!(*upper, Expression::BinaryMathExpression { .. }));
+        } else {
+            panic!("Expected TernaryMathExpression for within");
+        }
+    }
+
     #[test]
     fn test_unary_crypto_expressions() {
         let input_sha = "sha256 a";
@@ -408,6 +493,23 @@ mod tests {
         } else {
             panic!("Expected UnaryCryptoExpression for ripemd160");
         }
+
+        for (input, expected) in [
+            ("hash160 a", UnaryCryptoOp::Hash160),
+            ("hash256 a", UnaryCryptoOp::Hash256),
+            ("sha1 a", UnaryCryptoOp::Sha1),
+            (
+                "tagged_hash(\"TapLeaf\", a)",
+                UnaryCryptoOp::TaggedHash("TapLeaf".to_string()),
+            ),
+        ] {
+            let ast = assert_parses!(Expression0Parser::new(), input);
+            if let Expression::UnaryCryptoExpression { op, .. } = ast {
+                assert_eq!(op, expected);
+            } else {
+                panic!("Expected UnaryCryptoExpression for {}", input);
+            }
+        }
     }
 
     #[test]
@@ -0,0 +1,170 @@
+/*
+    Named spending paths, e.g. "path refund(sig_alice: signature)" and "return refund: ..".
+    - Input stack of a named path is taken by the return of its name, wherever the return is,
+      instead of by order of stacks and branches.
+    - Either every input stack is named or none is, and each named path has exactly one
+      reachable return of its name.
+    - Stacks are reordered to the order of spending paths before analysis, so the rest of the
+      compiler sees them as anonymous stacks in order.
+*/
+use crate::analyze::nowhere;
+use crate::ast::*;
+use crate::compile::path_leaves;
+use crate::diagnostic::Diagnostics;
+use crate::source::Locatable;
+
+pub fn resolve_paths(utxo: &mut Bithoven) -> Result<(), CompileError> {
+    let mut diagnostics = Diagnostics::default();
+    resolve_paths_diagnostics(utxo, &mut diagnostics);
+    diagnostics.into_result()
+}
+
+pub fn resolve_paths_diagnostics(utxo: &mut Bithoven, diagnostics: &mut Diagnostics) {
+    let mut labels: Vec<PathName> = vec![];
+    collect_labels(&utxo.output_script, &mut labels);
+    // Anonymous stacks are matched in order.
+    if labels.is_empty() && utxo.paths.iter().all(Option::is_none) {
+        return;
+    }
+
+    let mut errors: Vec<CompileError> = vec![];
+
+    // Declarations
+    let mut declared: Vec<&PathName> = vec![];
+    for (path, stack) in utxo.paths.iter().zip(&utxo.input_stack) {
+        match path {
+            Some(path) if declared.iter().any(|d| d.identifier == path.identifier) => {
+                errors.push(CompileError {
+                    loc: path.loc.to_owned(),
+                    kind: ErrorKind::DuplicateVariable(format!(
+                        "The name of spending path cannot be duplicate: {:?} already exists.",
+                        path.identifier.0
+                    )),
+                })
+            }
+            Some(path) => declared.push(path),
+            None => errors.push(CompileError {
+                loc: stack.last().map_or_else(
+                    || script_start(&utxo.output_script),
+                    |item| item.loc.to_owned(),
+                ),
+                kind: ErrorKind::StackMismatch(
+                    "Input stack must be named as the others, e.g. \"path refund(..)\"."
+                        .to_string(),
+                ),
+            }),
+        }
+    }
+    for label in &labels {
+        if !declared.iter().any(|d| d.identifier == label.identifier) {
+            errors.push(CompileError {
+                loc: label.loc.to_owned(),
+                kind: ErrorKind::UndefinedVariable(format!(
+                    "Spending path is not declared: {:?}.",
+                    label.identifier.0
+                )),
+            });
+        }
+    }
+
+    // Reachable return of each spending path, in order of the paths.
+    let mut order: Vec<usize> = vec![];
+    for leaf in path_leaves(&utxo.output_script) {
+        let Some((loc, label)) = leaf.iter().find_map(|stmt| match stmt {
+            Statement::ExpressionStatement(loc, label, _) => Some((loc, label)),
+            _ => None,
+        }) else {
+            // Missing return is reported by flow analysis.
+            for error in errors {
+                diagnostics.error(error);
+            }
+            return;
+        };
+        let Some(label) = label else {
+            errors.push(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::StackMismatch(
+                    "Return must give the name of its spending path, e.g. \"return refund: ..\"."
+                        .to_string(),
+                ),
+            });
+            continue;
+        };
+        let Some(index) = utxo
+            .paths
+            .iter()
+            .position(|path| path.as_ref().map(|p| &p.identifier) == Some(&label.identifier))
+        else {
+            continue;
+        };
+        if order.contains(&index) {
+            errors.push(CompileError {
+                loc: label.loc.to_owned(),
+                kind: ErrorKind::StackMismatch(format!(
+                    "Spending path {:?} has more than one reachable return.",
+                    label.identifier.0
+                )),
+            });
+            continue;
+        }
+        order.push(index);
+    }
+    for (index, path) in utxo.paths.iter().enumerate() {
+        if let Some(path) = path {
+            if !order.contains(&index) && declared.contains(&path) {
+                errors.push(CompileError {
+                    loc: path.loc.to_owned(),
+                    kind: ErrorKind::StackMismatch(format!(
+                        "Spending path {:?} has no reachable return.",
+                        path.identifier.0
+                    )),
+                });
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in errors {
+            diagnostics.error(error);
+        }
+        return;
+    }
+    utxo.input_stack = order
+        .iter()
+        .map(|&index| utxo.input_stack[index].to_owned())
+        .collect();
+    utxo.paths = order
+        .iter()
+        .map(|&index| utxo.paths[index].to_owned())
+        .collect();
+}
+
+// Names given to returns, including unreachable ones.
+fn collect_labels(block: &[Statement], labels: &mut Vec<PathName>) {
+    for stmt in block {
+        match stmt {
+            Statement::ExpressionStatement(_, Some(label), _) => labels.push(label.to_owned()),
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_labels(if_block, labels);
+                if let Some(else_block) = else_block {
+                    collect_labels(else_block, labels);
+                }
+            }
+            Statement::MatchStatement { arms, .. } => {
+                for arm in arms {
+                    collect_labels(&arm.block, labels);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn script_start(ast: &[Statement]) -> Location {
+    ast.first()
+        .map_or_else(nowhere, |stmt| stmt.to_owned().loc())
+}
@@ -0,0 +1,176 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::format::format_source;
+    use crate::lsp;
+    use crate::testing::run_tests;
+    use crate::{compile_program, compile_program_with_diagnostics};
+
+    use std::fs;
+
+    // example/htlc.bithoven with named paths, declared in the other order than branches.
+    const HTLC: &str = r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+path claim(condition: bool, preimage: string, sig_bob: signature)
+path refund(condition: bool, sig_alice: signature)
+{
+    if condition {
+        older 1000;
+        return refund: checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+    } else {
+        verify sha256 sha256 preimage == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+        return claim: checksig (sig_bob, "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+    }
+}
+"#;
+
+    // (line, kind name) of each error.
+    fn errors(source: &str) -> Vec<(usize, String)> {
+        compile_program_with_diagnostics(source.to_string(), "mainnet".to_string())
+            .diagnostics()
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| (d.line(), d.code()))
+            .collect()
+    }
+
+    #[test]
+    fn test_named_paths() {
+        let output = compile_program(HTLC.to_string()).unwrap();
+        let htlc = compile_program(fs::read_to_string("example/htlc.bithoven").unwrap()).unwrap();
+        assert_eq!(output.bytes(), htlc.bytes());
+
+        // Templates are in order of paths, with their names.
+        let witness = output.witness();
+        assert_eq!(witness[0].name(), Some("refund".to_string()));
+        assert_eq!(witness[0].elements()[0].name(), "sig_alice");
+        assert_eq!(witness[1].name(), Some("claim".to_string()));
+        assert_eq!(witness[1].path(), 1);
+        assert_eq!(htlc.witness()[0].name(), None);
+
+        // Formatter keeps the order of declaration.
+        assert_eq!(format_source(HTLC).unwrap(), HTLC);
+
+        let symbols = lsp::document_symbols(HTLC);
+        assert_eq!(symbols[0]["name"], "path refund");
+        assert_eq!(symbols[1]["name"], "path claim");
+    }
+
+    #[test]
+    fn test_path_mismatch() {
+        assert!(errors(HTLC).is_empty());
+
+        let anonymous = HTLC.replace("path claim(", "(");
+        assert_eq!(
+            errors(&anonymous),
+            vec![
+                (4, "StackMismatch".to_string()),
+                (12, "UndefinedVariable".to_string())
+            ]
+        );
+
+        let duplicate = HTLC.replace("path claim(", "path refund(");
+        assert!(errors(&duplicate).contains(&(5, "DuplicateVariable".to_string())));
+
+        let unknown = HTLC.replace("return claim:", "return clam:");
+        assert_eq!(
+            errors(&unknown),
+            vec![
+                (4, "StackMismatch".to_string()),
+                (12, "UndefinedVariable".to_string())
+            ]
+        );
+
+        let unnamed = HTLC.replace("return claim:", "return");
+        assert_eq!(
+            errors(&unnamed),
+            vec![
+                (4, "StackMismatch".to_string()),
+                (12, "StackMismatch".to_string())
+            ]
+        );
+
+        // Each named path has exactly one reachable return.
+        let twice = HTLC.replace("return claim:", "return refund:");
+        assert_eq!(
+            errors(&twice),
+            vec![
+                (4, "StackMismatch".to_string()),
+                (12, "StackMismatch".to_string())
+            ]
+        );
+
+        // Return label without any named path.
+        let source = fs::read_to_string("example/htlc.bithoven")
+            .unwrap()
+            .replace(
+                "return checksig (sig_bob",
+                "return claim: checksig (sig_bob",
+            );
+        assert_eq!(errors(&source)[0].1, "StackMismatch");
+        assert!(compile_program(source).is_err());
+    }
+
+    #[test]
+    fn test_named_path_tests() {
+        let tests = r#"
+key alice = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+key bob = "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+
+test "alice refunds" {
+    path refund;
+    sequence 1000;
+    witness sig_alice = sig(alice);
+    expect success;
+}
+
+test "bob claims by index" {
+    path 1;
+    witness preimage = "not a secret", sig_bob = sig(bob);
+    expect failure OP_EQUALVERIFY;
+}
+"#;
+        let report = run_tests(HTLC.to_string(), tests).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.results[0].path, 0);
+        assert_eq!(report.results[1].path_name, Some("claim".to_string()));
+
+        let missing = tests.replace("path refund;", "path timeout;");
+        assert!(matches!(
+            run_tests(HTLC.to_string(), &missing).unwrap_err().kind,
+            ErrorKind::InvalidTest(msg) if msg.contains("\"timeout\"")
+        ));
+    }
+
+    #[test]
+    fn test_named_path_artifacts() {
+        // Stacks are reordered to the returns, so "refund" is the first path but declared second.
+        let taproot = HTLC
+            .replace(
+                "pragma bithoven target segwit;",
+                "pragma bithoven target taproot;\npragma bithoven tapleaf path;",
+            )
+            .replace("\"0245a6", "\"45a6")
+            .replace("\"0345a6", "\"45a6");
+        let output = compile_program(taproot).unwrap();
+        let leaves = output.taproot().unwrap().leaves();
+        assert_eq!(leaves[0].name(), Some("refund".to_string()));
+        assert!(leaves[0].asm().contains("OP_CSV"));
+        assert_eq!(leaves[1].name(), Some("claim".to_string()));
+        assert!(leaves[1].asm().contains("OP_HASH256"));
+        for (template, leaf) in output.witness().iter().zip(&leaves) {
+            assert_eq!(template.name(), leaf.name());
+        }
+
+        // Consensus limits are reported by the name, not by index.
+        let opcodes = HTLC.replace("older 1000;", &"verify 2 + 3;\n".repeat(201));
+        let err = compile_program(opcodes).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            ErrorKind::OpcodeCountExceeded(msg) if msg.starts_with("Spending path \"refund\" counts")
+        ));
+        // At the declaration of the path.
+        assert_eq!(err.loc.line, 5);
+    }
+}
@@ -0,0 +1,1127 @@
+/*
+    Conversion between bithoven source and Miniscript policy.
+    - Export: each spending path is the conjunction of its locktimes, signatures and hash locks,
+      and paths are joined by or. A bool witness item choosing a branch is free for the spender,
+      so it adds no condition. Anything else, e.g. arithmetic or negation, is not representable.
+    - Import: a policy, or a descriptor whose miniscript is lifted to its policy, is expanded
+      to spending paths, with an if/else on a bool witness item for each or.
+*/
+use crate::analyze::{analyze, internal_key, nowhere};
+use crate::ast::*;
+use crate::constant::{bind_constants, fold_constants};
+use crate::format::{expression, param_line, stack_line, statement, target_name, INDENT};
+use crate::locktime::{locktime_operand, locktime_value};
+use crate::lsp::{KEYWORDS, TYPES};
+use crate::param::{bind_expression, bind_params, Unbound};
+use crate::parse;
+use crate::path::resolve_paths;
+use crate::source::{script_location, Locatable};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Most spending paths a thresh is expanded to.
+const MAX_COMBINATIONS: usize = 64;
+
+/// Semantic policy of Miniscript. Weights of or are not kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Policy {
+    Unsatisfiable,
+    Trivial,
+    Key(String),
+    After(u32),
+    Older(u32),
+    Sha256(String),
+    Hash256(String),
+    Ripemd160(String),
+    Hash160(String),
+    And(Vec<Policy>),
+    Or(Vec<Policy>),
+    Threshold(usize, Vec<Policy>),
+}
+
+// Policy in Miniscript policy language, where and/or are binary.
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let binary = |f: &mut fmt::Formatter<'_>, name: &str, subs: &[Policy]| -> fmt::Result {
+            match subs {
+                [] => write!(f, "TRIVIAL"),
+                [sub] => write!(f, "{}", sub),
+                [first, rest @ ..] => write!(f, "{}({},{})", name, first, Policy::nary(name, rest)),
+            }
+        };
+        match self {
+            Policy::Unsatisfiable => write!(f, "UNSATISFIABLE"),
+            Policy::Trivial => write!(f, "TRIVIAL"),
+            Policy::Key(key) => write!(f, "pk({})", key),
+            Policy::After(n) => write!(f, "after({})", n),
+            Policy::Older(n) => write!(f, "older({})", n),
+            Policy::Sha256(h) => write!(f, "sha256({})", h),
+            Policy::Hash256(h) => write!(f, "hash256({})", h),
+            Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
+            Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::And(subs) => binary(f, "and", subs),
+            Policy::Or(subs) => binary(f, "or", subs),
+            Policy::Threshold(k, subs) => {
+                write!(f, "thresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}", sub)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Policy {
+    fn nary(name: &str, subs: &[Policy]) -> Policy {
+        match name {
+            "and" => Policy::And(subs.to_vec()),
+            _ => Policy::Or(subs.to_vec()),
+        }
+    }
+
+    // Flatten nested and/or, and drop conditions which don't change the policy.
+    fn normalize(self) -> Policy {
+        match self {
+            Policy::And(subs) => {
+                let mut flat = vec![];
+                for sub in subs.into_iter().map(Policy::normalize) {
+                    match sub {
+                        Policy::Trivial => (),
+                        Policy::Unsatisfiable => return Policy::Unsatisfiable,
+                        Policy::And(inner) => flat.extend(inner),
+                        sub => flat.push(sub),
+                    }
+                }
+                match flat.len() {
+                    0 => Policy::Trivial,
+                    1 => flat.remove(0),
+                    _ => Policy::And(flat),
+                }
+            }
+            Policy::Or(subs) => {
+                let mut flat = vec![];
+                for sub in subs.into_iter().map(Policy::normalize) {
+                    match sub {
+                        Policy::Unsatisfiable => (),
+                        Policy::Trivial => return Policy::Trivial,
+                        Policy::Or(inner) => flat.extend(inner),
+                        sub => flat.push(sub),
+                    }
+                }
+                match flat.len() {
+                    0 => Policy::Unsatisfiable,
+                    1 => flat.remove(0),
+                    _ => Policy::Or(flat),
+                }
+            }
+            Policy::Threshold(k, subs) if k == subs.len() => Policy::And(subs).normalize(),
+            Policy::Threshold(1, subs) => Policy::Or(subs).normalize(),
+            Policy::Threshold(k, subs) => {
+                Policy::Threshold(k, subs.into_iter().map(Policy::normalize).collect())
+            }
+            policy => policy,
+        }
+    }
+
+    // Same policy with each key replaced.
+    fn map_keys(self, f: &impl Fn(String) -> String) -> Policy {
+        let map = |subs: Vec<Policy>| subs.into_iter().map(|sub| sub.map_keys(f)).collect();
+        match self {
+            Policy::Key(key) => Policy::Key(f(key)),
+            Policy::And(subs) => Policy::And(map(subs)),
+            Policy::Or(subs) => Policy::Or(map(subs)),
+            Policy::Threshold(k, subs) => Policy::Threshold(k, map(subs)),
+            policy => policy,
+        }
+    }
+}
+
+/// Policy of the contract, with the given values of template parameters. Source must compile.
+/// For taproot with an internal key, the key path is or-ed with the script.
+/// Pubkey and hash parameters without value are kept as their names, e.g. "pk(alice)".
+pub fn to_policy(source: &str, values: &[String]) -> Result<Policy, CompileError> {
+    let mut utxo = parse(source.to_string())?;
+    bind_constants(&mut utxo)?;
+    resolve_paths(&mut utxo)?;
+
+    let mut analyzed = utxo.clone();
+    bind_params(&mut analyzed, values, Unbound::Placeholder)?;
+    fold_constants(&mut analyzed.output_script);
+    analyze(
+        &analyzed.output_script,
+        analyzed.input_stack.clone(),
+        &analyzed.pragma,
+    )?;
+
+    bind_params(&mut utxo, values, Unbound::Keep)?;
+    fold_constants(&mut utxo.output_script);
+    let params: HashSet<String> = utxo
+        .params
+        .iter()
+        .map(|param| param.identifier.0.to_owned())
+        .collect();
+    let block = inline_bindings(&utxo.output_script, &HashMap::new());
+    let script = block_policy(&block, &params)?;
+    let policy = match (&utxo.pragma.target, internal_key(&utxo.pragma)) {
+        (Target::Taproot, Some(key)) => Policy::Or(vec![Policy::Key(key), script]),
+        _ => script,
+    }
+    .normalize();
+    if let Some(key) = duplicate_key(&policy) {
+        return Err(not_representable(
+            &script_location(&utxo.output_script),
+            format!(
+                "Key {} signs more than once on a spending path, which Miniscript rejects as duplicate.",
+                key
+            ),
+        ));
+    }
+    Ok(policy)
+}
+
+// Value of each binding is placed at its uses, as policy has no names for values.
+fn inline_bindings(block: &[Statement], bindings: &HashMap<String, Expression>) -> Vec<Statement> {
+    let mut bindings = bindings.to_owned();
+    let mut inlined = vec![];
+    for stmt in block {
+        let mut stmt = stmt.to_owned();
+        match &mut stmt {
+            Statement::LetStatement {
+                identifier, expr, ..
+            } => {
+                bind_expression(expr, &bindings);
+                bindings.insert(identifier.0.to_owned(), expr.to_owned());
+                continue;
+            }
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                bind_expression(condition_expr, &bindings);
+                *if_block = inline_bindings(if_block, &bindings);
+                if let Some(else_block) = else_block {
+                    *else_block = inline_bindings(else_block, &bindings);
+                }
+            }
+            Statement::MatchStatement { selector, arms, .. } => {
+                bind_expression(selector, &bindings);
+                for arm in arms.iter_mut() {
+                    arm.block = inline_bindings(&arm.block, &bindings);
+                }
+            }
+            Statement::VerifyStatement(_, expr) | Statement::ExpressionStatement(_, _, expr) => {
+                bind_expression(expr, &bindings)
+            }
+            Statement::LocktimeStatement { .. } => (),
+        }
+        inlined.push(stmt);
+    }
+    inlined
+}
+
+// Unbound parameters are left as variables of the given names.
+fn block_policy(block: &[Statement], params: &HashSet<String>) -> Result<Policy, CompileError> {
+    let mut conditions = vec![];
+    for stmt in block {
+        conditions.push(match stmt {
+            Statement::LocktimeStatement {
+                loc,
+                operand,
+                op,
+                unit,
+            } => {
+                let Expression::NumberLiteral(_, operand) = operand else {
+                    return Err(not_representable(
+                        loc,
+                        format!(
+                            "Locktime parameter \"{}\" needs a value to be in a policy.",
+                            expression(operand)
+                        ),
+                    ));
+                };
+                // Policy takes the consensus value, e.g. nSequence with the type flag for time.
+                let n = locktime_value(op, unit, *operand)
+                    .ok()
+                    .and_then(locktime)
+                    .ok_or_else(|| {
+                        not_representable(loc, format!("Locktime {} is out of range.", operand))
+                    })?;
+                match op {
+                    LocktimeOp::Csv => Policy::Older(n),
+                    LocktimeOp::Cltv => Policy::After(n),
+                }
+            }
+            Statement::VerifyStatement(_, expr) | Statement::ExpressionStatement(_, _, expr) => {
+                expression_policy(expr, params)?
+            }
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                // False condition costs nothing: empty signature, or any other preimage.
+                let if_policy = Policy::And(vec![
+                    expression_policy(condition_expr, params)?,
+                    block_policy(if_block, params)?,
+                ]);
+                let else_policy = match else_block {
+                    Some(else_block) => block_policy(else_block, params)?,
+                    None => Policy::Trivial,
+                };
+                Policy::Or(vec![if_policy, else_policy])
+            }
+            // Selector is chosen by the spender, like condition.
+            Statement::MatchStatement { selector, arms, .. } => {
+                let mut arm_policies = vec![];
+                for arm in arms {
+                    arm_policies.push(Policy::And(vec![
+                        expression_policy(selector, params)?,
+                        block_policy(&arm.block, params)?,
+                    ]));
+                }
+                Policy::Or(arm_policies)
+            }
+            // Inlined at its uses.
+            Statement::LetStatement { .. } => continue,
+        });
+    }
+    Ok(Policy::And(conditions))
+}
+
+fn expression_policy(expr: &Expression, params: &HashSet<String>) -> Result<Policy, CompileError> {
+    match expr {
+        // Witness item is chosen by the spender.
+        Expression::Variable(..) => Ok(Policy::Trivial),
+        Expression::BooleanLiteral(_, true) => Ok(Policy::Trivial),
+        Expression::BooleanLiteral(_, false) => Ok(Policy::Unsatisfiable),
+        Expression::CheckSigExpression { operand, .. } => factor_policy(operand, params),
+        Expression::LogicalExpression { lhs, op, rhs, .. } => {
+            let subs = vec![
+                expression_policy(lhs, params)?,
+                expression_policy(rhs, params)?,
+            ];
+            Ok(match op {
+                BinaryLogicalOp::BoolAnd => Policy::And(subs),
+                BinaryLogicalOp::BoolOr => Policy::Or(subs),
+            })
+        }
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::Equal,
+            rhs,
+            ..
+        } => match (&**lhs, &**rhs) {
+            (hash, Expression::StringLiteral(_, digest) | Expression::BytesLiteral(_, digest))
+            | (Expression::StringLiteral(_, digest) | Expression::BytesLiteral(_, digest), hash)
+            | (hash, Expression::Variable(_, Identifier(digest)))
+            | (Expression::Variable(_, Identifier(digest)), hash)
+                if !matches!(hash, Expression::Variable(..)) || params.contains(digest) =>
+            {
+                hash_policy(hash, digest, params.contains(digest)).ok_or_else(|| {
+                    not_representable(
+                        &expr.to_owned().loc(),
+                        format!(
+                            "Only hash of witness item compared with its digest is representable but: \"{}\".",
+                            expression(expr)
+                        ),
+                    )
+                })
+            }
+            _ => Err(not_representable(
+                &expr.to_owned().loc(),
+                format!(
+                    "Comparison is not a hash lock: \"{}\".",
+                    expression(expr)
+                ),
+            )),
+        },
+        _ => Err(not_representable(
+            &expr.to_owned().loc(),
+            format!(
+                "Expression is not representable in Miniscript policy: \"{}\".",
+                expression(expr)
+            ),
+        )),
+    }
+}
+
+fn factor_policy(factor: &Factor, params: &HashSet<String>) -> Result<Policy, CompileError> {
+    match factor {
+        Factor::SingleSigFactor { loc, pubkey, .. } => match &**pubkey {
+            Expression::StringLiteral(_, key) | Expression::PubKeyLiteral(_, key) => {
+                Ok(Policy::Key(key.to_owned()))
+            }
+            Expression::Variable(_, Identifier(key)) if params.contains(key) => {
+                Ok(Policy::Key(key.to_owned()))
+            }
+            // Policy has no key unknown until spent, e.g. P2PKH of Miniscript names its key.
+            _ => Err(not_representable(
+                loc,
+                "Public key must be a literal or parameter, not a witness item.".to_string(),
+            )),
+        },
+        Factor::MultiSigFactor { m, n, .. } => Ok(Policy::Threshold(
+            *m as usize,
+            n.iter()
+                .map(|factor| factor_policy(factor, params))
+                .collect::<Result<_, _>>()?,
+        )),
+    }
+}
+
+// Hash lock of a witness item, e.g. "sha256(sha256(x)) == <digest>" is hash256.
+// Digest of unbound parameter is kept as its name.
+fn hash_policy(hash: &Expression, digest: &str, named: bool) -> Option<Policy> {
+    let (outer, inner) = match hash {
+        Expression::UnaryCryptoExpression { operand, op, .. } => match &**operand {
+            Expression::Variable(..) => (op, None),
+            Expression::UnaryCryptoExpression {
+                operand, op: inner, ..
+            } if matches!(**operand, Expression::Variable(..)) => (op, Some(inner)),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let digest = match named {
+        true => digest.to_owned(),
+        false => digest.to_lowercase(),
+    };
+    let policy = match (outer, inner) {
+        (UnaryCryptoOp::Sha256, None) => Policy::Sha256(digest),
+        (UnaryCryptoOp::Sha256, Some(UnaryCryptoOp::Sha256)) => Policy::Hash256(digest),
+        (UnaryCryptoOp::Ripemd160, None) => Policy::Ripemd160(digest),
+        (UnaryCryptoOp::Ripemd160, Some(UnaryCryptoOp::Sha256)) => Policy::Hash160(digest),
+        (UnaryCryptoOp::Hash256, None) => Policy::Hash256(digest),
+        (UnaryCryptoOp::Hash160, None) => Policy::Hash160(digest),
+        _ => return None,
+    };
+    (named || check_digest(&policy)).then_some(policy)
+}
+
+// Locktime in range of Miniscript, 1 to 2^31 - 1.
+fn locktime(n: i64) -> Option<u32> {
+    match n {
+        1..0x8000_0000 => Some(n as u32),
+        _ => None,
+    }
+}
+
+fn check_digest(policy: &Policy) -> bool {
+    let (digest, len) = match policy {
+        Policy::Sha256(h) | Policy::Hash256(h) => (h, 32),
+        Policy::Ripemd160(h) | Policy::Hash160(h) => (h, 20),
+        _ => return true,
+    };
+    hex::decode(digest).is_ok_and(|bytes| bytes.len() == len)
+}
+
+// Key required twice by a spending path, e.g. "and(pk(A),pk(A))". Subs of or are never on
+// the same path, but any two subs of and or thresh are.
+fn duplicate_key(policy: &Policy) -> Option<String> {
+    let subs = match policy {
+        Policy::And(subs) | Policy::Or(subs) | Policy::Threshold(_, subs) => subs,
+        _ => return None,
+    };
+    if let Some(key) = subs.iter().find_map(duplicate_key) {
+        return Some(key);
+    }
+    if matches!(policy, Policy::Or(..) | Policy::Threshold(1, _)) {
+        return None;
+    }
+    let mut seen: Vec<String> = vec![];
+    for sub in subs {
+        let (mut keys, mut digests) = (vec![], vec![]);
+        collect(sub, &mut keys, &mut digests);
+        if let Some(key) = keys.iter().find(|key| seen.contains(key)) {
+            return Some(key.to_owned());
+        }
+        seen.extend(keys);
+    }
+    None
+}
+
+fn not_representable(loc: &Location, msg: String) -> CompileError {
+    CompileError {
+        loc: loc.to_owned(),
+        kind: ErrorKind::NotRepresentable(msg),
+    }
+}
+
+// Node of policy or descriptor text, e.g. "or(pk(A),older(144))". Tap tree "{A,B}" has no name.
+struct Term {
+    start: usize,
+    name: String,
+    args: Vec<Term>,
+}
+
+struct PolicyParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl PolicyParser<'_> {
+    fn term(&mut self) -> Result<Term, CompileError> {
+        self.skip_space();
+        let start = self.pos;
+        if self.eat('{') {
+            let args = self.args('}')?;
+            return Ok(Term {
+                start,
+                name: String::new(),
+                args,
+            });
+        }
+        let mut name = self.token();
+        // Weight of or, e.g. "9@pk(A)".
+        if self.eat('@') {
+            if name.parse::<u32>().is_err() {
+                return Err(invalid_policy(start, format!("Invalid weight {:?}.", name)));
+            }
+            return self.term();
+        }
+        if name.is_empty() {
+            return Err(invalid_policy(start, "Expected policy.".to_string()));
+        }
+        // Wrappers of miniscript, e.g. "v:pk(A)", don't change the policy.
+        if let Some((wrappers, fragment)) = name.rsplit_once(':') {
+            if !wrappers.chars().all(|c| "asctdvjnlu".contains(c)) {
+                return Err(invalid_policy(
+                    start,
+                    format!("Invalid wrapper {:?}.", wrappers),
+                ));
+            }
+            name = fragment.to_string();
+        }
+        let args = match self.eat('(') {
+            true => self.args(')')?,
+            false => vec![],
+        };
+        Ok(Term { start, name, args })
+    }
+
+    fn args(&mut self, close: char) -> Result<Vec<Term>, CompileError> {
+        let mut args = vec![self.term()?];
+        loop {
+            self.skip_space();
+            if self.eat(',') {
+                args.push(self.term()?);
+            } else if self.eat(close) {
+                return Ok(args);
+            } else {
+                return Err(invalid_policy(
+                    self.pos,
+                    format!("Expected ',' or '{}'.", close),
+                ));
+            }
+        }
+    }
+
+    // Name of fragment, key or number: anything up to a delimiter.
+    fn token(&mut self) -> String {
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| "(),{}@".contains(c) || c.is_whitespace())
+            .unwrap_or(rest.len());
+        self.pos += len;
+        rest[..len].to_string()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.text[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            return true;
+        }
+        false
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+}
+
+fn invalid_policy(pos: usize, msg: String) -> CompileError {
+    CompileError {
+        loc: position(pos),
+        kind: ErrorKind::InvalidPolicy(msg),
+    }
+}
+
+// Location in policy text, which is a single line.
+fn position(pos: usize) -> Location {
+    Location {
+        start: pos,
+        end: pos,
+        line: 1,
+        column: pos + 1,
+    }
+}
+
+// Contract of a policy: target and internal key from descriptor, and the spending conditions.
+struct Imported {
+    target: Option<Target>,
+    internal_key: Option<String>,
+    policy: Policy,
+}
+
+fn parse_policy(text: &str) -> Result<Imported, CompileError> {
+    // Checksum of descriptor is not verified.
+    let text = text.split('#').next().unwrap_or_default();
+    let mut parser = PolicyParser { text, pos: 0 };
+    let term = parser.term()?;
+    parser.skip_space();
+    if parser.pos != text.len() {
+        return Err(invalid_policy(
+            parser.pos,
+            "Unexpected text after policy.".to_string(),
+        ));
+    }
+
+    let (target, internal_key, script) = match term.name.as_str() {
+        "sh" => match inner(&term)? {
+            wsh if wsh.name == "wsh" => (Some(Target::Segwit), None, inner(wsh)?),
+            script => (Some(Target::Legacy), None, script),
+        },
+        "wsh" => (Some(Target::Segwit), None, inner(&term)?),
+        "tr" => match term.args.as_slice() {
+            [key, tree] if key.args.is_empty() => {
+                (Some(Target::Taproot), Some(key.name.to_owned()), tree)
+            }
+            [_] => {
+                return Err(not_representable(
+                    &position(term.start),
+                    "tr() without script tree has no script to compile.".to_string(),
+                ))
+            }
+            _ => {
+                return Err(invalid_policy(
+                    term.start,
+                    "tr() takes an internal key and a script tree.".to_string(),
+                ))
+            }
+        },
+        _ => (None, None, &term),
+    };
+    Ok(Imported {
+        target,
+        internal_key,
+        policy: lift(script)?,
+    })
+}
+
+fn inner(term: &Term) -> Result<&Term, CompileError> {
+    match term.args.as_slice() {
+        [arg] => Ok(arg),
+        _ => Err(invalid_policy(
+            term.start,
+            format!("{}() takes a single argument.", term.name),
+        )),
+    }
+}
+
+// Policy of a policy or miniscript fragment.
+fn lift(term: &Term) -> Result<Policy, CompileError> {
+    let error = |msg: String| Err(invalid_policy(term.start, msg));
+    let leaf = || match term.args.as_slice() {
+        [arg] if arg.args.is_empty() && !arg.name.is_empty() => Ok(arg.name.to_owned()),
+        _ => Err(invalid_policy(
+            term.start,
+            format!("{}() takes a single key, number or digest.", term.name),
+        )),
+    };
+    let subs = || term.args.iter().map(lift).collect::<Result<Vec<_>, _>>();
+    let threshold = |keys: bool| -> Result<Policy, CompileError> {
+        let k = match term.args.first() {
+            Some(k) if k.args.is_empty() => k.name.parse::<usize>().ok(),
+            _ => None,
+        };
+        let subs = match keys {
+            true => term.args[1..]
+                .iter()
+                .map(|key| match key.args.is_empty() {
+                    true => Ok(Policy::Key(key.name.to_owned())),
+                    false => Err(invalid_policy(key.start, "Expected key.".to_string())),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            false => term.args[1..]
+                .iter()
+                .map(lift)
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        match k {
+            Some(k) if (1..=subs.len()).contains(&k) => Ok(Policy::Threshold(k, subs)),
+            _ => Err(invalid_policy(
+                term.start,
+                format!(
+                    "Threshold of {}() must be from 1 to the number of its items.",
+                    term.name
+                ),
+            )),
+        }
+    };
+    let arity = |n: usize| -> Result<Vec<Policy>, CompileError> {
+        match term.args.len() == n {
+            true => subs(),
+            false => Err(invalid_policy(
+                term.start,
+                format!("{}() takes {} arguments.", term.name, n),
+            )),
+        }
+    };
+
+    let policy = match term.name.as_str() {
+        "UNSATISFIABLE" | "0" if term.args.is_empty() => Policy::Unsatisfiable,
+        "TRIVIAL" | "1" if term.args.is_empty() => Policy::Trivial,
+        "pk" | "pk_k" | "pk_h" | "pkh" => Policy::Key(leaf()?),
+        "after" | "older" => {
+            let n = leaf()?;
+            let Some(n) = n.parse::<i64>().ok().and_then(locktime) else {
+                return error(format!("Invalid locktime {:?}.", n));
+            };
+            match term.name.as_str() {
+                "after" => Policy::After(n),
+                _ => Policy::Older(n),
+            }
+        }
+        "sha256" | "hash256" | "ripemd160" | "hash160" => {
+            let digest = leaf()?.to_lowercase();
+            let policy = match term.name.as_str() {
+                "sha256" => Policy::Sha256(digest),
+                "hash256" => Policy::Hash256(digest),
+                "ripemd160" => Policy::Ripemd160(digest),
+                _ => Policy::Hash160(digest),
+            };
+            if !check_digest(&policy) {
+                return error(format!("Invalid digest of {}().", term.name));
+            }
+            policy
+        }
+        "and" | "and_v" | "and_b" | "and_n" => Policy::And(arity(2)?),
+        "or" | "or_b" | "or_c" | "or_d" | "or_i" => Policy::Or(arity(2)?),
+        "andor" => {
+            let mut subs = arity(3)?;
+            let z = subs.pop().unwrap_or(Policy::Unsatisfiable);
+            Policy::Or(vec![Policy::And(subs), z])
+        }
+        "thresh" => threshold(false)?,
+        "multi" | "multi_a" | "sortedmulti" | "sortedmulti_a" => threshold(true)?,
+        // Branch of tap tree.
+        "" => Policy::Or(arity(2)?),
+        // Descriptors of outputs without script.
+        "wpkh" | "combo" | "addr" | "raw" | "rawtr" => {
+            return Err(not_representable(
+                &position(term.start),
+                format!("{}() has no script to compile.", term.name),
+            ))
+        }
+        _ => return error(format!("Unknown fragment {:?}.", term.name)),
+    };
+    Ok(policy)
+}
+
+/// Bithoven source of a Miniscript policy, or of a descriptor "sh(..)", "wsh(..)" or "tr(..)".
+/// Target defaults to the one of descriptor, otherwise segwit. Key written as a name, e.g. "pk(alice)",
+/// becomes a pubkey parameter of the template, and other keys are copied as written.
+pub fn from_policy(text: &str, target: Option<Target>) -> Result<String, CompileError> {
+    let imported = parse_policy(text)?;
+    let target = match (target, imported.target) {
+        (Some(target), Some(descriptor)) if target != descriptor => {
+            return Err(invalid_policy(
+                0,
+                format!(
+                    "Descriptor is for {} target, not {}.",
+                    target_name(&descriptor),
+                    target_name(&target)
+                ),
+            ))
+        }
+        (target, descriptor) => target.or(descriptor),
+    };
+    let mut policy = imported.policy.normalize();
+    let mut internal_key = imported.internal_key;
+    if policy == Policy::Unsatisfiable {
+        return Err(not_representable(
+            &position(0),
+            "Policy can never be satisfied.".to_string(),
+        ));
+    }
+
+    let (mut keys, mut digests) = (vec![], vec![]);
+    collect(&policy, &mut keys, &mut digests);
+    // X-only key is only for taproot, otherwise segwit.
+    let target = target.unwrap_or(match keys.iter().any(|key| key.len() == 64) {
+        true => Target::Taproot,
+        false => Target::Segwit,
+    });
+    // Taproot takes x-only keys, so parity of compressed key is dropped(BIP386).
+    if target == Target::Taproot {
+        policy = policy.map_keys(&xonly_key);
+        internal_key = internal_key.map(xonly_key);
+        (keys, digests) = (vec![], vec![]);
+        collect(&policy, &mut keys, &mut digests);
+    }
+    let params: Vec<String> = keys.iter().filter(|key| is_name(key)).cloned().collect();
+    let mut importer = Importer {
+        names: names(&keys, &digests),
+        // Witness items are not to be named as parameters.
+        used: params.iter().map(|param| (param.to_owned(), 1)).collect(),
+        params: params.clone(),
+        stacks: vec![],
+    };
+    let block = importer.block(vec![policy], vec![])?;
+
+    let mut source = format!(
+        "pragma bithoven version {};\npragma bithoven target {};\n",
+        env!("CARGO_PKG_VERSION"),
+        target_name(&target)
+    );
+    if let Some(key) = internal_key {
+        source.push_str(&format!("pragma bithoven internal_key \"{}\";\n", key));
+    }
+    source.push('\n');
+    for name in &params {
+        let ty = match target {
+            Target::Taproot => Type::XOnly,
+            _ => Type::PubKey,
+        };
+        source.push_str(&param_line(&param(name, ty)));
+        source.push('\n');
+    }
+    if !params.is_empty() {
+        source.push('\n');
+    }
+    for stack in &importer.stacks {
+        source.push_str(&stack_line(&stack.iter().collect::<Vec<&StackParam>>()));
+        source.push('\n');
+    }
+    source.push_str("{\n");
+    print_block(&block, 1, &mut source);
+    source.push_str("}\n");
+    Ok(source)
+}
+
+// Keys and digests in order of appearance.
+fn collect(policy: &Policy, keys: &mut Vec<String>, digests: &mut Vec<String>) {
+    match policy {
+        Policy::Key(key) => {
+            if !keys.contains(key) {
+                keys.push(key.to_owned());
+            }
+        }
+        Policy::Sha256(h) | Policy::Hash256(h) | Policy::Ripemd160(h) | Policy::Hash160(h) => {
+            if !digests.contains(h) {
+                digests.push(h.to_owned());
+            }
+        }
+        Policy::And(subs) | Policy::Or(subs) | Policy::Threshold(_, subs) => {
+            for sub in subs {
+                collect(sub, keys, digests);
+            }
+        }
+        _ => (),
+    }
+}
+
+// X-only key of compressed key, other keys as they are.
+fn xonly_key(key: String) -> String {
+    match key.len() == 66 && (key.starts_with("02") || key.starts_with("03")) {
+        true if hex::decode(&key).is_ok() => key[2..].to_string(),
+        _ => key,
+    }
+}
+
+// Key written as an identifier, e.g. "alice", rather than hex or extended key.
+fn is_name(key: &str) -> bool {
+    key.len() <= 16
+        && key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&key)
+        && !TYPES.contains(&key)
+}
+
+// Name of witness item for each key and digest: "sig_alice" for key written as a name,
+// otherwise numbered in order of appearance, e.g. "sig_0", "preimage_1".
+fn names(keys: &[String], digests: &[String]) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    let numbered = keys.iter().filter(|key| !is_name(key)).collect::<Vec<_>>();
+    for key in keys {
+        let name = match numbered.iter().position(|other| *other == key) {
+            None => format!("sig_{}", key.to_lowercase()),
+            Some(_) if numbered.len() == 1 => "sig".to_string(),
+            Some(i) => format!("sig_{}", i),
+        };
+        names.insert(key.to_owned(), name);
+    }
+    for (i, digest) in digests.iter().enumerate() {
+        let name = match digests.len() {
+            1 => "preimage".to_string(),
+            _ => format!("preimage_{}", i),
+        };
+        names.insert(digest.to_owned(), name);
+    }
+    names
+}
+
+struct Importer {
+    names: HashMap<String, String>,
+    // Items with the same name on adjacent paths are shared, so each new item gets a new name.
+    used: HashMap<String, usize>,
+    // Keys given as template parameters.
+    params: Vec<String>,
+    stacks: Vec<Vec<StackParam>>,
+}
+
+impl Importer {
+    // Block satisfying all the conditions, with input stack of the path so far.
+    fn block(
+        &mut self,
+        conditions: Vec<Policy>,
+        mut stack: Vec<StackParam>,
+    ) -> Result<Vec<Statement>, CompileError> {
+        let (mut locktimes, mut checks, mut sigs, mut ors) = (vec![], vec![], vec![], vec![]);
+        for condition in conditions {
+            let subs = match condition.normalize() {
+                Policy::And(subs) => subs,
+                Policy::Trivial => vec![],
+                condition => vec![condition],
+            };
+            for sub in subs {
+                match sub {
+                    Policy::Or(subs) => ors.push(subs),
+                    Policy::Threshold(k, subs) if !all_keys(&subs) => {
+                        ors.push(self.expand(k, subs)?)
+                    }
+                    Policy::After(..) | Policy::Older(..) => locktimes.push(sub),
+                    Policy::Key(..) | Policy::Threshold(..) => sigs.push(sub),
+                    _ => checks.push(sub),
+                }
+            }
+        }
+
+        if ors.is_empty() && sigs.is_empty() && !stack.iter().any(|item| item.ty == Type::Signature)
+        {
+            // Compiler requires a signature on every path(NoSigRequired).
+            return Err(not_representable(
+                &position(0),
+                format!(
+                    "Spending path {} has no signature, which every path of bithoven requires.",
+                    Policy::And([locktimes, checks].concat()).normalize()
+                ),
+            ));
+        }
+
+        let mut block = vec![];
+        for locktime in locktimes {
+            let (value, op) = match locktime {
+                Policy::Older(n) => (n, LocktimeOp::Csv),
+                Policy::After(n) => (n, LocktimeOp::Cltv),
+                _ => continue,
+            };
+            let (operand, unit) = locktime_operand(&op, value as i64);
+            block.push(Statement::LocktimeStatement {
+                loc: nowhere(),
+                operand: Expression::NumberLiteral(nowhere(), operand),
+                op,
+                unit,
+            });
+        }
+        let mut exprs = vec![];
+        for check in checks.iter().chain(sigs.iter()) {
+            exprs.push(self.expression(check, &mut stack));
+        }
+
+        // Branch on the first or, and leave the others to both branches.
+        if !ors.is_empty() {
+            let mut subs = ors.remove(0);
+            for expr in exprs {
+                block.push(Statement::VerifyStatement(nowhere(), expr));
+            }
+            let condition = Identifier(self.item("condition", Type::Boolean, &mut stack));
+
+            let first = subs.remove(0);
+            let rest = match subs.len() {
+                1 => subs.remove(0),
+                _ => Policy::Or(subs),
+            };
+            let ors = ors.into_iter().map(Policy::Or).collect::<Vec<_>>();
+            let if_block = self.block([vec![first], ors.clone()].concat(), stack.clone())?;
+            let else_block = self.block([vec![rest], ors].concat(), stack)?;
+            block.push(Statement::IfStatement {
+                loc: nowhere(),
+                condition_expr: Expression::Variable(nowhere(), condition),
+                if_block,
+                else_block: Some(else_block),
+            });
+            return Ok(block);
+        }
+
+        let ret = exprs
+            .pop()
+            .unwrap_or(Expression::BooleanLiteral(nowhere(), true));
+        for expr in exprs {
+            block.push(Statement::VerifyStatement(nowhere(), expr));
+        }
+        block.push(Statement::ExpressionStatement(nowhere(), None, ret));
+        self.stacks.push(stack);
+        Ok(block)
+    }
+
+    // Thresh of conditions other than keys, as or of each combination.
+    fn expand(&self, k: usize, subs: Vec<Policy>) -> Result<Vec<Policy>, CompileError> {
+        let mut combinations: Vec<Vec<usize>> = vec![vec![]];
+        for i in 0..subs.len() {
+            let mut next = vec![];
+            for combination in combinations {
+                if combination.len() < k {
+                    next.push([combination.clone(), vec![i]].concat());
+                }
+                if combination.len() + (subs.len() - i) > k {
+                    next.push(combination);
+                }
+            }
+            if next.len() > MAX_COMBINATIONS {
+                return Err(not_representable(
+                    &nowhere(),
+                    format!(
+                        "thresh({}) of {} conditions is more than {} spending paths.",
+                        k,
+                        subs.len(),
+                        MAX_COMBINATIONS
+                    ),
+                ));
+            }
+            combinations = next;
+        }
+        Ok(combinations
+            .into_iter()
+            .map(|combination| {
+                Policy::And(combination.into_iter().map(|i| subs[i].clone()).collect())
+            })
+            .collect())
+    }
+
+    // Expression of key, multisig or hash lock, adding its witness items to the stack.
+    fn expression(&mut self, policy: &Policy, stack: &mut Vec<StackParam>) -> Expression {
+        let (op, digest) = match policy {
+            Policy::Key(key) => {
+                return Expression::CheckSigExpression {
+                    loc: nowhere(),
+                    operand: Box::new(self.signature(key, stack)),
+                    op: CheckSigOp::CheckSig,
+                }
+            }
+            Policy::Threshold(k, keys) => {
+                let n = keys
+                    .iter()
+                    .filter_map(|key| match key {
+                        Policy::Key(key) => Some(self.signature(key, stack)),
+                        _ => None,
+                    })
+                    .collect();
+                return Expression::CheckSigExpression {
+                    loc: nowhere(),
+                    operand: Box::new(Factor::MultiSigFactor {
+                        loc: nowhere(),
+                        m: *k as u32,
+                        n,
+                    }),
+                    op: CheckSigOp::CheckSig,
+                };
+            }
+            Policy::Sha256(h) => (UnaryCryptoOp::Sha256, h),
+            Policy::Hash256(h) => (UnaryCryptoOp::Hash256, h),
+            Policy::Ripemd160(h) => (UnaryCryptoOp::Ripemd160, h),
+            Policy::Hash160(h) => (UnaryCryptoOp::Hash160, h),
+            _ => return Expression::BooleanLiteral(nowhere(), true),
+        };
+        let preimage = self.names[digest].to_owned();
+        let preimage = Expression::Variable(
+            nowhere(),
+            Identifier(self.item(&preimage, Type::String, stack)),
+        );
+        let hash = Expression::UnaryCryptoExpression {
+            loc: nowhere(),
+            operand: Box::new(preimage),
+            op,
+        };
+        Expression::CompareExpression {
+            loc: nowhere(),
+            lhs: Box::new(hash),
+            op: BinaryCompareOp::Equal,
+            rhs: Box::new(Expression::StringLiteral(nowhere(), digest.to_owned())),
+        }
+    }
+
+    fn signature(&mut self, key: &str, stack: &mut Vec<StackParam>) -> Factor {
+        let sig = self.names[key].to_owned();
+        let sig = self.item(&sig, Type::Signature, stack);
+        let pubkey = match self.params.iter().any(|param| param == key) {
+            true => Expression::Variable(nowhere(), Identifier(key.to_owned())),
+            false => Expression::StringLiteral(nowhere(), key.to_owned()),
+        };
+        Factor::SingleSigFactor {
+            loc: nowhere(),
+            sig: Box::new(Expression::Variable(nowhere(), Identifier(sig))),
+            pubkey: Box::new(pubkey),
+        }
+    }
+
+    // New witness item, numbered if the name is taken, e.g. "sig_alice_1".
+    fn item(&mut self, base: &str, ty: Type, stack: &mut Vec<StackParam>) -> String {
+        let count = self.used.entry(base.to_string()).or_insert(0);
+        let name = match count {
+            0 => base.to_string(),
+            _ => format!("{}_{}", base, count),
+        };
+        *count += 1;
+        stack.push(param(&name, ty));
+        name
+    }
+}
+
+// Thresh of keys is a multisig.
+fn all_keys(subs: &[Policy]) -> bool {
+    subs.iter().all(|sub| matches!(sub, Policy::Key(..)))
+}
+
+fn param(name: &str, ty: Type) -> StackParam {
+    StackParam {
+        loc: nowhere(),
+        identifier: Identifier(name.to_string()),
+        ty,
+    }
+}
+
+fn print_block(block: &[Statement], depth: usize, out: &mut String) {
+    let indent = INDENT.repeat(depth);
+    for stmt in block {
+        match stmt {
+            Statement::IfStatement {
+                loc,
+                condition_expr,
+                if_block,
+                else_block,
+            } => {
+                let header = Statement::IfStatement {
+                    loc: loc.to_owned(),
+                    condition_expr: condition_expr.to_owned(),
+                    if_block: vec![],
+                    else_block: None,
+                };
+                out.push_str(&format!("{}{} {{\n", indent, statement(&header, &indent)));
+                print_block(if_block, depth + 1, out);
+                if let Some(else_block) = else_block {
+                    out.push_str(&format!("{}}} else {{\n", indent));
+                    print_block(else_block, depth + 1, out);
+                }
+                out.push_str(&format!("{}}}\n", indent));
+            }
+            _ => out.push_str(&format!("{}{};\n", indent, statement(stmt, &indent))),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::policy::*;
+    use crate::test_util::{error_kind, PUBKEY_A, PUBKEY_B, XONLY_A};
+    use crate::{compile_program, compile_program_with_params};
+
+    use std::fs;
+
+    const KEY_G: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const KEY_2G: &str = "03c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    #[test]
+    fn test_examples_round_trip() {
+        for entry in fs::read_dir("example").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "bithoven") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            // Arithmetic is not representable.
+            let Ok(policy) = to_policy(&source, &[]) else {
+                continue;
+            };
+
+            let imported = from_policy(&policy.to_string(), None).unwrap();
+            assert!(compile_program(imported.clone()).is_ok(), "{:?}", path);
+            assert_eq!(to_policy(&imported, &[]).unwrap(), policy, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn test_to_policy() {
+        let source = fs::read_to_string("example/htlc.bithoven").unwrap();
+        assert_eq!(
+            to_policy(&source, &[]).unwrap().to_string(),
+            format!(
+                "or(and(older(1000),pk({})),and(hash256(53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f),pk({})))",
+                PUBKEY_A, PUBKEY_B
+            )
+        );
+
+        // Condition of if other than bool item is required only by the if branch.
+        let source = format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+(sig_a: signature, sig_b: signature, sig_c: signature)
+(sig_a: signature, sig_d: signature)
+{{
+    if checksig (sig_a, "{}") {{
+        return checksig [1, (sig_b, "{}"), (sig_c, "{}")];
+    }} else {{
+        return checksig (sig_d, "{}");
+    }}
+}}
+"#,
+            PUBKEY_A, PUBKEY_B, KEY_G, KEY_G
+        );
+        assert_eq!(
+            to_policy(&source, &[]).unwrap().to_string(),
+            format!(
+                "or(and(pk({}),or(pk({}),pk({}))),pk({}))",
+                PUBKEY_A, PUBKEY_B, KEY_G, KEY_G
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_representable() {
+        let source = |stmt: &str| {
+            format!(
+                "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n(x: number, sig: signature)\n{{\n    {}\n    return checksig (sig, \"{}\");\n}}\n",
+                stmt, PUBKEY_A
+            )
+        };
+        let result = to_policy(&source("verify x > 10;"), &[]);
+        assert_eq!(result.clone().unwrap_err().loc.line, 5);
+        assert!(matches!(
+            error_kind(result),
+            ErrorKind::NotRepresentable(msg) if msg.contains("\"x > 10\"")
+        ));
+        assert!(matches!(
+            error_kind(to_policy(&source("verify !(x == 10);"), &[])),
+            ErrorKind::NotRepresentable(_)
+        ));
+        assert!(matches!(
+            error_kind(to_policy(
+                &source(&format!("verify sha1 x == \"{}\";", "aa".repeat(20))),
+                &[]
+            )),
+            ErrorKind::NotRepresentable(msg) if msg.contains("hash of witness item")
+        ));
+        // Key from witness has no name in policy.
+        let witness_key = source("")
+            .replace(
+                "(x: number, sig: signature)",
+                "(sig: signature, key: pubkey)",
+            )
+            .replace(&format!("\"{}\"", PUBKEY_A), "key");
+        assert!(matches!(
+            error_kind(to_policy(&witness_key, &[])),
+            ErrorKind::NotRepresentable(msg) if msg.contains("witness item")
+        ));
+        // Miniscript rejects a key signing twice on a path.
+        let inheritance = fs::read_to_string("example/inheritance.bithoven").unwrap();
+        assert!(matches!(
+            error_kind(to_policy(&inheritance, &[])),
+            ErrorKind::NotRepresentable(msg) if msg.contains("more than once")
+        ));
+        let twice = source("").replace(
+            "(x: number, sig: signature)",
+            "(sig_a: signature, sig: signature)",
+        );
+        let twice = twice.replace(
+            "return checksig (sig,",
+            &format!(
+                "verify checksig (sig_a, \"{}\");\n    return checksig (sig,",
+                PUBKEY_A
+            ),
+        );
+        assert!(compile_program(twice.clone()).is_ok());
+        assert!(matches!(
+            error_kind(to_policy(&twice, &[])),
+            ErrorKind::NotRepresentable(msg) if msg.contains(PUBKEY_A)
+        ));
+        // Source must compile.
+        assert!(matches!(
+            error_kind(to_policy(&source("verify y;"), &[])),
+            ErrorKind::UndefinedVariable(_)
+        ));
+        // Locktime needs the value of its parameter.
+        let template = source("older timeout;")
+            .replace(
+                "target segwit;\n",
+                "target segwit;\nparam timeout: number;\n",
+            )
+            .replace("(x: number, sig: signature)", "(sig: signature)");
+        assert!(matches!(
+            error_kind(to_policy(&template, &[])),
+            ErrorKind::NotRepresentable(_)
+        ));
+        assert!(to_policy(&template, &["timeout=144".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn test_from_policy() {
+        // Named keys are template parameters.
+        let policy = "or(pk(A),and(older(144),pk(B)))";
+        let source = from_policy(policy, None).unwrap();
+        assert_eq!(
+            source,
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+
+param A: pubkey;
+param B: pubkey;
+
+(condition: bool, sig_a: signature)
+(condition: bool, sig_b: signature)
+{
+    if condition {
+        return checksig (sig_a, A);
+    } else {
+        older 144;
+        return checksig (sig_b, B);
+    }
+}
+"#
+        );
+        assert_eq!(to_policy(&source, &[]).unwrap().to_string(), policy);
+        assert_eq!(
+            to_policy(&source, &[format!("A={}", PUBKEY_A)])
+                .unwrap()
+                .to_string(),
+            format!("or(pk({}),and(older(144),pk(B)))", PUBKEY_A)
+        );
+        let values = vec![format!("A={}", PUBKEY_A), format!("B={}", PUBKEY_B)];
+        assert!(compile_program_with_params(source, "mainnet".to_string(), values).is_ok());
+
+        // Or of both branches is left to each of them.
+        let source = from_policy(
+            &format!(
+                "and(or(pk({}),after(100)),or(99@pk({}),and(sha256({}),pk({}))))",
+                PUBKEY_A,
+                PUBKEY_B,
+                "ab".repeat(32),
+                KEY_G
+            ),
+            Some(Target::Legacy),
+        )
+        .unwrap();
+        assert!(source.contains("pragma bithoven target legacy;\n"));
+        assert_eq!(source.matches("return ").count(), 4);
+        assert!(source.contains(
+            "(condition: bool, sig_0: signature, condition_1: bool, sig_1: signature)\n"
+        ));
+    }
+
+    #[test]
+    fn test_from_descriptor() {
+        let source = from_policy(
+            &format!(
+                "wsh(andor(pk({}),older(1008),thresh(2,pk({}),s:pk({}),sln:after(500000))))#checksum",
+                PUBKEY_A, PUBKEY_A, PUBKEY_B
+            ),
+            None,
+        )
+        .unwrap();
+        assert!(source.contains("pragma bithoven target segwit;\n"));
+        assert!(compile_program(source).is_ok());
+
+        let source = from_policy(
+            &format!(
+                "tr({},{{pk({}),multi_a(2,{},{},{})}})",
+                XONLY_A,
+                XONLY_A,
+                XONLY_A,
+                &KEY_G[2..],
+                &KEY_2G[2..]
+            ),
+            None,
+        )
+        .unwrap();
+        assert!(source.contains(&format!(
+            "pragma bithoven target taproot;\npragma bithoven internal_key \"{}\";\n",
+            XONLY_A
+        )));
+        assert!(source.contains("return checksig [2,"));
+        // Key path is part of the policy.
+        assert!(to_policy(&source, &[])
+            .unwrap()
+            .to_string()
+            .starts_with(&format!("or(pk({}),or(pk({})", XONLY_A, XONLY_A)));
+    }
+
+    #[test]
+    fn test_from_taproot_compressed_keys() {
+        // BIP386 allows compressed keys in tr(), which are x-only in the program.
+        let source = from_policy(
+            &format!("tr({},{{pk({}),pk({})}})", PUBKEY_A, KEY_G, KEY_2G),
+            None,
+        )
+        .unwrap();
+        assert!(source.contains(&format!("pragma bithoven internal_key \"{}\";\n", XONLY_A)));
+        assert!(source.contains(&format!("\"{}\"", &KEY_G[2..])));
+        assert!(source.contains(&format!("\"{}\"", &KEY_2G[2..])));
+        assert!(!source.contains(KEY_G) && !source.contains(KEY_2G));
+
+        assert!(compile_program(source.clone()).is_ok());
+        assert_eq!(
+            to_policy(&source, &[]).unwrap().to_string(),
+            format!(
+                "or(pk({}),or(pk({}),pk({})))",
+                XONLY_A,
+                &KEY_G[2..],
+                &KEY_2G[2..]
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        for (policy, column) in [
+            ("or(pk(A),pk(B)", 15),
+            ("or(pk(A),pk(B)) x", 17),
+            ("or(pk(A),foo(B))", 10),
+            ("older(0)", 1),
+            ("thresh(3,pk(A),pk(B))", 1),
+            ("x@pk(A)", 1),
+            ("sha256(aa)", 1),
+            ("wsh(pk(A))", 1),
+        ] {
+            let result = from_policy(policy, Some(Target::Taproot));
+            assert!(
+                matches!(
+                    result,
+                    Err(CompileError {
+                        kind: ErrorKind::InvalidPolicy(_),
+                        ..
+                    })
+                ),
+                "{}: {:?}",
+                policy,
+                result
+            );
+            assert_eq!(result.unwrap_err().loc.column, column, "{}", policy);
+        }
+
+        // Two of the combinations of thresh have no key.
+        let thresh = format!("thresh(2,pk(A),older(10),sha256({}))", "ab".repeat(32));
+        assert!(matches!(
+            error_kind(from_policy(&thresh, None)),
+            ErrorKind::NotRepresentable(msg) if msg.contains("older(10)") && msg.contains("no signature")
+        ));
+        // Key outside of thresh signs every combination.
+        let signed = format!("and(pk(B),{})", thresh);
+        assert!(compile_program(from_policy(&signed, None).unwrap()).is_err());
+        assert!(compile_program_with_params(
+            from_policy(&signed, None).unwrap(),
+            "mainnet".to_string(),
+            vec![format!("A={}", PUBKEY_A), format!("B={}", PUBKEY_B)]
+        )
+        .is_ok());
+
+        for policy in ["tr(A)", "wpkh(A)", "and(pk(A),0)"] {
+            assert!(
+                matches!(
+                    error_kind(from_policy(policy, None)),
+                    ErrorKind::NotRepresentable(_)
+                ),
+                "{}",
+                policy
+            );
+        }
+    }
+}
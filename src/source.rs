@@ -19,7 +19,9 @@ impl Locatable for Statement {
         match self {
             // For struct variants with a named `loc` field
             Statement::IfStatement { loc, .. } => loc,
+            Statement::MatchStatement { loc, .. } => loc,
             Statement::LocktimeStatement { loc, .. } => loc,
+            Statement::LetStatement { loc, .. } => loc,
 
             // For tuple-struct variants, access by index
             Statement::VerifyStatement(loc, ..) => loc,
@@ -30,7 +32,9 @@ impl Locatable for Statement {
         match self {
             // For struct variants with a named `loc` field
             Statement::IfStatement { loc, .. } => loc,
+            Statement::MatchStatement { loc, .. } => loc,
             Statement::LocktimeStatement { loc, .. } => loc,
+            Statement::LetStatement { loc, .. } => loc,
 
             // For tuple-struct variants, access by index
             Statement::VerifyStatement(loc, ..) => loc,
@@ -46,10 +50,13 @@ impl Locatable for Expression {
             Expression::NumberLiteral(loc, ..) => loc,
             Expression::BooleanLiteral(loc, ..) => loc,
             Expression::StringLiteral(loc, ..) => loc,
+            Expression::BytesLiteral(loc, ..) => loc,
+            Expression::PubKeyLiteral(loc, ..) => loc,
             Expression::LogicalExpression { loc, .. } => loc,
             Expression::CompareExpression { loc, .. } => loc,
             Expression::UnaryMathExpression { loc, .. } => loc,
             Expression::BinaryMathExpression { loc, .. } => loc,
+            Expression::TernaryMathExpression { loc, .. } => loc,
             Expression::UnaryCryptoExpression { loc, .. } => loc,
             Expression::CheckSigExpression { loc, .. } => loc,
             Expression::ByteExpression { loc, .. } => loc,
@@ -61,10 +68,13 @@ impl Locatable for Expression {
             Expression::NumberLiteral(loc, ..) => loc,
             Expression::BooleanLiteral(loc, ..) => loc,
             Expression::StringLiteral(loc, ..) => loc,
+            Expression::BytesLiteral(loc, ..) => loc,
+            Expression::PubKeyLiteral(loc, ..) => loc,
             Expression::LogicalExpression { loc, .. } => loc,
             Expression::CompareExpression { loc, .. } => loc,
             Expression::UnaryMathExpression { loc, .. } => loc,
             Expression::BinaryMathExpression { loc, .. } => loc,
+            Expression::TernaryMathExpression { loc, .. } => loc,
             Expression::UnaryCryptoExpression { loc, .. } => loc,
             Expression::CheckSigExpression { loc, .. } => loc,
             Expression::ByteExpression { loc, .. } => loc,
@@ -87,6 +97,19 @@ impl Locatable for Factor {
     }
 }
 
+// Location of the whole script, which is its first statement.
+pub fn script_location(ast: &[Statement]) -> Location {
+    ast.first().map_or(
+        Location {
+            start: 0,
+            end: 0,
+            line: 1,
+            column: 1,
+        },
+        |stmt| stmt.to_owned().loc(),
+    )
+}
+
 // 1. Get line and column for each statement's location(span)
 
 /// Builds an index of the starting byte offset for each line in the source code.
@@ -108,6 +131,14 @@ pub fn get_line_and_column(line_index: &[usize], byte_offset: usize) -> (usize,
     (line, column)
 }
 
+pub fn set_pragma_location(pragma: &mut Pragma, line_index: &[usize]) {
+    for option in &mut pragma.options {
+        let (line, column) = get_line_and_column(line_index, option.loc.start);
+        option.loc.line = line;
+        option.loc.column = column;
+    }
+}
+
 pub fn set_stack_location(stack_vec: &mut Vec<Vec<StackParam>>, line_index: &[usize]) {
     for stack in stack_vec {
         for item in stack {
@@ -122,6 +153,31 @@ pub fn set_stack_location(stack_vec: &mut Vec<Vec<StackParam>>, line_index: &[us
     }
 }
 
+pub fn set_path_location<'a>(paths: impl Iterator<Item = &'a mut PathName>, line_index: &[usize]) {
+    for path in paths {
+        let (line, column) = get_line_and_column(line_index, path.loc.start);
+        path.loc.line = line;
+        path.loc.column = column;
+    }
+}
+
+pub fn set_param_location(params: &mut [StackParam], line_index: &[usize]) {
+    for param in params {
+        let (line, column) = get_line_and_column(line_index, param.loc.start);
+        param.loc.line = line;
+        param.loc.column = column;
+    }
+}
+
+pub fn set_constant_location(constants: &mut [Constant], line_index: &[usize]) {
+    for constant in constants {
+        let (line, column) = get_line_and_column(line_index, constant.loc.start);
+        constant.loc.line = line;
+        constant.loc.column = column;
+        set_expr_location(&mut constant.value, line_index);
+    }
+}
+
 pub fn set_ast_location(ast: &mut Vec<Statement>, line_index: &[usize]) {
     for stmt in ast {
         set_stmt_location(stmt, line_index);
@@ -142,9 +198,14 @@ pub fn set_stmt_location(stmt: &mut Statement, line_index: &[usize]) {
         Statement::VerifyStatement(_loc, expr) => {
             set_expr_location(expr, line_index);
         }
-        Statement::ExpressionStatement(_loc, expr) => {
+        Statement::ExpressionStatement(_loc, path, expr) => {
+            set_path_location(path.iter_mut(), line_index);
             set_expr_location(expr, line_index);
         }
+        Statement::LocktimeStatement { operand, .. }
+        | Statement::LetStatement { expr: operand, .. } => {
+            set_expr_location(operand, line_index);
+        }
         Statement::IfStatement {
             loc: _,
             condition_expr: expr,
@@ -162,7 +223,17 @@ pub fn set_stmt_location(stmt: &mut Statement, line_index: &[usize]) {
                 }
             }
         }
-        _ => (),
+        Statement::MatchStatement { selector, arms, .. } => {
+            set_expr_location(selector, line_index);
+            for arm in arms {
+                let (line, column) = get_line_and_column(line_index, arm.loc.start);
+                arm.loc.line = line;
+                arm.loc.column = column;
+                for arm_stmt in arm.block.iter_mut() {
+                    set_stmt_location(arm_stmt, line_index);
+                }
+            }
+        }
     }
 }
 
@@ -222,6 +293,17 @@ pub fn set_expr_location(expr: &mut Expression, line_index: &[usize]) {
             set_expr_location(lhs, line_index);
             set_expr_location(rhs, line_index);
         }
+        Expression::TernaryMathExpression {
+            loc: _,
+            operand,
+            lower,
+            upper,
+            op: _,
+        } => {
+            set_expr_location(operand, line_index);
+            set_expr_location(lower, line_index);
+            set_expr_location(upper, line_index);
+        }
         Expression::ByteExpression {
             loc: _,
             operand,
@@ -280,3 +362,127 @@ pub fn strip_comments(source: &str) -> String {
     }
     COMMENT_REGEX.replace_all(source, "").to_string()
 }
+
+lazy_static! {
+    // String literal, line comment and block comment.
+    static ref TRIVIA_REGEX: Regex = Regex::new(r#"("(?:\\\\|\\"|[^"\\])*")|(//[^\n\r]*)|(/\*[^*]*\*+(?:[^/*][^*]*\*+)*/)"#).unwrap();
+}
+
+/// Blanks out strings and comments with spaces, keeping the byte offsets.
+pub fn blank_trivia(source: &str) -> String {
+    TRIVIA_REGEX
+        .replace_all(source, |caps: &regex::Captures| " ".repeat(caps[0].len()))
+        .to_string()
+}
+
+/// Spans of comments, not in string literals.
+pub fn collect_comments(source: &str) -> Vec<(usize, usize)> {
+    TRIVIA_REGEX
+        .captures_iter(source)
+        .filter(|caps| caps.get(1).is_none())
+        .map(|caps| (caps.get(0).unwrap().start(), caps.get(0).unwrap().end()))
+        .collect()
+}
+
+// 3. Lint attributes on statements, e.g. "#[allow(pubkey_reuse, timelock_mixing)]".
+
+/// Parses lint attributes, each applied to the statement right after it.
+/// Attributes are skipped by the grammar, so they are found here with the statement locations.
+pub fn parse_attributes(
+    source: &str,
+    ast: &[Statement],
+    line_index: &[usize],
+) -> Result<Vec<LintAttribute>, CompileError> {
+    lazy_static! {
+        static ref ATTRIBUTE_REGEX: Regex = Regex::new(r"#\[[^\]]*\]").unwrap();
+        static ref LINT_REGEX: Regex =
+            Regex::new(r"^#\[\s*(allow|warn|deny)\s*\(\s*(\w+(?:\s*,\s*\w+)*)\s*,?\s*\)\s*\]$")
+                .unwrap();
+    }
+    let blank = blank_trivia(source);
+
+    let mut stmt_locs: Vec<Location> = vec![];
+    collect_stmt_locations(ast, &mut stmt_locs);
+
+    let mut attributes: Vec<LintAttribute> = vec![];
+    for found in ATTRIBUTE_REGEX.find_iter(&blank) {
+        let (line, column) = get_line_and_column(line_index, found.start());
+        let loc = Location {
+            start: found.start(),
+            end: found.end(),
+            line,
+            column,
+        };
+        let invalid_attribute = |msg: String| CompileError {
+            loc: loc.to_owned(),
+            kind: ErrorKind::ParseError(msg),
+        };
+
+        let caps = LINT_REGEX.captures(found.as_str()).ok_or_else(|| {
+            invalid_attribute(format!(
+                "Attribute must be one of #[allow(..)], #[warn(..)] and #[deny(..)] but: {:?}.",
+                found.as_str()
+            ))
+        })?;
+        let level = match &caps[1] {
+            "allow" => LintLevel::Allow,
+            "warn" => LintLevel::Warn,
+            _ => LintLevel::Deny,
+        };
+        let lints = caps[2]
+            .split(',')
+            .map(|lint| lint.trim().to_string())
+            .collect();
+
+        // Statement right after the attribute, skipping other attributes.
+        let scope = stmt_locs
+            .iter()
+            .filter(|stmt| stmt.start >= found.end())
+            .min_by_key(|stmt| stmt.start)
+            .filter(|stmt| {
+                ATTRIBUTE_REGEX
+                    .replace_all(&blank[found.end()..stmt.start], "")
+                    .trim()
+                    .is_empty()
+            })
+            .ok_or_else(|| {
+                invalid_attribute(format!(
+                    "Attribute must be followed by a statement: {:?}.",
+                    found.as_str()
+                ))
+            })?;
+
+        attributes.push(LintAttribute {
+            loc: loc.to_owned(),
+            scope: scope.to_owned(),
+            level,
+            lints,
+        });
+    }
+
+    Ok(attributes)
+}
+
+fn collect_stmt_locations(ast: &[Statement], locs: &mut Vec<Location>) {
+    for stmt in ast {
+        locs.push(stmt.to_owned().loc());
+        match stmt {
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_stmt_locations(if_block, locs);
+                if let Some(else_block) = else_block {
+                    collect_stmt_locations(else_block, locs);
+                }
+            }
+            Statement::MatchStatement { arms, .. } => {
+                for arm in arms {
+                    collect_stmt_locations(&arm.block, locs);
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::ast::*;
+
+use crate::address::build_taproot_locking;
+
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootSpendInfo};
+use bitcoin::{Network, ScriptBuf};
+
+use serde::{Deserialize, Serialize};
+
+use wasm_bindgen::prelude::*;
+
+// BIP341 NUMS point, "lift_x(SHA256(G))". Nobody knows its private key,
+// so the output can be spent only by script path.
+pub const UNSPENDABLE_INTERNAL_KEY: &str =
+    "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+// A tapleaf with what is needed to spend it by script path.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[wasm_bindgen]
+pub struct TapLeafOutput {
+    path: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    asm: String,
+    hex: String,
+    leaf_hash: String,
+    control_block: String,
+}
+
+#[wasm_bindgen]
+impl TapLeafOutput {
+    // Index of the input stack(spending path), or 0 if whole script is a single leaf.
+    #[wasm_bindgen(getter)]
+    pub fn path(&self) -> usize {
+        self.path
+    }
+    // Name of the spending path, none if its input stack is anonymous or whole script is a single leaf.
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn asm(&self) -> String {
+        self.asm.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn hex(&self) -> String {
+        self.hex.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn leaf_hash(&self) -> String {
+        self.leaf_hash.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn control_block(&self) -> String {
+        self.control_block.clone()
+    }
+}
+
+// Taproot output committing to the compiled tapleaves.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[wasm_bindgen]
+pub struct TaprootOutput {
+    internal_key: String,
+    merkle_root: String,
+    output_key: String,
+    address: String,
+    leaves: Vec<TapLeafOutput>,
+}
+
+#[wasm_bindgen]
+impl TaprootOutput {
+    #[wasm_bindgen(getter)]
+    pub fn internal_key(&self) -> String {
+        self.internal_key.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn merkle_root(&self) -> String {
+        self.merkle_root.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn output_key(&self) -> String {
+        self.output_key.clone()
+    }
+    // Bech32m address of the network compiled for.
+    #[wasm_bindgen(getter)]
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn leaves(&self) -> Vec<TapLeafOutput> {
+        self.leaves.clone()
+    }
+}
+
+impl TaprootOutput {
+    pub fn tweaked_output_key(&self) -> TweakedPublicKey {
+        TweakedPublicKey::dangerous_assume_tweaked(
+            self.output_key
+                .parse::<XOnlyPublicKey>()
+                .expect("Output key is built from valid key."),
+        )
+    }
+}
+
+/*
+    Build taproot output from (spending path, its name, compiled script) of each tapleaf.
+    - Every leaf has the same weight, so the tree is balanced.
+    - Without internal key, the unspendable NUMS point is used.
+*/
+pub fn build_taproot(
+    leaves: Vec<(usize, Option<String>, Vec<u8>)>,
+    internal_key: Option<String>,
+    network: Network,
+    loc: &Location,
+) -> Result<TaprootOutput, CompileError> {
+    let taproot_error = |msg: String| CompileError {
+        loc: loc.to_owned(),
+        kind: ErrorKind::TaprootError(msg),
+    };
+
+    let internal_key = internal_key.unwrap_or(UNSPENDABLE_INTERNAL_KEY.to_string());
+    let key = internal_key
+        .parse::<XOnlyPublicKey>()
+        .map_err(|e| taproot_error(format!("Invalid internal key {:?}: {}.", internal_key, e)))?;
+
+    let scripts = leaves
+        .into_iter()
+        .map(|(path, name, script)| (path, name, ScriptBuf::from_bytes(script)))
+        .collect::<Vec<(usize, Option<String>, ScriptBuf)>>();
+
+    let secp = Secp256k1::verification_only();
+    let spend_info = TaprootSpendInfo::with_huffman_tree(
+        &secp,
+        key,
+        scripts.iter().map(|(_, _, script)| (1, script.to_owned())),
+    )
+    .map_err(|e| taproot_error(format!("Failed to build taproot tree: {}.", e)))?;
+
+    let mut leaf_outputs: Vec<TapLeafOutput> = vec![];
+    for (path, name, script) in scripts {
+        let control_block = spend_info
+            .control_block(&(script.to_owned(), LeafVersion::TapScript))
+            .ok_or(taproot_error(format!(
+                "No control block for tapleaf of spending path {}.",
+                name.as_ref()
+                    .map_or(path.to_string(), |name| format!("{:?}", name))
+            )))?;
+        leaf_outputs.push(TapLeafOutput {
+            path,
+            name,
+            asm: script.to_asm_string(),
+            hex: script.to_hex_string(),
+            leaf_hash: TapLeafHash::from_script(&script, LeafVersion::TapScript).to_string(),
+            control_block: hex::encode(control_block.serialize()),
+        });
+    }
+
+    Ok(TaprootOutput {
+        internal_key,
+        merkle_root: spend_info
+            .merkle_root()
+            .map_or(String::new(), |root| root.to_string()),
+        output_key: spend_info.output_key().to_string(),
+        address: build_taproot_locking(spend_info.output_key(), network).address(),
+        leaves: leaf_outputs,
+    })
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::compile::path_leaves;
+    use crate::compile_program;
+    use crate::taproot::UNSPENDABLE_INTERNAL_KEY;
+    use crate::test_util::XONLY_A;
+
+    use bitcoin::key::TweakedPublicKey;
+    use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+    use bitcoin::taproot::ControlBlock;
+    use bitcoin::ScriptBuf;
+    use std::fs;
+    use std::str::FromStr;
+
+    const XONLY_B: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn htlc(pragma: &str) -> String {
+        format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target taproot;
+            {}
+            (condition: bool, sig_alice: signature)
+            (condition: bool, preimage: string, sig_bob: signature)
+            {{
+                if condition {{
+                    older 1000;
+                    return checksig (sig_alice, "{}");
+                }} else {{
+                    verify sha256 sha256 preimage == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+                    return checksig (sig_bob, "{}");
+                }}
+            }}
+            "#,
+            pragma, XONLY_A, XONLY_B
+        )
+    }
+
+    // Control block of every leaf must commit to the output key.
+    fn assert_commitment(taproot: &crate::TaprootOutput) {
+        let secp = Secp256k1::verification_only();
+        let output_key = XOnlyPublicKey::from_str(&taproot.output_key()).unwrap();
+        for leaf in taproot.leaves() {
+            let control_block =
+                ControlBlock::decode(&hex::decode(leaf.control_block()).unwrap()).unwrap();
+            let script = ScriptBuf::from_hex(&leaf.hex()).unwrap();
+            assert!(control_block.verify_taproot_commitment(&secp, output_key, &script));
+        }
+    }
+
+    #[test]
+    fn test_single_leaf_by_default() {
+        let output = compile_program(htlc("")).unwrap();
+        let taproot = output.taproot().expect("Taproot output missing");
+
+        assert_eq!(taproot.leaves().len(), 1);
+        assert_eq!(taproot.leaves()[0].hex(), output.hex());
+        assert_eq!(taproot.internal_key(), UNSPENDABLE_INTERNAL_KEY);
+        // Merkle root of a single leaf is the leaf hash.
+        assert_eq!(taproot.merkle_root(), taproot.leaves()[0].leaf_hash());
+        assert!(taproot.address().starts_with("bc1p"));
+        assert_commitment(&taproot);
+    }
+
+    #[test]
+    fn test_leaf_per_path() {
+        let output = compile_program(htlc("pragma bithoven tapleaf path;")).unwrap();
+        let taproot = output.taproot().unwrap();
+        let leaves = taproot.leaves();
+
+        // No single script for the whole contract.
+        assert!(output.bytes().is_empty());
+        assert_eq!(leaves.len(), 2);
+        assert_eq!(leaves[0].path(), 0);
+        assert_eq!(leaves[1].path(), 1);
+        // Leaf is chosen by its control block, so the bool selector is dropped with its branch.
+        assert!(!leaves[0].asm().contains("OP_IF"));
+        assert!(leaves[0].asm().starts_with("OP_PUSHBYTES_2 e803 OP_CSV"));
+        assert!(leaves[1].asm().starts_with("OP_HASH256"));
+        assert!(!leaves[1].asm().contains("OP_CSV"));
+        for template in output.witness() {
+            assert!(!template.elements().iter().any(|e| e.name() == "condition"));
+        }
+        assert_eq!(output.witness()[0].elements()[0].name(), "sig_alice");
+        assert_commitment(&taproot);
+
+        let expected = TweakedPublicKey::dangerous_assume_tweaked(
+            XOnlyPublicKey::from_str(&taproot.output_key()).unwrap(),
+        );
+        assert_eq!(
+            bitcoin::Address::p2tr_tweaked(expected, bitcoin::KnownHrp::Mainnet).to_string(),
+            taproot.address()
+        );
+    }
+
+    #[test]
+    fn test_internal_key_pragma() {
+        let output = compile_program(htlc(&format!(
+            "pragma bithoven tapleaf path;\npragma bithoven internal_key \"{}\";",
+            XONLY_A
+        )))
+        .unwrap();
+        let taproot = output.taproot().unwrap();
+
+        assert_eq!(taproot.internal_key(), XONLY_A);
+        assert!(taproot.leaves()[0].control_block()[2..].starts_with(XONLY_A));
+        assert_commitment(&taproot);
+    }
+
+    #[test]
+    fn test_invalid_internal_key() {
+        let err = compile_program(htlc("pragma bithoven internal_key \"0011\";")).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidPragma(_)));
+    }
+
+    #[test]
+    fn test_taproot_pragma_requires_taproot_target() {
+        let source =
+            htlc("pragma bithoven tapleaf path;").replace("target taproot", "target segwit");
+        let err = compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidPragma(_)));
+        // Other targets have no taproot output.
+        let output = compile_program(fs::read_to_string("example/htlc.bithoven").unwrap()).unwrap();
+        assert!(output.taproot().is_none());
+    }
+
+    #[test]
+    fn test_path_leaves_nested_branches() {
+        // Three spending paths, in order of input stack.
+        let source = fs::read_to_string("example/prediction_market.bithoven").unwrap();
+        let parsed = crate::bithoven::BithovenParser::new()
+            .parse(&source)
+            .unwrap();
+        let leaves = path_leaves(&parsed.output_script);
+
+        assert_eq!(leaves.len(), parsed.input_stack.len());
+        for leaf in &leaves {
+            assert!(!leaf
+                .iter()
+                .any(|stmt| matches!(stmt, Statement::IfStatement { .. })));
+        }
+        // Refund path keeps both timelocks.
+        let locktimes = leaves[2]
+            .iter()
+            .filter(|stmt| matches!(stmt, Statement::LocktimeStatement { .. }))
+            .count();
+        assert_eq!(locktimes, 2);
+    }
+}
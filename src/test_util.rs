@@ -0,0 +1,25 @@
+// Helpers shared by the tests of each module.
+#![cfg(test)]
+
+use crate::ast::*;
+use crate::compile_program_with_diagnostics;
+use crate::diagnostic::DiagnosticOutput;
+
+use std::fmt::Debug;
+use std::fs;
+
+pub const PUBKEY_A: &str = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+pub const PUBKEY_B: &str = "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+pub const XONLY_A: &str = "45a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+
+pub fn example(filename: &str) -> String {
+    fs::read_to_string(format!("example/{}", filename)).unwrap()
+}
+
+pub fn diagnose(source: &str) -> DiagnosticOutput {
+    compile_program_with_diagnostics(source.to_string(), "mainnet".to_string())
+}
+
+pub fn error_kind(result: Result<impl Debug, CompileError>) -> ErrorKind {
+    result.unwrap_err().kind
+}
@@ -0,0 +1,512 @@
+/*
+    Tests of a contract, written in a sidecar file(e.g. htlc.bithoven.test).
+
+    key alice = "0245a6...";
+
+    test "alice spends after 1000 blocks" {
+        path 0;
+        sequence 1000;
+        witness condition = true, sig_alice = sig(alice);
+        expect success;
+    }
+
+    - key: Dummy key, sig(name) is the signature which is valid only for it.
+    - path: Spending path(input stack) to test by index or by name of named path, 0 by default.
+    - sequence, locktime, version: Transaction context, see vm::TxContext for default.
+    - witness: Value of each witness item by name. Item fixed by the path(e.g. if selector) can be omitted.
+      Value is bool, number, string(hex or utf-8) or signature.
+    - expect: "success", "failure", or "failure <error>" to match the error message.
+*/
+use crate::ast::*;
+use crate::source::*;
+use crate::vm::*;
+use crate::{compile_program, WitnessTemplate};
+
+use bitcoin::hashes::{sha256, Hash};
+use logos::Logos;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(skip r"[ \t\r\n\f]+")]
+#[logos(skip r"//[^\n]*")]
+enum Token {
+    #[token("{")]
+    OpenBrace,
+    #[token("}")]
+    CloseBrace,
+    #[token("(")]
+    OpenParen,
+    #[token(")")]
+    CloseParen,
+    #[token("=")]
+    Assign,
+    #[token(",")]
+    Comma,
+    #[token(";")]
+    Semicolon,
+    #[regex(r#""[^"]*""#, |lex| { let s = lex.slice(); s[1..s.len() - 1].to_string() })]
+    Str(String),
+    #[regex(r"-?[0-9]+", |lex| lex.slice().parse::<i64>().ok())]
+    Number(i64),
+    // Keywords are words, so they can still be used as names.
+    #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
+    Word(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WitnessValue {
+    Bool(bool),
+    Number(i64),
+    // Hex or utf-8, same as string literal of contract.
+    Bytes(String),
+    // Dummy signature of the key.
+    Sig(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expectation {
+    Success,
+    // Error message should contain the given text.
+    Failure(Option<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub loc: Location,
+    pub name: String,
+    pub path: usize,
+    // Name of the spending path, which takes precedence over the index.
+    pub path_name: Option<String>,
+    pub ctx: TxContext,
+    pub witness: Vec<(String, WitnessValue)>,
+    pub expect: Expectation,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TestSuite {
+    // (name, pubkey)
+    pub keys: Vec<(String, Vec<u8>)>,
+    pub tests: Vec<TestCase>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestResult {
+    pub name: String,
+    pub path: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_name: Option<String>,
+    pub passed: bool,
+    // Why the test failed, empty if passed.
+    pub message: String,
+    pub execution: Execution,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TestReport {
+    // Number of spending paths of the contract.
+    pub paths: usize,
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, std::ops::Range<usize>)>,
+    pos: usize,
+    source: &'a str,
+    line_index: Vec<usize>,
+}
+
+impl Parser<'_> {
+    fn location(&self) -> Location {
+        let (start, end) = match self.tokens.get(self.pos) {
+            Some((_, span)) => (span.start, span.end),
+            None => (self.source.len(), self.source.len()),
+        };
+        let (line, column) = get_line_and_column(&self.line_index, start);
+        Location {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    fn error(&self, msg: String) -> CompileError {
+        CompileError {
+            loc: self.location(),
+            kind: ErrorKind::InvalidTest(msg),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.to_owned());
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), CompileError> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            other => Err(self.error(format!("Expected {:?}, but {:?}.", expected, other))),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.to_owned();
+                self.pos += 1;
+                Ok(word)
+            }
+            other => Err(self.error(format!("Expected name, but {:?}.", other))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Str(s)) => {
+                let s = s.to_owned();
+                self.pos += 1;
+                Ok(s)
+            }
+            other => Err(self.error(format!("Expected string, but {:?}.", other))),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, CompileError> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Ok(n)
+            }
+            other => Err(self.error(format!("Expected number, but {:?}.", other))),
+        }
+    }
+
+    fn unsigned<T: TryFrom<i64>>(&mut self, field: &str) -> Result<T, CompileError> {
+        let loc = self.location();
+        let n = self.number()?;
+        T::try_from(n).map_err(|_| CompileError {
+            loc,
+            kind: ErrorKind::InvalidTest(format!("Invalid {}: {}.", field, n)),
+        })
+    }
+
+    fn value(&mut self) -> Result<WitnessValue, CompileError> {
+        match self.next() {
+            Some(Token::Word(word)) if word == "true" => Ok(WitnessValue::Bool(true)),
+            Some(Token::Word(word)) if word == "false" => Ok(WitnessValue::Bool(false)),
+            Some(Token::Word(word)) if word == "sig" => {
+                self.expect(Token::OpenParen)?;
+                let key = self.word()?;
+                self.expect(Token::CloseParen)?;
+                Ok(WitnessValue::Sig(key))
+            }
+            Some(Token::Number(n)) => Ok(WitnessValue::Number(n)),
+            Some(Token::Str(s)) => Ok(WitnessValue::Bytes(s)),
+            other => {
+                self.pos -= 1;
+                Err(self.error(format!("Expected witness value, but {:?}.", other)))
+            }
+        }
+    }
+
+    fn test_case(&mut self) -> Result<TestCase, CompileError> {
+        let loc = self.location();
+        let name = self.string()?;
+        self.expect(Token::OpenBrace)?;
+
+        let mut test = TestCase {
+            loc,
+            name,
+            path: 0,
+            path_name: None,
+            ctx: TxContext::default(),
+            witness: vec![],
+            expect: Expectation::Success,
+        };
+        while self.peek() != Some(&Token::CloseBrace) {
+            let field = self.word()?;
+            match field.as_str() {
+                "path" => match self.peek() {
+                    Some(Token::Word(_)) => test.path_name = Some(self.word()?),
+                    _ => test.path = self.unsigned("path")?,
+                },
+                "sequence" => test.ctx.sequence = self.unsigned("sequence")?,
+                "locktime" => test.ctx.lock_time = self.unsigned("locktime")?,
+                "version" => test.ctx.version = self.unsigned("version")?,
+                "witness" => loop {
+                    let item = self.word()?;
+                    self.expect(Token::Assign)?;
+                    let value = self.value()?;
+                    test.witness.push((item, value));
+                    if self.peek() != Some(&Token::Comma) {
+                        break;
+                    }
+                    self.pos += 1;
+                },
+                "expect" => {
+                    test.expect = match self.word()?.as_str() {
+                        "success" => Expectation::Success,
+                        "failure" => match self.peek() {
+                            Some(Token::Str(_)) => Expectation::Failure(Some(self.string()?)),
+                            Some(Token::Word(_)) => Expectation::Failure(Some(self.word()?)),
+                            _ => Expectation::Failure(None),
+                        },
+                        other => {
+                            self.pos -= 1;
+                            return Err(self
+                                .error(format!("Expected success or failure, but {:?}.", other)));
+                        }
+                    }
+                }
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error(format!("Unknown test field: {:?}.", field)));
+                }
+            }
+            self.expect(Token::Semicolon)?;
+        }
+        self.expect(Token::CloseBrace)?;
+
+        Ok(test)
+    }
+}
+
+pub fn parse_tests(source: &str) -> Result<TestSuite, CompileError> {
+    let line_index = build_line_index(source);
+    let mut tokens = vec![];
+    for (token, span) in Token::lexer(source).spanned() {
+        match token {
+            Ok(token) => tokens.push((token, span)),
+            Err(_) => {
+                let (line, column) = get_line_and_column(&line_index, span.start);
+                return Err(CompileError {
+                    loc: Location {
+                        start: span.start,
+                        end: span.end,
+                        line,
+                        column,
+                    },
+                    kind: ErrorKind::InvalidTest(format!("Invalid token: \"{}\"", &source[span])),
+                });
+            }
+        }
+    }
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        source,
+        line_index,
+    };
+    let mut suite = TestSuite::default();
+    while parser.peek().is_some() {
+        match parser.word()?.as_str() {
+            "key" => {
+                let name = parser.word()?;
+                parser.expect(Token::Assign)?;
+                let pubkey = hex::decode(parser.string()?).map_err(|e| {
+                    parser.pos -= 1;
+                    parser.error(format!("Invalid key {:?}: {}.", name, e))
+                })?;
+                parser.expect(Token::Semicolon)?;
+                suite.keys.push((name, pubkey));
+            }
+            "test" => suite.tests.push(parser.test_case()?),
+            other => {
+                parser.pos -= 1;
+                return Err(parser.error(format!("Expected key or test, but {:?}.", other)));
+            }
+        }
+    }
+
+    Ok(suite)
+}
+
+// 64 bytes, deterministic to the key name.
+pub fn dummy_sig(key: &str) -> Vec<u8> {
+    let hash = sha256::Hash::hash(format!("bithoven:{}", key).as_bytes()).to_byte_array();
+    [hash, hash].concat()
+}
+
+fn encode_value(
+    value: &WitnessValue,
+    keys: &HashMap<String, Vec<u8>>,
+    test: &TestCase,
+) -> Result<Vec<u8>, CompileError> {
+    match value {
+        WitnessValue::Bool(b) => Ok(if *b { vec![1] } else { vec![] }),
+        WitnessValue::Number(n) => Ok(encode_num(*n)),
+        WitnessValue::Bytes(s) => Ok(hex::decode(s).unwrap_or(s.as_bytes().to_vec())),
+        WitnessValue::Sig(key) => match keys.get(key) {
+            Some(_) => Ok(dummy_sig(key)),
+            None => Err(CompileError {
+                loc: test.loc.to_owned(),
+                kind: ErrorKind::InvalidTest(format!("Undefined key: {:?}.", key)),
+            }),
+        },
+    }
+}
+
+// Script to execute, rules, the witness stack(bottom first) and control block(tapscript) of the test.
+type Spending = (Vec<u8>, SigVersion, Vec<Vec<u8>>, Vec<u8>);
+
+fn witness_stack(
+    template: &WitnessTemplate,
+    test: &TestCase,
+    keys: &HashMap<String, Vec<u8>>,
+) -> Result<Spending, CompileError> {
+    let test_error = |msg: String| CompileError {
+        loc: test.loc.to_owned(),
+        kind: ErrorKind::InvalidTest(msg),
+    };
+
+    let path = template
+        .name()
+        .map_or(template.path().to_string(), |name| format!("{:?}", name));
+    let elements = template.elements();
+    for (item, _) in &test.witness {
+        if !elements
+            .iter()
+            .any(|e| e.name() == *item && e.kind() != "script")
+        {
+            return Err(test_error(format!(
+                "No witness item {:?} in spending path {}.",
+                item, path
+            )));
+        }
+    }
+
+    let mut script = vec![];
+    let mut version = match template.layout().as_str() {
+        "script_sig" => SigVersion::Legacy,
+        _ => SigVersion::WitnessV0,
+    };
+    let mut stack = vec![];
+    let mut control_block = vec![];
+    for element in elements {
+        match element.kind().as_str() {
+            "script" => {
+                script = hex::decode(element.value().unwrap_or_default())
+                    .map_err(|e| test_error(format!("Invalid script: {}.", e)))?;
+            }
+            "control_block" => {
+                version = SigVersion::Tapscript;
+                control_block = hex::decode(element.value().unwrap_or_default())
+                    .map_err(|e| test_error(format!("Invalid control block: {}.", e)))?;
+            }
+            _ => {
+                let given = test
+                    .witness
+                    .iter()
+                    .find(|(item, _)| *item == element.name());
+                let item = match (given, element.value()) {
+                    (Some((_, value)), _) => encode_value(value, keys, test)?,
+                    // Literal fixed by the path.
+                    (None, Some(value)) => hex::decode(value).unwrap_or_default(),
+                    (None, None) => {
+                        return Err(test_error(format!(
+                            "Missing witness item {:?} of spending path {}.",
+                            element.name(),
+                            path
+                        )))
+                    }
+                };
+                stack.push(item);
+            }
+        }
+    }
+
+    Ok((script, version, stack, control_block))
+}
+
+/*
+    Run tests against the compiled contract.
+    - Every test is executed by the script interpreter with the mock signature checker.
+    - Error in the contract or the test file is returned, failed test is reported in result.
+*/
+pub fn run_tests(source: String, tests: &str) -> Result<TestReport, CompileError> {
+    let output = compile_program(source)?;
+    let suite = parse_tests(tests)?;
+    let templates = output.witness();
+
+    let keys = suite
+        .keys
+        .iter()
+        .cloned()
+        .collect::<HashMap<String, Vec<u8>>>();
+    let mut checker = MockSigChecker::default();
+    for (name, pubkey) in &keys {
+        checker.valid.insert((dummy_sig(name), pubkey.to_owned()));
+    }
+
+    let mut results = vec![];
+    for test in &suite.tests {
+        let template = match &test.path_name {
+            Some(name) => templates
+                .iter()
+                .find(|template| template.name().as_ref() == Some(name))
+                .ok_or(CompileError {
+                    loc: test.loc.to_owned(),
+                    kind: ErrorKind::InvalidTest(format!("No spending path {:?}.", name)),
+                })?,
+            None => templates.get(test.path).ok_or(CompileError {
+                loc: test.loc.to_owned(),
+                kind: ErrorKind::InvalidTest(format!(
+                    "No spending path {}, contract has {}.",
+                    test.path,
+                    templates.len()
+                )),
+            })?,
+        };
+        let (script, version, stack, control_block) = witness_stack(template, test, &keys)?;
+        let ctx = TxContext {
+            control_block,
+            ..test.ctx.clone()
+        };
+        let execution = execute(&script, stack, version, &ctx, &checker);
+
+        let message = match (&test.expect, &execution.error) {
+            (Expectation::Success, None) => String::new(),
+            (Expectation::Success, Some(e)) => format!("Expected success, but failed: {}", e),
+            (Expectation::Failure(_), None) => "Expected failure, but succeeded".to_string(),
+            (Expectation::Failure(None), Some(_)) => String::new(),
+            (Expectation::Failure(Some(expected)), Some(e)) => {
+                if e.to_string().contains(expected.as_str()) {
+                    String::new()
+                } else {
+                    format!("Expected failure with {:?}, but failed: {}", expected, e)
+                }
+            }
+        };
+        results.push(TestResult {
+            name: test.name.to_owned(),
+            path: template.path(),
+            path_name: template.name(),
+            passed: message.is_empty(),
+            message,
+            execution,
+        });
+    }
+
+    Ok(TestReport {
+        paths: templates.len(),
+        results,
+    })
+}
@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::*;
+    use crate::test_util::example;
+    use crate::testing::*;
+
+    use bitcoin::hashes::{sha256d, Hash};
+
+    fn test_error(source: &str, tests: &str) -> CompileError {
+        run_tests(source.to_string(), tests).expect_err("Test file unexpectedly valid")
+    }
+
+    #[test]
+    fn test_parse_tests() {
+        let suite = parse_tests(
+            r#"
+            key alice = "aa";
+            test "first" {
+                path 1;
+                sequence 10;
+                locktime 20;
+                version 1;
+                witness a = true, b = -1, c = "beef", d = sig(alice);
+                expect failure "Verify";
+            }
+            test "second" {}
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(suite.keys, vec![("alice".to_string(), vec![0xaa])]);
+        let first = &suite.tests[0];
+        assert_eq!(first.path, 1);
+        assert_eq!(
+            (first.ctx.sequence, first.ctx.lock_time, first.ctx.version),
+            (10, 20, 1)
+        );
+        assert_eq!(
+            first.witness,
+            vec![
+                ("a".to_string(), WitnessValue::Bool(true)),
+                ("b".to_string(), WitnessValue::Number(-1)),
+                ("c".to_string(), WitnessValue::Bytes("beef".to_string())),
+                ("d".to_string(), WitnessValue::Sig("alice".to_string())),
+            ]
+        );
+        assert_eq!(
+            first.expect,
+            Expectation::Failure(Some("Verify".to_string()))
+        );
+        assert_eq!(first.loc.line, 3);
+        // Defaults
+        assert_eq!(suite.tests[1].path, 0);
+        assert_eq!(suite.tests[1].expect, Expectation::Success);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_tests("test \"a\" {\n    path -1;\n}").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidTest(_)));
+        assert_eq!((err.loc.line, err.loc.column), (2, 10));
+
+        let err = parse_tests("test \"a\" {\n    expect maybe;\n}").unwrap_err();
+        assert_eq!(err.loc.line, 2);
+        assert!(parse_tests("key alice = \"zz\";").is_err());
+        assert!(parse_tests("test \"a\" { path 0 }").is_err());
+        assert!(parse_tests("test \"a\" { path 0; ").is_err());
+        assert!(parse_tests("test \"a\" { witness x = @; }").is_err());
+    }
+
+    #[test]
+    fn test_htlc_example() {
+        let report = run_tests(example("htlc.bithoven"), &example("htlc.bithoven.test")).unwrap();
+
+        assert_eq!(report.paths, 2);
+        assert_eq!(report.results.len(), 4);
+        for result in &report.results {
+            assert!(result.passed, "{}: {}", result.name, result.message);
+        }
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_failed_expectations_are_reported() {
+        let hash = hex::encode(sha256d::Hash::hash(b"secret").to_byte_array());
+        let source = example("htlc.bithoven").replace(
+            "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f",
+            &hash,
+        );
+        let tests = r#"
+            key bob = "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+            test "bob spends with preimage" {
+                path 1;
+                witness preimage = "secret", sig_bob = sig(bob);
+                expect success;
+            }
+            test "wrong expectation" {
+                path 1;
+                witness preimage = "secret", sig_bob = sig(bob);
+                expect failure;
+            }
+            test "wrong error" {
+                path 1;
+                witness preimage = "secret", sig_bob = "";
+                expect failure NullFail;
+            }
+        "#;
+        let report = run_tests(source, tests).unwrap();
+
+        assert!(report.results[0].passed, "{}", report.results[0].message);
+        assert!(!report.results[1].passed);
+        assert_eq!(report.results[1].message, "Expected failure, but succeeded");
+        assert!(!report.results[2].passed);
+        assert!(report.results[2].message.contains("EvalFalse"));
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_invalid_witness() {
+        let source = example("htlc.bithoven");
+        let err = test_error(&source, "test \"a\" { path 2; }");
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidTest(msg) if msg.contains("No spending path 2"))
+        );
+
+        // sig_alice is missing.
+        let err = test_error(&source, "test \"a\" { path 0; }");
+        assert!(matches!(err.kind, ErrorKind::InvalidTest(msg) if msg.contains("sig_alice")));
+
+        let err = test_error(&source, "test \"a\" { witness sig_bob = \"\"; }");
+        assert!(matches!(err.kind, ErrorKind::InvalidTest(msg) if msg.contains("sig_bob")));
+
+        let err = test_error(&source, "test \"a\" { witness sig_alice = sig(carol); }");
+        assert!(matches!(err.kind, ErrorKind::InvalidTest(msg) if msg.contains("carol")));
+    }
+}
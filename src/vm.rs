@@ -0,0 +1,821 @@
+/*
+    Bitcoin Script interpreter to simulate compiled contracts.
+    - Executes a script against the witness stack and transaction context.
+    - Follows legacy, segwit v0 and tapscript rules, including standard policy
+      which makes a spending transaction relayed(MINIMALIF, NULLDUMMY, NULLFAIL, CLEANSTACK).
+    - Signatures are checked by a mock verifier, since there is no transaction to sign.
+*/
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext};
+use bitcoin::script::Instruction;
+use bitcoin::Script;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+use std::fmt;
+
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1000;
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+pub const VALIDATION_WEIGHT_PER_SIGOP: i64 = 50;
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+// BIP65 and BIP68
+pub const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+// Rules to execute script with.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SigVersion {
+    // P2SH redeem script
+    Legacy,
+    // P2WSH witness script
+    WitnessV0,
+    // Tapleaf script
+    Tapscript,
+}
+
+// Transaction fields which script can see, other than signature hash.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TxContext {
+    pub version: i32,
+    pub lock_time: u32,
+    pub sequence: u32,
+    // Control block of the tapleaf spent, which is in the witness funding tapscript signature checks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub control_block: Vec<u8>,
+}
+
+impl Default for TxContext {
+    fn default() -> Self {
+        TxContext {
+            version: 2,
+            lock_time: 0,
+            sequence: SEQUENCE_FINAL,
+            control_block: vec![],
+        }
+    }
+}
+
+// Verifies signature against public key, in place of signature hash of a real transaction.
+pub trait SignatureChecker {
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], version: SigVersion) -> bool;
+}
+
+// Mock verifier. Signature is valid if (sig, pubkey) is registered,
+// or if it is not empty when accept_any is set.
+#[derive(Clone, Debug, Default)]
+pub struct MockSigChecker {
+    pub valid: HashSet<(Vec<u8>, Vec<u8>)>,
+    pub accept_any: bool,
+}
+
+impl MockSigChecker {
+    pub fn accept_any() -> Self {
+        MockSigChecker {
+            valid: HashSet::new(),
+            accept_any: true,
+        }
+    }
+}
+
+impl SignatureChecker for MockSigChecker {
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], _version: SigVersion) -> bool {
+        !sig.is_empty()
+            && (self.accept_any || self.valid.contains(&(sig.to_vec(), pubkey.to_vec())))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VmError {
+    EvalFalse,
+    OpReturn,
+    Verify(String),
+    BadOpcode(String),
+    DisabledOpcode(String),
+    InvalidStackOperation(String),
+    InvalidAltstackOperation,
+    UnbalancedConditional,
+    MinimalIf,
+    InvalidNumber(String),
+    PushSize,
+    OpCount,
+    StackSize,
+    ScriptSize,
+    PubkeyCount,
+    SigCount,
+    NullDummy,
+    NullFail,
+    PubkeyType,
+    TapscriptCheckMultiSig,
+    TapscriptValidationWeight,
+    NegativeLocktime,
+    UnsatisfiedLocktime(String),
+    CleanStack(usize),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// State after executing an instruction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    // Byte offset of the instruction in script.
+    pub pc: usize,
+    pub opcode: String,
+    // Whether the instruction was in an executed branch.
+    pub executed: bool,
+    // Hex of each item, the top is the last.
+    pub stack: Vec<String>,
+    pub altstack: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Execution {
+    pub steps: Vec<Step>,
+    pub stack: Vec<String>,
+    pub error: Option<VmError>,
+}
+
+impl Execution {
+    pub fn success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/*
+    Script number
+    - Little endian with sign bit at the most significant bit of the last byte.
+    - Operands are limited to 4 bytes(5 bytes for locktime), and must be minimally encoded.
+*/
+pub fn decode_num(data: &[u8], max_size: usize) -> Result<i64, VmError> {
+    if data.len() > max_size {
+        return Err(VmError::InvalidNumber(format!(
+            "Number exceeds {} bytes: {}.",
+            max_size,
+            hex::encode(data)
+        )));
+    }
+    if let Some(last) = data.last() {
+        // Most significant byte of 0x00 or 0x80 is not minimal, unless it is for the sign.
+        if last & 0x7f == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0) {
+            return Err(VmError::InvalidNumber(format!(
+                "Number is not minimally encoded: {}.",
+                hex::encode(data)
+            )));
+        }
+    }
+
+    let mut result: i64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        result |= (*byte as i64) << (8 * i);
+    }
+    match data.last() {
+        Some(last) if last & 0x80 != 0 => Ok(-(result & !(0x80_i64 << (8 * (data.len() - 1))))),
+        _ => Ok(result),
+    }
+}
+
+pub fn encode_num(value: i64) -> Vec<u8> {
+    let mut result: Vec<u8> = vec![];
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+    while abs > 0 {
+        result.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    // Sign bit goes to an extra byte if the most significant bit is taken.
+    if let Some(last) = result.last_mut() {
+        if *last & 0x80 != 0 {
+            result.push(if negative { 0x80 } else { 0 });
+        } else if negative {
+            *last |= 0x80;
+        }
+    }
+    result
+}
+
+// Any non-zero is true, except negative zero.
+pub fn cast_to_bool(data: &[u8]) -> bool {
+    data.iter()
+        .enumerate()
+        .any(|(i, byte)| *byte != 0 && !(i == data.len() - 1 && *byte == 0x80))
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+struct Machine<'a> {
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+    // Execution state of each nested IF.
+    exec: Vec<bool>,
+    op_count: usize,
+    validation_weight: i64,
+    version: SigVersion,
+    ctx: &'a TxContext,
+    checker: &'a dyn SignatureChecker,
+}
+
+impl Machine<'_> {
+    fn executing(&self) -> bool {
+        self.exec.iter().all(|e| *e)
+    }
+
+    fn pop(&mut self, op: &str) -> Result<Vec<u8>, VmError> {
+        self.stack
+            .pop()
+            .ok_or(VmError::InvalidStackOperation(op.to_string()))
+    }
+
+    fn pop_num(&mut self, op: &str) -> Result<i64, VmError> {
+        let data = self.pop(op)?;
+        decode_num(&data, 4)
+    }
+
+    fn pop_bool(&mut self, op: &str) -> Result<bool, VmError> {
+        let data = self.pop(op)?;
+        Ok(cast_to_bool(&data))
+    }
+
+    // Item at depth(0 = top).
+    fn peek(&self, depth: usize, op: &str) -> Result<Vec<u8>, VmError> {
+        if depth >= self.stack.len() {
+            return Err(VmError::InvalidStackOperation(op.to_string()));
+        }
+        Ok(self.stack[self.stack.len() - 1 - depth].to_owned())
+    }
+
+    fn require(&self, count: usize, op: &str) -> Result<(), VmError> {
+        if self.stack.len() < count {
+            return Err(VmError::InvalidStackOperation(op.to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_sig(&mut self, sig: &[u8], pubkey: &[u8]) -> Result<bool, VmError> {
+        let success = match self.version {
+            SigVersion::Tapscript => {
+                if pubkey.is_empty() {
+                    return Err(VmError::PubkeyType);
+                }
+                if !sig.is_empty() {
+                    // BIP342: every executed signature check consumes validation weight.
+                    self.validation_weight -= VALIDATION_WEIGHT_PER_SIGOP;
+                    if self.validation_weight < 0 {
+                        return Err(VmError::TapscriptValidationWeight);
+                    }
+                }
+                // Unknown public key type is always valid, for future upgrade.
+                if pubkey.len() != 32 {
+                    !sig.is_empty()
+                } else {
+                    self.checker.check_sig(sig, pubkey, self.version)
+                }
+            }
+            _ => self.checker.check_sig(sig, pubkey, self.version),
+        };
+        // NULLFAIL: failed check must have empty signature.
+        if !success && !sig.is_empty() {
+            return Err(VmError::NullFail);
+        }
+        Ok(success)
+    }
+
+    fn check_locktime(&mut self) -> Result<(), VmError> {
+        let locktime = decode_num(&self.peek(0, "OP_CHECKLOCKTIMEVERIFY")?, 5)?;
+        if locktime < 0 {
+            return Err(VmError::NegativeLocktime);
+        }
+        let tx_locktime = self.ctx.lock_time as i64;
+        // Both must be height or both must be time.
+        if (locktime < LOCKTIME_THRESHOLD) != (tx_locktime < LOCKTIME_THRESHOLD) {
+            return Err(VmError::UnsatisfiedLocktime(format!(
+                "Locktime type mismatch: {} and nLockTime {}.",
+                locktime, tx_locktime
+            )));
+        }
+        if locktime > tx_locktime {
+            return Err(VmError::UnsatisfiedLocktime(format!(
+                "Locktime {} is greater than nLockTime {}.",
+                locktime, tx_locktime
+            )));
+        }
+        // Final input disables nLockTime.
+        if self.ctx.sequence == SEQUENCE_FINAL {
+            return Err(VmError::UnsatisfiedLocktime(
+                "nSequence is final, which disables nLockTime.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_sequence(&mut self) -> Result<(), VmError> {
+        let sequence = decode_num(&self.peek(0, "OP_CHECKSEQUENCEVERIFY")?, 5)?;
+        if sequence < 0 {
+            return Err(VmError::NegativeLocktime);
+        }
+        let sequence = sequence as u32;
+        // Behaves as NOP if disabled.
+        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Ok(());
+        }
+        let tx_sequence = self.ctx.sequence;
+        if self.ctx.version < 2 {
+            return Err(VmError::UnsatisfiedLocktime(format!(
+                "Relative locktime requires transaction version 2 but: {}.",
+                self.ctx.version
+            )));
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Err(VmError::UnsatisfiedLocktime(
+                "nSequence disables relative locktime.".to_string(),
+            ));
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let (required, actual) = (sequence & mask, tx_sequence & mask);
+        if (required & SEQUENCE_LOCKTIME_TYPE_FLAG) != (actual & SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return Err(VmError::UnsatisfiedLocktime(format!(
+                "Relative locktime type mismatch: {:#x} and nSequence {:#x}.",
+                sequence, tx_sequence
+            )));
+        }
+        if required > actual {
+            return Err(VmError::UnsatisfiedLocktime(format!(
+                "Relative locktime {:#x} is greater than nSequence {:#x}.",
+                required, actual
+            )));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, op: bitcoin::opcodes::Opcode) -> Result<(), VmError> {
+        let name = op.to_string();
+        let context = match self.version {
+            SigVersion::Tapscript => ClassifyContext::TapScript,
+            _ => ClassifyContext::Legacy,
+        };
+        // Fails even if not executed.
+        match op.classify(context) {
+            Class::IllegalOp => return Err(VmError::DisabledOpcode(name)),
+            Class::PushNum(n) => {
+                if self.executing() {
+                    self.stack.push(encode_num(n as i64));
+                }
+                return Ok(());
+            }
+            _ => (),
+        }
+        if self.version != SigVersion::Tapscript {
+            self.op_count += 1;
+            if self.op_count > MAX_OPS_PER_SCRIPT {
+                return Err(VmError::OpCount);
+            }
+        }
+
+        // Flow control is evaluated in unexecuted branches too.
+        match op {
+            OP_IF | OP_NOTIF => {
+                let mut value = false;
+                if self.executing() {
+                    let data = self.pop(&name)?;
+                    // MINIMALIF: condition must be empty or 0x01.
+                    if self.version != SigVersion::Legacy
+                        && (data.len() > 1 || data.len() == 1 && data[0] != 1)
+                    {
+                        return Err(VmError::MinimalIf);
+                    }
+                    value = cast_to_bool(&data);
+                    if op == OP_NOTIF {
+                        value = !value;
+                    }
+                }
+                self.exec.push(value);
+                return Ok(());
+            }
+            OP_ELSE => {
+                let last = self.exec.last_mut().ok_or(VmError::UnbalancedConditional)?;
+                *last = !*last;
+                return Ok(());
+            }
+            OP_ENDIF => {
+                self.exec.pop().ok_or(VmError::UnbalancedConditional)?;
+                return Ok(());
+            }
+            _ => (),
+        }
+        if !self.executing() {
+            return Ok(());
+        }
+
+        match op {
+            OP_NOP | OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9
+            | OP_NOP10 => (),
+            OP_CLTV => self.check_locktime()?,
+            OP_CSV => self.check_sequence()?,
+            OP_VERIFY => {
+                if !self.pop_bool(&name)? {
+                    return Err(VmError::Verify(name));
+                }
+            }
+            OP_RETURN => return Err(VmError::OpReturn),
+
+            // Stack
+            OP_TOALTSTACK => {
+                let data = self.pop(&name)?;
+                self.altstack.push(data);
+            }
+            OP_FROMALTSTACK => {
+                let data = self
+                    .altstack
+                    .pop()
+                    .ok_or(VmError::InvalidAltstackOperation)?;
+                self.stack.push(data);
+            }
+            OP_2DROP => {
+                self.require(2, &name)?;
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            OP_2DUP => {
+                let (a, b) = (self.peek(1, &name)?, self.peek(0, &name)?);
+                self.stack.extend([a, b]);
+            }
+            OP_3DUP => {
+                let (a, b, c) = (
+                    self.peek(2, &name)?,
+                    self.peek(1, &name)?,
+                    self.peek(0, &name)?,
+                );
+                self.stack.extend([a, b, c]);
+            }
+            OP_2OVER => {
+                let (a, b) = (self.peek(3, &name)?, self.peek(2, &name)?);
+                self.stack.extend([a, b]);
+            }
+            OP_2ROT => {
+                self.require(6, &name)?;
+                let len = self.stack.len();
+                let items = self.stack.drain(len - 6..len - 4).collect::<Vec<Vec<u8>>>();
+                self.stack.extend(items);
+            }
+            OP_2SWAP => {
+                self.require(4, &name)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 4, len - 2);
+                self.stack.swap(len - 3, len - 1);
+            }
+            OP_IFDUP => {
+                let top = self.peek(0, &name)?;
+                if cast_to_bool(&top) {
+                    self.stack.push(top);
+                }
+            }
+            OP_DEPTH => self.stack.push(encode_num(self.stack.len() as i64)),
+            OP_DROP => {
+                self.pop(&name)?;
+            }
+            OP_DUP => {
+                let top = self.peek(0, &name)?;
+                self.stack.push(top);
+            }
+            OP_NIP => {
+                self.require(2, &name)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            }
+            OP_OVER => {
+                let item = self.peek(1, &name)?;
+                self.stack.push(item);
+            }
+            OP_PICK | OP_ROLL => {
+                let depth = self.pop_num(&name)?;
+                if depth < 0 || depth as usize >= self.stack.len() {
+                    return Err(VmError::InvalidStackOperation(name));
+                }
+                let index = self.stack.len() - 1 - depth as usize;
+                let item = if op == OP_ROLL {
+                    self.stack.remove(index)
+                } else {
+                    self.stack[index].to_owned()
+                };
+                self.stack.push(item);
+            }
+            OP_ROT => {
+                self.require(3, &name)?;
+                let len = self.stack.len();
+                let item = self.stack.remove(len - 3);
+                self.stack.push(item);
+            }
+            OP_SWAP => {
+                self.require(2, &name)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 2, len - 1);
+            }
+            OP_TUCK => {
+                self.require(2, &name)?;
+                let top = self.peek(0, &name)?;
+                let len = self.stack.len();
+                self.stack.insert(len - 2, top);
+            }
+            OP_SIZE => {
+                let size = self.peek(0, &name)?.len();
+                self.stack.push(encode_num(size as i64));
+            }
+
+            // Bitwise logic
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let (b, a) = (self.pop(&name)?, self.pop(&name)?);
+                if op == OP_EQUALVERIFY {
+                    if a != b {
+                        return Err(VmError::Verify(name));
+                    }
+                } else {
+                    self.stack.push(encode_bool(a == b));
+                }
+            }
+
+            // Arithmetic
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                let a = self.pop_num(&name)?;
+                let result = match op {
+                    OP_1ADD => a + 1,
+                    OP_1SUB => a - 1,
+                    OP_NEGATE => -a,
+                    OP_ABS => a.abs(),
+                    OP_NOT => (a == 0) as i64,
+                    _ => (a != 0) as i64,
+                };
+                self.stack.push(encode_num(result));
+            }
+            OP_ADD
+            | OP_SUB
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX => {
+                let (b, a) = (self.pop_num(&name)?, self.pop_num(&name)?);
+                let result = match op {
+                    OP_ADD => a + b,
+                    OP_SUB => a - b,
+                    OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                    OP_NUMNOTEQUAL => (a != b) as i64,
+                    OP_LESSTHAN => (a < b) as i64,
+                    OP_GREATERTHAN => (a > b) as i64,
+                    OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                if op == OP_NUMEQUALVERIFY {
+                    if result == 0 {
+                        return Err(VmError::Verify(name));
+                    }
+                } else {
+                    self.stack.push(encode_num(result));
+                }
+            }
+            OP_WITHIN => {
+                let (max, min, x) = (
+                    self.pop_num(&name)?,
+                    self.pop_num(&name)?,
+                    self.pop_num(&name)?,
+                );
+                self.stack.push(encode_bool(min <= x && x < max));
+            }
+
+            // Crypto
+            OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                let data = self.pop(&name)?;
+                let digest = match op {
+                    OP_RIPEMD160 => ripemd160::Hash::hash(&data).to_byte_array().to_vec(),
+                    OP_SHA1 => sha1::Hash::hash(&data).to_byte_array().to_vec(),
+                    OP_SHA256 => sha256::Hash::hash(&data).to_byte_array().to_vec(),
+                    OP_HASH160 => hash160::Hash::hash(&data).to_byte_array().to_vec(),
+                    _ => sha256d::Hash::hash(&data).to_byte_array().to_vec(),
+                };
+                self.stack.push(digest);
+            }
+            OP_CODESEPARATOR => (),
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let (pubkey, sig) = (self.pop(&name)?, self.pop(&name)?);
+                let success = self.check_sig(&sig, &pubkey)?;
+                if op == OP_CHECKSIGVERIFY {
+                    if !success {
+                        return Err(VmError::Verify(name));
+                    }
+                } else {
+                    self.stack.push(encode_bool(success));
+                }
+            }
+            OP_CHECKSIGADD => {
+                if self.version != SigVersion::Tapscript {
+                    return Err(VmError::BadOpcode(name));
+                }
+                let pubkey = self.pop(&name)?;
+                let n = self.pop_num(&name)?;
+                let sig = self.pop(&name)?;
+                let success = self.check_sig(&sig, &pubkey)?;
+                self.stack.push(encode_num(n + success as i64));
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                if self.version == SigVersion::Tapscript {
+                    return Err(VmError::TapscriptCheckMultiSig);
+                }
+                let n = self.pop_num(&name)?;
+                if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&n) {
+                    return Err(VmError::PubkeyCount);
+                }
+                // Every pubkey counts as an opcode.
+                self.op_count += n as usize;
+                if self.op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(VmError::OpCount);
+                }
+                let mut pubkeys: Vec<Vec<u8>> = vec![];
+                for _ in 0..n {
+                    pubkeys.push(self.pop(&name)?);
+                }
+                let m = self.pop_num(&name)?;
+                if m < 0 || m > n {
+                    return Err(VmError::SigCount);
+                }
+                let mut sigs: Vec<Vec<u8>> = vec![];
+                for _ in 0..m {
+                    sigs.push(self.pop(&name)?);
+                }
+                // NULLDUMMY: one more item is popped, which must be empty.
+                if !self.pop(&name)?.is_empty() {
+                    return Err(VmError::NullDummy);
+                }
+
+                // Signatures must be in the same order of pubkeys.
+                let mut success = true;
+                let (mut isig, mut ikey) = (0, 0);
+                while success && isig < sigs.len() {
+                    if self
+                        .checker
+                        .check_sig(&sigs[isig], &pubkeys[ikey], self.version)
+                    {
+                        isig += 1;
+                    }
+                    ikey += 1;
+                    // Not enough pubkeys left for the signatures.
+                    if sigs.len() - isig > pubkeys.len() - ikey {
+                        success = false;
+                    }
+                }
+                if !success && sigs.iter().any(|sig| !sig.is_empty()) {
+                    return Err(VmError::NullFail);
+                }
+                if op == OP_CHECKMULTISIGVERIFY {
+                    if !success {
+                        return Err(VmError::Verify(name));
+                    }
+                } else {
+                    self.stack.push(encode_bool(success));
+                }
+            }
+            _ => return Err(VmError::BadOpcode(name)),
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self, pc: usize, opcode: String, executed: bool) -> Step {
+        Step {
+            pc,
+            opcode,
+            executed,
+            stack: self.stack.iter().map(hex::encode).collect(),
+            altstack: self.altstack.iter().map(hex::encode).collect(),
+        }
+    }
+}
+
+/*
+    Execute script with the witness stack, which is in serialization order(stack bottom first).
+    - Legacy: witness is what scriptSig pushes before the redeem script.
+    - Script succeeds if the top is true, and for segwit and tapscript, it must be the only item.
+*/
+pub fn execute(
+    script: &[u8],
+    witness: Vec<Vec<u8>>,
+    version: SigVersion,
+    ctx: &TxContext,
+    checker: &dyn SignatureChecker,
+) -> Execution {
+    // Serialized witness size, which is the budget of tapscript signature checks(BIP342):
+    // count of items, then the stack items, script and control block, each with its size.
+    let serialized_size = |len: usize| bitcoin::VarInt(len as u64).size() + len;
+    let witness_size = bitcoin::VarInt(witness.len() as u64 + 2).size()
+        + witness
+            .iter()
+            .map(|item| serialized_size(item.len()))
+            .sum::<usize>()
+        + serialized_size(script.len())
+        + serialized_size(ctx.control_block.len());
+
+    let mut machine = Machine {
+        stack: witness,
+        altstack: vec![],
+        exec: vec![],
+        op_count: 0,
+        validation_weight: VALIDATION_WEIGHT_OFFSET + witness_size as i64,
+        version,
+        ctx,
+        checker,
+    };
+    let mut steps: Vec<Step> = vec![];
+    let result = run(&mut machine, script, &mut steps);
+
+    Execution {
+        steps,
+        stack: machine.stack.iter().map(hex::encode).collect(),
+        error: result.err(),
+    }
+}
+
+fn run(machine: &mut Machine, script: &[u8], steps: &mut Vec<Step>) -> Result<(), VmError> {
+    let script = Script::from_bytes(script);
+    if machine.version != SigVersion::Tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(VmError::ScriptSize);
+    }
+    if machine
+        .stack
+        .iter()
+        .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(VmError::PushSize);
+    }
+
+    // BIP342: OP_SUCCESSx anywhere makes the script succeed.
+    if machine.version == SigVersion::Tapscript {
+        for instruction in script.instructions() {
+            if let Ok(Instruction::Op(op)) = instruction {
+                if op.classify(ClassifyContext::TapScript) == Class::SuccessOp {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    for instruction in script.instruction_indices() {
+        let (pc, instruction) = instruction.map_err(|e| VmError::BadOpcode(format!("{}", e)))?;
+        let executed = machine.executing();
+        let opcode = match instruction {
+            Instruction::PushBytes(data) => {
+                if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(VmError::PushSize);
+                }
+                if executed {
+                    machine.stack.push(data.as_bytes().to_vec());
+                }
+                format!("<{}>", hex::encode(data.as_bytes()))
+            }
+            Instruction::Op(op) => {
+                machine.step(op)?;
+                op.to_string()
+            }
+        };
+        if machine.stack.len() + machine.altstack.len() > MAX_STACK_SIZE {
+            return Err(VmError::StackSize);
+        }
+        steps.push(machine.snapshot(pc, opcode, executed));
+    }
+
+    if !machine.exec.is_empty() {
+        return Err(VmError::UnbalancedConditional);
+    }
+    match machine.stack.last() {
+        Some(top) if cast_to_bool(top) => (),
+        _ => return Err(VmError::EvalFalse),
+    }
+    if machine.version != SigVersion::Legacy && machine.stack.len() != 1 {
+        return Err(VmError::CleanStack(machine.stack.len()));
+    }
+
+    Ok(())
+}
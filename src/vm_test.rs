@@ -0,0 +1,321 @@
+#[cfg(test)]
+mod tests {
+    use crate::compile_program;
+    use crate::test_util::{PUBKEY_A, PUBKEY_B, XONLY_A};
+    use crate::vm::*;
+
+    use bitcoin::hashes::{sha256d, Hash};
+    use std::fs;
+
+    const XONLY_B: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    // htlc with the hashlock of "secret".
+    fn htlc() -> Vec<u8> {
+        let hash = sha256d::Hash::hash(b"secret").to_string();
+        let hash = hex::encode(
+            hex::decode(hash)
+                .unwrap()
+                .into_iter()
+                .rev()
+                .collect::<Vec<u8>>(),
+        );
+        let source = fs::read_to_string("example/htlc.bithoven")
+            .unwrap()
+            .replace(
+                "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f",
+                &hash,
+            );
+        compile_program(source).unwrap().bytes()
+    }
+
+    fn checker(pairs: &[(&str, &str)]) -> MockSigChecker {
+        MockSigChecker {
+            valid: pairs
+                .iter()
+                .map(|(sig, pubkey)| (hex::decode(sig).unwrap(), hex::decode(pubkey).unwrap()))
+                .collect(),
+            accept_any: false,
+        }
+    }
+
+    fn items(hexes: &[&str]) -> Vec<Vec<u8>> {
+        hexes.iter().map(|h| hex::decode(h).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_script_number() {
+        for n in [0, 1, -1, 127, 128, -128, 255, 256, 1000, -32768, 2147483647] {
+            assert_eq!(decode_num(&encode_num(n), 5).unwrap(), n);
+        }
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-1), vec![0x81]);
+        // Not minimal: trailing zero.
+        assert!(decode_num(&[0x01, 0x00], 4).is_err());
+        assert!(decode_num(&[0x01, 0x02, 0x03, 0x04, 0x05], 4).is_err());
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+    }
+
+    #[test]
+    fn test_htlc_both_paths() {
+        let script = htlc();
+        let sigs = checker(&[("aa", PUBKEY_A), ("bb", PUBKEY_B)]);
+
+        // Alice after 1000 blocks.
+        let ctx = TxContext {
+            sequence: 1000,
+            ..TxContext::default()
+        };
+        let execution = execute(
+            &script,
+            items(&["aa", "01"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert!(execution.success(), "{:?}", execution.error);
+        assert_eq!(execution.stack, vec!["01"]);
+
+        // Bob with preimage, else branch is skipped in the trace.
+        let execution = execute(
+            &script,
+            vec![hex::decode("bb").unwrap(), b"secret".to_vec(), vec![]],
+            SigVersion::WitnessV0,
+            &TxContext::default(),
+            &sigs,
+        );
+        assert!(execution.success(), "{:?}", execution.error);
+        assert!(execution
+            .steps
+            .iter()
+            .any(|step| step.opcode == "OP_CSV" && !step.executed));
+    }
+
+    #[test]
+    fn test_htlc_failures() {
+        let script = htlc();
+        let sigs = checker(&[("aa", PUBKEY_A), ("bb", PUBKEY_B)]);
+
+        // Final nSequence disables relative locktime.
+        let execution = execute(
+            &script,
+            items(&["aa", "01"]),
+            SigVersion::WitnessV0,
+            &TxContext::default(),
+            &sigs,
+        );
+        assert!(matches!(
+            execution.error,
+            Some(VmError::UnsatisfiedLocktime(_))
+        ));
+
+        // Relative locktime not reached.
+        let ctx = TxContext {
+            sequence: 999,
+            ..TxContext::default()
+        };
+        let execution = execute(
+            &script,
+            items(&["aa", "01"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert!(matches!(
+            execution.error,
+            Some(VmError::UnsatisfiedLocktime(_))
+        ));
+
+        // Wrong signature must be empty to fail without error.
+        let ctx = TxContext {
+            sequence: 1000,
+            ..TxContext::default()
+        };
+        let execution = execute(
+            &script,
+            items(&["bb", "01"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert_eq!(execution.error, Some(VmError::NullFail));
+        let execution = execute(
+            &script,
+            items(&["", "01"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert_eq!(execution.error, Some(VmError::EvalFalse));
+
+        // Wrong preimage.
+        let execution = execute(
+            &script,
+            vec![hex::decode("bb").unwrap(), b"public".to_vec(), vec![]],
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert_eq!(
+            execution.error,
+            Some(VmError::Verify("OP_EQUALVERIFY".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_minimal_if_and_clean_stack() {
+        let script = htlc();
+        let ctx = TxContext {
+            sequence: 1000,
+            ..TxContext::default()
+        };
+        let sigs = MockSigChecker::accept_any();
+
+        let execution = execute(
+            &script,
+            items(&["aa", "02"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert_eq!(execution.error, Some(VmError::MinimalIf));
+        // Legacy does not require minimal condition nor clean stack.
+        let execution = execute(
+            &script,
+            items(&["00", "aa", "02"]),
+            SigVersion::Legacy,
+            &ctx,
+            &sigs,
+        );
+        assert!(execution.success(), "{:?}", execution.error);
+
+        let execution = execute(
+            &script,
+            items(&["00", "aa", "01"]),
+            SigVersion::WitnessV0,
+            &ctx,
+            &sigs,
+        );
+        assert_eq!(execution.error, Some(VmError::CleanStack(2)));
+    }
+
+    #[test]
+    fn test_absolute_locktime() {
+        // <500000> OP_CLTV
+        let script = hex::decode("0320a107b1").unwrap();
+        let run = |lock_time: u32, sequence: u32| {
+            let ctx = TxContext {
+                lock_time,
+                sequence,
+                ..TxContext::default()
+            };
+            execute(
+                &script,
+                vec![],
+                SigVersion::Legacy,
+                &ctx,
+                &MockSigChecker::default(),
+            )
+        };
+        assert!(run(500000, 0).success());
+        assert!(run(499999, 0).error.is_some());
+        assert!(run(500000, SEQUENCE_FINAL).error.is_some());
+        // Timestamp can't satisfy height.
+        assert!(run(1700000000, 0).error.is_some());
+    }
+
+    #[test]
+    fn test_tapscript_multisig() {
+        let source = format!(
+            r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target taproot;
+            (sig_alice: signature, sig_bob: signature)
+            {{
+                return checksig [2, (sig_alice, "{}"), (sig_bob, "{}")];
+            }}
+            "#,
+            XONLY_A, XONLY_B
+        );
+        let script = compile_program(source).unwrap().bytes();
+        let sigs = checker(&[("aa", XONLY_A), ("bb", XONLY_B)]);
+
+        let execution = execute(
+            &script,
+            items(&["bb", "aa"]),
+            SigVersion::Tapscript,
+            &TxContext::default(),
+            &sigs,
+        );
+        assert!(execution.success(), "{:?}", execution.error);
+        assert!(execution
+            .steps
+            .iter()
+            .any(|step| step.opcode == "OP_CHECKSIGADD"));
+
+        // One empty signature makes 1 of 2.
+        let execution = execute(
+            &script,
+            items(&["", "aa"]),
+            SigVersion::Tapscript,
+            &TxContext::default(),
+            &sigs,
+        );
+        assert_eq!(execution.error, Some(VmError::EvalFalse));
+    }
+
+    #[test]
+    fn test_tapscript_validation_weight() {
+        // (OP_1 OP_CHECKSIGVERIFY) * 4 OP_1: public key of unknown type, so any signature is valid.
+        let script = hex::decode("51ad51ad51ad51ad51").unwrap();
+        let run = |sigs: &[&str], control_block: usize| {
+            let ctx = TxContext {
+                control_block: vec![0xc0; control_block],
+                ..TxContext::default()
+            };
+            execute(
+                &script,
+                items(sigs),
+                SigVersion::Tapscript,
+                &ctx,
+                &MockSigChecker::default(),
+            )
+        };
+
+        // 4 signature checks need 200: 50 + 1 count + 8 signatures + 10 script + 130 control block.
+        assert_eq!(
+            run(&["01", "01", "01", "01"], 129).error,
+            Some(VmError::TapscriptValidationWeight)
+        );
+        assert!(run(&["0101", "01", "01", "01"], 129).success());
+        // Control block of a deeper leaf funds it as well.
+        assert!(run(&["01", "01", "01", "01"], 161).success());
+    }
+
+    #[test]
+    fn test_opcode_rules() {
+        let ctx = TxContext::default();
+        let sigs = MockSigChecker::default();
+
+        // OP_0 OP_IF OP_CAT OP_ENDIF OP_1: disabled even if not executed.
+        let script = hex::decode("00637e6851").unwrap();
+        let execution = execute(&script, vec![], SigVersion::WitnessV0, &ctx, &sigs);
+        assert!(matches!(execution.error, Some(VmError::DisabledOpcode(_))));
+        // OP_CAT is OP_SUCCESS in tapscript.
+        let execution = execute(&script, vec![], SigVersion::Tapscript, &ctx, &sigs);
+        assert!(execution.success());
+
+        // OP_0 OP_0 OP_0 OP_CHECKMULTISIG is disabled in tapscript.
+        let script = hex::decode("000000ae").unwrap();
+        let execution = execute(&script, vec![], SigVersion::Tapscript, &ctx, &sigs);
+        assert_eq!(execution.error, Some(VmError::TapscriptCheckMultiSig));
+
+        // OP_1 OP_TOALTSTACK OP_FROMALTSTACK is traced.
+        let script = hex::decode("516b6c").unwrap();
+        let execution = execute(&script, vec![], SigVersion::WitnessV0, &ctx, &sigs);
+        assert!(execution.success());
+        assert_eq!(execution.steps[1].altstack, vec!["01"]);
+        assert!(execution.steps[1].stack.is_empty());
+        assert_eq!(execution.steps[2].stack, vec!["01"]);
+    }
+}
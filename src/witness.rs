@@ -0,0 +1,235 @@
+use crate::ast::*;
+use crate::compile::path_leaves;
+use crate::vm::encode_num;
+use crate::BithovenOutput;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+// An element of witness(or scriptSig for legacy) to satisfy a spending path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[wasm_bindgen]
+pub struct WitnessElement {
+    kind: String,
+    name: String,
+    ty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+#[wasm_bindgen]
+impl WitnessElement {
+    // "param" to be filled by signer, "literal" fixed by the path,
+    // "script" and "control_block" given by the compiler.
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn ty(&self) -> String {
+        self.ty.clone()
+    }
+    // Hex of the element, none for "param".
+    #[wasm_bindgen(getter)]
+    pub fn value(&self) -> Option<String> {
+        self.value.clone()
+    }
+}
+
+// Witness of a spending path, elements in serialization order(stack bottom first).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[wasm_bindgen]
+pub struct WitnessTemplate {
+    path: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    layout: String,
+    elements: Vec<WitnessElement>,
+}
+
+#[wasm_bindgen]
+impl WitnessTemplate {
+    #[wasm_bindgen(getter)]
+    pub fn path(&self) -> usize {
+        self.path
+    }
+    // Name of the spending path, none if its input stack is anonymous.
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+    // "script_sig" for legacy, "witness" for segwit and taproot.
+    #[wasm_bindgen(getter)]
+    pub fn layout(&self) -> String {
+        self.layout.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn elements(&self) -> Vec<WitnessElement> {
+        self.elements.clone()
+    }
+}
+
+// Items fixed by the branches a path takes, in hex: "verify b" needs true, "verify !b" false,
+// and selector of match the value of its arm.
+// OP_IF requires minimal true(0x01) and false(empty).
+// Signature of "verify !checksig" must be empty, as a failing non-empty one is rejected(NULLFAIL).
+pub fn path_literals(leaf: &[Statement], stack: &[StackParam]) -> HashMap<String, String> {
+    let is_typed = |id: &Identifier, ty: Type| {
+        stack
+            .iter()
+            .any(|item| item.identifier == *id && item.ty == ty)
+    };
+    let is_bool = |id: &Identifier| is_typed(id, Type::Boolean);
+
+    let mut literals: HashMap<String, String> = HashMap::new();
+    for stmt in leaf {
+        match stmt {
+            Statement::VerifyStatement(_, Expression::Variable(_, id)) if is_bool(id) => {
+                literals.insert(id.0.to_owned(), "01".to_string());
+            }
+            Statement::VerifyStatement(
+                _,
+                Expression::CompareExpression {
+                    lhs,
+                    op: BinaryCompareOp::NumEqual,
+                    rhs,
+                    ..
+                },
+            ) => {
+                if let (Expression::Variable(_, id), Expression::NumberLiteral(_, value)) =
+                    (&**lhs, &**rhs)
+                {
+                    if is_typed(id, Type::Number) {
+                        literals.insert(id.0.to_owned(), hex::encode(encode_num(*value)));
+                    }
+                }
+            }
+            Statement::VerifyStatement(
+                _,
+                Expression::UnaryMathExpression {
+                    operand,
+                    op: UnaryMathOp::Not,
+                    ..
+                },
+            ) => match &**operand {
+                Expression::Variable(_, id) if is_bool(id) => {
+                    literals.insert(id.0.to_owned(), String::new());
+                }
+                Expression::CheckSigExpression { operand, .. } => {
+                    if let Factor::SingleSigFactor { sig, .. } = &**operand {
+                        if let Expression::Variable(_, id) = &**sig {
+                            if is_typed(id, Type::Signature) {
+                                literals.insert(id.0.to_owned(), String::new());
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    literals
+}
+
+/*
+    Build witness template of every spending path from the compiled output.
+    - Legacy: <items> <redeem script> in scriptSig.
+    - Segwit: <items> <witness script>.
+    - Taproot: <items> <tapleaf script> <control block>.
+*/
+pub fn witness_templates(
+    input: &[Vec<StackParam>],
+    paths: &[Option<PathName>],
+    ast: &[Statement],
+    target: &Target,
+    output: &BithovenOutput,
+) -> Vec<WitnessTemplate> {
+    let leaves = path_leaves(ast);
+    let script_element = |name: &str, value: Option<String>| WitnessElement {
+        kind: "script".to_string(),
+        name: name.to_string(),
+        ty: "script".to_string(),
+        value,
+    };
+
+    input
+        .iter()
+        .enumerate()
+        .map(|(path, stack)| {
+            let literals = leaves
+                .get(path)
+                .map_or(HashMap::new(), |leaf| path_literals(leaf, stack));
+
+            // Input stack is parsed in reverse, so the first item is the bottom.
+            let mut elements = stack
+                .iter()
+                .map(|item| match literals.get(&item.identifier.0) {
+                    Some(value) => WitnessElement {
+                        kind: "literal".to_string(),
+                        name: item.identifier.0.to_owned(),
+                        ty: item.ty.to_string(),
+                        value: Some(value.to_owned()),
+                    },
+                    None => WitnessElement {
+                        kind: "param".to_string(),
+                        name: item.identifier.0.to_owned(),
+                        ty: item.ty.to_string(),
+                        value: None,
+                    },
+                })
+                .collect::<Vec<WitnessElement>>();
+
+            let locking = output.locking();
+            match target {
+                Target::Legacy => elements.push(script_element(
+                    "redeem_script",
+                    locking.and_then(|l| l.redeem_script()),
+                )),
+                Target::Segwit => elements.push(script_element(
+                    "witness_script",
+                    locking.and_then(|l| l.witness_script()),
+                )),
+                Target::Taproot => {
+                    let leaves = output.taproot().map_or(vec![], |t| t.leaves());
+                    // Single tapleaf is shared by every path.
+                    if let Some(leaf) = leaves
+                        .iter()
+                        .find(|leaf| leaf.path() == path)
+                        .or(leaves.first())
+                    {
+                        elements.push(script_element("tapleaf_script", Some(leaf.hex())));
+                        elements.push(WitnessElement {
+                            kind: "control_block".to_string(),
+                            name: "control_block".to_string(),
+                            ty: "control_block".to_string(),
+                            value: Some(leaf.control_block()),
+                        });
+                    }
+                }
+            }
+
+            WitnessTemplate {
+                path,
+                name: paths
+                    .get(path)
+                    .and_then(|name| name.as_ref())
+                    .map(|name| name.identifier.0.to_owned()),
+                layout: match target {
+                    Target::Legacy => "script_sig",
+                    _ => "witness",
+                }
+                .to_string(),
+                elements,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod tests {
+    use crate::compile_program;
+    use crate::test_util::example;
+
+    // (kind, name, value) of each element.
+    fn summary(template: &crate::WitnessTemplate) -> Vec<(String, String, Option<String>)> {
+        template
+            .elements()
+            .iter()
+            .map(|e| (e.kind(), e.name(), e.value()))
+            .collect()
+    }
+
+    #[test]
+    fn test_segwit_witness_template() {
+        let output = compile_program(example("htlc.bithoven")).unwrap();
+        let witness = output.witness();
+
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[0].layout(), "witness");
+        // Stack bottom first, the if selector on top and the witness script last.
+        let first = summary(&witness[0]);
+        assert_eq!(first[0], ("param".into(), "sig_alice".into(), None));
+        assert_eq!(
+            first[1],
+            ("literal".into(), "condition".into(), Some("01".into()))
+        );
+        assert_eq!(
+            first[2],
+            ("script".into(), "witness_script".into(), Some(output.hex()))
+        );
+
+        let second = summary(&witness[1]);
+        assert_eq!(second[0].1, "sig_bob");
+        assert_eq!(second[1].1, "preimage");
+        assert_eq!(
+            second[2],
+            ("literal".into(), "condition".into(), Some("".into()))
+        );
+        assert_eq!(witness[1].elements()[1].ty(), "string");
+    }
+
+    #[test]
+    fn test_legacy_script_sig_template() {
+        let source = example("singlesig.bithoven").replace("target segwit", "target legacy");
+        let output = compile_program(source).unwrap();
+        let witness = output.witness();
+
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness[0].layout(), "script_sig");
+        assert_eq!(
+            summary(&witness[0]),
+            vec![
+                ("param".into(), "sig_alice".into(), None),
+                ("script".into(), "redeem_script".into(), Some(output.hex())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_taproot_template_has_control_block() {
+        let source = example("prediction_market.bithoven").replace(
+            "pragma bithoven target taproot;",
+            "pragma bithoven target taproot;\npragma bithoven tapleaf path;",
+        );
+        let output = compile_program(source).unwrap();
+        let leaves = output.taproot().unwrap().leaves();
+        let witness = output.witness();
+
+        assert_eq!(witness.len(), 3);
+        for (template, leaf) in witness.iter().zip(leaves.iter()) {
+            let elements = template.elements();
+            let len = elements.len();
+            assert_eq!(elements[len - 2].value(), Some(leaf.hex()));
+            assert_eq!(elements[len - 1].kind(), "control_block");
+            assert_eq!(elements[len - 1].value(), Some(leaf.control_block()));
+        }
+        // Conditions are not a plain bool, so nothing is fixed.
+        assert!(witness
+            .iter()
+            .flat_map(|t| t.elements())
+            .all(|e| e.kind() != "literal"));
+    }
+
+    #[test]
+    fn test_match_selector() {
+        // Selector is fixed to the value of the arm, as a minimal script number.
+        let key = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let output = compile_program(format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+(choice: number, sig_a: signature)
+(choice: number, sig_b: signature)
+(choice: number, sig_c: signature)
+{{
+    match choice {{
+        0 => {{ return checksig (sig_a, "{0}"); }}
+        1 => {{ return checksig (sig_b, "{0}"); }}
+        200 => {{ return checksig (sig_c, "{0}"); }}
+    }}
+}}"#,
+            key
+        ))
+        .unwrap();
+        let selectors = output
+            .witness()
+            .iter()
+            .map(|template| summary(template)[1].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            selectors,
+            vec![
+                ("literal".into(), "choice".into(), Some("".into())),
+                ("literal".into(), "choice".into(), Some("01".into())),
+                ("literal".into(), "choice".into(), Some("c800".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failing_checksig_signature_is_empty() {
+        // Else branch of "if checksig" is taken only with an empty signature.
+        let output = compile_program(example("escrow.bithoven")).unwrap();
+        let witness = output.witness();
+        let empty = |path: usize| {
+            summary(&witness[path])
+                .into_iter()
+                .filter(|(kind, _, _)| kind == "literal")
+                .collect::<Vec<_>>()
+        };
+
+        assert!(empty(0).is_empty());
+        assert_eq!(
+            empty(1),
+            vec![(
+                "literal".into(),
+                "sig_seller_release".into(),
+                Some("".into())
+            )]
+        );
+        assert_eq!(
+            empty(2),
+            vec![("literal".into(), "sig_buyer".into(), Some("".into()))]
+        );
+        assert_eq!(summary(&witness[1])[0].0, "param");
+    }
+
+    #[test]
+    fn test_nested_selectors() {
+        // Every path is selected by "condition".
+        let output = compile_program(example("nestedif.bithoven")).unwrap();
+        for template in output.witness() {
+            let literals = template
+                .elements()
+                .iter()
+                .filter(|e| e.kind() == "literal")
+                .count();
+            assert!(literals > 0, "No selector for path {}", template.path());
+        }
+    }
+}